@@ -0,0 +1,124 @@
+# Deutsch
+
+settings-title = Einstellungen
+settings-language = Sprache
+settings-autosave-interval = Automatisch speichern alle (Minuten, 0 schaltet es aus)
+settings-autosave-slots = Plätze für automatisches Speichern
+settings-mods = Mods, in Ladereihenfolge
+settings-mods-none = Keine Mods im Ordner mods
+settings-mods-restart = Starte das Spiel neu, um die Änderungen an den Mods zu übernehmen
+settings-mods-apply = Jetzt neu starten, das laufende Spiel wird nicht gespeichert
+
+menu-continue = Fortsetzen
+menu-new-game = Neues Spiel
+menu-join = Beitreten
+menu-quit = Beenden
+menu-mod-errors = { $count } Fehler in Mods, die Einstellungen (F3) listen sie auf
+
+join-title = Einem Spiel beitreten
+join-address = Adresse
+join-name = Name
+join-password = Admin-Passwort
+join-observe = Nur zuschauen, ohne Team
+join-lan = Spiele im lokalen Netzwerk
+join-lan-empty = Noch keine gefunden
+join-lan-game = { $address }, { $players } Spieler
+join-join = Beitreten
+join-failed = Verbindung zu { $address } nicht möglich: { $error }
+net-title = Mehrspieler
+net-joining = Verbinde mit { $address }
+net-receiving = Empfange die Welt von { $address }: { $percent } %
+net-refused = { $address } hat den Beitritt abgelehnt: { $reason }
+net-kicked = { $by } hat dich aus { $address } hinausgeworfen
+net-broken = Die Welt von { $address } kann nicht geladen werden: { $error }
+net-disconnected = Verbindung zu { $address } verloren
+
+scripting-unavailable = Einheitenprogramme laufen in der Browserversion noch nicht, Einheiten stehen still. Lade das Spiel herunter, um mit Skripten zu spielen.
+
+template-dialog-title = Neues Programm
+template-dialog-select-unit = Wähle eine Einheit aus, um ihr ein Programm einzusetzen
+template-dialog-insert = Einsetzen
+template-dialog-share = Teilen
+template-dialog-name = Name
+template-dialog-description = Beschreibung
+template-dialog-export = Programm der ausgewählten Einheit als Text kopieren
+template-dialog-import = Importieren
+template-dialog-exported = Der Programmtext wurde in die Zwischenablage kopiert
+template-dialog-imported = { $name } wurde in die ausgewählte Einheit importiert
+template-dialog-import-failed = Das Programm kann nicht importiert werden: { $error }
+template-wander = Umherwandern
+template-wander-description = Wandert in zufällige Richtungen umher
+template-wall-follow = Wand folgen
+template-wall-follow-description = Folgt Wänden und hält sie dabei rechts
+template-patrol = Patrouille
+template-patrol-description = Läuft eine Route von Wegpunkten im Kreis ab
+template-miner = Bergbau
+template-miner-description = Bringt Gegenstände von einer Abbaustelle zur Einheit mit dem Label "depot", braucht ein Inventar
+
+console-title = Konsole
+console-help = Befehle: /spawn <blueprint> <x> <y>, /copy <blueprint>, /paste <blueprint> <x> <y>, /export <blueprint>, /import <blueprint> <string>, /team <team>, /kick <player>, /pause, /usage, /save <name>, /load [name], /help
+console-usage-spawn = Verwendung: /spawn <blueprint> <x> <y>
+console-usage-team = Verwendung: /team <team>
+console-usage-kick = Verwendung: /kick <player>
+console-usage-copy = Verwendung: /copy <blueprint>, kopiert die ausgewählte Einheit
+console-usage-paste = Verwendung: /paste <blueprint> <x> <y>
+console-usage-export = Verwendung: /export <blueprint>
+console-usage-import = Verwendung: /import <blueprint> <string>
+console-usage-save = Verwendung: /save <name>, Namen dürfen nur Buchstaben, Ziffern, - und _ enthalten
+console-blueprint-copied = die ausgewählte Einheit wurde als { $blueprint } kopiert
+console-blueprint-exported = { $blueprint } (in die Zwischenablage kopiert): { $string }
+console-blueprint-imported = { $blueprint } importiert
+console-blueprint-failed = Blueprint { $blueprint }: { $error }
+console-blueprint-missing = es gibt keinen Blueprint namens { $blueprint }
+console-unknown-command = unbekannter Befehl { $command }, Befehle: /spawn <blueprint> <x> <y>, /copy <blueprint>, /paste <blueprint> <x> <y>, /export <blueprint>, /import <blueprint> <string>, /team <team>, /kick <player>, /pause, /usage, /save <name>, /load [name], /help
+console-chat = <du> { $text }
+console-chat-from = <{ $player }> { $text }
+console-kicked = { $player } wurde hinausgeworfen
+console-not-playing = { $player } spielt nicht mit
+console-paused = das Spiel ist pausiert, /pause setzt es fort
+console-resumed = das Spiel geht weiter
+console-no-unit = es gibt keine Einheit { $unit }
+console-script-usage = Skripte von Team { $team }: { $instructions }k Anweisungen/s, { $memory } KiB
+console-script-usage-throttled = Skripte von Team { $team }: { $instructions }k Anweisungen/s, { $memory } KiB, gedrosselt
+console-script-usage-none = kein Team führt Skripte aus
+console-spawning = { $blueprint } wird bei { $x }, { $y } erzeugt
+console-team-joined = die ausgewählte Einheit ist Team { $team } beigetreten
+console-select-unit = wähle zuerst eine Einheit aus
+console-save-saved = die Welt wurde als { $save } gespeichert
+console-save-loaded = { $save } geladen, { $units } Einheiten
+console-save-failed = { $save } kann nicht gespeichert werden: { $error }
+console-load-failed = Laden nicht möglich: { $error }
+console-load-mismatch = Warnung: { $mismatch }
+
+browser-title = Prototypen
+browser-search = Suche
+browser-spawn = Eins erzeugen
+
+history-title = Einheitenverlauf
+history-select-unit = Wähle eine Einheit aus, um aufzuzeichnen, was sie tut
+history-live = Live
+history-tick = Tick { $tick }, vor { $ago } Ticks
+history-position = Position
+history-rotation = Drehung
+history-status = Programm
+history-energy = Energie
+history-speed = Geschwindigkeit
+history-blocked-by = blockiert von
+history-obstacle = Hindernis
+history-move = Bewegung
+history-rotate = Drehen
+history-heading = Kurs
+history-target-speed = Zielgeschwindigkeit
+history-force = Kraft / Drehmoment
+history-hand-brake = Handbremse
+history-overclocked = übertaktet
+history-storage = Änderungen der Blackbox
+history-no-changes = in diesem Tick nichts geschrieben
+
+api-title = API-Referenz
+api-version = Einheiten-API { $version }
+api-since = seit { $version }
+api-search = Suche
+api-kind-method = Methode
+api-kind-field = Feld
+api-kind-equipment = Ausrüstung
@@ -0,0 +1,124 @@
+# English, also the fallback for keys missing in other languages
+
+settings-title = Settings
+settings-language = Language
+settings-autosave-interval = Autosave every (minutes, 0 turns it off)
+settings-autosave-slots = Autosave slots
+settings-mods = Mods, in load order
+settings-mods-none = No mods in the mods folder
+settings-mods-restart = Restart the game to apply the changes to the mods
+settings-mods-apply = Restart now, the running game isn't saved
+
+menu-continue = Continue
+menu-new-game = New game
+menu-join = Join
+menu-quit = Quit
+menu-mod-errors = { $count } mod errors, the settings (F3) list them
+
+join-title = Join a game
+join-address = Address
+join-name = Name
+join-password = Admin password
+join-observe = Only watch, without a team
+join-lan = Games on the local network
+join-lan-empty = None found yet
+join-lan-game = { $address }, { $players } players
+join-join = Join
+join-failed = Can't connect to { $address }: { $error }
+net-title = Multiplayer
+net-joining = Joining { $address }
+net-receiving = Receiving the world from { $address }: { $percent }%
+net-refused = { $address } didn't let you join: { $reason }
+net-kicked = { $by } kicked you from { $address }
+net-broken = Can't load the world from { $address }: { $error }
+net-disconnected = Lost the connection to { $address }
+
+scripting-unavailable = Unit programs don't run in the browser version yet, units stand still. Download the game to play with scripts.
+
+template-dialog-title = New program
+template-dialog-select-unit = Select a unit to insert a program into it
+template-dialog-insert = Insert
+template-dialog-share = Share
+template-dialog-name = Name
+template-dialog-description = Description
+template-dialog-export = Copy the selected unit's program as a string
+template-dialog-import = Import
+template-dialog-exported = Copied the program string to the clipboard
+template-dialog-imported = Imported { $name } into the selected unit
+template-dialog-import-failed = Can't import the program: { $error }
+template-wander = Wander
+template-wander-description = Wanders around in random directions
+template-wall-follow = Wall follow
+template-wall-follow-description = Follows walls, keeping them on its right
+template-patrol = Patrol
+template-patrol-description = Walks a route of waypoints in a loop
+template-miner = Miner
+template-miner-description = Brings items from a mining spot to the unit labelled "depot", needs an inventory
+
+console-title = Console
+console-help = commands: /spawn <blueprint> <x> <y>, /copy <blueprint>, /paste <blueprint> <x> <y>, /export <blueprint>, /import <blueprint> <string>, /team <team>, /kick <player>, /pause, /usage, /save <name>, /load [name], /help
+console-usage-spawn = usage: /spawn <blueprint> <x> <y>
+console-usage-team = usage: /team <team>
+console-usage-kick = usage: /kick <player>
+console-usage-copy = usage: /copy <blueprint>, copies the selected unit
+console-usage-paste = usage: /paste <blueprint> <x> <y>
+console-usage-export = usage: /export <blueprint>
+console-usage-import = usage: /import <blueprint> <string>
+console-usage-save = usage: /save <name>, names can only contain letters, digits, - and _
+console-blueprint-copied = copied the selected unit as { $blueprint }
+console-blueprint-exported = { $blueprint } (copied to the clipboard): { $string }
+console-blueprint-imported = imported { $blueprint }
+console-blueprint-failed = blueprint { $blueprint }: { $error }
+console-blueprint-missing = there's no blueprint named { $blueprint }
+console-unknown-command = unknown command { $command }, commands: /spawn <blueprint> <x> <y>, /copy <blueprint>, /paste <blueprint> <x> <y>, /export <blueprint>, /import <blueprint> <string>, /team <team>, /kick <player>, /pause, /usage, /save <name>, /load [name], /help
+console-chat = <you> { $text }
+console-chat-from = <{ $player }> { $text }
+console-kicked = kicked { $player }
+console-not-playing = { $player } isn't playing
+console-paused = the game is paused, /pause goes on
+console-resumed = the game goes on
+console-no-unit = there's no unit { $unit }
+console-script-usage = team { $team } scripts: { $instructions }k instructions/s, { $memory } KiB
+console-script-usage-throttled = team { $team } scripts: { $instructions }k instructions/s, { $memory } KiB, throttled
+console-script-usage-none = no team runs scripts
+console-spawning = spawning { $blueprint } at { $x }, { $y }
+console-team-joined = the selected unit joined team { $team }
+console-select-unit = select a unit first
+console-save-saved = saved the world as { $save }
+console-save-loaded = loaded { $save }, { $units } units
+console-save-failed = can't save { $save }: { $error }
+console-load-failed = can't load: { $error }
+console-load-mismatch = warning: { $mismatch }
+
+browser-title = Prototypes
+browser-search = search
+browser-spawn = Spawn one
+
+history-title = Unit history
+history-select-unit = Select a unit to record what it does
+history-live = Live
+history-tick = tick { $tick }, { $ago } ticks ago
+history-position = position
+history-rotation = rotation
+history-status = program
+history-energy = energy
+history-speed = speed
+history-blocked-by = blocked by
+history-obstacle = obstacle
+history-move = move
+history-rotate = rotate
+history-heading = heading
+history-target-speed = target speed
+history-force = force / torque
+history-hand-brake = hand brake
+history-overclocked = overclocked
+history-storage = Black box changes
+history-no-changes = nothing written this tick
+
+api-title = API reference
+api-version = unit handle API { $version }
+api-since = since { $version }
+api-search = search
+api-kind-method = method
+api-kind-field = field
+api-kind-equipment = equipment
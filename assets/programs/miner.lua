@@ -0,0 +1,47 @@
+-- Picks up items lying around the mining spot and brings them to the unit labelled "depot".
+-- Needs an inventory.
+local MINE = {6, 2}
+local LOAD = 10
+local going_to_depot = false
+
+-- returns the distance left, `move` is relative to where the unit faces
+local function move_towards(handle, goal)
+    local gps = handle.gps
+    local dx, dy = goal[1] - gps.position[1], goal[2] - gps.position[2]
+    local distance = math.sqrt(dx * dx + dy * dy)
+    if distance > 0 then
+        local angle = math.rad(gps.rotation)
+        local scale = math.min(distance, 1) / distance
+        handle:move(
+            (dx * math.cos(angle) - dy * math.sin(angle)) * scale,
+            (dx * math.sin(angle) + dy * math.cos(angle)) * scale
+        )
+    end
+    return distance
+end
+
+function on_tick(handle)
+    local inventory = handle.inventory
+    if inventory == nil then
+        handle:hibernate()
+        return
+    end
+    local carried = 0
+    for _, count in pairs(inventory) do
+        carried = carried + count
+    end
+    if going_to_depot then
+        local depot = handle:find_unit("depot")
+        if depot == nil then
+            handle:hibernate(60)
+        elseif move_towards(handle, depot) < 1.5 then
+            for item, count in pairs(inventory) do
+                handle:drop_item(item, count)
+            end
+            going_to_depot = false
+        end
+    elseif move_towards(handle, MINE) < 1 then
+        handle:pick_up_items()
+        going_to_depot = carried >= LOAD
+    end
+end
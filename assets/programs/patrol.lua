@@ -0,0 +1,21 @@
+-- Walks a route of waypoints in a loop. Change the list to change the route.
+local waypoints = {{0, 0}, {4, 0}, {4, -4}, {0, -4}}
+local target = 1
+
+function on_tick(handle)
+    local gps = handle.gps
+    local goal = waypoints[target]
+    local dx, dy = goal[1] - gps.position[1], goal[2] - gps.position[2]
+    local distance = math.sqrt(dx * dx + dy * dy)
+    if distance < 0.1 then
+        target = target % #waypoints + 1
+        return
+    end
+    -- `move` is relative to where the unit faces, rotation is in degrees clockwise
+    local angle = math.rad(gps.rotation)
+    local scale = math.min(distance, 1) / distance
+    handle:move(
+        (dx * math.cos(angle) - dy * math.sin(angle)) * scale,
+        (dx * math.sin(angle) + dy * math.cos(angle)) * scale
+    )
+end
@@ -0,0 +1,22 @@
+-- Follows walls, keeping them on its right. Turns left when blocked and tries to turn right every
+-- second, so it goes around corners.
+local directions = {{1, 0}, {0, 1}, {-1, 0}, {0, -1}} -- counterclockwise, starting east
+local current = 1
+local last_position = nil
+local ticks_since_turn = 0
+
+function on_tick(handle)
+    local position = handle.gps.position
+    local moved = last_position == nil
+        or math.abs(position[1] - last_position[1]) + math.abs(position[2] - last_position[2]) > 0.001
+    last_position = position
+    ticks_since_turn = ticks_since_turn + 1
+    if not moved then
+        current = current % 4 + 1 -- left
+        ticks_since_turn = 0
+    elseif ticks_since_turn >= 60 then
+        current = (current + 2) % 4 + 1 -- right
+        ticks_since_turn = 0
+    end
+    handle:move(directions[current][1], directions[current][2])
+end
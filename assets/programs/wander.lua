@@ -0,0 +1,17 @@
+-- Wanders around, picking a new direction every few seconds or when it gets stuck
+local direction = {1, 0}
+local next_turn = 0
+local last_position = nil
+
+function on_tick(handle)
+    local position = handle.gps.position
+    local stuck = last_position ~= nil
+        and math.abs(position[1] - last_position[1]) + math.abs(position[2] - last_position[2]) < 0.001
+    if stuck or handle.time_since_start >= next_turn then
+        local angle = handle:random() * 2 * math.pi
+        direction = {math.cos(angle), math.sin(angle)}
+        next_turn = handle.time_since_start + 2 + handle:random() * 3
+    end
+    last_position = position
+    handle:move(direction[1], direction[2])
+end
@@ -0,0 +1,14 @@
+-- A unit told to move right gets five tiles further within ten seconds and survives the trip.
+-- cargo run --features headless -- --scenario scenarios/move_right.lua
+
+local program = [[
+function on_tick(handle)
+    handle:move(1, 0)
+end
+]]
+
+function setup(scenario)
+    local unit = scenario:spawn({program = program, movement = "default", health = "default"}, -3, -9)
+    scenario:assert_unit_reaches(unit, {x = 2, y = -9, radius = 0.5}, 600)
+    scenario:assert_alive(unit, 600)
+end
@@ -0,0 +1,584 @@
+//! Enums for representing data stored in data storages. Takes inspiration from mlua's Value.
+
+use serde::{Serialize, Deserialize, Deserializer, de::Error as _};
+use std::collections::HashMap;
+// Lua doesn't run in the browser yet
+#[cfg(not(target_arch = "wasm32"))]
+use mlua::prelude::*;
+use thiserror::Error;
+use ordered_float::OrderedFloat;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum DataValue {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(String),
+    Sequence(Vec<DataValue>),
+    Table(HashMap<DataValueHashEq, DataValue>)
+}
+
+// `Deserialize` is written by hand below, so number keys are normalized like everywhere else
+#[derive(Debug, Clone, Serialize, Hash, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum DataValueHashEq {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    // never NaN or integral, those are rejected or turned into integers like Lua does with keys
+    Number(OrderedFloat<f64>),
+    String(String),
+    Sequence(Vec<DataValueHashEq>),
+}
+
+impl From<DataValueHashEq> for DataValue {
+    fn from(data: DataValueHashEq) -> Self {
+        match data {
+            DataValueHashEq::Nil => Self::Nil,
+            DataValueHashEq::Boolean(b) => Self::Boolean(b),
+            DataValueHashEq::Integer(i) => Self::Integer(i),
+            DataValueHashEq::Number(n) => Self::Number(n.into_inner()),
+            DataValueHashEq::String(s) => Self::String(s),
+            DataValueHashEq::Sequence(sq) => Self::Sequence(sq.into_iter().map(Into::into).collect())
+        }
+    }
+}
+
+/// Lua normalizes float keys with an integral value into integers, this does the same
+fn hash_eq_from_number(n: f64) -> Result<DataValueHashEq, DataValueConversionError> {
+    if n.is_nan() {
+        Err(DataValueConversionError::Nan)
+    } else if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+        Ok(DataValueHashEq::Integer(n as i64))
+    } else {
+        Ok(DataValueHashEq::Number(OrderedFloat(n)))
+    }
+}
+
+/// Limits on values converted from Lua, so scripts can't push arbitrarily large data into storages.
+/// Set per Lua state with `Lua::set_app_data`, `Default` is used when none is set.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Copy, Debug)]
+pub struct DataValueLimits {
+    pub max_depth: usize, // nested tables
+    pub max_size: usize // approximate bytes
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for DataValueLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 32,
+            max_size: 64 * 1024
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DataValue {
+    fn from_lua_limited(lua_value: LuaValue, limits: &DataValueLimits, depth: usize, size: &mut usize) -> LuaResult<Self> {
+        let type_name = lua_value.type_name();
+        let value_size = match &lua_value {
+            LuaValue::String(s) => s.as_bytes().len(),
+            LuaValue::Integer(_) | LuaValue::Number(_) => 8,
+            _ => 1
+        };
+        *size += value_size;
+        if *size > limits.max_size {
+            return Err(LuaError::RuntimeError(format!("data is larger than {} bytes", limits.max_size)))
+        }
+        match lua_value {
+            LuaValue::Nil => Ok(Self::Nil),
+            LuaValue::Boolean(b) => Ok(Self::Boolean(b)),
+            LuaValue::Integer(i) => Ok(Self::Integer(i)),
+            LuaValue::Number(n) => Ok(Self::Number(n)),
+            LuaValue::String(s) => Ok(Self::String(s.to_str()?.into())),
+            LuaValue::Table(t) => {
+                if depth >= limits.max_depth {
+                    return Err(LuaError::RuntimeError(format!("data is nested deeper than {} tables", limits.max_depth)))
+                }
+                let mut pairs = Vec::new();
+                for pair in t.pairs::<LuaValue, LuaValue>() {
+                    let (key, value) = pair?;
+                    let key = Self::from_lua_limited(key, limits, depth + 1, size)?;
+                    let value = Self::from_lua_limited(value, limits, depth + 1, size)?;
+                    pairs.push((key, value));
+                }
+                // keys are unique, so if all of them are in 1..=len the table is a sequence
+                let len = pairs.len() as i64;
+                if pairs.iter().all(|(key, _)| matches!(key, Self::Integer(i) if (1..=len).contains(i))) {
+                    pairs.sort_by_key(|(key, _)| match key {
+                        Self::Integer(i) => *i,
+                        _ => unreachable!()
+                    });
+                    Ok(Self::Sequence(pairs.into_iter().map(|(_, value)| value).collect()))
+                } else {
+                    let table = pairs.into_iter()
+                        .map(|(key, value)| Ok((key.try_into().map_err(LuaError::external)?, value)))
+                        .collect::<LuaResult<HashMap<DataValueHashEq, DataValue>>>()?;
+                    Ok(Self::Table(table))
+                }
+            },
+            _ => Err(LuaError::FromLuaConversionError { from: type_name, to: "DataValue", message: Some("type not supported".into()) })
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'lua> FromLua<'lua> for DataValue {
+    fn from_lua(lua_value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
+        let limits = lua.app_data_ref::<DataValueLimits>().map(|limits| *limits).unwrap_or_default();
+        Self::from_lua_limited(lua_value, &limits, 0, &mut 0)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'lua> ToLua<'lua> for DataValue {
+    fn to_lua(self, lua: &'lua Lua) -> LuaResult<LuaValue<'lua>> {
+        match self {
+            Self::Nil => Ok(LuaValue::Nil),
+            Self::Boolean(b) => Ok(LuaValue::Boolean(b)),
+            Self::Integer(i) => Ok(LuaValue::Integer(i)),
+            Self::Number(n) => Ok(LuaValue::Number(n)),
+            Self::String(s) => s.to_lua(lua),
+            Self::Sequence(seq) => seq.to_lua(lua),
+            Self::Table(t) => t.to_lua(lua)
+        }
+    }
+}
+
+impl TryFrom<DataValue> for DataValueHashEq {
+    type Error = DataValueConversionError;
+
+    fn try_from(value: DataValue) -> Result<Self, Self::Error> {
+        match value {
+            DataValue::Nil => Ok(Self::Nil),
+            DataValue::Boolean(b) => Ok(Self::Boolean(b)),
+            DataValue::Integer(i) => Ok(Self::Integer(i)),
+            DataValue::Number(n) => hash_eq_from_number(n),
+            DataValue::String(s) => Ok(Self::String(s)),
+            DataValue::Sequence(sq) => Ok(Self::Sequence(sq.into_iter().map(TryInto::try_into).collect::<Result<Vec<Self>, Self::Error>>()?)),
+            DataValue::Table(t) => Err(Self::Error::Table(t))
+        }
+    }
+}
+
+// Goes through `DataValue`, an untagged `2.0` would otherwise become a float key that never
+// equals the integer key `2`
+impl<'de> Deserialize<'de> for DataValueHashEq {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        DataValue::deserialize(deserializer)?.try_into().map_err(D::Error::custom)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'lua> FromLua<'lua> for DataValueHashEq {
+    fn from_lua(lua_value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
+        DataValue::from_lua(lua_value, lua)?.try_into().map_err(LuaError::external)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'lua> ToLua<'lua> for DataValueHashEq {
+    fn to_lua(self, lua: &'lua Lua) -> LuaResult<LuaValue<'lua>> {
+        DataValue::from(self).to_lua(lua)
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum DataValueConversionError {
+    #[error("DataValueHashEq can't contain NaN")]
+    Nan,
+    #[error("DataValueHashEq can't contain HashMap")]
+    Table(HashMap<DataValueHashEq, DataValue>),
+}
+
+#[derive(Debug, Error)]
+pub enum DataValueDecodeError {
+    #[error("invalid encoded data: {0}")]
+    Binary(#[from] rmp_serde::decode::Error),
+    #[error("invalid table key: {0}")]
+    Key(#[from] DataValueConversionError)
+}
+
+// Externally tagged mirrors of the types above. The untagged JSON form can't tell an integral
+// float from an integer, binary formats use these so values come back exactly as they were.
+#[derive(Serialize, Deserialize)]
+enum TaggedValue {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(String),
+    Sequence(Vec<TaggedValue>),
+    Table(Vec<(TaggedKey, TaggedValue)>)
+}
+
+#[derive(Serialize, Deserialize)]
+enum TaggedKey {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(String),
+    Sequence(Vec<TaggedKey>)
+}
+
+impl From<&DataValue> for TaggedValue {
+    fn from(value: &DataValue) -> Self {
+        match value {
+            DataValue::Nil => Self::Nil,
+            DataValue::Boolean(b) => Self::Boolean(*b),
+            DataValue::Integer(i) => Self::Integer(*i),
+            DataValue::Number(n) => Self::Number(*n),
+            DataValue::String(s) => Self::String(s.clone()),
+            DataValue::Sequence(sq) => Self::Sequence(sq.iter().map(Into::into).collect()),
+            DataValue::Table(t) => Self::Table(t.iter().map(|(k, v)| (k.into(), v.into())).collect())
+        }
+    }
+}
+
+impl TryFrom<TaggedValue> for DataValue {
+    type Error = DataValueConversionError;
+
+    fn try_from(value: TaggedValue) -> Result<Self, Self::Error> {
+        match value {
+            TaggedValue::Nil => Ok(Self::Nil),
+            TaggedValue::Boolean(b) => Ok(Self::Boolean(b)),
+            TaggedValue::Integer(i) => Ok(Self::Integer(i)),
+            TaggedValue::Number(n) => Ok(Self::Number(n)),
+            TaggedValue::String(s) => Ok(Self::String(s)),
+            TaggedValue::Sequence(sq) => Ok(Self::Sequence(sq.into_iter().map(TryInto::try_into).collect::<Result<_, _>>()?)),
+            TaggedValue::Table(t) => Ok(Self::Table(t.into_iter().map(|(k, v)| Ok((k.try_into()?, v.try_into()?))).collect::<Result<_, Self::Error>>()?))
+        }
+    }
+}
+
+impl From<&DataValueHashEq> for TaggedKey {
+    fn from(key: &DataValueHashEq) -> Self {
+        match key {
+            DataValueHashEq::Nil => Self::Nil,
+            DataValueHashEq::Boolean(b) => Self::Boolean(*b),
+            DataValueHashEq::Integer(i) => Self::Integer(*i),
+            DataValueHashEq::Number(n) => Self::Number(n.into_inner()),
+            DataValueHashEq::String(s) => Self::String(s.clone()),
+            DataValueHashEq::Sequence(sq) => Self::Sequence(sq.iter().map(Into::into).collect())
+        }
+    }
+}
+
+// encoded data can come from anywhere, so float keys are checked like ones from Lua
+impl TryFrom<TaggedKey> for DataValueHashEq {
+    type Error = DataValueConversionError;
+
+    fn try_from(key: TaggedKey) -> Result<Self, Self::Error> {
+        match key {
+            TaggedKey::Nil => Ok(Self::Nil),
+            TaggedKey::Boolean(b) => Ok(Self::Boolean(b)),
+            TaggedKey::Integer(i) => Ok(Self::Integer(i)),
+            TaggedKey::Number(n) => hash_eq_from_number(n),
+            TaggedKey::String(s) => Ok(Self::String(s)),
+            TaggedKey::Sequence(sq) => Ok(Self::Sequence(sq.into_iter().map(TryInto::try_into).collect::<Result<_, _>>()?))
+        }
+    }
+}
+
+impl DataValue {
+    /// Compact MessagePack encoding for save files, network messages and dumps
+    pub fn to_binary(&self) -> Vec<u8> {
+        rmp_serde::to_vec(&TaggedValue::from(self)).unwrap()
+    }
+
+    pub fn from_binary(bytes: &[u8]) -> Result<Self, DataValueDecodeError> {
+        Ok(rmp_serde::from_slice::<TaggedValue>(bytes)?.try_into()?)
+    }
+}
+
+impl From<serde_json::Value> for DataValue {
+    fn from(value: serde_json::Value) -> Self {
+        use serde_json::Value;
+        match value {
+            Value::Null => Self::Nil,
+            Value::Bool(b) => Self::Boolean(b),
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => Self::Integer(i),
+                None => Self::Number(n.as_f64().unwrap_or(f64::NAN))
+            },
+            Value::String(s) => Self::String(s),
+            Value::Array(a) => Self::Sequence(a.into_iter().map(Into::into).collect()),
+            Value::Object(o) => Self::Table(o.into_iter().map(|(k, v)| (DataValueHashEq::String(k), v.into())).collect())
+        }
+    }
+}
+
+impl TryFrom<DataValue> for serde_json::Value {
+    type Error = DataValueInteropError;
+
+    fn try_from(value: DataValue) -> Result<Self, Self::Error> {
+        match value {
+            DataValue::Nil => Ok(Self::Null),
+            DataValue::Boolean(b) => Ok(Self::Bool(b)),
+            DataValue::Integer(i) => Ok(Self::from(i)),
+            DataValue::Number(n) => serde_json::Number::from_f64(n).map(Self::Number).ok_or(Self::Error::NonFiniteNumber(n)),
+            DataValue::String(s) => Ok(Self::String(s)),
+            DataValue::Sequence(sq) => Ok(Self::Array(sq.into_iter().map(TryInto::try_into).collect::<Result<_, _>>()?)),
+            DataValue::Table(t) => Ok(Self::Object(t.into_iter().map(|(k, v)| Ok((string_key(k)?, v.try_into()?))).collect::<Result<_, Self::Error>>()?))
+        }
+    }
+}
+
+impl From<toml::Value> for DataValue {
+    fn from(value: toml::Value) -> Self {
+        use toml::Value;
+        match value {
+            Value::Boolean(b) => Self::Boolean(b),
+            Value::Integer(i) => Self::Integer(i),
+            Value::Float(n) => Self::Number(n),
+            Value::String(s) => Self::String(s),
+            Value::Datetime(d) => Self::String(d.to_string()),
+            Value::Array(a) => Self::Sequence(a.into_iter().map(Into::into).collect()),
+            Value::Table(t) => Self::Table(t.into_iter().map(|(k, v)| (DataValueHashEq::String(k), v.into())).collect())
+        }
+    }
+}
+
+impl TryFrom<DataValue> for toml::Value {
+    type Error = DataValueInteropError;
+
+    fn try_from(value: DataValue) -> Result<Self, Self::Error> {
+        match value {
+            DataValue::Nil => Err(Self::Error::Nil),
+            DataValue::Boolean(b) => Ok(Self::Boolean(b)),
+            DataValue::Integer(i) => Ok(Self::Integer(i)),
+            DataValue::Number(n) => Ok(Self::Float(n)),
+            DataValue::String(s) => Ok(Self::String(s)),
+            DataValue::Sequence(sq) => Ok(Self::Array(sq.into_iter().map(TryInto::try_into).collect::<Result<_, _>>()?)),
+            DataValue::Table(t) => Ok(Self::Table(t.into_iter().map(|(k, v)| Ok((string_key(k)?, v.try_into()?))).collect::<Result<_, Self::Error>>()?))
+        }
+    }
+}
+
+// JSON objects and TOML tables only have string keys
+fn string_key(key: DataValueHashEq) -> Result<String, DataValueInteropError> {
+    match key {
+        DataValueHashEq::String(s) => Ok(s),
+        key => Err(DataValueInteropError::NonStringKey(key))
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum DataValueInteropError {
+    #[error("JSON can't contain {0}")]
+    NonFiniteNumber(f64),
+    #[error("TOML can't contain nil")]
+    Nil,
+    #[error("table key {0:?} isn't a string")]
+    NonStringKey(DataValueHashEq)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(pairs: Vec<(DataValueHashEq, DataValue)>) -> DataValue {
+        DataValue::Table(pairs.into_iter().collect())
+    }
+
+    fn round_trip(value: &DataValue) -> DataValue {
+        DataValue::from_binary(&value.to_binary()).unwrap()
+    }
+
+    #[test]
+    fn binary_round_trips_nested_tables() {
+        let value = table(vec![
+            (DataValueHashEq::String("route".into()), DataValue::Sequence(vec![
+                table(vec![(DataValueHashEq::String("x".into()), DataValue::Number(1.5)), (DataValueHashEq::String("y".into()), DataValue::Integer(-3))]),
+                DataValue::Sequence(vec![DataValue::Boolean(true), DataValue::Nil, DataValue::String("depot".into())])
+            ])),
+            (DataValueHashEq::String("empty".into()), table(vec![]))
+        ]);
+        assert_eq!(round_trip(&value), value);
+    }
+
+    #[test]
+    fn binary_keeps_integral_floats_apart_from_integers() {
+        let value = DataValue::Sequence(vec![DataValue::Integer(2), DataValue::Number(2.0)]);
+        let decoded = round_trip(&value);
+        assert_eq!(decoded, value);
+        assert!(matches!(decoded, DataValue::Sequence(items) if matches!(items[..], [DataValue::Integer(2), DataValue::Number(n)] if n == 2.0)));
+    }
+
+    #[test]
+    fn binary_round_trips_non_string_keys() {
+        let value = table(vec![
+            (DataValueHashEq::Integer(7), DataValue::String("seven".into())),
+            (DataValueHashEq::Boolean(false), DataValue::Integer(0)),
+            (DataValueHashEq::Number(OrderedFloat(0.5)), DataValue::Integer(1)),
+            (DataValueHashEq::Sequence(vec![DataValueHashEq::Integer(1), DataValueHashEq::String("a".into())]), DataValue::Nil)
+        ]);
+        assert_eq!(round_trip(&value), value);
+    }
+
+    #[test]
+    fn binary_rejects_garbage() {
+        assert!(DataValue::from_binary(&[0xc1, 0x00, 0xff]).is_err());
+    }
+
+    #[test]
+    fn json_round_trips_values() {
+        let value = table(vec![
+            (DataValueHashEq::String("name".into()), DataValue::String("scout".into())),
+            (DataValueHashEq::String("speed".into()), DataValue::Number(2.0)),
+            (DataValueHashEq::String("route".into()), DataValue::Sequence(vec![DataValue::Integer(-3), DataValue::Nil, DataValue::Boolean(true)])),
+            (DataValueHashEq::String("empty".into()), table(vec![]))
+        ]);
+        let json = serde_json::Value::try_from(value.clone()).unwrap();
+        assert_eq!(json, serde_json::json!({"name": "scout", "speed": 2.0, "route": [-3, null, true], "empty": {}}));
+        assert_eq!(DataValue::from(json), value);
+    }
+
+    #[test]
+    fn json_numbers_outside_integers_become_floats() {
+        let value = DataValue::from(serde_json::json!([u64::MAX, 1.5]));
+        assert_eq!(value, DataValue::Sequence(vec![DataValue::Number(u64::MAX as f64), DataValue::Number(1.5)]));
+    }
+
+    #[test]
+    fn json_rejects_what_it_cant_hold() {
+        let error = serde_json::Value::try_from(DataValue::Sequence(vec![DataValue::Number(f64::INFINITY)])).unwrap_err();
+        assert!(matches!(error, DataValueInteropError::NonFiniteNumber(n) if n == f64::INFINITY));
+        let error = serde_json::Value::try_from(table(vec![(DataValueHashEq::Integer(1), DataValue::Boolean(true))])).unwrap_err();
+        assert!(matches!(error, DataValueInteropError::NonStringKey(DataValueHashEq::Integer(1))));
+    }
+
+    #[test]
+    fn toml_round_trips_values() {
+        let value = table(vec![
+            (DataValueHashEq::String("name".into()), DataValue::String("scout".into())),
+            (DataValueHashEq::String("speed".into()), DataValue::Number(2.0)),
+            (DataValueHashEq::String("count".into()), DataValue::Integer(3)),
+            (DataValueHashEq::String("tags".into()), DataValue::Sequence(vec![DataValue::String("a".into()), DataValue::String("b".into())])),
+            (DataValueHashEq::String("nested".into()), table(vec![(DataValueHashEq::String("on".into()), DataValue::Boolean(false))]))
+        ]);
+        // `toml::Value::try_from` is toml's own, for any `Serialize`
+        let converted: toml::Value = value.clone().try_into().unwrap();
+        let text = toml::to_string(&converted).unwrap();
+        assert_eq!(DataValue::from(text.parse::<toml::Value>().unwrap()), value);
+    }
+
+    #[test]
+    fn toml_dates_become_strings() {
+        let parsed: toml::Value = "built = 2022-09-01T12:00:00Z".parse().unwrap();
+        assert_eq!(DataValue::from(parsed), table(vec![(DataValueHashEq::String("built".into()), DataValue::String("2022-09-01T12:00:00Z".into()))]));
+    }
+
+    #[test]
+    fn toml_rejects_what_it_cant_hold() {
+        let error = TryInto::<toml::Value>::try_into(table(vec![(DataValueHashEq::String("gone".into()), DataValue::Nil)])).unwrap_err();
+        assert!(matches!(error, DataValueInteropError::Nil));
+        let error = TryInto::<toml::Value>::try_into(table(vec![(DataValueHashEq::Boolean(true), DataValue::Integer(1))])).unwrap_err();
+        assert!(matches!(error, DataValueInteropError::NonStringKey(DataValueHashEq::Boolean(true))));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn from_lua_with(limits: DataValueLimits, source: &str) -> LuaResult<DataValue> {
+        let lua = Lua::new();
+        lua.set_app_data(limits);
+        let value: LuaValue = lua.load(source).eval()?;
+        DataValue::from_lua(value, &lua)
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn limits_allow_data_within_them() {
+        let limits = DataValueLimits { max_depth: 3, max_size: 64 };
+        let value = from_lua_with(limits, "{ { { 1 } } }").unwrap();
+        assert_eq!(value, DataValue::Sequence(vec![DataValue::Sequence(vec![DataValue::Sequence(vec![DataValue::Integer(1)])])]));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn limits_reject_deep_nesting() {
+        let limits = DataValueLimits { max_depth: 3, max_size: 1024 };
+        let error = from_lua_with(limits, "{ { { { 1 } } } }").unwrap_err();
+        assert!(error.to_string().contains("nested deeper than 3 tables"), "{}", error);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn limits_reject_large_data() {
+        let limits = DataValueLimits { max_depth: 32, max_size: 100 };
+        let error = from_lua_with(limits, "return string.rep('x', 101)").unwrap_err();
+        assert!(error.to_string().contains("larger than 100 bytes"), "{}", error);
+        // the size adds up over the whole table, not per value
+        let error = from_lua_with(limits, "return { string.rep('x', 60), string.rep('x', 60) }").unwrap_err();
+        assert!(error.to_string().contains("larger than 100 bytes"), "{}", error);
+    }
+
+    #[test]
+    fn decoded_float_keys_are_normalized() {
+        let encoded = rmp_serde::to_vec(&TaggedValue::Table(vec![(TaggedKey::Number(2.0), TaggedValue::Boolean(true))])).unwrap();
+        assert_eq!(DataValue::from_binary(&encoded).unwrap(), table(vec![(DataValueHashEq::Integer(2), DataValue::Boolean(true))]));
+        let encoded = rmp_serde::to_vec(&TaggedValue::Table(vec![(TaggedKey::Number(f64::NAN), TaggedValue::Nil)])).unwrap();
+        assert!(matches!(DataValue::from_binary(&encoded), Err(DataValueDecodeError::Key(DataValueConversionError::Nan))));
+    }
+
+    #[test]
+    fn deserialized_float_keys_are_normalized() {
+        let key: DataValueHashEq = serde_json::from_str("2.0").unwrap();
+        assert_eq!(key, DataValueHashEq::Integer(2));
+        let key: DataValueHashEq = serde_json::from_str("[1.0, 2.5]").unwrap();
+        assert_eq!(key, DataValueHashEq::Sequence(vec![DataValueHashEq::Integer(1), DataValueHashEq::Number(OrderedFloat(2.5))]));
+        assert!(serde_json::from_str::<DataValueHashEq>("{\"a\": 1}").is_err());
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn lua_float_keys_match_integer_keys() {
+        let lua = Lua::new();
+        let value: LuaValue = lua.load("{ [2.5] = 'half', [-0.25] = 'quarter', x = 1 }").eval().unwrap();
+        let value = DataValue::from_lua(value, &lua).unwrap();
+        assert_eq!(value, table(vec![
+            (DataValueHashEq::Number(OrderedFloat(2.5)), DataValue::String("half".into())),
+            (DataValueHashEq::Number(OrderedFloat(-0.25)), DataValue::String("quarter".into())),
+            (DataValueHashEq::String("x".into()), DataValue::Integer(1))
+        ]));
+        assert_eq!(DataValueHashEq::try_from(DataValue::Number(3.0)).unwrap(), DataValueHashEq::Integer(3));
+        assert!(matches!(DataValueHashEq::try_from(DataValue::Number(f64::NAN)), Err(DataValueConversionError::Nan)));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn lua_tables_with_consecutive_keys_are_sequences() {
+        let lua = Lua::new();
+        let value: LuaValue = lua.load("{ 'a', 'b', [3] = 'c' }").eval().unwrap();
+        assert_eq!(DataValue::from_lua(value, &lua).unwrap(), DataValue::Sequence(vec![DataValue::String("a".into()), DataValue::String("b".into()), DataValue::String("c".into())]));
+        let value: LuaValue = lua.load("{ [1] = true, [3] = false }").eval().unwrap();
+        assert_eq!(DataValue::from_lua(value, &lua).unwrap(), table(vec![(DataValueHashEq::Integer(1), DataValue::Boolean(true)), (DataValueHashEq::Integer(3), DataValue::Boolean(false))]));
+        let value: LuaValue = lua.load("{}").eval().unwrap();
+        assert_eq!(DataValue::from_lua(value, &lua).unwrap(), DataValue::Sequence(Vec::new()));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn lua_round_trips_values() {
+        let lua = Lua::new();
+        let value = table(vec![
+            (DataValueHashEq::String("name".into()), DataValue::String("scout".into())),
+            (DataValueHashEq::Integer(7), DataValue::Number(2.0)),
+            (DataValueHashEq::Boolean(false), DataValue::Sequence(vec![DataValue::Integer(1), DataValue::Number(0.5)]))
+        ]);
+        let lua_value = value.clone().to_lua(&lua).unwrap();
+        assert_eq!(DataValue::from_lua(lua_value, &lua).unwrap(), value);
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn lua_functions_are_rejected() {
+        let lua = Lua::new();
+        let value: LuaValue = lua.load("{ f = print }").eval().unwrap();
+        assert!(DataValue::from_lua(value, &lua).is_err());
+    }
+}
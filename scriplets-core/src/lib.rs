@@ -0,0 +1,12 @@
+//! Parts of the simulation that don't depend on bevy: script values, their validation, the
+//! migration of data written by older versions, how units move and when their programs run. The
+//! game wraps them in components and finds obstacles for the movement with its physics.
+
+pub mod data_value;
+pub mod schema;
+pub mod migration;
+pub mod movement;
+pub mod program;
+
+/// Simulation steps per second, per tick amounts like speeds are the per second ones divided by it
+pub const TICK_RATE: u64 = 60;
@@ -0,0 +1,74 @@
+//! Upgrades data written by older versions of the game, one format version at a time.
+
+use thiserror::Error;
+
+/// Upgrades data of format version `from` to `from + 1`
+pub struct Migration<T: 'static> {
+    pub from: u32,
+    pub description: &'static str,
+    pub migrate: fn(&mut T) -> Result<(), String>
+}
+
+#[derive(Debug, Clone, Error)]
+pub enum MigrationError {
+    #[error("format version {found} is newer than {current}, it was written by a newer version of the game")]
+    TooNew { found: u32, current: u32 },
+    #[error("there's no migration from format version {0}")]
+    Missing(u32),
+    #[error("migrating from format version {from} failed: {message}")]
+    Failed { from: u32, message: String }
+}
+
+/// Runs the migrations from `version` up to `current` in order. Data that's already current is left
+/// alone.
+pub fn migrate<T>(value: &mut T, version: u32, current: u32, migrations: &[Migration<T>]) -> Result<(), MigrationError> {
+    if version > current {
+        return Err(MigrationError::TooNew { found: version, current })
+    }
+    for from in version..current {
+        let migration = migrations.iter().find(|migration| migration.from == from).ok_or(MigrationError::Missing(from))?;
+        (migration.migrate)(value).map_err(|message| MigrationError::Failed { from, message })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // each migration appends its version, so the order they ran in is visible
+    fn migrations() -> Vec<Migration<Vec<u32>>> {
+        vec![
+            Migration { from: 1, description: "second", migrate: |value| { value.push(1); Ok(()) } },
+            Migration { from: 0, description: "first", migrate: |value| { value.push(0); Ok(()) } },
+            Migration { from: 2, description: "broken", migrate: |_| Err("no".to_string()) }
+        ]
+    }
+
+    #[test]
+    fn runs_migrations_in_order() {
+        let mut value = Vec::new();
+        migrate(&mut value, 0, 2, &migrations()).unwrap();
+        assert_eq!(value, vec![0, 1]);
+        let mut value = Vec::new();
+        migrate(&mut value, 1, 2, &migrations()).unwrap();
+        assert_eq!(value, vec![1]);
+    }
+
+    #[test]
+    fn leaves_current_data_alone() {
+        let mut value = Vec::new();
+        migrate(&mut value, 2, 2, &migrations()).unwrap();
+        assert!(value.is_empty());
+    }
+
+    #[test]
+    fn reports_errors() {
+        let mut value = Vec::new();
+        assert!(matches!(migrate(&mut value, 3, 2, &migrations()), Err(MigrationError::TooNew { found: 3, current: 2 })));
+        assert!(matches!(migrate(&mut value, 0, 5, &migrations()), Err(MigrationError::Failed { from: 2, .. })));
+        // migrations before the broken one have already run
+        assert_eq!(value, vec![0, 1]);
+        assert!(matches!(migrate(&mut value, 3, 4, &migrations()), Err(MigrationError::Missing(3))));
+    }
+}
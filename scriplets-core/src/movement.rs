@@ -0,0 +1,534 @@
+//! How units move: their movement prototypes, what scripts ask for and one tick of moving. The
+//! game finds obstacles with rapier through `Obstacles`, tests with whatever stands in for it.
+
+use std::f32::consts::PI;
+use glam::Vec2;
+use serde::Deserialize;
+use crate::TICK_RATE;
+
+// casts per tick of a unit sliding along walls
+const SLIDE_ITERATIONS: usize = 3;
+// gap kept between a sliding unit and what it slides along, tiles
+const SLIDE_SKIN: f32 = 0.01;
+
+// TODO: reimplement acceleration movement type to support steering around a point
+//  Or make a new movement type which works as stated above
+/// How a unit moves, from its movement prototype. Never changes after spawning, what scripts ask for
+/// is in `MovementIntent` and what came of it in `MovementState`.
+#[derive(Deserialize, Clone)]
+pub struct MovementStats {
+    pub name: String,
+    pub movement_type: MovementType,
+    // movement characteristics
+    #[serde(default)]
+    pub speed: f32, // tiles / second
+    #[serde(default)]
+    pub max_speed: f32,
+    #[serde(default)]
+    pub max_speed_backwards: Option<f32>,
+    #[serde(default)]
+    pub acceleration: f32, // tiles / second^2
+    #[serde(default)]
+    pub braking_acceleration: Option<f32>,
+    #[serde(default)]
+    pub passive_deceleration: f32,
+    #[serde(default)]
+    pub rotation_speed: f32, // degrees / second
+    #[serde(default)]
+    pub rotation_offset: f32,
+    #[serde(default)]
+    pub overclock: Option<Overclock>,
+    #[serde(default = "default_mass")]
+    pub mass: f32,
+    #[serde(default)]
+    pub collision_response: CollisionResponse,
+    // physics driven units only
+    #[serde(default)]
+    pub friction: f32,
+    #[serde(default)]
+    pub max_force: f32,
+    #[serde(default)]
+    pub max_torque: f32
+}
+
+fn default_mass() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum MovementType {
+    Omnidirectional,
+    AcceleratedSteering,
+    Train,
+    PhysicsDriven
+}
+
+impl MovementType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Omnidirectional => "omnidirectional",
+            Self::AcceleratedSteering => "accelerated-steering",
+            Self::Train => "train",
+            Self::PhysicsDriven => "physics-driven"
+        }
+    }
+}
+
+/// What kinematic units do when their move runs into something
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum CollisionResponse {
+    #[default]
+    Stop,
+    // glide along the surface with whatever motion is left
+    Slide
+}
+
+/// Faster movement for units that can get rid of the heat, toggled by scripts
+#[derive(Deserialize, Clone)]
+pub struct Overclock {
+    pub speed_multiplier: f32,
+    pub heat: f32 // heat / second
+}
+
+/// What the unit's script or manual control asks the movement to do
+#[derive(Clone, Default)]
+pub struct MovementIntent {
+    pub input_move: Vec2,
+    pub input_rotation: f32,
+    // degrees clockwise, omnidirectional units turn towards it until they face it
+    pub target_heading: Option<f32>,
+    // accelerated steering units speed up or brake to hold it, tiles / second
+    pub target_speed: Option<f32>,
+    pub input_force: Vec2,
+    pub input_torque: f32,
+    pub hand_brake: bool,
+    pub overclocked: bool
+}
+
+/// The part of the movement that changes every tick
+#[derive(Clone, Default)]
+pub struct MovementState {
+    // tiles / second, starts at the prototype's speed
+    pub speed: f32
+}
+
+/// Where a unit is and where it faces, counterclockwise in radians
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub struct Pose {
+    pub position: Vec2,
+    pub rotation: f32
+}
+
+impl Pose {
+    pub fn right(&self) -> Vec2 {
+        Vec2::from_angle(self.rotation)
+    }
+
+    pub fn up(&self) -> Vec2 {
+        self.right().perp()
+    }
+}
+
+/// Where a moving shape touched something. `toi` is the part of the motion done before, `normal`
+/// points away from the obstacle.
+#[derive(Clone, Copy, Debug)]
+pub struct Hit<O> {
+    pub obstacle: O,
+    pub toi: f32,
+    pub normal: Vec2
+}
+
+/// What a unit can run into. The shape is always the moving unit's own.
+pub trait Obstacles {
+    type Obstacle: Copy;
+
+    /// The first obstacle the shape touches moving by `delta` from `pose`
+    fn cast(&self, pose: Pose, delta: Vec2) -> Option<Hit<Self::Obstacle>>;
+
+    /// An obstacle the shape overlaps at `pose`
+    fn overlap(&self, pose: Pose) -> Option<Self::Obstacle>;
+}
+
+/// The shape cast of a tick's move, for debug drawing and impacts
+#[derive(Clone, Copy, Debug)]
+pub struct Cast<O> {
+    pub origin: Vec2,
+    pub delta: Vec2,
+    pub hit: Option<O>,
+    // tiles / second the unit was going when the cast was made
+    pub speed: f32
+}
+
+/// What came of one tick of kinematic movement
+#[derive(Clone, Copy, Debug)]
+pub struct Step<O> {
+    pub pose: Pose,
+    // what the move or turn ran into
+    pub blocked_by: Option<O>,
+    pub cast: Option<Cast<O>>
+}
+
+impl MovementStats {
+    /// The overclock while it's turned on
+    pub fn overclock(&self, intent: &MovementIntent) -> Option<&Overclock> {
+        self.overclock.as_ref().filter(|_| intent.overclocked)
+    }
+
+    pub fn initial_state(&self) -> MovementState {
+        MovementState { speed: self.speed }
+    }
+
+    /// Moves a unit that isn't physics driven for one tick. The intents it carried out are reset,
+    /// the ones that last until they're changed are kept.
+    pub fn step<W: Obstacles>(&self, intent: &mut MovementIntent, state: &mut MovementState, pose: Pose, speed_multiplier: f32, world: &W) -> Step<W::Obstacle> {
+        match self.movement_type {
+            MovementType::Omnidirectional => self.step_omnidirectional(intent, pose, speed_multiplier, world),
+            MovementType::AcceleratedSteering => self.step_accelerated_steering(intent, state, pose, speed_multiplier, world),
+            MovementType::Train | MovementType::PhysicsDriven => Step { pose, blocked_by: None, cast: None }
+        }
+    }
+
+    fn step_omnidirectional<W: Obstacles>(&self, intent: &mut MovementIntent, mut pose: Pose, speed_multiplier: f32, world: &W) -> Step<W::Obstacle> {
+        let mut blocked_by = None;
+        let mut cast = None;
+        if intent.hand_brake {
+            return Step { pose, blocked_by, cast }
+        }
+        // a heading target takes over rotating until the unit faces it
+        let input_rotation = match intent.target_heading {
+            Some(target) if self.rotation_speed > 0.0 => {
+                let current = -pose.rotation.to_degrees();
+                let difference = (target - current + 180.0).rem_euclid(360.0) - 180.0;
+                (difference / (self.rotation_speed / TICK_RATE as f32)).clamp(-1.0, 1.0)
+            }
+            _ => intent.input_rotation
+        };
+        if input_rotation != 0.0 {
+            let turned = Pose {
+                rotation: pose.rotation - (self.rotation_speed * input_rotation.clamp(-1.0, 1.0) * PI) / (180.0 * TICK_RATE as f32),
+                ..pose
+            };
+            // turning in place can swing corners into walls, so the turned shape is checked first
+            match world.overlap(turned) {
+                Some(obstacle) => blocked_by = Some(obstacle),
+                None => pose = turned
+            }
+        }
+        // less than a full turn means this tick's turn ended on the heading
+        if intent.target_heading.is_some() && input_rotation.abs() < 1.0 && blocked_by.is_none() {
+            intent.target_heading = None;
+        }
+        if intent.input_move != Vec2::ZERO {
+            let unrotated_move = intent.input_move.clamp_length_max(1.0) * (self.speed * speed_multiplier / TICK_RATE as f32);
+            let delta = unrotated_move.rotate(pose.right());
+            let hit = world.cast(pose, delta);
+            cast = Some(Cast { origin: pose.position, delta, hit: hit.map(|hit| hit.obstacle), speed: delta.length() * TICK_RATE as f32 });
+            match hit {
+                Some(hit) => {
+                    blocked_by = Some(hit.obstacle);
+                    if let CollisionResponse::Slide = self.collision_response {
+                        pose.position += slide(world, pose, delta);
+                    }
+                }
+                None => pose.position += delta
+            }
+            intent.input_move = Vec2::ZERO;
+        }
+        Step { pose, blocked_by, cast }
+    }
+
+    fn step_accelerated_steering<W: Obstacles>(&self, intent: &mut MovementIntent, state: &mut MovementState, mut pose: Pose, speed_multiplier: f32, world: &W) -> Step<W::Obstacle> {
+        let mut blocked_by = None;
+        let mut cast = None;
+        let input_move_vec = intent.input_move.clamp(Vec2::NEG_X + Vec2::NEG_Y, Vec2::X + Vec2::Y);
+        let max_speed = self.max_speed * speed_multiplier;
+        let max_speed_backwards = -self.max_speed_backwards.unwrap_or(self.max_speed) * speed_multiplier;
+        let acceleration = self.acceleration;
+        let braking_acceleration = -self.braking_acceleration.unwrap_or(acceleration);
+        let passive_deceleration = self.passive_deceleration;
+        let is_moving_forward = state.speed > 0.0;
+        let is_moving_backwards = state.speed < 0.0;
+        let new_speed = {
+            let acceleration = {
+                if intent.hand_brake {
+                    if state.speed > 0.0 {
+                        braking_acceleration
+                    } else {
+                        -braking_acceleration
+                    }
+                } else if (state.speed > 0.0 && input_move_vec.x > 0.0) || (state.speed < 0.0 && input_move_vec.x < 0.0) {
+                    acceleration
+                } else if (state.speed > 0.0 && input_move_vec.x < 0.0) || (state.speed < 0.0 && input_move_vec.x > 0.0) {
+                    braking_acceleration
+                } else if state.speed != 0.0 {
+                    -passive_deceleration
+                } else {
+                    acceleration
+                }
+            };
+            let new_speed_uncapped = (state.speed + acceleration * input_move_vec.x / TICK_RATE as f32).clamp(max_speed_backwards, max_speed);
+            if is_moving_forward {
+                new_speed_uncapped.clamp(0.0, f32::MAX)
+            } else if is_moving_backwards {
+                new_speed_uncapped.clamp(f32::MIN, 0.0)
+            } else {
+                new_speed_uncapped
+            }
+        };
+        // cruise control replaces the throttle, the hand brake still wins
+        let new_speed = match intent.target_speed.filter(|_| !intent.hand_brake) {
+            Some(target) => {
+                let difference = target.clamp(max_speed_backwards, max_speed) - state.speed;
+                let rate = if state.speed != 0.0 && difference.signum() != state.speed.signum() {
+                    -braking_acceleration
+                } else {
+                    acceleration
+                };
+                state.speed + difference.clamp(-rate / TICK_RATE as f32, rate / TICK_RATE as f32)
+            }
+            None => new_speed
+        };
+        state.speed = new_speed;
+        if state.speed != 0.0 {
+            let linear_delta = state.speed / TICK_RATE as f32;
+            let starting_translation = pose.position + pose.up() * self.rotation_offset;
+            let mut rot_angle = (self.rotation_speed * PI / (TICK_RATE as f32 * 180.0)) * input_move_vec.y;
+            if state.speed < 0.0 {
+                rot_angle = -rot_angle;
+            }
+            let result_rotation = pose.rotation - rot_angle;
+            let turning_scale = linear_delta / rot_angle;
+            let rot_vec_normalized = Vec2::from_angle(rot_angle);
+            let turning_radius = pose.right() + pose.up() * self.rotation_offset * turning_scale;
+            let turning_origin = starting_translation - turning_radius;
+            let result_translation = turning_radius.rotate(rot_vec_normalized) + turning_origin - pose.up() * self.rotation_offset;
+
+            let delta = result_translation - starting_translation;
+            let result = Pose { position: result_translation, rotation: result_rotation };
+            let hit = world.cast(result, delta);
+            cast = Some(Cast { origin: result.position, delta, hit: hit.map(|hit| hit.obstacle), speed: state.speed.abs() });
+            match hit {
+                Some(hit) => {
+                    blocked_by = Some(hit.obstacle);
+                    // sliding units keep their heading, turning is what pushed them into the wall
+                    if let CollisionResponse::Slide = self.collision_response {
+                        let moved = slide(world, pose, delta);
+                        pose.position += moved;
+                        state.speed = moved.length() * TICK_RATE as f32 * state.speed.signum();
+                    }
+                }
+                None => pose = result
+            }
+            intent.input_move = Vec2::ZERO
+        }
+        Step { pose, blocked_by, cast }
+    }
+
+    /// Force and torque physics driven units push themselves with this tick, the force in world
+    /// space and the torque counterclockwise. Resets the intents.
+    pub fn physics_forces(&self, intent: &mut MovementIntent, pose: Pose, speed_multiplier: f32) -> (Vec2, f32) {
+        let (force, torque) = if intent.hand_brake {
+            (Vec2::ZERO, 0.0)
+        } else {
+            (intent.input_force.clamp_length_max(1.0) * self.max_force * speed_multiplier, intent.input_torque.clamp(-1.0, 1.0) * self.max_torque)
+        };
+        intent.input_force = Vec2::ZERO;
+        intent.input_torque = 0.0;
+        // forces are relative to where the unit faces, torque is clockwise like rotation
+        (force.rotate(pose.right()), -torque)
+    }
+}
+
+/// Moves as far as possible towards `delta`, then along whatever was hit with the motion that's
+/// left, for a few casts. Returns how far the unit got.
+pub fn slide<W: Obstacles>(world: &W, pose: Pose, delta: Vec2) -> Vec2 {
+    let mut moved = Vec2::ZERO;
+    let mut remaining = delta;
+    for _ in 0..SLIDE_ITERATIONS {
+        if remaining.length() <= f32::EPSILON {
+            break
+        }
+        let hit = match world.cast(Pose { position: pose.position + moved, ..pose }, remaining) {
+            Some(hit) => hit,
+            None => return moved + remaining
+        };
+        // stop just short of the contact, so the next cast doesn't start touching it
+        let travel = (hit.toi - SLIDE_SKIN / remaining.length()).clamp(0.0, 1.0);
+        moved += remaining * travel;
+        let rest = remaining * (1.0 - travel);
+        remaining = rest - hit.normal * rest.dot(hit.normal);
+    }
+    moved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points moving among walls that block everything with an x at or past theirs
+    struct Walls(Vec<f32>);
+
+    impl Obstacles for Walls {
+        type Obstacle = usize;
+
+        fn cast(&self, pose: Pose, delta: Vec2) -> Option<Hit<usize>> {
+            self.0.iter().enumerate()
+                .filter(|(_, x)| pose.position.x + delta.x >= **x && delta.x > 0.0)
+                .map(|(index, x)| Hit { obstacle: index, toi: ((x - pose.position.x) / delta.x).max(0.0), normal: Vec2::NEG_X })
+                .min_by(|a, b| a.toi.total_cmp(&b.toi))
+        }
+
+        fn overlap(&self, pose: Pose) -> Option<usize> {
+            self.0.iter().position(|x| pose.position.x >= *x)
+        }
+    }
+
+    fn stats(movement_type: &str) -> MovementStats {
+        serde_json::from_value(serde_json::json!({
+            "name": "test",
+            "movement_type": movement_type,
+            "speed": 6.0,
+            "max_speed": 3.0,
+            "acceleration": 6.0,
+            "braking_acceleration": 12.0,
+            "rotation_speed": 90.0
+        })).unwrap()
+    }
+
+    // runs the same intent for `ticks` ticks, like a program setting it every tick
+    fn run(stats: &MovementStats, intent: &MovementIntent, state: &mut MovementState, mut pose: Pose, ticks: usize, world: &Walls) -> (Pose, Option<usize>) {
+        let mut blocked_by = None;
+        for _ in 0..ticks {
+            let step = stats.step(&mut intent.clone(), state, pose, 1.0, world);
+            pose = step.pose;
+            blocked_by = blocked_by.or(step.blocked_by);
+        }
+        (pose, blocked_by)
+    }
+
+    #[test]
+    fn omnidirectional_units_move_at_their_speed() {
+        let stats = stats("omnidirectional");
+        let intent = MovementIntent { input_move: Vec2::X, ..Default::default() };
+        let (pose, blocked_by) = run(&stats, &intent, &mut stats.initial_state(), Pose::default(), 60, &Walls(Vec::new()));
+        assert!((pose.position - Vec2::new(6.0, 0.0)).length() < 1e-3);
+        assert_eq!(blocked_by, None);
+    }
+
+    #[test]
+    fn moves_are_relative_to_the_heading() {
+        let stats = stats("omnidirectional");
+        let intent = MovementIntent { input_move: Vec2::X, ..Default::default() };
+        let facing_up = Pose { position: Vec2::ZERO, rotation: PI / 2.0 };
+        let (pose, _) = run(&stats, &intent, &mut stats.initial_state(), facing_up, 10, &Walls(Vec::new()));
+        assert!((pose.position - Vec2::new(0.0, 1.0)).length() < 1e-3);
+    }
+
+    #[test]
+    fn moves_are_consumed_and_the_hand_brake_holds() {
+        let stats = stats("omnidirectional");
+        let mut intent = MovementIntent { input_move: Vec2::X, ..Default::default() };
+        let mut state = stats.initial_state();
+        let step = stats.step(&mut intent, &mut state, Pose::default(), 1.0, &Walls(Vec::new()));
+        assert_eq!(intent.input_move, Vec2::ZERO);
+        assert!(step.pose.position.x > 0.0);
+        let mut braked = MovementIntent { input_move: Vec2::X, hand_brake: true, ..Default::default() };
+        assert_eq!(stats.step(&mut braked, &mut state, Pose::default(), 1.0, &Walls(Vec::new())).pose, Pose::default());
+    }
+
+    #[test]
+    fn walls_stop_units() {
+        let stats = stats("omnidirectional");
+        let intent = MovementIntent { input_move: Vec2::X, ..Default::default() };
+        let (pose, blocked_by) = run(&stats, &intent, &mut stats.initial_state(), Pose::default(), 60, &Walls(vec![1.0]));
+        assert_eq!(blocked_by, Some(0));
+        // the last move that fit, a unit that's stopped doesn't creep up to the wall
+        assert!(pose.position.x <= 1.0 && pose.position.x > 0.85);
+    }
+
+    #[test]
+    fn sliding_units_stop_at_the_wall_and_keep_the_sideways_part() {
+        let mut stats = stats("omnidirectional");
+        stats.collision_response = CollisionResponse::Slide;
+        let intent = MovementIntent { input_move: Vec2::new(1.0, 1.0), ..Default::default() };
+        let (pose, blocked_by) = run(&stats, &intent, &mut stats.initial_state(), Pose::default(), 60, &Walls(vec![1.0]));
+        assert_eq!(blocked_by, Some(0));
+        assert!(pose.position.x < 1.0 && pose.position.x > 1.0 - 2.0 * SLIDE_SKIN);
+        assert!(pose.position.y > 4.0);
+    }
+
+    #[test]
+    fn units_turn_towards_their_heading() {
+        let stats = stats("omnidirectional");
+        let mut intent = MovementIntent { target_heading: Some(-90.0), ..Default::default() };
+        let mut pose = Pose::default();
+        // 90 degrees at 90 degrees per second, the heading is reached on the tick after
+        for _ in 0..61 {
+            pose = stats.step(&mut intent, &mut stats.initial_state(), pose, 1.0, &Walls(Vec::new())).pose;
+        }
+        assert!(intent.target_heading.is_none());
+        assert!((pose.rotation - PI / 2.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn turns_into_walls_are_blocked() {
+        let stats = stats("omnidirectional");
+        let mut intent = MovementIntent { input_rotation: 1.0, ..Default::default() };
+        let inside = Pose { position: Vec2::new(2.0, 0.0), rotation: 0.0 };
+        let step = stats.step(&mut intent, &mut stats.initial_state(), inside, 1.0, &Walls(vec![1.0]));
+        assert_eq!(step.blocked_by, Some(0));
+        assert_eq!(step.pose, inside);
+    }
+
+    #[test]
+    fn steering_units_accelerate_up_to_their_maximum() {
+        let stats = stats("accelerated-steering");
+        let mut state = MovementState::default();
+        let throttle = MovementIntent { input_move: Vec2::new(1.0, 1.0), ..Default::default() };
+        let (pose, _) = run(&stats, &throttle, &mut state, Pose::default(), 30, &Walls(Vec::new()));
+        assert!((state.speed - 3.0).abs() < 1e-3);
+        assert!(pose.position.length() > 0.0 && pose.rotation < 0.0);
+        run(&stats, &throttle, &mut state, Pose::default(), 30, &Walls(Vec::new()));
+        assert!((state.speed - 3.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn the_hand_brake_stops_steering_units() {
+        let stats = stats("accelerated-steering");
+        let mut state = MovementState { speed: 3.0 };
+        let braking = MovementIntent { input_move: Vec2::new(1.0, 1.0), hand_brake: true, ..Default::default() };
+        run(&stats, &braking, &mut state, Pose::default(), 10, &Walls(Vec::new()));
+        assert!((state.speed - 1.0).abs() < 1e-3);
+        run(&stats, &braking, &mut state, Pose::default(), 5, &Walls(Vec::new()));
+        assert!(state.speed.abs() < 1e-3);
+    }
+
+    #[test]
+    fn cruise_control_holds_the_target_speed() {
+        let stats = stats("accelerated-steering");
+        let mut state = MovementState::default();
+        let cruise = MovementIntent { input_move: Vec2::new(0.0, 1.0), target_speed: Some(2.0), ..Default::default() };
+        run(&stats, &cruise, &mut state, Pose::default(), 60, &Walls(Vec::new()));
+        assert!((state.speed - 2.0).abs() < 1e-3);
+        // slowing down brakes, which is faster than accelerating
+        let slower = MovementIntent { target_speed: Some(1.0), ..cruise };
+        run(&stats, &slower, &mut state, Pose::default(), 5, &Walls(Vec::new()));
+        assert!((state.speed - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn physics_forces_follow_the_heading() {
+        let mut stats = stats("physics-driven");
+        stats.max_force = 10.0;
+        stats.max_torque = 2.0;
+        let mut intent = MovementIntent { input_force: Vec2::new(2.0, 0.0), input_torque: 0.5, ..Default::default() };
+        let facing_up = Pose { position: Vec2::ZERO, rotation: PI / 2.0 };
+        let (force, torque) = stats.physics_forces(&mut intent, facing_up, 1.0);
+        assert!((force - Vec2::new(0.0, 10.0)).length() < 1e-3);
+        assert_eq!(torque, -1.0);
+        assert_eq!(intent.input_force, Vec2::ZERO);
+        assert_eq!(intent.input_torque, 0.0);
+    }
+}
@@ -0,0 +1,231 @@
+//! When unit programs run and what state they're in. Running them is up to the game, which has the
+//! interpreter, this only keeps track of the runs.
+
+use crate::TICK_RATE;
+
+/// Longest tick interval a program can have, once an hour
+pub const MAX_TICK_INTERVAL: u64 = 60 * 60 * TICK_RATE;
+const ERROR_LOG_CAPACITY: usize = 16;
+// program runs in a row without intents before the unit hibernates on its own
+const AUTO_HIBERNATE_RUNS: u32 = 600;
+// how long it hibernates then, programs waiting for something that isn't an event need to run again
+const AUTO_HIBERNATE_TICKS: u64 = 120;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProgramStatus {
+    Running,
+    Idle, // no on_tick function
+    Hibernating,
+    Faulted,
+    Crashed // panicked in the interpreter or a host callback, only a reload recovers
+}
+
+#[derive(Clone, Debug)]
+pub struct ProgramSchedule {
+    status: ProgramStatus,
+    error_log: Vec<String>,
+    // the program runs once every `tick_interval` simulation ticks
+    tick_interval: u64,
+    // `u64::MAX` when sleeping until woken up
+    hibernating_until: Option<u64>,
+    // simulation tick of the last run
+    last_run: Option<u64>,
+    idle_runs: u32
+}
+
+impl Default for ProgramSchedule {
+    fn default() -> Self {
+        Self {
+            status: ProgramStatus::Idle,
+            error_log: Vec::new(),
+            tick_interval: 1,
+            hibernating_until: None,
+            last_run: None,
+            idle_runs: 0
+        }
+    }
+}
+
+impl ProgramSchedule {
+    pub fn status(&self) -> ProgramStatus {
+        self.status
+    }
+
+    pub fn error_log(&self) -> &[String] {
+        &self.error_log
+    }
+
+    pub fn tick_interval(&self) -> u64 {
+        self.tick_interval
+    }
+
+    pub fn set_tick_interval(&mut self, tick_interval: u64) {
+        self.tick_interval = tick_interval.clamp(1, MAX_TICK_INTERVAL);
+    }
+
+    /// Units with the same interval are spread over buckets by unit id, so they don't all run on
+    /// the same tick and keep their bucket after loading a save. Throttled units run `throttle`
+    /// times less often.
+    pub fn is_due(&self, tick: u64, unit_id: u64, throttle: u64) -> bool {
+        (tick + unit_id).is_multiple_of(self.tick_interval.saturating_mul(throttle))
+    }
+
+    /// Ticks since the program last ran. Throttling, hibernating and stuns make it longer than the
+    /// tick interval, before the first run it's the interval.
+    pub fn delta_ticks(&self, tick: u64) -> u64 {
+        self.last_run.map_or(self.tick_interval, |last_run| tick - last_run)
+    }
+
+    /// Faulted and crashed programs don't run until they're reloaded
+    pub fn can_run(&self) -> bool {
+        !matches!(self.status, ProgramStatus::Faulted | ProgramStatus::Crashed)
+    }
+
+    pub fn start_run(&mut self, tick: u64) {
+        self.last_run = Some(tick);
+    }
+
+    /// Records how a run that didn't crash went: whether the program has an `on_tick` function or
+    /// the error it ran into, and whether it affected the world. Programs that keep running without
+    /// affecting anything hibernate for a while.
+    pub fn finish_run(&mut self, tick: u64, result: Result<bool, String>, intent: bool) {
+        match result {
+            Ok(true) => self.status = ProgramStatus::Running,
+            Ok(false) => self.status = ProgramStatus::Idle,
+            Err(error) => self.fault(error)
+        }
+        if intent {
+            self.idle_runs = 0;
+        } else if self.status == ProgramStatus::Running {
+            self.idle_runs += 1;
+            if self.idle_runs >= AUTO_HIBERNATE_RUNS {
+                self.hibernate(Some(AUTO_HIBERNATE_TICKS), tick);
+            }
+        }
+    }
+
+    /// Stops running the program for `ticks` ticks, or until woken up if `None`
+    pub fn hibernate(&mut self, ticks: Option<u64>, tick: u64) {
+        self.hibernating_until = Some(ticks.map_or(u64::MAX, |ticks| tick.saturating_add(ticks)));
+        self.status = ProgramStatus::Hibernating;
+        self.idle_runs = 0;
+    }
+
+    pub fn wake_up(&mut self) {
+        if self.hibernating_until.take().is_some() && self.status == ProgramStatus::Hibernating {
+            self.status = ProgramStatus::Idle;
+        }
+    }
+
+    pub fn is_hibernating(&self, tick: u64) -> bool {
+        matches!(self.hibernating_until, Some(until) if tick < until)
+    }
+
+    /// A reloaded program starts over as idle, whatever happened to the old one
+    pub fn reload(&mut self) {
+        self.status = ProgramStatus::Idle;
+    }
+
+    pub fn fault(&mut self, error: String) {
+        self.log(error);
+        self.status = ProgramStatus::Faulted;
+    }
+
+    pub fn crash(&mut self, message: &str) {
+        self.log(format!("crashed: {}", message));
+        self.status = ProgramStatus::Crashed;
+    }
+
+    fn log(&mut self, entry: String) {
+        if self.error_log.len() == ERROR_LOG_CAPACITY {
+            self.error_log.remove(0);
+        }
+        self.error_log.push(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs the program on every tick it's due, like the game's unit tick
+    fn run(schedule: &mut ProgramSchedule, ticks: std::ops::Range<u64>, unit_id: u64, throttle: u64, intent: bool) -> Vec<u64> {
+        let mut runs = Vec::new();
+        for tick in ticks {
+            if schedule.can_run() && schedule.is_due(tick, unit_id, throttle) && !schedule.is_hibernating(tick) {
+                schedule.start_run(tick);
+                schedule.finish_run(tick, Ok(true), intent);
+                runs.push(tick);
+            }
+        }
+        runs
+    }
+
+    #[test]
+    fn units_are_spread_over_buckets() {
+        let mut schedule = ProgramSchedule::default();
+        schedule.set_tick_interval(4);
+        assert_eq!(run(&mut schedule.clone(), 0..12, 1, 1, true), vec![3, 7, 11]);
+        assert_eq!(run(&mut schedule.clone(), 0..12, 2, 1, true), vec![2, 6, 10]);
+        assert_eq!(run(&mut schedule, 0..12, 1, 2, true), vec![7]);
+    }
+
+    #[test]
+    fn tick_intervals_are_clamped_and_delta_ticks_count_from_the_last_run() {
+        let mut schedule = ProgramSchedule::default();
+        schedule.set_tick_interval(u64::MAX);
+        assert_eq!(schedule.tick_interval(), MAX_TICK_INTERVAL);
+        assert!(!schedule.is_due(1, 0, u64::MAX));
+        schedule.set_tick_interval(0);
+        assert_eq!(schedule.tick_interval(), 1);
+        assert_eq!(schedule.delta_ticks(10), 1);
+        schedule.start_run(10);
+        assert_eq!(schedule.delta_ticks(15), 5);
+    }
+
+    #[test]
+    fn idle_programs_hibernate_on_their_own() {
+        let mut schedule = ProgramSchedule::default();
+        let runs = run(&mut schedule, 0..1000, 0, 1, false);
+        // the last idle run starts the hibernation, the one after it is due when it ends
+        assert_eq!(runs.len() as u64, 1000 - (AUTO_HIBERNATE_TICKS - 1));
+        assert!(!runs.contains(&(AUTO_HIBERNATE_RUNS as u64)));
+        assert!(runs.contains(&(AUTO_HIBERNATE_RUNS as u64 + AUTO_HIBERNATE_TICKS - 1)));
+        let mut busy = ProgramSchedule::default();
+        assert_eq!(run(&mut busy, 0..1000, 0, 1, true).len(), 1000);
+    }
+
+    #[test]
+    fn hibernating_until_woken_up() {
+        let mut schedule = ProgramSchedule::default();
+        schedule.hibernate(None, 5);
+        assert_eq!(schedule.status(), ProgramStatus::Hibernating);
+        assert!(run(&mut schedule, 0..100, 0, 1, true).is_empty());
+        schedule.wake_up();
+        assert_eq!(schedule.status(), ProgramStatus::Idle);
+        assert_eq!(run(&mut schedule, 100..110, 0, 1, true).len(), 10);
+    }
+
+    #[test]
+    fn faulted_programs_stop_until_reloaded() {
+        let mut schedule = ProgramSchedule::default();
+        schedule.finish_run(0, Err("attempt to call a nil value".to_string()), false);
+        assert_eq!(schedule.status(), ProgramStatus::Faulted);
+        assert!(run(&mut schedule, 1..10, 0, 1, true).is_empty());
+        schedule.crash("out of memory");
+        assert_eq!(schedule.error_log(), ["attempt to call a nil value", "crashed: out of memory"]);
+        schedule.reload();
+        assert_eq!(run(&mut schedule, 10..20, 0, 1, true).len(), 10);
+    }
+
+    #[test]
+    fn error_log_keeps_the_latest_errors() {
+        let mut schedule = ProgramSchedule::default();
+        for error in 0..20 {
+            schedule.fault(error.to_string());
+        }
+        assert_eq!(schedule.error_log().len(), ERROR_LOG_CAPACITY);
+        assert_eq!(schedule.error_log().first().map(String::as_str), Some("4"));
+        assert_eq!(schedule.error_log().last().map(String::as_str), Some("19"));
+    }
+}
@@ -0,0 +1,240 @@
+//! Expected shape of `DataValue` payloads, checked before consumers look inside them.
+
+use std::fmt::Write;
+use thiserror::Error;
+use crate::data_value::{DataValue, DataValueHashEq};
+
+#[derive(Debug, Clone)]
+pub enum Schema {
+    Any,
+    Nil,
+    Boolean,
+    Integer { min: Option<i64>, max: Option<i64> },
+    // integers are accepted too
+    Number { min: Option<f64>, max: Option<f64> },
+    String { max_len: Option<usize> },
+    Sequence { items: Box<Schema>, max_len: Option<usize> },
+    // `other` is the schema for keys not listed in `fields`, those aren't allowed when it's `None`
+    Table { fields: Vec<(String, Schema)>, other: Option<Box<Schema>> },
+    // also matches nil and missing table fields
+    Optional(Box<Schema>),
+    // matches what any of them matches
+    OneOf(Vec<Schema>)
+}
+
+impl Schema {
+    pub fn string() -> Self {
+        Self::String { max_len: None }
+    }
+
+    pub fn optional(self) -> Self {
+        Self::Optional(Box::new(self))
+    }
+
+    pub fn table<'a>(fields: impl IntoIterator<Item = (&'a str, Schema)>) -> Self {
+        Self::Table {
+            fields: fields.into_iter().map(|(name, schema)| (name.to_string(), schema)).collect(),
+            other: None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("{path}: {message}")]
+pub struct SchemaError {
+    pub path: String,
+    pub message: String
+}
+
+fn type_name(value: &DataValue) -> &'static str {
+    match value {
+        DataValue::Nil => "nil",
+        DataValue::Boolean(_) => "boolean",
+        DataValue::Integer(_) => "integer",
+        DataValue::Number(_) => "number",
+        DataValue::String(_) => "string",
+        DataValue::Sequence(_) => "sequence",
+        DataValue::Table(_) => "table"
+    }
+}
+
+fn check_range<T: PartialOrd + std::fmt::Display>(value: T, min: Option<T>, max: Option<T>) -> Result<(), String> {
+    match (min, max) {
+        (Some(min), _) if value < min => Err(format!("{} is less than {}", value, min)),
+        (_, Some(max)) if value > max => Err(format!("{} is more than {}", value, max)),
+        _ => Ok(())
+    }
+}
+
+fn check_len(len: usize, max_len: Option<usize>) -> Result<(), String> {
+    match max_len {
+        Some(max_len) if len > max_len => Err(format!("length {} is more than {}", len, max_len)),
+        _ => Ok(())
+    }
+}
+
+fn key_path(path: &str, key: &DataValueHashEq) -> String {
+    let mut result = path.to_string();
+    match key {
+        DataValueHashEq::String(s) if !path.is_empty() => write!(result, ".{}", s).unwrap(),
+        DataValueHashEq::String(s) => result.push_str(s),
+        key => write!(result, "[{:?}]", key).unwrap()
+    }
+    result
+}
+
+impl DataValue {
+    pub fn validate(&self, schema: &Schema) -> Result<(), SchemaError> {
+        self.validate_at(schema, "")
+    }
+
+    fn validate_at(&self, schema: &Schema, path: &str) -> Result<(), SchemaError> {
+        let error = |message: String| SchemaError {
+            path: if path.is_empty() { "value".to_string() } else { path.to_string() },
+            message
+        };
+        let result = match (schema, self) {
+            (Schema::Any, _) => Ok(()),
+            (Schema::Optional(_), DataValue::Nil) => Ok(()),
+            (Schema::Optional(schema), value) => return value.validate_at(schema, path),
+            (Schema::OneOf(schemas), value) if schemas.iter().any(|schema| value.validate_at(schema, path).is_ok()) => Ok(()),
+            (Schema::Nil, DataValue::Nil) | (Schema::Boolean, DataValue::Boolean(_)) => Ok(()),
+            (Schema::Integer { min, max }, DataValue::Integer(i)) => check_range(*i, *min, *max),
+            (Schema::Number { min, max }, DataValue::Integer(i)) => check_range(*i as f64, *min, *max),
+            (Schema::Number { min, max }, DataValue::Number(n)) => check_range(*n, *min, *max),
+            (Schema::String { max_len }, DataValue::String(s)) => check_len(s.len(), *max_len),
+            (Schema::Sequence { items, max_len }, DataValue::Sequence(sequence)) => {
+                check_len(sequence.len(), *max_len).map_err(error)?;
+                for (i, item) in sequence.iter().enumerate() {
+                    item.validate_at(items, &format!("{}[{}]", path, i + 1))?;
+                }
+                Ok(())
+            }
+            // an empty Lua table converts to an empty sequence
+            (Schema::Table { .. }, DataValue::Sequence(sequence)) if sequence.is_empty() => {
+                return DataValue::Table(Default::default()).validate_at(schema, path)
+            }
+            (Schema::Table { fields, other }, DataValue::Table(table)) => {
+                for (name, field_schema) in fields {
+                    let key = DataValueHashEq::String(name.clone());
+                    table.get(&key).unwrap_or(&DataValue::Nil).validate_at(field_schema, &key_path(path, &key))?;
+                }
+                for (key, value) in table {
+                    if matches!(key, DataValueHashEq::String(s) if fields.iter().any(|(name, _)| name == s)) {
+                        continue
+                    }
+                    match other {
+                        Some(other) => value.validate_at(other, &key_path(path, key))?,
+                        None => return Err(error(format!("unexpected key {:?}", key)))
+                    }
+                }
+                Ok(())
+            }
+            (schema, value) => Err(format!("expected {}, found {}", schema_name(schema), type_name(value)))
+        };
+        result.map_err(error)
+    }
+}
+
+fn schema_name(schema: &Schema) -> String {
+    match schema {
+        Schema::Any => "anything".to_string(),
+        Schema::Nil => "nil".to_string(),
+        Schema::Boolean => "boolean".to_string(),
+        Schema::Integer { .. } => "integer".to_string(),
+        Schema::Number { .. } => "number".to_string(),
+        Schema::String { .. } => "string".to_string(),
+        Schema::Sequence { .. } => "sequence".to_string(),
+        Schema::Table { .. } => "table".to_string(),
+        Schema::Optional(schema) => schema_name(schema),
+        Schema::OneOf(schemas) => schemas.iter().map(schema_name).collect::<Vec<_>>().join(" or ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(pairs: Vec<(&str, DataValue)>) -> DataValue {
+        DataValue::Table(pairs.into_iter().map(|(key, value)| (DataValueHashEq::String(key.to_string()), value)).collect())
+    }
+
+    fn order() -> Schema {
+        Schema::table([
+            ("target", Schema::string()),
+            ("count", Schema::Integer { min: Some(1), max: Some(10) }),
+            ("speed", Schema::Number { min: Some(0.0), max: None }.optional()),
+            ("path", Schema::Sequence { items: Box::new(Schema::Integer { min: None, max: None }), max_len: Some(3) }.optional())
+        ])
+    }
+
+    #[test]
+    fn accepts_matching_values() {
+        let value = table(vec![
+            ("target", DataValue::String("base".into())),
+            ("count", DataValue::Integer(3)),
+            // integers are fine where numbers are expected
+            ("speed", DataValue::Integer(2)),
+            ("path", DataValue::Sequence(vec![DataValue::Integer(1), DataValue::Integer(2)]))
+        ]);
+        assert!(value.validate(&order()).is_ok());
+        let minimal = table(vec![("target", DataValue::String("base".into())), ("count", DataValue::Integer(1))]);
+        assert!(minimal.validate(&order()).is_ok());
+    }
+
+    #[test]
+    fn reports_path_of_the_problem() {
+        let value = table(vec![
+            ("target", DataValue::String("base".into())),
+            ("count", DataValue::Integer(1)),
+            ("path", DataValue::Sequence(vec![DataValue::Integer(1), DataValue::String("two".into())]))
+        ]);
+        let error = value.validate(&order()).unwrap_err();
+        assert_eq!(error.path, "path[2]");
+        assert_eq!(error.message, "expected integer, found string");
+        let missing = table(vec![("count", DataValue::Integer(1))]);
+        assert_eq!(missing.validate(&order()).unwrap_err().path, "target");
+    }
+
+    #[test]
+    fn rejects_values_out_of_range() {
+        let value = table(vec![("target", DataValue::String("base".into())), ("count", DataValue::Integer(11))]);
+        assert_eq!(value.validate(&order()).unwrap_err().message, "11 is more than 10");
+        let value = table(vec![
+            ("target", DataValue::String("base".into())),
+            ("count", DataValue::Integer(1)),
+            ("path", DataValue::Sequence(vec![DataValue::Integer(1); 4]))
+        ]);
+        assert_eq!(value.validate(&order()).unwrap_err().message, "length 4 is more than 3");
+        assert!(DataValue::Number(-0.5).validate(&Schema::Number { min: Some(0.0), max: None }).is_err());
+    }
+
+    #[test]
+    fn checks_unknown_keys() {
+        let value = table(vec![
+            ("target", DataValue::String("base".into())),
+            ("count", DataValue::Integer(1)),
+            ("extra", DataValue::Boolean(true))
+        ]);
+        assert!(value.validate(&order()).unwrap_err().message.starts_with("unexpected key"));
+        let open = Schema::Table { fields: Vec::new(), other: Some(Box::new(Schema::Boolean)) };
+        assert!(table(vec![("a", DataValue::Boolean(true))]).validate(&open).is_ok());
+        assert_eq!(table(vec![("a", DataValue::Nil)]).validate(&open).unwrap_err().path, "a");
+    }
+
+    #[test]
+    fn one_of_accepts_any_alternative() {
+        let schema = Schema::OneOf(vec![Schema::Boolean, Schema::String { max_len: Some(3) }]);
+        assert!(DataValue::Boolean(true).validate(&schema).is_ok());
+        assert!(DataValue::String("abc".into()).validate(&schema).is_ok());
+        assert_eq!(DataValue::String("abcd".into()).validate(&schema).unwrap_err().message, "expected boolean or string, found string");
+        assert_eq!(DataValue::Integer(1).validate(&schema).unwrap_err().message, "expected boolean or string, found integer");
+    }
+
+    #[test]
+    fn empty_sequence_is_an_empty_table() {
+        let schema = Schema::table([("name", Schema::string().optional())]);
+        assert!(DataValue::Sequence(Vec::new()).validate(&schema).is_ok());
+        assert!(DataValue::Sequence(vec![DataValue::Nil]).validate(&schema).is_err());
+    }
+}
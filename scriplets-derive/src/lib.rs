@@ -36,15 +36,37 @@ pub fn prototype_derive(input: TokenStream) -> TokenStream {
     }
 }
 
-#[proc_macro_derive(ComponentPrototype)]
+// Without `#[component(Target)]` the prototype is its own component and gets cloned, with it the
+// component is made with `Target: From<&Prototype>`
+#[proc_macro_derive(ComponentPrototype, attributes(component))]
 pub fn component_prototype_derive(input: TokenStream) -> TokenStream {
     let declaration = parse_declaration(input.into()).unwrap();
     if let Declaration::Struct(struct_decl) = declaration {
         let struct_name = struct_decl.name;
-        quote! {
-            impl ComponentPrototype<'_> for #struct_name {
-                fn to_component(&self) -> Self {
-                    self.clone()
+        let component = struct_decl.attributes.iter().find_map(|attr| {
+            if attr.get_single_path_segment()? == "component" {
+                if let AttributeValue::Group(_, toks) = &attr.value {
+                    Some(toks)
+                } else {
+                    None
+                }
+            } else {
+                None
+            }
+        });
+        match component {
+            Some(component) => quote! {
+                impl ComponentPrototype<'_, #(#component)*> for #struct_name {
+                    fn to_component(&self) -> #(#component)* {
+                        <#(#component)*>::from(self)
+                    }
+                }
+            },
+            None => quote! {
+                impl ComponentPrototype<'_> for #struct_name {
+                    fn to_component(&self) -> Self {
+                        self.clone()
+                    }
                 }
             }
         }.into()
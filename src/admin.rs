@@ -0,0 +1,160 @@
+use bevy::{prelude::*, ecs::system::SystemParam};
+use serde::{Serialize, Deserialize};
+use super::{Unit, UnitId, Paused};
+use crate::blueprint::BlueprintEvent;
+use crate::team::{Team, ScriptUsage};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::server::Server;
+#[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+use crate::client::Client;
+
+/// Commands that change the game for everyone playing it. The console gives them, on a server
+/// also players the server lists as admins, see `Server`. Clients send theirs to the server
+/// instead of carrying them out. Chat goes the same ways, so its events are added here too.
+pub struct AdminPlugin;
+
+impl Plugin for AdminPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<AdminEvent>()
+            .add_event::<AdminOutcome>()
+            .add_event::<ChatEvent>()
+            .add_system(run_admin_commands);
+        // only the console shows them
+        #[cfg(not(feature = "headless"))]
+        app.add_event::<ConsoleMessage>();
+    }
+}
+
+/// Chat typed into the console, sent to the other players by the server or client
+pub struct ChatEvent {
+    pub text: String
+}
+
+/// Lines from other players and the server
+#[cfg(not(feature = "headless"))]
+pub enum ConsoleMessage {
+    Chat { from: String, text: String },
+    Notice(String)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum AdminCommand {
+    /// Disconnects the player of that name
+    Kick { name: String },
+    /// Stops the simulation or lets it go on
+    Pause,
+    /// Spawns a saved blueprint or the prototypes of that name, see `BlueprintEvent::Spawn`
+    Spawn { name: String, position: [f32; 2] },
+    SetTeam { unit: u64, team: u32 },
+    /// Reports every team's script usage, see `ScriptUsage`
+    Usage
+}
+
+pub struct AdminEvent {
+    pub command: AdminCommand,
+    /// Player that gave the command, `None` for this game's own console
+    pub by: Option<String>
+}
+
+/// What came of an `AdminEvent`, for the console of whoever gave the command
+pub struct AdminOutcome {
+    pub by: Option<String>,
+    pub result: AdminResult
+}
+
+pub enum AdminResult {
+    Kicked { name: String },
+    NotPlaying { name: String },
+    Paused(bool),
+    Spawning { name: String, position: Vec2 },
+    TeamChanged { unit: u64, team: u32 },
+    NoSuchUnit { unit: u64 },
+    // by team
+    Usage(Vec<TeamUsage>)
+}
+
+/// A team's script usage during the last second
+pub struct TeamUsage {
+    pub team: u32,
+    pub instructions: u64,
+    pub memory: usize,
+    pub throttled: bool
+}
+
+impl AdminResult {
+    /// How servers tell players what came of their commands
+    pub fn describe(&self) -> String {
+        match self {
+            AdminResult::Kicked { name } => format!("kicked {}", name),
+            AdminResult::NotPlaying { name } => format!("{} isn't playing", name),
+            AdminResult::Paused(true) => "the game is paused".to_string(),
+            AdminResult::Paused(false) => "the game goes on".to_string(),
+            AdminResult::Spawning { name, position } => format!("spawning {} at {}, {}", name, position.x, position.y),
+            AdminResult::TeamChanged { unit, team } => format!("unit {} joined team {}", unit, team),
+            AdminResult::NoSuchUnit { unit } => format!("there's no unit {}", unit),
+            AdminResult::Usage(teams) if teams.is_empty() => "no team runs scripts".to_string(),
+            AdminResult::Usage(teams) => teams.iter()
+                .map(|usage| {
+                    let throttled = if usage.throttled { ", throttled" } else { "" };
+                    format!("team {} scripts: {}k instructions/s, {} KiB{}", usage.team, usage.instructions / 1000, usage.memory / 1024, throttled)
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+    }
+}
+
+/// What admin commands act on besides the server
+#[derive(SystemParam)]
+struct AdminTargets<'w, 's> {
+    paused: ResMut<'w, Paused>,
+    blueprints: EventWriter<'w, 's, BlueprintEvent>,
+    teams: Query<'w, 's, (&'static UnitId, &'static mut Team), With<Unit>>,
+    script_usage: Res<'w, ScriptUsage>
+}
+
+fn run_admin_commands(
+    mut events: EventReader<AdminEvent>,
+    mut outcomes: EventWriter<AdminOutcome>,
+    mut targets: AdminTargets,
+    #[cfg(not(target_arch = "wasm32"))] mut server: Option<ResMut<Server>>,
+    #[cfg(not(any(target_arch = "wasm32", feature = "headless")))] client: Option<Res<Client>>)
+{
+    // the world is the server's, see `client::send_console_input`
+    #[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+    if client.is_some() {
+        return
+    }
+    for AdminEvent { command, by } in events.iter() {
+        let result = match command {
+            #[cfg(not(target_arch = "wasm32"))]
+            AdminCommand::Kick { name } if server.as_mut().is_some_and(|server| server.kick(name, by.as_deref().unwrap_or("the host"))) => AdminResult::Kicked { name: name.clone() },
+            AdminCommand::Kick { name } => AdminResult::NotPlaying { name: name.clone() },
+            AdminCommand::Pause => {
+                targets.paused.0 = !targets.paused.0;
+                AdminResult::Paused(targets.paused.0)
+            }
+            AdminCommand::Spawn { name, position } => {
+                let position = Vec2::from(*position);
+                targets.blueprints.send(BlueprintEvent::Spawn { name: name.clone(), position, program: None });
+                AdminResult::Spawning { name: name.clone(), position }
+            }
+            AdminCommand::SetTeam { unit, team } => match targets.teams.iter_mut().find(|(id, _)| id.0 == *unit) {
+                Some((_, mut unit_team)) => {
+                    *unit_team = Team(*team);
+                    AdminResult::TeamChanged { unit: *unit, team: *team }
+                }
+                None => AdminResult::NoSuchUnit { unit: *unit }
+            },
+            AdminCommand::Usage => {
+                let mut teams: Vec<TeamUsage> = targets.script_usage.iter()
+                    .map(|(team, usage)| TeamUsage { team: team.0, instructions: usage.instructions, memory: usage.memory, throttled: usage.throttled })
+                    .collect();
+                teams.sort_by_key(|usage| usage.team);
+                AdminResult::Usage(teams)
+            }
+        };
+        outcomes.send(AdminOutcome { by: by.clone(), result });
+    }
+}
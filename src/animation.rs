@@ -0,0 +1,134 @@
+use std::{collections::HashMap, time::Duration};
+use bevy::{prelude::*, ecs::schedule::SystemLabel};
+use serde::Deserialize;
+use super::{Prototype, Prototypes, Unit};
+use crate::health::Health;
+
+const DAMAGED_THRESHOLD: f32 = 0.5; // fraction of max health
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AnimationStateLabel;
+
+#[derive(Prototype, Deserialize)]
+#[prot_category(animation)]
+pub struct AnimationPrototype {
+    name: String,
+    texture: String,
+    tile_size: [f32; 2], // pixels
+    columns: usize,
+    rows: usize,
+    states: HashMap<AnimationState, AnimationClip>
+}
+
+impl AnimationPrototype {
+    pub fn texture(&self) -> &str {
+        &self.texture
+    }
+
+    // Falls back to the idle clip for states the sheet doesn't define
+    fn clip(&self, state: AnimationState) -> Option<&AnimationClip> {
+        self.states.get(&state).or_else(|| self.states.get(&AnimationState::Idle))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AnimationClip {
+    frames: Vec<usize>,
+    fps: f32
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum AnimationState {
+    #[default]
+    Idle,
+    Moving,
+    Damaged
+}
+
+#[derive(Default)]
+pub struct AnimationAtlases(HashMap<String, Handle<TextureAtlas>>);
+
+impl AnimationAtlases {
+    pub fn load(assets: &AssetServer, texture_atlases: &mut Assets<TextureAtlas>, prototypes: &Prototypes) -> Self {
+        Self(prototypes.animation.values().map(|animation| {
+            let texture = assets.load(animation.texture.as_str());
+            let atlas = TextureAtlas::from_grid(texture, Vec2::from(animation.tile_size), animation.columns, animation.rows);
+            (animation.name.clone(), texture_atlases.add(atlas))
+        }).collect())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Handle<TextureAtlas>> {
+        self.0.get(name)
+    }
+
+    pub fn sprite_sheet_bundle(&self, name: &str) -> Option<SpriteSheetBundle> {
+        self.0.get(name).map(|atlas| SpriteSheetBundle {
+            texture_atlas: atlas.clone(),
+            sprite: TextureAtlasSprite {
+                custom_size: Some(Vec2::splat(1.0)),
+                ..default()
+            },
+            ..default()
+        })
+    }
+}
+
+#[derive(Component)]
+pub struct SpriteAnimation {
+    prototype: String,
+    pub state: AnimationState,
+    frame: usize,
+    timer: Timer
+}
+
+impl SpriteAnimation {
+    pub fn new(prototype: &str) -> Self {
+        Self {
+            prototype: prototype.to_string(),
+            state: AnimationState::Idle,
+            frame: 0,
+            timer: Timer::from_seconds(1.0, true)
+        }
+    }
+}
+
+type AnimatedUnit = (&'static mut SpriteAnimation, ChangeTrackers<Transform>, Option<&'static Health>);
+
+pub fn update_unit_animation_states(mut units: Query<AnimatedUnit, With<Unit>>) {
+    for (mut animation, transform_tracker, health) in units.iter_mut() {
+        let state = if health.map_or(false, |health| health.current < health.max_health * DAMAGED_THRESHOLD) {
+            AnimationState::Damaged
+        } else if transform_tracker.is_changed() {
+            AnimationState::Moving
+        } else {
+            AnimationState::Idle
+        };
+        if animation.state != state {
+            animation.state = state;
+        }
+    }
+}
+
+pub fn animate_sprites(
+    mut sprites: Query<(&mut SpriteAnimation, &mut TextureAtlasSprite)>,
+    prototypes: Res<Prototypes>,
+    time: Res<Time>)
+{
+    for (mut animation, mut sprite) in sprites.iter_mut() {
+        let clip = match AnimationPrototype::from_pt(&prototypes, &animation.prototype).and_then(|p| p.clip(animation.state)) {
+            Some(clip) if !clip.frames.is_empty() && clip.fps > 0.0 => clip,
+            _ => continue
+        };
+        if animation.is_changed() {
+            animation.frame = 0;
+            animation.timer.set_duration(Duration::from_secs_f32(1.0 / clip.fps));
+            animation.timer.reset();
+        } else {
+            animation.timer.tick(time.delta());
+            let finished = animation.timer.times_finished_this_tick() as usize;
+            animation.frame = (animation.frame + finished) % clip.frames.len();
+        }
+        sprite.index = clip.frames[animation.frame % clip.frames.len()];
+    }
+}
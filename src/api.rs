@@ -0,0 +1,194 @@
+use std::fmt::Write;
+use strum::AsRefStr;
+#[cfg(not(feature = "headless"))]
+use bevy::prelude::*;
+#[cfg(not(feature = "headless"))]
+use bevy_egui::{EguiContext, egui};
+#[cfg(not(feature = "headless"))]
+use crate::locale::Localize;
+
+#[cfg(not(feature = "headless"))]
+const TOGGLE_KEY: KeyCode = KeyCode::F2;
+/// Version of the unit handle API, entries added since the last release are marked with it
+pub const API_VERSION: &str = "0.1";
+
+#[derive(Clone, Copy, PartialEq, Eq, AsRefStr)]
+#[strum(serialize_all = "lowercase")]
+pub enum ApiKind {
+    Method,
+    Field,
+    Equipment
+}
+
+/// Documentation of one part of the unit handle. Registering a handle method or field that isn't
+/// listed in `UNIT_HANDLE_API` fails to compile, see `documented!` in `program.rs`.
+/// Methods that need equipment are nil on units without it, see `gated!`.
+pub struct ApiEntry {
+    pub name: &'static str,
+    pub kind: ApiKind,
+    // only shown in the API reference window
+    #[cfg_attr(feature = "headless", allow(dead_code))]
+    pub signature: &'static str,
+    /// Type in LuaLS annotation syntax, used for `scriplets.d.lua`
+    pub lua_type: &'static str,
+    pub description: &'static str,
+    /// API version the entry was added in
+    pub since: &'static str
+}
+
+const fn entry(kind: ApiKind, name: &'static str, signature: &'static str, lua_type: &'static str, description: &'static str, since: &'static str) -> ApiEntry {
+    ApiEntry { name, kind, signature, lua_type, description, since }
+}
+
+pub const UNIT_HANDLE_API: &[ApiEntry] = &[
+    entry(ApiKind::Method, "move", "handle:move(x, y)", "(fun(self: UnitHandle, x: number, y: number))|nil", "Only with movement. Moves the unit this tick, relative to where it faces. The vector is clamped to a length of 1.", "0.1"),
+    entry(ApiKind::Method, "rotate", "handle:rotate(speed)", "(fun(self: UnitHandle, speed: number))|nil", "Only with movement. Rotates the unit clockwise this tick, from -1 to 1 of its rotation speed. Cancels turning towards a heading.", "0.1"),
+    entry(ApiKind::Method, "set_heading", "handle:set_heading(degrees)", "(fun(self: UnitHandle, degrees: number))|nil", "Only with omnidirectional movement. Keeps turning the unit at its rotation speed until it faces this many degrees clockwise, like the gps rotation.", "0.1"),
+    entry(ApiKind::Method, "set_target_speed", "handle:set_target_speed(speed?)", "(fun(self: UnitHandle, speed?: number))|nil", "Only with accelerated steering movement. Accelerates or brakes to hold this speed in tiles per second, negative for reversing, instead of the throttle of move. Nil turns it off.", "0.1"),
+    entry(ApiKind::Method, "toggle_hand_brake", "handle:toggle_hand_brake()", "(fun(self: UnitHandle))|nil", "Only with movement. Pulls or releases the hand brake.", "0.1"),
+    entry(ApiKind::Method, "apply_force", "handle:apply_force(x, y)", "(fun(self: UnitHandle, x: number, y: number))|nil", "Only with physics driven movement. Pushes the unit this tick, relative to where it faces. The vector is clamped to a length of 1 of its max force.", "0.1"),
+    entry(ApiKind::Method, "apply_torque", "handle:apply_torque(torque)", "(fun(self: UnitHandle, torque: number))|nil", "Only with physics driven movement. Turns the unit clockwise this tick, from -1 to 1 of its max torque.", "0.1"),
+    entry(ApiKind::Method, "set_overclock", "handle:set_overclock(enabled)", "(fun(self: UnitHandle, enabled: boolean))|nil", "Only with overclockable movement. Moves faster while turned on, but heats the unit up.", "0.1"),
+    entry(ApiKind::Method, "play_sound", "handle:play_sound(name)", "fun(self: UnitHandle, name: string)", "Plays an audio prototype at the unit's position.", "0.1"),
+    entry(ApiKind::Method, "drop_item", "handle:drop_item(item, count?)", "(fun(self: UnitHandle, item: string, count?: integer))|nil", "Only with an inventory. Drops items from the inventory, one if no count is given.", "0.1"),
+    entry(ApiKind::Method, "pick_up_items", "handle:pick_up_items()", "(fun(self: UnitHandle))|nil", "Only with an inventory. Picks up items lying around the unit.", "0.1"),
+    entry(ApiKind::Method, "set_label", "handle:set_label(label?)", "(fun(self: UnitHandle, label?: string))|nil", "Only with a label. Sets or clears the label other units of the team find this one by.", "0.1"),
+    entry(ApiKind::Method, "find_unit", "handle:find_unit(label) -> {x, y}?", "(fun(self: UnitHandle, label: string): number[]|nil)|nil", "Only with a team. Position of the unit of the same team with this label.", "0.1"),
+    entry(ApiKind::Method, "unit_ref", "handle:unit_ref(id) -> ref", "fun(self: UnitHandle, id: integer): UnitRef", "Reference to the unit with this id, like the refs in events, that can be kept between runs. Negative ids are an error. ref:is_alive(), ref:position() -> {x, y}? and ref:team() -> team? are looked up on every call, units of other teams out of sight count as not alive and their position and team are nil. ref.id is the id again and refs to the same unit compare equal.", "0.1"),
+    entry(ApiKind::Method, "subscribe", "handle:subscribe(event, filter?)", "(fun(self: UnitHandle, event: string, filter?: table))|nil", "Only with event subscriptions. Queues world events of this kind, only the ones matching every key of the filter.", "0.1"),
+    entry(ApiKind::Method, "unsubscribe", "handle:unsubscribe(event)", "(fun(self: UnitHandle, event: string))|nil", "Only with event subscriptions. Stops queueing this kind of event.", "0.1"),
+    entry(ApiKind::Method, "poll_events", "handle:poll_events() -> {event}", "(fun(self: UnitHandle): table[])|nil", "Only with event subscriptions. Takes every queued event, their unit is a UnitRef.", "0.1"),
+    entry(ApiKind::Method, "random", "handle:random() -> number", "fun(self: UnitHandle): number", "Random number from 0 up to but not including 1. Unlike math.random it comes out the same in every run with the same world seed, every unit gets its own sequence.", "0.1"),
+    entry(ApiKind::Method, "random_int", "handle:random_int(min, max) -> integer", "fun(self: UnitHandle, min: integer, max: integer): integer", "Random integer from min to max, both included, from the same sequence as random.", "0.1"),
+    entry(ApiKind::Method, "hibernate", "handle:hibernate(ticks?)", "fun(self: UnitHandle, ticks?: integer)", "Stops running the program for a number of ticks, or until something wakes the unit up. Programs that run 600 times in a row without doing anything hibernate for 120 ticks on their own. Reading, like polling events or looking units up, doesn't count as doing something.", "0.1"),
+    entry(ApiKind::Field, "id", "handle.id", "integer", "Stable number of the unit, counting up from 1 in spawn order and kept through saves. Events refer to units by it, and units run their programs and move in this order every tick.", "0.1"),
+    entry(ApiKind::Field, "label", "handle.label", "string|nil", "The unit's label, or nil.", "0.1"),
+    entry(ApiKind::Field, "time_since_start", "handle.time_since_start", "number", "Seconds since the unit was spawned.", "0.1"),
+    entry(ApiKind::Field, "global_time", "handle.global_time", "number", "Seconds since the game started.", "0.1"),
+    entry(ApiKind::Field, "tick", "handle.tick", "integer", "Simulation ticks since the game started, the same on every machine.", "0.1"),
+    entry(ApiKind::Field, "delta_ticks", "handle.delta_ticks", "integer", "Simulation ticks since the program last ran. Usually the tick interval, but longer after hibernating, stuns or when the team's scripts are throttled. Divide by tick_rate for seconds.", "0.1"),
+    entry(ApiKind::Field, "tick_rate", "handle.tick_rate", "integer", "Simulation ticks per second.", "0.1"),
+    entry(ApiKind::Field, "gps", "handle.gps -> {position, rotation}", "{position: number[], rotation: number}", "Position in tiles and rotation in degrees clockwise.", "0.1"),
+    entry(ApiKind::Field, "health", "handle.health -> {current, max, armor}?", "{current: number, max: number, armor: number}|nil", "Health, nil without health equipment.", "0.1"),
+    entry(ApiKind::Field, "shield", "handle.shield -> {current, max}?", "{current: number, max: number}|nil", "Shield, nil without one.", "0.1"),
+    entry(ApiKind::Field, "energy", "handle.energy -> {stored, capacity}?", "{stored: number, capacity: number}|nil", "Stored energy, nil without energy equipment.", "0.1"),
+    entry(ApiKind::Field, "heat", "handle.heat -> {current, capacity, overheated}?", "{current: number, capacity: number, overheated: boolean}|nil", "Heat, nil without a heat stat. Overheated units can't use their equipment until they cooled down to half the capacity.", "0.1"),
+    entry(ApiKind::Field, "weather", "handle.weather -> {current, visibility, sheltered}", "{current: string|nil, visibility: number, sheltered: boolean}", "Current weather or nil when clear, how far sensors reach relative to clear weather and whether the unit is inside a shelter.", "0.1"),
+    entry(ApiKind::Field, "status_effects", "handle.status_effects -> {{name, kind, remaining}}", "{name: string, kind: string, remaining: number}[]", "Active status effects, remaining in seconds.", "0.1"),
+    entry(ApiKind::Field, "inventory", "handle.inventory -> {item = count}?", "table<string, integer>|nil", "Carried items, nil without an inventory.", "0.1"),
+    entry(ApiKind::Field, "movement", "handle.movement -> {movement_type, speed, ...}?", "{movement_type: string, speed: number, max_speed: number, max_speed_backwards: number|nil, acceleration: number, braking_acceleration: number, passive_deceleration: number, rotation_speed: number, is_hand_brake_pulled: boolean, blocked: boolean, target_speed: number|nil}|nil", "Movement prototype values, current speed, whether the hand brake is pulled, whether the last move or turn ran into something and the speed set with set_target_speed.", "0.1"),
+    entry(ApiKind::Field, "last_move_result", "handle.last_move_result -> {success, blocked_by, displacement, heading_reached}?", "{success: boolean, blocked_by: UnitRef|nil, displacement: number[], heading_reached: boolean}|nil", "Whether the last tick's move and turn went through, a ref to the unit that blocked them, nil when it was a wall or creature, how far the unit actually moved in tiles and whether it faces the heading set with set_heading. Physics driven units never report a blocker.", "0.1"),
+    entry(ApiKind::Equipment, "turret", "handle.turret", "table|nil", "Aims the turret: aim, angle, is_aimed.", "0.1"),
+    entry(ApiKind::Equipment, "builder", "handle.builder", "table|nil", "Builds structures: build, range, is_building.", "0.1"),
+    entry(ApiKind::Equipment, "repair_tool", "handle.repair_tool", "table|nil", "Repairs and salvages: repair, salvage, stop, range, is_repairing.", "0.1"),
+    entry(ApiKind::Equipment, "circuit", "handle.circuit", "table|nil", "Circuit network signals: read, write, connect, disconnect_all, range, connections. Signals are a table of numbers, booleans and strings, write raises an error naming the first signal that isn't one.", "0.1"),
+    entry(ApiKind::Equipment, "storage", "handle.storage", "table|nil", "Persistent unit storage: read, write, keys, lock, unlock, usage, quota, is_locked, is_tamper_evident. Tamper-evident storages refuse reads and writes once their contents don't match the hash recorded by their own writes, e.g. after a save file was edited.", "0.1"),
+    entry(ApiKind::Equipment, "equipment_grid", "handle.equipment_grid", "table|nil", "Installs equipment items from the inventory into slots: install, uninstall, slots, pending, last_error. Changes are carried out one per tick, failures set last_error and send an equipment_failed event.", "0.1"),
+    entry(ApiKind::Equipment, "team_storage", "handle.team_storage", "table|nil", "Blackboard shared by the team: read, write, keys, usage, quota. All units of a team share a quota of 256 KiB.", "0.1"),
+    entry(ApiKind::Equipment, "crypto", "handle.crypto", "table", "Creates keys for locking storage: new_key.", "0.1"),
+    entry(ApiKind::Equipment, "telemetry", "handle.telemetry", "table", "Metrics for telemetry and the metrics panel: emit, is_collecting.", "0.1")
+];
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false
+        }
+        i += 1;
+    }
+    true
+}
+
+pub const fn is_documented(kind: ApiKind, name: &str) -> bool {
+    let mut i = 0;
+    while i < UNIT_HANDLE_API.len() {
+        let entry = &UNIT_HANDLE_API[i];
+        if entry.kind as u8 == kind as u8 && str_eq(entry.name, name) {
+            return true
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Reports entries of a kind that were documented but never registered
+pub fn check_registered(kind: ApiKind, registered: &[&str]) {
+    for entry in UNIT_HANDLE_API.iter().filter(|entry| entry.kind == kind) {
+        debug_assert!(registered.contains(&entry.name), "{} {} is documented but not registered", kind.as_ref(), entry.name);
+    }
+}
+
+const UNIT_REF_DEFINITION: &str = "
+---@class UnitRef
+---@field id integer
+---@field is_alive fun(self: UnitRef): boolean
+---@field position fun(self: UnitRef): number[]|nil
+---@field team fun(self: UnitRef): integer|nil
+";
+
+/// Definitions for the Lua language server, so editors can complete the handle API
+pub fn lua_definitions() -> String {
+    let mut definitions = format!("---@meta\n-- unit handle API {}\n\n---@class UnitHandle\n", API_VERSION);
+    for entry in UNIT_HANDLE_API {
+        writeln!(definitions, "---@field {} {} {} Since {}.", entry.name, entry.lua_type, entry.description, entry.since).unwrap();
+    }
+    definitions.push_str(UNIT_REF_DEFINITION);
+    definitions.push_str("\n---Called every time the unit's program runs\n---@type fun(handle: UnitHandle)\non_tick = nil\n");
+    definitions
+}
+
+#[cfg(not(feature = "headless"))]
+#[derive(Default)]
+pub struct ApiReference {
+    open: bool,
+    search: String
+}
+
+#[cfg(not(feature = "headless"))]
+pub fn toggle_api_reference(keys: Res<Input<KeyCode>>, mut reference: ResMut<ApiReference>) {
+    if keys.just_pressed(TOGGLE_KEY) {
+        reference.open = !reference.open;
+    }
+}
+
+// the entries themselves stay English, they're also written to `scriplets.d.lua`
+#[cfg(not(feature = "headless"))]
+pub fn show_api_reference(mut egui_context: ResMut<EguiContext>, mut reference: ResMut<ApiReference>, localize: Res<Localize>) {
+    if !reference.open {
+        return
+    }
+    let reference = &mut *reference;
+    egui::Window::new(localize.text("api-title")).open(&mut reference.open).default_width(500.0).show(egui_context.ctx_mut(), |ui| {
+        ui.weak(localize.format("api-version", &[("version", &API_VERSION)]));
+        ui.horizontal(|ui| {
+            ui.label(localize.text("api-search"));
+            ui.text_edit_singleline(&mut reference.search);
+        });
+        let search = reference.search.to_lowercase();
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for kind in [ApiKind::Method, ApiKind::Field, ApiKind::Equipment] {
+                let entries: Vec<&ApiEntry> = UNIT_HANDLE_API.iter()
+                    .filter(|entry| entry.kind == kind)
+                    .filter(|entry| entry.signature.to_lowercase().contains(&search) || entry.description.to_lowercase().contains(&search))
+                    .collect();
+                if entries.is_empty() {
+                    continue
+                }
+                ui.heading(localize.text(&format!("api-kind-{}", kind.as_ref())));
+                for entry in entries {
+                    ui.horizontal(|ui| {
+                        ui.label(egui::RichText::new(entry.signature).monospace().strong());
+                        ui.weak(localize.format("api-since", &[("version", &entry.since)]));
+                    });
+                    ui.label(entry.description);
+                    ui.add_space(4.0);
+                }
+            }
+        });
+    });
+}
@@ -0,0 +1,322 @@
+use std::{collections::HashMap, fs::File, io::Read, path::{Component, Path, PathBuf}, sync::Mutex};
+use bevy::{prelude::*, asset::{AssetIo, AssetIoError, Metadata, FileType}, utils::BoxedFuture};
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+use zip::{ZipArchive, result::ZipError};
+use crate::loading::{ActiveMod, ModList, PROTOTYPES_PATH};
+use crate::settings::ModSettings;
+use crate::logging::ASSETS;
+
+const MODS_DIR: &str = "mods";
+const MANIFEST_NAME: &str = "mod.toml";
+
+/// `mod.toml` at the root of every mod archive or folder
+#[derive(Deserialize)]
+struct ModManifest {
+    name: String,
+    version: Version,
+    #[serde(default)]
+    dependencies: HashMap<String, VersionReq>
+}
+
+/// Where a mod's files are, a zip archive or a folder with the same layout
+enum ModSource {
+    Archive(Mutex<ZipArchive<File>>),
+    Folder(PathBuf)
+}
+
+impl ModSource {
+    fn read(&self, name: &str) -> Option<Vec<u8>> {
+        let mut bytes = Vec::new();
+        match self {
+            Self::Archive(archive) => {
+                archive.lock().unwrap().by_name(name).ok()?.read_to_end(&mut bytes).ok()?;
+            }
+            Self::Folder(folder) => bytes = std::fs::read(folder.join(name)).ok()?
+        }
+        Some(bytes)
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        match self {
+            Self::Archive(archive) => archive.lock().unwrap().file_names().any(|entry| entry == name),
+            Self::Folder(folder) => folder.join(name).is_file()
+        }
+    }
+}
+
+struct ModArchive {
+    path: PathBuf,
+    manifest: ModManifest,
+    source: ModSource
+}
+
+fn open_mod(path: &Path) -> Result<ModArchive, String> {
+    let source = if path.is_dir() {
+        ModSource::Folder(path.to_path_buf())
+    } else {
+        let archive = File::open(path).map_err(ZipError::from).and_then(ZipArchive::new).map_err(|error| error.to_string())?;
+        ModSource::Archive(Mutex::new(archive))
+    };
+    let manifest = source.read(MANIFEST_NAME).ok_or_else(|| format!("there's no {}", MANIFEST_NAME))?;
+    let manifest = std::str::from_utf8(&manifest).map_err(|error| format!("invalid {}: {}", MANIFEST_NAME, error))?;
+    let manifest = toml::from_str(manifest).map_err(|error| format!("invalid {}: {}", MANIFEST_NAME, error))?;
+    Ok(ModArchive { path: path.to_path_buf(), manifest, source })
+}
+
+// zip archives and folders with a manifest, other files in `mods` are left alone
+fn is_mod(path: &Path) -> bool {
+    if path.is_dir() {
+        path.join(MANIFEST_NAME).is_file()
+    } else {
+        path.extension().map_or(false, |extension| extension == "zip")
+    }
+}
+
+// Drops mods with duplicate names or dependencies that aren't there, then orders the rest so every
+// mod comes after its dependencies. Otherwise the order they're given in is kept.
+fn resolve(mut mods: Vec<ModArchive>, settings: &ModSettings, errors: &mut Vec<String>) -> Vec<ModArchive> {
+    let mut seen: HashMap<String, PathBuf> = HashMap::new();
+    mods.retain(|mod_archive| match seen.get(&mod_archive.manifest.name) {
+        Some(first) => {
+            errors.push(format!("{} and {} are both named {}", first.display(), mod_archive.path.display(), mod_archive.manifest.name));
+            false
+        }
+        None => {
+            seen.insert(mod_archive.manifest.name.clone(), mod_archive.path.clone());
+            true
+        }
+    });
+    // dropping a mod can leave others without their dependency, so this repeats until nothing changes
+    loop {
+        let versions: HashMap<String, Version> = mods.iter().map(|mod_archive| (mod_archive.manifest.name.clone(), mod_archive.manifest.version.clone())).collect();
+        let count = mods.len();
+        mods.retain(|mod_archive| {
+            for (dependency, requirement) in &mod_archive.manifest.dependencies {
+                let problem = match versions.get(dependency) {
+                    Some(version) if requirement.matches(version) => continue,
+                    Some(version) => format!("{} {} is loaded", dependency, version),
+                    None if !settings.is_enabled(dependency) => format!("{} is disabled", dependency),
+                    None => format!("{} is missing", dependency)
+                };
+                errors.push(format!("{} needs {} {}, but {}", mod_archive.manifest.name, dependency, requirement, problem));
+                return false
+            }
+            true
+        });
+        if mods.len() == count {
+            break
+        }
+    }
+    let mut sorted: Vec<ModArchive> = Vec::new();
+    while !mods.is_empty() {
+        let ready = mods.iter().position(|mod_archive| mod_archive.manifest.dependencies.keys()
+            .all(|dependency| sorted.iter().any(|sorted| &sorted.manifest.name == dependency)));
+        match ready {
+            Some(index) => sorted.push(mods.remove(index)),
+            None => {
+                let names: Vec<&str> = mods.iter().map(|mod_archive| mod_archive.manifest.name.as_str()).collect();
+                errors.push(format!("{} depend on each other", names.join(", ")));
+                break
+            }
+        }
+    }
+    sorted
+}
+
+/// Reads assets from the mods in the `mods` folder before the ones underneath, so mods are
+/// installed by dropping their archive, or a folder with the same layout, there. Mods load after
+/// their dependencies and otherwise in the load order from the settings, with mods it doesn't list
+/// yet after the others in the order of their file names. Later ones win when several contain the
+/// same file, except for `prototypes.json`. Disabled mods are left out.
+pub struct ArchiveAssetIo {
+    inner: Box<dyn AssetIo>,
+    mods: Vec<ModSource>
+}
+
+impl ArchiveAssetIo {
+    pub fn new(inner: Box<dyn AssetIo>, settings: &ModSettings) -> (Self, ModList) {
+        let mut paths: Vec<PathBuf> = match std::fs::read_dir(MODS_DIR) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| is_mod(path))
+                .collect(),
+            Err(_) => Vec::new()
+        };
+        paths.sort();
+        let mut mod_list = ModList::default();
+        let mut mods: Vec<ModArchive> = paths.iter().filter_map(|path| match open_mod(path) {
+            Ok(mod_archive) => Some(mod_archive),
+            Err(error) => {
+                mod_list.errors.push(format!("{}: {}", path.display(), error));
+                None
+            }
+        }).collect();
+        for mod_archive in &mods {
+            if !mod_list.installed.contains(&mod_archive.manifest.name) {
+                mod_list.installed.push(mod_archive.manifest.name.clone());
+            }
+        }
+        mods.retain(|mod_archive| {
+            let enabled = settings.is_enabled(&mod_archive.manifest.name);
+            if !enabled {
+                info!(target: ASSETS, "Mod {} is disabled", mod_archive.manifest.name);
+            }
+            enabled
+        });
+        let order = settings.ordered(&mod_list.installed);
+        mods.sort_by_key(|mod_archive| order.iter().position(|name| *name == mod_archive.manifest.name));
+        let mods = resolve(mods, settings, &mut mod_list.errors);
+        for error in &mod_list.errors {
+            error!(target: ASSETS, "Can't load mod, {}", error);
+        }
+        let mut sources = Vec::new();
+        for mod_archive in mods {
+            info!(target: ASSETS, "Using mod {} {} from {}", mod_archive.manifest.name, mod_archive.manifest.version, mod_archive.path.display());
+            let prototypes = mod_archive.source.read(PROTOTYPES_PATH);
+            mod_list.active.push(ActiveMod { name: mod_archive.manifest.name, version: mod_archive.manifest.version.to_string(), prototypes });
+            sources.push(mod_archive.source);
+        }
+        (Self { inner, mods: sources }, mod_list)
+    }
+
+    fn read(&self, path: &Path) -> Option<Vec<u8>> {
+        let name = entry_name(path)?;
+        self.mods.iter().rev().find_map(|source| source.read(&name))
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        let name = match entry_name(path) {
+            Some(name) => name,
+            None => return false
+        };
+        self.mods.iter().any(|source| source.contains(&name))
+    }
+}
+
+// Entries are named with forward slashes on every platform, paths leaving the mod aren't read.
+// Mods' prototypes are merged into the base ones while loading instead of replacing them, see
+// `ActiveMod::prototypes`.
+fn entry_name(path: &Path) -> Option<String> {
+    if path.components().any(|component| !matches!(component, Component::Normal(_))) || path == Path::new(PROTOTYPES_PATH) {
+        return None
+    }
+    Some(path.to_str()?.replace('\\', "/"))
+}
+
+impl AssetIo for ArchiveAssetIo {
+    fn load_path<'a>(&'a self, path: &'a Path) -> BoxedFuture<'a, Result<Vec<u8>, AssetIoError>> {
+        Box::pin(async move {
+            match self.read(path) {
+                Some(bytes) => Ok(bytes),
+                None => self.inner.load_path(path).await
+            }
+        })
+    }
+
+    // directories inside mods aren't listed, assets are only ever loaded by path
+    fn read_directory(&self, path: &Path) -> Result<Box<dyn Iterator<Item = PathBuf>>, AssetIoError> {
+        self.inner.read_directory(path)
+    }
+
+    fn get_metadata(&self, path: &Path) -> Result<Metadata, AssetIoError> {
+        if self.contains(path) {
+            return Ok(Metadata::new(FileType::File))
+        }
+        self.inner.get_metadata(path)
+    }
+
+    // mods aren't watched, changing one needs a restart
+    fn watch_path_for_changes(&self, path: &Path) -> Result<(), AssetIoError> {
+        self.inner.watch_path_for_changes(path)
+    }
+
+    fn watch_for_changes(&self) -> Result<(), AssetIoError> {
+        self.inner.watch_for_changes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // folders that don't exist, resolving only looks at the manifests
+    fn mod_folder(name: &str, version: &str, dependencies: &[(&str, &str)]) -> ModArchive {
+        let manifest = ModManifest {
+            name: name.to_string(),
+            version: Version::parse(version).unwrap(),
+            dependencies: dependencies.iter().map(|(name, requirement)| (name.to_string(), VersionReq::parse(requirement).unwrap())).collect()
+        };
+        let path = PathBuf::from(MODS_DIR).join(name);
+        ModArchive { path: path.clone(), manifest, source: ModSource::Folder(path) }
+    }
+
+    fn names(mods: &[ModArchive]) -> Vec<&str> {
+        mods.iter().map(|mod_archive| mod_archive.manifest.name.as_str()).collect()
+    }
+
+    #[test]
+    fn drops_mods_with_missing_dependencies() {
+        let mut errors = Vec::new();
+        let mods = vec![mod_folder("tanks", "1.0.0", &[("weapons", "^1")]), mod_folder("drones", "1.0.0", &[]), mod_folder("artillery", "1.0.0", &[("tanks", "^1")])];
+        let resolved = resolve(mods, &ModSettings::default(), &mut errors);
+        // artillery goes too, its dependency was dropped
+        assert_eq!(names(&resolved), vec!["drones"]);
+        assert_eq!(errors, vec!["tanks needs weapons ^1, but weapons is missing", "artillery needs tanks ^1, but tanks is missing"]);
+    }
+
+    #[test]
+    fn tells_disabled_dependencies_apart() {
+        let mut errors = Vec::new();
+        let mut settings = ModSettings::default();
+        settings.disabled.push("weapons".to_string());
+        resolve(vec![mod_folder("tanks", "1.0.0", &[("weapons", "^1")])], &settings, &mut errors);
+        assert_eq!(errors, vec!["tanks needs weapons ^1, but weapons is disabled"]);
+    }
+
+    #[test]
+    fn drops_mods_with_mismatched_versions() {
+        let mut errors = Vec::new();
+        let mods = vec![mod_folder("weapons", "2.1.0", &[]), mod_folder("tanks", "1.0.0", &[("weapons", "^1.2")])];
+        let resolved = resolve(mods, &ModSettings::default(), &mut errors);
+        assert_eq!(names(&resolved), vec!["weapons"]);
+        assert_eq!(errors, vec!["tanks needs weapons ^1.2, but weapons 2.1.0 is loaded"]);
+    }
+
+    #[test]
+    fn orders_mods_after_their_dependencies() {
+        let mut errors = Vec::new();
+        let mods = vec![
+            mod_folder("artillery", "1.0.0", &[("tanks", "^1"), ("weapons", "*")]),
+            mod_folder("tanks", "1.0.0", &[("weapons", "^1")]),
+            mod_folder("drones", "1.0.0", &[]),
+            mod_folder("weapons", "1.0.0", &[])
+        ];
+        let resolved = resolve(mods, &ModSettings::default(), &mut errors);
+        // the first one whose dependencies are in place goes next every time
+        assert_eq!(names(&resolved), vec!["drones", "weapons", "tanks", "artillery"]);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn drops_duplicate_names() {
+        let mut errors = Vec::new();
+        let resolved = resolve(vec![mod_folder("tanks", "1.0.0", &[]), mod_folder("tanks", "2.0.0", &[])], &ModSettings::default(), &mut errors);
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].manifest.version, Version::new(1, 0, 0));
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn reports_cycles() {
+        let mut errors = Vec::new();
+        let mods = vec![
+            mod_folder("drones", "1.0.0", &[]),
+            mod_folder("tanks", "1.0.0", &[("weapons", "^1")]),
+            mod_folder("weapons", "1.0.0", &[("tanks", "^1")])
+        ];
+        let resolved = resolve(mods, &ModSettings::default(), &mut errors);
+        assert_eq!(names(&resolved), vec!["drones"]);
+        assert_eq!(errors, vec!["tanks, weapons depend on each other"]);
+    }
+}
@@ -0,0 +1,71 @@
+use serde::Deserialize;
+use super::{Prototype, Prototypes};
+#[cfg(not(feature = "headless"))]
+use std::collections::HashMap;
+#[cfg(not(feature = "headless"))]
+use bevy::prelude::*;
+#[cfg(not(feature = "headless"))]
+use crate::logging::ASSETS;
+
+#[cfg(not(feature = "headless"))]
+const HEARING_RANGE: f32 = 30.0; // tiles
+
+#[derive(Prototype, Deserialize)]
+#[prot_category(audio)]
+pub struct AudioPrototype {
+    name: String,
+    path: String,
+    #[cfg(not(feature = "headless"))]
+    #[serde(default = "default_volume")]
+    volume: f32
+}
+
+#[cfg(not(feature = "headless"))]
+fn default_volume() -> f32 {
+    1.0
+}
+
+#[cfg(not(feature = "headless"))]
+#[derive(Default)]
+pub struct SoundHandles(HashMap<String, Handle<AudioSource>>);
+
+impl AudioPrototype {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+impl SoundHandles {
+    pub fn load(assets: &AssetServer, prototypes: &Prototypes) -> Self {
+        Self(prototypes.audio.values().map(|audio| (audio.name.clone(), assets.load(audio.path.as_str()))).collect())
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+pub struct SoundEvent {
+    pub name: String,
+    pub position: Vec2
+}
+
+// bevy_audio has no spatial audio, so sounds are just attenuated by the distance to the camera
+#[cfg(not(feature = "headless"))]
+pub fn play_sounds(
+    mut sound_events: EventReader<SoundEvent>,
+    audio: Res<Audio>,
+    sounds: Res<SoundHandles>,
+    prototypes: Res<Prototypes>,
+    camera: Query<&Transform, With<Camera2d>>)
+{
+    let listener = camera.single().translation.truncate();
+    for event in sound_events.iter() {
+        if let (Some(sound), Some(prototype)) = (sounds.0.get(&event.name), AudioPrototype::from_pt(&prototypes, &event.name)) {
+            let attenuation = (1.0 - event.position.distance(listener) / HEARING_RANGE).clamp(0.0, 1.0);
+            if attenuation > 0.0 {
+                audio.play_with_settings(sound.clone(), PlaybackSettings::ONCE.with_volume(prototype.volume * attenuation));
+            }
+        } else {
+            warn!(target: ASSETS, "Unknown sound {}", event.name);
+        }
+    }
+}
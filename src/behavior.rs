@@ -0,0 +1,97 @@
+use bevy::prelude::*;
+#[cfg(not(target_arch = "wasm32"))]
+use mlua::prelude::*;
+use serde::Deserialize;
+use super::{Prototype, ComponentPrototype, Prototypes};
+
+/// Behavior tree evaluated from the root every time the unit's program runs, instead of `on_tick`.
+/// Leaves are global Lua functions called with the unit handle.
+#[derive(Component, Prototype, ComponentPrototype, Deserialize, Clone)]
+#[prot_category(behavior_tree)]
+pub struct BehaviorTree {
+    name: String,
+    root: BehaviorNode
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum BehaviorNode {
+    // runs children until one doesn't fail
+    Selector { children: Vec<BehaviorNode> },
+    // runs children until one doesn't succeed
+    Sequence { children: Vec<BehaviorNode> },
+    Inverter { child: Box<BehaviorNode> },
+    Leaf { function: String }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BehaviorStatus {
+    Success,
+    Failure,
+    Running
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'lua> FromLua<'lua> for BehaviorStatus {
+    fn from_lua(lua_value: LuaValue<'lua>, _lua: &'lua Lua) -> LuaResult<Self> {
+        match &lua_value {
+            LuaValue::Boolean(true) => Ok(Self::Success),
+            LuaValue::Boolean(false) => Ok(Self::Failure),
+            LuaValue::String(s) => match s.to_str()? {
+                "success" => Ok(Self::Success),
+                "failure" => Ok(Self::Failure),
+                "running" => Ok(Self::Running),
+                other => Err(LuaError::RuntimeError(format!("unknown behavior status {}", other)))
+            },
+            _ => Err(LuaError::FromLuaConversionError {
+                from: lua_value.type_name(),
+                to: "BehaviorStatus",
+                message: Some("leaves must return a boolean, \"success\", \"failure\" or \"running\"".into())
+            })
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl BehaviorNode {
+    fn run<'lua>(&self, lua: &'lua Lua, handle: &LuaAnyUserData<'lua>) -> LuaResult<BehaviorStatus> {
+        match self {
+            Self::Selector { children } => {
+                for child in children {
+                    let status = child.run(lua, handle)?;
+                    if status != BehaviorStatus::Failure {
+                        return Ok(status)
+                    }
+                }
+                Ok(BehaviorStatus::Failure)
+            }
+            Self::Sequence { children } => {
+                for child in children {
+                    let status = child.run(lua, handle)?;
+                    if status != BehaviorStatus::Success {
+                        return Ok(status)
+                    }
+                }
+                Ok(BehaviorStatus::Success)
+            }
+            Self::Inverter { child } => Ok(match child.run(lua, handle)? {
+                BehaviorStatus::Success => BehaviorStatus::Failure,
+                BehaviorStatus::Failure => BehaviorStatus::Success,
+                BehaviorStatus::Running => BehaviorStatus::Running
+            }),
+            Self::Leaf { function } => {
+                let leaf = lua.globals().get::<_, Option<LuaFunction>>(function.as_str())?
+                    .ok_or_else(|| LuaError::RuntimeError(format!("behavior tree leaf {} is not defined", function)))?;
+                leaf.call(handle.clone())
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl BehaviorTree {
+    pub fn run<'lua>(&self, lua: &'lua Lua, handle: &LuaAnyUserData<'lua>) -> LuaResult<BehaviorStatus> {
+        self.root.run(lua, handle)
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+use serde::Deserialize;
+use super::{Prototype, Prototypes, Unit, TICK_RATE};
+use crate::{inventory::GroundItem, effects::ApplyEffectEvent};
+
+// how fast items are pulled toward the middle of the belt, fraction of the offset per tick
+const CENTERING: f32 = 0.1;
+
+#[derive(Prototype, Deserialize)]
+#[prot_category(belt)]
+pub struct BeltPrototype {
+    name: String,
+    speed: f32, // tiles / second
+    // status effect applied to units standing on the belt
+    #[serde(default)]
+    unit_effect: Option<String>
+}
+
+/// Conveyor belt tile, moving things in the direction the structure faces
+#[derive(Component)]
+pub struct Belt {
+    pub prototype: String
+}
+
+struct BeltTile<'a> {
+    direction: Vec2,
+    center: Vec2,
+    prototype: &'a BeltPrototype
+}
+
+fn belt_tiles<'a>(belts: &Query<(&Belt, &Transform)>, prototypes: &'a Prototypes) -> HashMap<IVec2, BeltTile<'a>> {
+    belts.iter().filter_map(|(belt, transform)| {
+        let prototype = BeltPrototype::from_pt(prototypes, &belt.prototype)?;
+        let center = transform.translation.truncate();
+        let tile = BeltTile { direction: transform.right().truncate(), center, prototype };
+        Some((center.round().as_ivec2(), tile))
+    }).collect()
+}
+
+// Items follow the belt under them. Turns and junctions need no special casing: once an item
+// crosses onto the next tile it follows that tile's direction and gets pulled onto its centerline.
+pub fn move_belt_items(
+    belts: Query<(&Belt, &Transform)>,
+    mut items: Query<&mut Transform, (With<GroundItem>, Without<Belt>)>,
+    prototypes: Res<Prototypes>)
+{
+    let tiles = belt_tiles(&belts, &prototypes);
+    if tiles.is_empty() {
+        return
+    }
+    for mut transform in items.iter_mut() {
+        let position = transform.translation.truncate();
+        if let Some(tile) = tiles.get(&position.round().as_ivec2()) {
+            let offset = position - tile.center;
+            let lateral = offset - tile.direction * offset.dot(tile.direction);
+            let delta = tile.direction * tile.prototype.speed / TICK_RATE as f32 - lateral * CENTERING;
+            transform.translation += delta.extend(0.0);
+        }
+    }
+}
+
+pub fn apply_belt_unit_effects(
+    belts: Query<(&Belt, &Transform)>,
+    units: Query<(Entity, &Transform), With<Unit>>,
+    prototypes: Res<Prototypes>,
+    mut effect_events: EventWriter<ApplyEffectEvent>)
+{
+    let tiles = belt_tiles(&belts, &prototypes);
+    if tiles.is_empty() {
+        return
+    }
+    for (entity, transform) in units.iter() {
+        let tile = tiles.get(&transform.translation.truncate().round().as_ivec2());
+        if let Some(effect) = tile.and_then(|tile| tile.prototype.unit_effect.as_ref()) {
+            effect_events.send(ApplyEffectEvent { target: entity, effect: effect.clone() });
+        }
+    }
+}
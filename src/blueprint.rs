@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use bevy::{prelude::*, ecs::system::SystemParam};
+#[cfg(not(feature = "headless"))]
+use bevy_egui::EguiContext;
+use thiserror::Error;
+use super::{Prototype, Prototypes, MovementStats, Unit};
+#[cfg(not(feature = "headless"))]
+use super::UnitSpawner;
+use crate::program::UnitProgram;
+#[cfg(not(feature = "headless"))]
+use crate::plot::{SelectedUnit, cursor_world_position};
+use crate::{turret::Turret, equipment::EquipmentGrid, health::Health, energy::Energy, heat::Heat, inventory::Inventory, construction::Builder, repair::RepairTool, circuit::CircuitConnector, storage::Storage, behavior::BehaviorTree, watchdog::Watchdog, collider::ColliderName, visual::VisualName};
+use crate::data_value::{DataValue, DataValueHashEq};
+use scriplets_core::program::MAX_TICK_INTERVAL;
+use crate::schema::{Schema, SchemaError};
+use crate::migration::{Migration, MigrationError, migrate};
+use crate::logging::ASSETS;
+
+#[cfg(not(feature = "headless"))]
+const CLONE_KEY: KeyCode = KeyCode::C;
+// clones spawned at once while shift is held
+#[cfg(not(feature = "headless"))]
+const SWARM_SIZE: usize = 10;
+#[cfg(not(feature = "headless"))]
+const CLONE_SPACING: f32 = 1.0; // tiles
+/// Format version of blueprint payloads, bump it and add a migration when changing their layout
+pub const BLUEPRINT_FORMAT: u32 = 1;
+const FORMAT_VERSION_KEY: &str = "format_version";
+// upgrades from every older format version, see `migrate`
+const BLUEPRINT_MIGRATIONS: &[Migration<DataValue>] = &[];
+
+// the program and its watchdog are grouped in a nested tuple, queries only take up to 15 elements
+type BlueprintSourceQuery<'w, 's> = Query<'w, 's, ((&'static UnitProgram, Option<&'static Watchdog>), &'static MovementStats, Option<&'static Children>, Option<&'static Health>, Option<&'static Energy>, Option<&'static Heat>, Option<&'static Inventory>, Option<&'static Builder>, Option<&'static RepairTool>, Option<&'static CircuitConnector>, Option<&'static Storage>, Option<&'static EquipmentGrid>, Option<&'static BehaviorTree>, Option<&'static ColliderName>, Option<&'static VisualName>), With<Unit>>;
+
+/// Everything a unit's blueprint is taken from
+#[derive(SystemParam)]
+pub struct BlueprintSources<'w, 's> {
+    units: BlueprintSourceQuery<'w, 's>,
+    turrets: Query<'w, 's, &'static Turret>
+}
+
+impl BlueprintSources<'_, '_> {
+    pub fn storage(&self, unit: Entity) -> Option<&Storage> {
+        self.units.get(unit).ok().and_then(|(.., storage, _, _, _, _)| storage)
+    }
+}
+
+/// Unit setup that can be saved under a name and spawned again. Equipment is stored as prototype
+/// names, so pasted units start with fresh components.
+#[derive(Clone, Default)]
+pub struct Blueprint {
+    pub program: String,
+    pub tick_interval: u64,
+    pub movement: String,
+    pub turret: Option<String>,
+    pub health: Option<String>,
+    pub energy: Option<String>,
+    pub heat: Option<String>,
+    pub inventory: Option<String>,
+    pub builder: Option<String>,
+    pub repair_tool: Option<String>,
+    pub circuit_connector: Option<String>,
+    pub storage: Option<String>,
+    pub equipment_grid: Option<String>,
+    pub behavior_tree: Option<String>,
+    pub watchdog: Option<String>,
+    pub collider: Option<String>,
+    pub visual: Option<String>
+}
+
+impl Blueprint {
+    fn equipment(&self) -> [(&'static str, &Option<String>); 14] {
+        [
+            ("turret", &self.turret),
+            ("health", &self.health),
+            ("energy", &self.energy),
+            ("heat", &self.heat),
+            ("inventory", &self.inventory),
+            ("builder", &self.builder),
+            ("repair_tool", &self.repair_tool),
+            ("circuit_connector", &self.circuit_connector),
+            ("storage", &self.storage),
+            ("equipment_grid", &self.equipment_grid),
+            ("behavior_tree", &self.behavior_tree),
+            ("watchdog", &self.watchdog),
+            ("collider", &self.collider),
+            ("visual", &self.visual)
+        ]
+    }
+
+    fn equipment_mut(&mut self) -> [(&'static str, &mut Option<String>); 14] {
+        [
+            ("turret", &mut self.turret),
+            ("health", &mut self.health),
+            ("energy", &mut self.energy),
+            ("heat", &mut self.heat),
+            ("inventory", &mut self.inventory),
+            ("builder", &mut self.builder),
+            ("repair_tool", &mut self.repair_tool),
+            ("circuit_connector", &mut self.circuit_connector),
+            ("storage", &mut self.storage),
+            ("equipment_grid", &mut self.equipment_grid),
+            ("behavior_tree", &mut self.behavior_tree),
+            ("watchdog", &mut self.watchdog),
+            ("collider", &mut self.collider),
+            ("visual", &mut self.visual)
+        ]
+    }
+
+    fn schema() -> Schema {
+        let mut fields = vec![
+            ("program", Schema::string().optional()),
+            ("tick_interval", Schema::Integer { min: Some(1), max: Some(MAX_TICK_INTERVAL as i64) }.optional()),
+            ("movement", Schema::string())
+        ];
+        fields.extend(Self::default().equipment().map(|(name, _)| (name, Schema::string().optional())));
+        Schema::table(fields)
+    }
+
+    /// Unit built from the prototypes called `name`: the movement prototype, which has to exist, and
+    /// every equipment that has a prototype of that name. Behavior trees are left out, they would
+    /// replace the program.
+    pub fn from_prototypes(prototypes: &Prototypes, name: &str) -> Option<Self> {
+        if !prototypes.contains("movement", name) {
+            return None
+        }
+        let mut blueprint = Blueprint { movement: name.to_string(), tick_interval: 1, ..default() };
+        for (category, equipment) in blueprint.equipment_mut() {
+            if category != "behavior_tree" && prototypes.contains(category, name) {
+                *equipment = Some(name.to_string());
+            }
+        }
+        Some(blueprint)
+    }
+
+    /// Blueprint of a unit as it is now, `None` for entities that aren't units
+    pub fn of_unit(unit: Entity, sources: &BlueprintSources) -> Option<Self> {
+        let ((program, watchdog), movement, children, health, energy, heat, inventory, builder, repair_tool, connector, storage, grid, behavior_tree, collider, visual) = sources.units.get(unit).ok()?;
+        let turret = children.and_then(|children| children.iter().find_map(|child| sources.turrets.get(*child).ok()));
+        Some(Blueprint {
+            program: String::from_utf8_lossy(&program.program).into_owned(),
+            tick_interval: program.tick_interval(),
+            movement: movement.name().to_string(),
+            turret: turret.map(|turret| turret.name().to_string()),
+            health: health.map(|health| health.name().to_string()),
+            energy: energy.map(|energy| energy.name().to_string()),
+            heat: heat.map(|heat| heat.name().to_string()),
+            inventory: inventory.map(|inventory| inventory.name().to_string()),
+            builder: builder.map(|builder| builder.name().to_string()),
+            repair_tool: repair_tool.map(|repair_tool| repair_tool.name().to_string()),
+            circuit_connector: connector.map(|connector| connector.name().to_string()),
+            storage: storage.map(|storage| storage.name().to_string()),
+            equipment_grid: grid.map(|grid| grid.name().to_string()),
+            behavior_tree: behavior_tree.map(|behavior_tree| behavior_tree.name().to_string()),
+            watchdog: watchdog.map(|watchdog| watchdog.name().to_string()),
+            collider: collider.map(|collider| collider.0.clone()),
+            visual: visual.map(|visual| visual.0.clone())
+        })
+    }
+
+    pub fn export(&self) -> String {
+        serde_json::to_string(&DataValue::from(self)).unwrap()
+    }
+
+    pub fn import(string: &str) -> Result<Self, BlueprintError> {
+        serde_json::from_str::<DataValue>(string)?.try_into()
+    }
+}
+
+fn key(name: &str) -> DataValueHashEq {
+    DataValueHashEq::String(name.to_string())
+}
+
+impl From<&Blueprint> for DataValue {
+    fn from(blueprint: &Blueprint) -> Self {
+        let mut table = HashMap::new();
+        table.insert(key(FORMAT_VERSION_KEY), DataValue::Integer(BLUEPRINT_FORMAT as i64));
+        table.insert(key("program"), DataValue::String(blueprint.program.clone()));
+        table.insert(key("tick_interval"), DataValue::Integer(blueprint.tick_interval as i64));
+        table.insert(key("movement"), DataValue::String(blueprint.movement.clone()));
+        for (name, prototype) in blueprint.equipment() {
+            if let Some(prototype) = prototype {
+                table.insert(key(name), DataValue::String(prototype.clone()));
+            }
+        }
+        DataValue::Table(table)
+    }
+}
+
+impl TryFrom<DataValue> for Blueprint {
+    type Error = BlueprintError;
+
+    // payloads without a format version are from before it was added, which was the first format
+    fn try_from(mut value: DataValue) -> Result<Self, Self::Error> {
+        let version = match &mut value {
+            DataValue::Table(table) => match table.remove(&key(FORMAT_VERSION_KEY)) {
+                Some(DataValue::Integer(version)) => u32::try_from(version).ok().filter(|version| *version > 0).ok_or(BlueprintError::Version)?,
+                Some(_) => return Err(BlueprintError::Version),
+                None => 1
+            },
+            _ => 1
+        };
+        migrate(&mut value, version, BLUEPRINT_FORMAT, BLUEPRINT_MIGRATIONS)?;
+        value.validate(&Blueprint::schema())?;
+        let mut table = match value {
+            DataValue::Table(table) => table,
+            _ => HashMap::new()
+        };
+        let tick_interval = match table.remove(&key("tick_interval")) {
+            Some(DataValue::Integer(tick_interval)) => tick_interval as u64,
+            _ => 1
+        };
+        let mut take_string = |name: &str| match table.remove(&key(name)) {
+            Some(DataValue::String(string)) => Some(string),
+            _ => None
+        };
+        let mut blueprint = Blueprint {
+            program: take_string("program").unwrap_or_default(),
+            tick_interval,
+            movement: take_string("movement").unwrap_or_default(),
+            ..default()
+        };
+        for (name, prototype) in blueprint.equipment_mut() {
+            *prototype = take_string(name);
+        }
+        Ok(blueprint)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum BlueprintError {
+    #[error("invalid blueprint, {0}")]
+    Invalid(#[from] SchemaError),
+    #[error("blueprint string is malformed: {0}")]
+    Malformed(#[from] serde_json::Error),
+    #[error("blueprint format_version has to be a positive integer")]
+    Version,
+    #[error("can't upgrade blueprint, {0}")]
+    Migration(#[from] MigrationError)
+}
+
+/// Saved blueprints by name
+#[derive(Default)]
+pub struct Blueprints(pub HashMap<String, DataValue>);
+
+/// Units waiting to be spawned by `spawn_queued_units`
+#[derive(Default)]
+pub struct UnitSpawnQueue(pub Vec<(Blueprint, Vec2)>);
+
+pub enum BlueprintEvent {
+    // given in the console, which a headless build has none of
+    #[cfg(not(feature = "headless"))]
+    Copy { unit: Entity, name: String },
+    #[cfg(not(feature = "headless"))]
+    Paste { name: String, position: Vec2 },
+    // pastes with another program, for scenario scripts and console commands setting up units
+    Spawn { name: String, position: Vec2, program: Option<String> },
+    #[cfg(not(feature = "headless"))]
+    Export { name: String },
+    #[cfg(not(feature = "headless"))]
+    Import { name: String, string: String }
+}
+
+/// What came of a `BlueprintEvent`, for the console to show
+pub enum BlueprintOutcome {
+    #[cfg(not(feature = "headless"))]
+    Copied { name: String },
+    #[cfg(not(feature = "headless"))]
+    Exported { name: String, string: String },
+    #[cfg(not(feature = "headless"))]
+    Imported { name: String },
+    Failed { name: String, error: String },
+    Missing { name: String }
+}
+
+/// Blueprint events and what came of them, for the console
+#[cfg(not(feature = "headless"))]
+#[derive(SystemParam)]
+pub struct BlueprintRequests<'w, 's> {
+    pub events: EventWriter<'w, 's, BlueprintEvent>,
+    pub outcomes: EventReader<'w, 's, BlueprintOutcome>
+}
+
+pub fn handle_blueprint_events(
+    mut blueprint_events: EventReader<BlueprintEvent>,
+    mut outcomes: EventWriter<BlueprintOutcome>,
+    // only the console copies and imports blueprints
+    #[cfg(not(feature = "headless"))] mut blueprints: ResMut<Blueprints>,
+    #[cfg(feature = "headless")] blueprints: Res<Blueprints>,
+    mut spawn_queue: ResMut<UnitSpawnQueue>,
+    prototypes: Res<Prototypes>,
+    #[cfg(not(feature = "headless"))] sources: BlueprintSources)
+{
+    for event in blueprint_events.iter() {
+        let outcome = match event {
+            #[cfg(not(feature = "headless"))]
+            BlueprintEvent::Copy { unit, name } => match Blueprint::of_unit(*unit, &sources) {
+                Some(blueprint) => {
+                    blueprints.0.insert(name.clone(), DataValue::from(&blueprint));
+                    BlueprintOutcome::Copied { name: name.clone() }
+                }
+                None => BlueprintOutcome::Failed { name: name.clone(), error: "only units can be copied".to_string() }
+            },
+            #[cfg(not(feature = "headless"))]
+            BlueprintEvent::Paste { name, position } => match blueprints.0.get(name).cloned().map(Blueprint::try_from) {
+                Some(Ok(blueprint)) => {
+                    spawn_queue.0.push((blueprint, *position));
+                    continue
+                }
+                Some(Err(error)) => BlueprintOutcome::Failed { name: name.clone(), error: error.to_string() },
+                None => BlueprintOutcome::Missing { name: name.clone() }
+            },
+            // saved blueprints come first, then units built from the prototypes of that name
+            BlueprintEvent::Spawn { name, position, program } => match blueprints.0.get(name).cloned().map(Blueprint::try_from).or_else(|| Blueprint::from_prototypes(&prototypes, name).map(Ok)) {
+                Some(Ok(mut blueprint)) => {
+                    if let Some(program) = program {
+                        blueprint.program = program.clone();
+                    }
+                    spawn_queue.0.push((blueprint, *position));
+                    continue
+                }
+                Some(Err(error)) => BlueprintOutcome::Failed { name: name.clone(), error: error.to_string() },
+                None => BlueprintOutcome::Missing { name: name.clone() }
+            },
+            #[cfg(not(feature = "headless"))]
+            BlueprintEvent::Export { name } => match blueprints.0.get(name).cloned().map(Blueprint::try_from) {
+                Some(Ok(blueprint)) => BlueprintOutcome::Exported { name: name.clone(), string: blueprint.export() },
+                Some(Err(error)) => BlueprintOutcome::Failed { name: name.clone(), error: error.to_string() },
+                None => BlueprintOutcome::Missing { name: name.clone() }
+            },
+            #[cfg(not(feature = "headless"))]
+            BlueprintEvent::Import { name, string } => match Blueprint::import(string) {
+                Ok(blueprint) => {
+                    blueprints.0.insert(name.clone(), DataValue::from(&blueprint));
+                    BlueprintOutcome::Imported { name: name.clone() }
+                }
+                Err(error) => BlueprintOutcome::Failed { name: name.clone(), error: error.to_string() }
+            }
+        };
+        match &outcome {
+            BlueprintOutcome::Failed { name, error } => warn!(target: ASSETS, "Blueprint {}: {}", name, error),
+            BlueprintOutcome::Missing { name } => warn!(target: ASSETS, "No blueprint named {}", name),
+            #[cfg(not(feature = "headless"))]
+            _ => ()
+        }
+        outcomes.send(outcome);
+    }
+}
+
+/// C clones the selected unit at the cursor, shift+C spawns a swarm of clones. They get the unit's
+/// program and a copy of its black box, which makes stress-testing scripts easy.
+#[cfg(not(feature = "headless"))]
+pub fn clone_selected_unit(
+    mut spawner: UnitSpawner,
+    mut egui_context: ResMut<EguiContext>,
+    keys: Res<Input<KeyCode>>,
+    windows: Res<Windows>,
+    camera: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    selected: Res<SelectedUnit>,
+    sources: BlueprintSources)
+{
+    if !keys.just_pressed(CLONE_KEY) || egui_context.ctx_mut().wants_keyboard_input() {
+        return
+    }
+    let unit = match selected.0 {
+        Some(unit) => unit,
+        None => return
+    };
+    let (blueprint, position) = match Blueprint::of_unit(unit, &sources).zip(cursor_world_position(&windows, &camera)) {
+        Some(clone) => clone,
+        None => return
+    };
+    let count = if keys.any_pressed([KeyCode::LShift, KeyCode::RShift]) { SWARM_SIZE } else { 1 };
+    // clones are laid out in a square grid centered on the cursor
+    let columns = (count as f32).sqrt().ceil() as usize;
+    let center = Vec2::splat((columns - 1) as f32 / 2.0);
+    for i in 0..count {
+        let offset = (Vec2::new((i % columns) as f32, (i / columns) as f32) - center) * CLONE_SPACING;
+        let clone = match spawner.spawn(&blueprint, position + offset) {
+            Some(clone) => clone,
+            None => return
+        };
+        if let Some(storage) = sources.storage(unit) {
+            spawner.commands.entity(clone).insert(storage.clone());
+        }
+    }
+    info!(target: ASSETS, "Cloned unit {:?} {} times", unit, count);
+}
@@ -0,0 +1,101 @@
+use bevy::prelude::*;
+use bevy_egui::{EguiContext, egui};
+use serde_json::Value;
+use super::{Prototype, Prototypes, UnitSpawner};
+use crate::{blueprint::{Blueprint, UnitSpawnQueue}, creature::CreaturePrototype, locale::Localize};
+
+const TOGGLE_KEY: KeyCode = KeyCode::F4;
+
+/// Lists every loaded prototype with its fields as they were read from `prototypes.json`, opened
+/// with F4. Movement and creature prototypes can be spawned at the center of the screen.
+#[derive(Default)]
+pub struct PrototypeBrowser {
+    open: bool,
+    search: String
+}
+
+enum SpawnRequest {
+    Unit(String),
+    Creature(String)
+}
+
+pub fn toggle_prototype_browser(keys: Res<Input<KeyCode>>, mut browser: ResMut<PrototypeBrowser>) {
+    if keys.just_pressed(TOGGLE_KEY) {
+        browser.open = !browser.open;
+    }
+}
+
+// strings are shown without quotes, everything else as compact JSON
+fn field_text(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        value => value.to_string()
+    }
+}
+
+pub fn show_prototype_browser(
+    mut spawner: UnitSpawner,
+    mut egui_context: ResMut<EguiContext>,
+    mut browser: ResMut<PrototypeBrowser>,
+    localize: Res<Localize>,
+    mut spawn_queue: ResMut<UnitSpawnQueue>,
+    camera: Query<&Transform, With<Camera2d>>)
+{
+    if !browser.open {
+        return
+    }
+    let browser = &mut *browser;
+    let mut spawn = None;
+    egui::Window::new(localize.text("browser-title")).open(&mut browser.open).default_width(500.0).show(egui_context.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.label(localize.text("browser-search"));
+            ui.text_edit_singleline(&mut browser.search);
+        });
+        let search = browser.search.to_lowercase();
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for category in Prototypes::CATEGORIES {
+                let mut entries: Vec<(&str, &Value)> = spawner.prototypes.source(category).iter()
+                    .map(|prototype| (prototype.get("name").and_then(Value::as_str).unwrap_or_default(), prototype))
+                    .filter(|(name, _)| name.to_lowercase().contains(&search))
+                    .collect();
+                if entries.is_empty() {
+                    continue
+                }
+                entries.sort_unstable_by_key(|(name, _)| *name);
+                egui::CollapsingHeader::new(format!("{} ({})", category, entries.len())).id_source(category).show(ui, |ui| {
+                    for (name, prototype) in entries {
+                        egui::CollapsingHeader::new(name).id_source((category, name)).show(ui, |ui| {
+                            let request = match *category {
+                                "movement" => Some(SpawnRequest::Unit(name.to_string())),
+                                "creature" => Some(SpawnRequest::Creature(name.to_string())),
+                                _ => None
+                            };
+                            if let Some(request) = request {
+                                if ui.button(localize.text("browser-spawn")).clicked() {
+                                    spawn = Some(request);
+                                }
+                            }
+                            egui::Grid::new((category, name, "fields")).striped(true).show(ui, |ui| {
+                                for (field, value) in prototype.as_object().into_iter().flatten().filter(|(field, _)| *field != "name") {
+                                    ui.label(field.as_str());
+                                    ui.label(egui::RichText::new(field_text(value)).monospace());
+                                    ui.end_row();
+                                }
+                            });
+                        });
+                    }
+                });
+            }
+        });
+    });
+    let position = camera.get_single().map_or(Vec2::ZERO, |transform| transform.translation.truncate());
+    match spawn {
+        Some(SpawnRequest::Unit(movement)) => spawn_queue.0.push((Blueprint { movement, tick_interval: 1, ..default() }, position)),
+        Some(SpawnRequest::Creature(name)) => {
+            if let Some(creature) = CreaturePrototype::from_pt(&spawner.prototypes, &name) {
+                creature.spawn(&mut spawner.commands, &spawner.visuals, &spawner.prototypes, position);
+            }
+        }
+        None => ()
+    }
+}
@@ -0,0 +1,132 @@
+use bevy::{prelude::*, render::camera::ScalingMode, input::mouse::{MouseWheel, MouseScrollUnit, MouseMotion}};
+use bevy_egui::EguiContext;
+use super::{Unit, MovementLabel};
+use crate::audio::play_sounds;
+use crate::plot::SelectedUnit;
+use crate::status::StatusIndicator;
+
+pub const RESOLUTION: f32 = 16.0 / 9.0;
+pub const MIN_ZOOM: f32 = 1.0;
+pub const MAX_ZOOM: f32 = 20.0;
+const FOLLOW_KEY: KeyCode = KeyCode::F;
+// zoom from which labels and status indicators are hidden, they're unreadable and with thousands
+// of units on screen cost more to draw than the units
+const DETAIL_ZOOM: f32 = 8.0;
+
+/// The 2d camera and its mouse controls. F makes it follow the selected unit until the camera is
+/// panned. Sounds are positioned relative to the camera, so they are played here too.
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<CameraFollow>()
+            .init_resource::<DetailLevel>()
+            .add_startup_system(spawn_camera)
+            .add_system(move_and_zoom_camera)
+            .add_system(update_detail_level.after(move_and_zoom_camera))
+            .add_system(hide_status_indicators.after(update_detail_level))
+            .add_system(toggle_camera_follow)
+            .add_system(follow_unit.after(MovementLabel))
+            .add_system(play_sounds);
+    }
+}
+
+/// Unit the camera stays centered on
+#[derive(Default)]
+pub struct CameraFollow(pub Option<Entity>);
+
+/// Whether the camera is zoomed out too far for details drawn on top of units
+#[derive(Default)]
+pub struct DetailLevel {
+    pub zoomed_out: bool
+}
+
+fn update_detail_level(camera: Query<&OrthographicProjection, With<Camera2d>>, mut detail: ResMut<DetailLevel>) {
+    let zoomed_out = camera.single().scale >= DETAIL_ZOOM;
+    // only on changes, labels and indicators are all updated when it changes
+    if detail.zoomed_out != zoomed_out {
+        detail.zoomed_out = zoomed_out;
+    }
+}
+
+// New indicators are hidden too when the camera is already zoomed out
+fn hide_status_indicators(detail: Res<DetailLevel>, mut indicators: Query<(&mut Visibility, ChangeTrackers<StatusIndicator>)>) {
+    for (mut visibility, tracker) in indicators.iter_mut() {
+        if detail.is_changed() || tracker.is_added() {
+            visibility.is_visible = !detail.zoomed_out;
+        }
+    }
+}
+
+fn spawn_camera(mut commands: Commands) {
+    let mut camera = Camera2dBundle::default();
+
+    camera.projection.top = 1.0;
+    camera.projection.bottom = -1.0;
+    camera.projection.right = 1.0 * RESOLUTION;
+    camera.projection.left = -1.0 * RESOLUTION;
+
+    camera.projection.scaling_mode = ScalingMode::None;
+
+    commands.spawn_bundle(camera);
+}
+
+fn move_and_zoom_camera(
+    mut camera: Query<(&mut OrthographicProjection, &mut Transform), With<Camera2d>>,
+    input: Res<Input<MouseButton>>,
+    mut follow: ResMut<CameraFollow>,
+    mut mouse_scroll_evr: EventReader<MouseWheel>,
+    mut mouse_move_evr: EventReader<MouseMotion>)
+{
+    let (mut camera, mut camera_transform) = camera.single_mut();
+    for scroll_event in mouse_scroll_evr.iter() {
+        match scroll_event.unit {
+            MouseScrollUnit::Line => camera.scale = (camera.scale - 0.5 * scroll_event.y).clamp(MIN_ZOOM, MAX_ZOOM),
+            MouseScrollUnit::Pixel => camera.scale = (camera.scale - 0.1 * scroll_event.y).clamp(MIN_ZOOM, MAX_ZOOM)
+        }
+    }
+    for move_event in mouse_move_evr.iter() {
+        if input.pressed(MouseButton::Middle) {
+            let mut delta = move_event.delta * 0.0025 * camera.scale;
+            delta.x = -delta.x;
+            camera_transform.translation += delta.extend(0.0);
+            follow.0 = None;
+        }
+    }
+}
+
+fn toggle_camera_follow(
+    mut follow: ResMut<CameraFollow>,
+    mut egui_context: ResMut<EguiContext>,
+    keys: Res<Input<KeyCode>>,
+    selected: Res<SelectedUnit>)
+{
+    if !keys.just_pressed(FOLLOW_KEY) || egui_context.ctx_mut().wants_keyboard_input() {
+        return
+    }
+    follow.0 = match follow.0 {
+        Some(_) => None,
+        None => selected.0
+    };
+}
+
+fn follow_unit(
+    mut follow: ResMut<CameraFollow>,
+    mut camera: Query<&mut Transform, With<Camera2d>>,
+    units: Query<&Transform, (With<Unit>, Without<Camera2d>)>)
+{
+    let unit = match follow.0 {
+        Some(unit) => unit,
+        None => return
+    };
+    match units.get(unit) {
+        Ok(transform) => {
+            let mut camera_transform = camera.single_mut();
+            camera_transform.translation.x = transform.translation.x;
+            camera_transform.translation.y = transform.translation.y;
+        }
+        // the unit was destroyed
+        Err(_) => follow.0 = None
+    }
+}
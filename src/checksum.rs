@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+use bevy::prelude::*;
+use blake3::{Hash, Hasher};
+use super::{Unit, UnitId, SimTick, MovementState, units_in_order};
+use crate::{health::Health, energy::Energy, heat::Heat};
+use crate::program::{UnitProgram, ProgramStatus};
+
+// ten seconds of ticks
+const RECENT_TICKS: usize = 600;
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChecksumLabel;
+
+/// Rolling hash of the simulation state, updated at the end of every tick. Each tick's checksum
+/// covers the previous one, so two runs with the same checksum at a tick went through the same
+/// states up to it. Only units are hashed for now: their id, transform, speed, health, energy,
+/// heat and program status, in id order.
+pub struct StateChecksum {
+    rolling: Hash,
+    recent: VecDeque<(u64, Hash)>
+}
+
+impl Default for StateChecksum {
+    fn default() -> Self {
+        Self { rolling: Hasher::new().finalize(), recent: VecDeque::new() }
+    }
+}
+
+impl StateChecksum {
+    pub fn current(&self) -> Hash {
+        self.rolling
+    }
+
+    /// Checksum at the end of `tick`, if it's one of the last ten seconds
+    pub fn at(&self, tick: u64) -> Option<Hash> {
+        self.recent.iter().find(|(recent_tick, _)| *recent_tick == tick).map(|(_, hash)| *hash)
+    }
+
+    // chains the checksum of `tick` to the one before, `hash_state` adds the tick's state
+    fn advance(&mut self, tick: u64, hash_state: impl FnOnce(&mut Hasher)) {
+        let mut hasher = Hasher::new();
+        hasher.update(self.rolling.as_bytes());
+        hasher.update(&tick.to_le_bytes());
+        hash_state(&mut hasher);
+        self.rolling = hasher.finalize();
+        if self.recent.len() == RECENT_TICKS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((tick, self.rolling));
+    }
+}
+
+// everything hashed per unit
+type ChecksummedUnit = (&'static UnitId, &'static Transform, &'static UnitProgram, Option<&'static MovementState>, Option<&'static Health>, Option<&'static Energy>, Option<&'static Heat>);
+
+fn status_byte(status: ProgramStatus) -> u8 {
+    match status {
+        ProgramStatus::Running => 0,
+        ProgramStatus::Idle => 1,
+        ProgramStatus::Hibernating => 2,
+        ProgramStatus::Faulted => 3,
+        ProgramStatus::Crashed => 4
+    }
+}
+
+pub fn update_state_checksum(
+    sim_tick: Res<SimTick>,
+    mut checksum: ResMut<StateChecksum>,
+    ids: Query<(Entity, &UnitId)>,
+    units: Query<ChecksummedUnit, With<Unit>>)
+{
+    checksum.advance(sim_tick.0, |hasher| {
+        for entity in units_in_order(&ids) {
+            let (id, transform, program, state, health, energy, heat) = match units.get(entity) {
+                Ok(unit) => unit,
+                Err(_) => continue
+            };
+            let values = [transform.translation.x, transform.translation.y, transform.rotation.z, transform.rotation.w]
+                .into_iter()
+                .chain(state.map(|state| state.movement.speed))
+                .chain(health.map(|health| health.current))
+                .chain(energy.map(|energy| energy.stored))
+                .chain(heat.map(|heat| heat.current));
+            hash_unit(hasher, id.0, values, program.status());
+        }
+    });
+}
+
+fn hash_unit(hasher: &mut Hasher, id: u64, values: impl Iterator<Item = f32>, status: ProgramStatus) {
+    hasher.update(&id.to_le_bytes());
+    for value in values {
+        hasher.update(&value.to_bits().to_le_bytes());
+    }
+    hasher.update(&[status_byte(status)]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one tick with a single unit at `x`
+    fn advance(checksum: &mut StateChecksum, tick: u64, x: f32, status: ProgramStatus) {
+        checksum.advance(tick, |hasher| hash_unit(hasher, 1, [x, 0.0, 0.0, 1.0].into_iter(), status));
+    }
+
+    #[test]
+    fn same_states_give_same_checksums() {
+        let (mut first, mut second) = (StateChecksum::default(), StateChecksum::default());
+        for tick in 1..=3 {
+            advance(&mut first, tick, tick as f32, ProgramStatus::Running);
+            advance(&mut second, tick, tick as f32, ProgramStatus::Running);
+            assert_eq!(first.current(), second.current());
+        }
+    }
+
+    #[test]
+    fn checksums_differ_from_the_first_tick_that_differs() {
+        let (mut first, mut second) = (StateChecksum::default(), StateChecksum::default());
+        advance(&mut first, 1, 0.0, ProgramStatus::Running);
+        advance(&mut second, 1, 0.0, ProgramStatus::Running);
+        advance(&mut first, 2, 0.5, ProgramStatus::Running);
+        advance(&mut second, 2, 0.5, ProgramStatus::Faulted);
+        // the states match again, the checksums don't since they cover the ticks before
+        advance(&mut first, 3, 1.0, ProgramStatus::Running);
+        advance(&mut second, 3, 1.0, ProgramStatus::Running);
+        assert_eq!(first.at(1), second.at(1));
+        assert_ne!(first.at(2), second.at(2));
+        assert_ne!(first.at(3), second.at(3));
+    }
+
+    #[test]
+    fn only_recent_ticks_are_kept() {
+        let mut checksum = StateChecksum::default();
+        for tick in 1..=RECENT_TICKS as u64 + 5 {
+            advance(&mut checksum, tick, 0.0, ProgramStatus::Idle);
+        }
+        assert_eq!(checksum.at(5), None);
+        assert!(checksum.at(6).is_some());
+        assert_eq!(checksum.at(RECENT_TICKS as u64 + 5), Some(checksum.current()));
+    }
+}
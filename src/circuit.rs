@@ -0,0 +1,176 @@
+use std::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+#[cfg(not(target_arch = "wasm32"))]
+use mlua::prelude::*;
+use serde::Deserialize;
+use super::{Prototype, ComponentPrototype, Prototypes};
+use crate::data_value::{DataValue, DataValueHashEq};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::program::raise_intent;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::schema::Schema;
+
+pub type Signals = HashMap<DataValueHashEq, DataValue>;
+
+/// Wired signal network endpoint. Every tick the outputs of all connectors in a network are merged
+/// and the result becomes the input of each of them.
+#[derive(Component, Prototype, ComponentPrototype, Deserialize, Clone)]
+#[prot_category(circuit_connector)]
+pub struct CircuitConnector {
+    name: String,
+    range: f32, // tiles
+    #[serde(skip)]
+    connections: HashSet<Entity>,
+    #[serde(skip)]
+    output: Signals,
+    #[serde(skip)]
+    input: Signals,
+    #[serde(skip)]
+    connect_request: Option<Vec2>,
+    #[serde(skip)]
+    disconnect_request: bool
+}
+
+// Numbers are summed, anything else is overwritten by the later value
+fn merge_signals(merged: &mut Signals, signals: &Signals) {
+    for (key, value) in signals {
+        let merged_value = match (merged.remove(key), value) {
+            (Some(DataValue::Integer(a)), DataValue::Integer(b)) => DataValue::Integer(a.wrapping_add(*b)),
+            (Some(DataValue::Integer(a)), DataValue::Number(b)) => DataValue::Number(a as f64 + b),
+            (Some(DataValue::Number(a)), DataValue::Integer(b)) => DataValue::Number(a + *b as f64),
+            (Some(DataValue::Number(a)), DataValue::Number(b)) => DataValue::Number(a + b),
+            (_, value) => value.clone()
+        };
+        merged.insert(key.clone(), merged_value);
+    }
+}
+
+pub fn connect_circuits(
+    mut connectors: Query<(Entity, &mut CircuitConnector, &Transform)>,
+    rapier_context: Res<RapierContext>)
+{
+    let mut new_links = Vec::new();
+    for (entity, mut connector, transform) in connectors.iter_mut() {
+        if connector.disconnect_request {
+            connector.disconnect_request = false;
+            for other in connector.connections.drain() {
+                new_links.push((other, entity, false));
+            }
+        }
+        let point = match connector.connect_request.take() {
+            Some(point) if transform.translation.truncate().distance(point) <= connector.range => point,
+            _ => continue
+        };
+        let filter = QueryFilter::default().exclude_collider(entity);
+        rapier_context.intersections_with_point(point, filter, |other| {
+            new_links.push((entity, other, true));
+            false
+        });
+    }
+    for (a, b, connect) in new_links {
+        if !connect {
+            if let Ok((_, mut connector, _)) = connectors.get_mut(a) {
+                connector.connections.remove(&b);
+            }
+        } else if connectors.get(b).is_ok() {
+            for (from, to) in [(a, b), (b, a)] {
+                if let Ok((_, mut connector, _)) = connectors.get_mut(from) {
+                    connector.connections.insert(to);
+                }
+            }
+        }
+    }
+}
+
+pub fn propagate_circuits(mut connectors: Query<(Entity, &mut CircuitConnector)>) {
+    let mut visited = HashSet::new();
+    let mut entities: Vec<Entity> = connectors.iter().map(|(entity, _)| entity).collect();
+    // stable merge order regardless of query iteration order
+    entities.sort();
+    for start in entities {
+        if !visited.insert(start) {
+            continue
+        }
+        let mut network = vec![start];
+        let mut i = 0;
+        while i < network.len() {
+            if let Ok((_, connector)) = connectors.get(network[i]) {
+                for &other in connector.connections.iter() {
+                    if connectors.get(other).is_ok() && visited.insert(other) {
+                        network.push(other);
+                    }
+                }
+            }
+            i += 1;
+        }
+        network.sort();
+        let mut merged = Signals::new();
+        for entity in network.iter() {
+            if let Ok((_, connector)) = connectors.get(*entity) {
+                merge_signals(&mut merged, &connector.output);
+            }
+        }
+        for entity in network.iter() {
+            if let Ok((_, mut connector)) = connectors.get_mut(*entity) {
+                connector.input = merged.clone();
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct LuaCircuitHandle<'a> {
+    pub connector: &'a mut CircuitConnector
+}
+
+// Signals are flat, networks sum numbers and overwrite anything else when merging them
+#[cfg(not(target_arch = "wasm32"))]
+fn signals_schema() -> Schema {
+    let value = Schema::OneOf(vec![Schema::Number { min: None, max: None }, Schema::Boolean, Schema::string()]);
+    Schema::Table { fields: Vec::new(), other: Some(Box::new(value)) }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LuaUserData for LuaCircuitHandle<'_> {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("read", |lua, lua_handle, ()| {
+            DataValue::Table(lua_handle.connector.input.clone()).to_lua(lua)
+        });
+        methods.add_method_mut("write", |lua, lua_handle, signals: DataValue| {
+            raise_intent(lua)?;
+            let signals = match signals {
+                DataValue::Nil => DataValue::Table(Signals::new()),
+                DataValue::Sequence(sequence) => DataValue::Table(sequence.into_iter()
+                    .enumerate()
+                    .map(|(i, value)| (DataValueHashEq::Integer(i as LuaInteger + 1), value))
+                    .collect()),
+                signals => signals
+            };
+            signals.validate(&signals_schema()).map_err(|error| LuaError::RuntimeError(format!("invalid circuit signals, {}", error)))?;
+            if let DataValue::Table(table) = signals {
+                lua_handle.connector.output = table;
+            }
+            Ok(())
+        });
+        methods.add_method_mut("connect", |lua, lua_handle, (x, y): (f32, f32)| {
+            raise_intent(lua)?;
+            lua_handle.connector.connect_request = Some(Vec2::new(x, y));
+            Ok(())
+        });
+        methods.add_method_mut("disconnect_all", |lua, lua_handle, ()| {
+            raise_intent(lua)?;
+            lua_handle.connector.disconnect_request = true;
+            Ok(())
+        });
+    }
+
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("range", |_lua, lua_handle| {
+            Ok(lua_handle.connector.range)
+        });
+        fields.add_field_method_get("connections", |_lua, lua_handle| {
+            Ok(lua_handle.connector.connections.len())
+        });
+    }
+}
@@ -0,0 +1,663 @@
+use std::{collections::{HashMap, HashSet}, io, net::{TcpStream, ToSocketAddrs, UdpSocket, SocketAddr, Ipv4Addr}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+use bevy::{prelude::*, time::FixedTimestep};
+use bevy_egui::{EguiContext, egui};
+use bevy_rapier2d::prelude::RapierConfiguration;
+use super::{Prototype, UnitId, SimTick, UnitSpawner, Prototypes, TICK_RATE};
+use crate::net::{ServerConnection, ClientMessage, ServerMessage, Hello, GameContent, Announcement, Transport, TcpTransport, Connection, PROTOCOL_VERSION, DISCOVERY_PORT, default_name};
+use crate::replication::{Update, WorldView, ReplicatedUnit, ReplicatedObject, ObjectKind, QuantizedTransform};
+use crate::blueprint::Blueprint;
+use crate::save::{decode_save, spawn_saved_units};
+use crate::manual_control::{ControlInput, ManualControl};
+use crate::loading::{AppState, ModList};
+use crate::menu::MainMenu;
+use crate::locale::Localize;
+use crate::camera::CameraFollow;
+use crate::admin::{AdminEvent, ChatEvent, ConsoleMessage};
+use crate::creature::CreaturePrototype;
+use crate::construction::StructurePrototype;
+use crate::{inventory::spawn_ground_item, logistics::drone_sprite};
+use crate::{team::Team, health::Health, energy::Energy, registry::UnitLabel, logging::NET};
+
+/// `scriplets --join <host:port>` plays on the server at that address instead of starting a game
+pub const JOIN_FLAG: &str = "--join";
+/// `--name <name>` is who to join as, players joining with the same name again get their team back
+pub const NAME_FLAG: &str = "--name";
+/// `--observe` joins without a team, seeing every unit
+pub const OBSERVE_FLAG: &str = "--observe";
+/// `--password <password>` joins as one of the admins in the server's `server.toml`
+pub const PASSWORD_FLAG: &str = "--password";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// games that weren't announced for this long are gone from the list
+const LAN_GAME_TIMEOUT: Duration = Duration::from_secs(3);
+// screen heights per second observers pan the camera with WASD
+const OBSERVER_PAN_SPEED: f32 = 1.0;
+
+/// Plays on a server when there's a `Client`. The world doesn't step on its own then, it's loaded
+/// from the save the server sends when joining. From there units are spawned, moved and despawned
+/// as the server's updates say and driving one sends the input to the server. The main menu's join
+/// dialog lists the games announced on the local network. Observers drive no units, WASD pans the
+/// camera for them instead. Chat and admin commands from the console go to the server.
+pub struct ClientPlugin;
+
+impl Plugin for ClientPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<JoinDialog>()
+            .add_system_set(SystemSet::on_update(AppState::MainMenu)
+                .with_system(discover_games)
+                .with_system(show_join_dialog.after(discover_games)))
+            .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(say_hello))
+            .add_system_set(SystemSet::on_update(AppState::Playing)
+                .with_system(receive_updates.label(ReceiveUpdatesLabel))
+                .with_system(sync_replicas.after(ReceiveUpdatesLabel))
+                .with_system(move_objects.after(ReceiveUpdatesLabel))
+                .with_system(interpolate_replicas.after(ReceiveUpdatesLabel))
+                .with_system(release_units)
+                .with_system(pan_observer_camera)
+                .with_system(send_console_input)
+                .with_system(show_connection_status))
+            .add_system(send_manual_input.with_run_criteria(FixedTimestep::steps_per_second(TICK_RATE as f64)))
+            .add_system(stop_physics);
+    }
+}
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+struct ReceiveUpdatesLabel;
+
+enum ClientState {
+    Joining,
+    // the save with the world, `len` is 0 until its first part arrived. Observers have no team.
+    Receiving { team: Option<Team>, world: Vec<u8>, len: u64 },
+    Playing { team: Option<Team> },
+    Refused(String),
+    // by whom
+    Kicked(String),
+    Broken(String),
+    Disconnected
+}
+
+/// Connection to the server, inserted by `--join`
+pub struct Client {
+    connection: ServerConnection,
+    address: String,
+    name: String,
+    observer: bool,
+    password: Option<String>,
+    state: ClientState,
+    // what the server sent so far and the entities of its units
+    view: WorldView,
+    units: HashMap<u64, Entity>,
+    objects: HashMap<u64, Entity>,
+    // ticks between the last two updates and seconds since the last one, for interpolating
+    update_ticks: u64,
+    since_update: f32,
+    // the view stopped matching the server's, reported once until a snapshot replaces it
+    desynced: bool
+}
+
+impl Client {
+    /// Observers watch every unit without a team of their own. Admins need their password.
+    pub fn connect(address: &str, name: String, observer: bool, password: Option<String>) -> io::Result<Self> {
+        let socket_address = address.to_socket_addrs()?.next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} doesn't resolve to an address", address)))?;
+        let stream = TcpStream::connect_timeout(&socket_address, CONNECT_TIMEOUT)?;
+        info!(target: NET, "Connected to {}", address);
+        Ok(Self::new(TcpTransport::new(stream)?, address.to_string(), name, observer, password))
+    }
+
+    /// Plays on a server in the same process, see `Server::connect_local`
+    pub fn local(transport: impl Transport + 'static, name: String) -> Self {
+        Self::new(transport, "the local server".to_string(), name, false, None)
+    }
+
+    fn new(transport: impl Transport + 'static, address: String, name: String, observer: bool, password: Option<String>) -> Self {
+        Self {
+            connection: Connection::new(transport),
+            address,
+            name,
+            observer,
+            password,
+            state: ClientState::Joining,
+            view: WorldView::default(),
+            units: HashMap::new(),
+            objects: HashMap::new(),
+            update_ticks: 1,
+            since_update: 0.0,
+            desynced: false
+        }
+    }
+
+    fn team(&self) -> Option<Team> {
+        match self.state {
+            ClientState::Playing { team } => team,
+            _ => None
+        }
+    }
+
+    pub fn observing(&self) -> bool {
+        self.observer
+    }
+}
+
+struct LanGame {
+    address: SocketAddr,
+    players: u32,
+    seen: Instant
+}
+
+/// The main menu's dialog joining a game by address or from the ones on the local network
+pub struct JoinDialog {
+    address: String,
+    name: String,
+    password: String,
+    observe: bool,
+    // listens for announcements while the dialog is open
+    discovery: Option<UdpSocket>,
+    games: Vec<LanGame>,
+    // connecting blocks until the server answers
+    connecting: Option<JoinHandle<io::Result<Client>>>,
+    error: Option<String>
+}
+
+impl Default for JoinDialog {
+    fn default() -> Self {
+        Self { address: String::new(), name: default_name(), password: String::new(), observe: false, discovery: None, games: Vec::new(), connecting: None, error: None }
+    }
+}
+
+/// Unit the server sent, moved from where the previous update had it to where the last one has
+#[derive(Component)]
+pub struct Replica {
+    from: QuantizedTransform,
+    unit: ReplicatedUnit
+}
+
+/// Creature, structure or other object the server sent, spawned like the server spawned it. Its
+/// components do nothing on clients, it's only moved to where the last update has it.
+#[derive(Component)]
+pub struct ObjectReplica(ReplicatedObject);
+
+// the server needs the prototypes hash and such, which are only there once loading finished
+fn say_hello(client: Option<ResMut<Client>>, prototypes: Res<Prototypes>, mod_list: Res<ModList>) {
+    if let Some(mut client) = client {
+        let (name, observer, password) = (client.name.clone(), client.observer, client.password.clone());
+        let content = GameContent::of(&prototypes, &mod_list);
+        client.connection.send(&ClientMessage::Hello(Hello { protocol: PROTOCOL_VERSION, name, observer, password, content }));
+    }
+}
+
+fn receive_updates(
+    client: Option<ResMut<Client>>,
+    mut spawner: UnitSpawner,
+    assets: Res<AssetServer>,
+    mut sim_tick: ResMut<SimTick>,
+    mut replicas: Query<&mut Replica>,
+    time: Res<Time>,
+    mut console: EventWriter<ConsoleMessage>)
+{
+    let mut client = match client {
+        Some(client) => client,
+        None => return
+    };
+    client.since_update += time.delta_seconds();
+    // units spawned during this frame, their components are only there once the commands ran
+    let mut spawned = HashMap::new();
+    while let Some(message) = client.connection.receive() {
+        match message {
+            Ok(ServerMessage::Welcome { team }) => {
+                match team {
+                    Some(team) => info!(target: NET, "Joined {} as team {}", client.address, team),
+                    None => info!(target: NET, "Observing {}", client.address)
+                }
+                client.state = ClientState::Receiving { team: team.map(Team), world: Vec::new(), len: 0 };
+            }
+            Ok(ServerMessage::World { len, part }) => match &mut client.state {
+                ClientState::Receiving { team, world, len: expected } => {
+                    let team = *team;
+                    *expected = len;
+                    world.extend(part);
+                    if world.len() as u64 >= len {
+                        let world = std::mem::take(world);
+                        client.state = match load_world(&mut client, &world, &mut spawner, &mut sim_tick, &mut spawned) {
+                            Ok(()) => ClientState::Playing { team },
+                            Err(error) => {
+                                warn!(target: NET, "Can't load the world from {}: {}", client.address, error);
+                                ClientState::Broken(error)
+                            }
+                        };
+                    }
+                }
+                _ => warn!(target: NET, "{} sent the world again", client.address)
+            },
+            Ok(ServerMessage::Refused { reason }) => {
+                warn!(target: NET, "{} refused to let us join: {}", client.address, reason);
+                client.state = ClientState::Refused(reason);
+            }
+            Ok(ServerMessage::Chat { from, text }) => console.send(ConsoleMessage::Chat { from, text }),
+            Ok(ServerMessage::Notice { text }) => console.send(ConsoleMessage::Notice(text)),
+            Ok(ServerMessage::Kicked { by }) => {
+                warn!(target: NET, "{} kicked us from {}", by, client.address);
+                client.state = ClientState::Kicked(by);
+            }
+            Ok(ServerMessage::Update(update)) => {
+                apply_object_updates(&mut client, &update, &mut spawner, &assets);
+                apply_update(&mut client, &update, &mut spawner, &mut replicas, &mut spawned);
+                client.desynced &= !update.snapshot;
+                if !client.desynced && client.view.checksum() != update.checksum {
+                    warn!(target: NET, "Desync at tick {}, the units differ from what {} sent", update.tick, client.address);
+                    client.desynced = true;
+                }
+                client.update_ticks = update.tick.saturating_sub(sim_tick.0).max(1);
+                client.since_update = 0.0;
+                sim_tick.0 = update.tick;
+            }
+            Err(error) => warn!(target: NET, "Can't read a message from {}: {}", client.address, error)
+        }
+    }
+    for (id, replica) in spawned {
+        if let Some(entity) = client.units.get(&id) {
+            spawner.commands.entity(*entity).insert(replica);
+        }
+    }
+    if client.connection.is_closed() && matches!(client.state, ClientState::Joining | ClientState::Receiving { .. } | ClientState::Playing { .. }) {
+        warn!(target: NET, "Lost the connection to {}", client.address);
+        client.state = ClientState::Disconnected;
+    }
+}
+
+// Units are spawned like the save was loaded, then updated by the deltas that follow
+fn load_world(client: &mut Client, bytes: &[u8], spawner: &mut UnitSpawner, sim_tick: &mut SimTick, spawned: &mut HashMap<u64, Replica>) -> Result<(), String> {
+    let save = decode_save(bytes).map_err(|error| error.to_string())?;
+    for entity in client.units.values() {
+        spawner.commands.entity(*entity).despawn_recursive();
+    }
+    client.units.clear();
+    spawned.clear();
+    client.view = save.view();
+    sim_tick.0 = client.view.tick;
+    let mut mismatches = Vec::new();
+    for (id, entity) in spawn_saved_units(save.units, spawner, &format!("the world of {}", client.address), &mut mismatches) {
+        client.units.insert(id, entity);
+        if let Some(unit) = client.view.units.get(&id) {
+            spawned.insert(id, Replica { from: unit.transform, unit: unit.clone() });
+        }
+    }
+    info!(target: NET, "Loaded the world of {}, {} units", client.address, client.units.len());
+    Ok(())
+}
+
+fn apply_update(client: &mut Client, update: &Update, spawner: &mut UnitSpawner, replicas: &mut Query<&mut Replica>, spawned: &mut HashMap<u64, Replica>) {
+    if update.snapshot {
+        let ids: HashSet<u64> = update.spawned.iter().map(|spawn| spawn.id).collect();
+        client.units.retain(|id, entity| {
+            if !ids.contains(id) {
+                spawner.commands.entity(*entity).despawn_recursive();
+                spawned.remove(id);
+            }
+            ids.contains(id)
+        });
+    }
+    client.view.apply(update);
+    let mut moved: Vec<u64> = update.changed.iter().map(|(id, _)| *id).collect();
+    for spawn in &update.spawned {
+        if client.units.contains_key(&spawn.id) {
+            moved.push(spawn.id);
+            continue
+        }
+        let blueprint = match Blueprint::import(&spawn.blueprint) {
+            Ok(blueprint) => blueprint,
+            Err(error) => {
+                warn!(target: NET, "Can't spawn unit {}: {}", spawn.id, error);
+                continue
+            }
+        };
+        let entity = match spawner.spawn(&blueprint, spawn.unit.transform.position()) {
+            Some(entity) => entity,
+            None => {
+                warn!(target: NET, "Can't spawn unit {}: unknown movement prototype {}", spawn.id, blueprint.movement);
+                continue
+            }
+        };
+        // replaces the id it was spawned with
+        spawner.commands.entity(entity).insert(UnitId(spawn.id));
+        client.units.insert(spawn.id, entity);
+        spawned.insert(spawn.id, Replica { from: spawn.unit.transform, unit: spawn.unit.clone() });
+    }
+    for id in moved {
+        let (entity, unit) = match (client.units.get(&id), client.view.units.get(&id)) {
+            (Some(entity), Some(unit)) => (*entity, unit.clone()),
+            _ => continue
+        };
+        if let Some(replica) = spawned.get_mut(&id) {
+            replica.from = replica.unit.transform;
+            replica.unit = unit;
+        } else if let Ok(mut replica) = replicas.get_mut(entity) {
+            replica.from = replica.unit.transform;
+            replica.unit = unit;
+        }
+    }
+    for id in &update.despawned {
+        if let Some(entity) = client.units.remove(id) {
+            spawner.commands.entity(entity).despawn_recursive();
+            spawned.remove(id);
+        }
+    }
+}
+
+// Objects of a kind the client doesn't have for that id yet are spawned, the others only moved.
+// Runs before the view has the update applied, which has the kinds the client has.
+fn apply_object_updates(client: &mut Client, update: &Update, spawner: &mut UnitSpawner, assets: &AssetServer) {
+    let mut removed = update.removed_objects.clone();
+    if update.snapshot {
+        removed.extend(client.objects.keys().filter(|id| !update.objects.iter().any(|(object_id, _)| object_id == *id)));
+    }
+    for (id, object) in &update.objects {
+        let entity = match client.objects.get(id) {
+            Some(entity) if client.view.objects.get(id).map_or(false, |known| known.kind == object.kind) => *entity,
+            known => {
+                if let Some(entity) = known {
+                    spawner.commands.entity(*entity).despawn_recursive();
+                }
+                match spawn_object(spawner, assets, object) {
+                    Some(entity) => entity,
+                    None => {
+                        warn!(target: NET, "Can't spawn object {}: unknown prototype in {:?}", id, object.kind);
+                        client.objects.remove(id);
+                        continue
+                    }
+                }
+            }
+        };
+        spawner.commands.entity(entity).insert(ObjectReplica(object.clone()));
+        client.objects.insert(*id, entity);
+    }
+    for id in removed {
+        if let Some(entity) = client.objects.remove(&id) {
+            spawner.commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+fn spawn_object(spawner: &mut UnitSpawner, assets: &AssetServer, object: &ReplicatedObject) -> Option<Entity> {
+    let position = object.transform.position();
+    let transform = Transform::from_translation(position.extend(0.0)).with_rotation(Quat::from_rotation_z(object.transform.rotation()));
+    let prototypes = &spawner.prototypes;
+    let entity = match &object.kind {
+        ObjectKind::Creature(name) => CreaturePrototype::from_pt(prototypes, name)?.spawn(&mut spawner.commands, &spawner.visuals, prototypes, position),
+        ObjectKind::Structure(name) => StructurePrototype::from_pt(prototypes, name)?.spawn(&mut spawner.commands, assets, prototypes, transform),
+        ObjectKind::ConstructionSite(name) => spawner.commands.spawn_bundle(StructurePrototype::from_pt(prototypes, name)?.ghost_bundle(assets, transform)).id(),
+        ObjectKind::GroundItem { item, count } => spawn_ground_item(&mut spawner.commands, item.clone(), *count, position),
+        ObjectKind::Drone => spawner.commands.spawn_bundle(drone_sprite(position)).id()
+    };
+    Some(entity)
+}
+
+// objects keep the depth they were spawned with, it isn't sent
+fn move_objects(mut objects: Query<(&ObjectReplica, &mut Transform), Changed<ObjectReplica>>) {
+    for (object, mut transform) in objects.iter_mut() {
+        let translation = object.0.transform.position().extend(transform.translation.z);
+        let rotation = Quat::from_rotation_z(object.0.transform.rotation());
+        if transform.translation != translation || transform.rotation != rotation {
+            transform.translation = translation;
+            transform.rotation = rotation;
+        }
+    }
+}
+
+type ReplicaComponents = (&'static Replica, &'static mut Team, &'static mut UnitLabel, Option<&'static mut Health>, Option<&'static mut Energy>);
+
+fn sync_replicas(mut replicas: Query<ReplicaComponents, Changed<Replica>>) {
+    for (replica, mut team, mut label, health, energy) in replicas.iter_mut() {
+        if team.0 != replica.unit.team {
+            *team = Team(replica.unit.team);
+        }
+        if label.0 != replica.unit.label {
+            label.0 = replica.unit.label.clone();
+        }
+        if let (Some(mut health), Some(current)) = (health, replica.unit.health) {
+            health.current = current;
+        }
+        if let (Some(mut energy), Some(stored)) = (energy, replica.unit.energy) {
+            energy.stored = stored;
+        }
+    }
+}
+
+// Units are shown one update behind, moving towards the last one until the next arrives
+fn interpolate_replicas(client: Option<Res<Client>>, mut replicas: Query<(&Replica, &mut Transform)>) {
+    let client = match client {
+        Some(client) => client,
+        None => return
+    };
+    let alpha = (client.since_update * TICK_RATE as f32 / client.update_ticks as f32).min(1.0);
+    for (replica, mut transform) in replicas.iter_mut() {
+        let (position, rotation) = replica.from.lerp(replica.unit.transform, alpha);
+        let translation = position.extend(transform.translation.z);
+        let rotation = Quat::from_rotation_z(rotation);
+        // only on changes, labels and status indicators follow changed transforms
+        if transform.translation != translation || transform.rotation != rotation {
+            transform.translation = translation;
+            transform.rotation = rotation;
+        }
+    }
+}
+
+// sent for every tick like local manual input is read, stamped with the tick of the last update so
+// the server can drop input that arrives too late
+fn send_manual_input(client: Option<ResMut<Client>>, sim_tick: Res<SimTick>, mut input: ControlInput, driven: Query<&UnitId, With<ManualControl>>) {
+    let mut client = match client.filter(|client| client.team().is_some()) {
+        Some(client) => client,
+        None => return
+    };
+    if driven.is_empty() {
+        return
+    }
+    let (input_move, input_rotation, hand_brake) = input.read();
+    for id in driven.iter() {
+        client.connection.send(&ClientMessage::Drive { unit: id.0, tick: sim_tick.0, input_move: input_move.to_array(), input_rotation, hand_brake });
+    }
+}
+
+// every frame, removals are only reported for the frame they happened in
+fn release_units(client: Option<ResMut<Client>>, released: RemovedComponents<ManualControl>, ids: Query<&UnitId>) {
+    let mut client = match client.filter(|client| client.team().is_some()) {
+        Some(client) => client,
+        None => return
+    };
+    for entity in released.iter() {
+        if let Ok(id) = ids.get(entity) {
+            client.connection.send(&ClientMessage::Release { unit: id.0 });
+        }
+    }
+}
+
+fn pan_observer_camera(
+    client: Option<Res<Client>>,
+    mut egui_context: ResMut<EguiContext>,
+    keys: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    mut follow: ResMut<CameraFollow>,
+    mut camera: Query<(&OrthographicProjection, &mut Transform), With<Camera2d>>)
+{
+    if !client.is_some_and(|client| client.observing()) || egui_context.ctx_mut().wants_keyboard_input() {
+        return
+    }
+    let axis = |negative, positive| keys.pressed(positive) as i32 as f32 - keys.pressed(negative) as i32 as f32;
+    let direction = Vec2::new(axis(KeyCode::A, KeyCode::D), axis(KeyCode::S, KeyCode::W));
+    if direction == Vec2::ZERO {
+        return
+    }
+    let (projection, mut transform) = camera.single_mut();
+    // the projection is 2 units high at a scale of 1
+    transform.translation += (direction.normalize() * OBSERVER_PAN_SPEED * 2.0 * projection.scale * time.delta_seconds()).extend(0.0);
+    follow.0 = None;
+}
+
+// once the server took the player, the server says what came of admin commands
+fn send_console_input(client: Option<ResMut<Client>>, mut chat: EventReader<ChatEvent>, mut admin: EventReader<AdminEvent>) {
+    let mut client = match client.filter(|client| matches!(client.state, ClientState::Receiving { .. } | ClientState::Playing { .. })) {
+        Some(client) => client,
+        None => return
+    };
+    for ChatEvent { text } in chat.iter() {
+        client.connection.send(&ClientMessage::Chat { text: text.clone() });
+    }
+    for AdminEvent { command, .. } in admin.iter() {
+        client.connection.send(&ClientMessage::Admin(command.clone()));
+    }
+}
+
+// positions come from the server, rapier would move units between its updates
+fn stop_physics(client: Option<Res<Client>>, mut rapier: ResMut<RapierConfiguration>) {
+    if client.is_some() && rapier.physics_pipeline_active {
+        rapier.physics_pipeline_active = false;
+    }
+}
+
+fn show_connection_status(mut egui_context: ResMut<EguiContext>, client: Option<Res<Client>>, localize: Res<Localize>) {
+    let client = match client {
+        Some(client) => client,
+        None => return
+    };
+    let text = match &client.state {
+        ClientState::Joining => localize.format("net-joining", &[("address", &client.address)]),
+        ClientState::Receiving { world, len, .. } => {
+            let percent = if *len == 0 { 0 } else { world.len() as u64 * 100 / len };
+            localize.format("net-receiving", &[("address", &client.address), ("percent", &percent)])
+        }
+        ClientState::Playing { .. } => return,
+        ClientState::Refused(reason) => localize.format("net-refused", &[("address", &client.address), ("reason", reason)]),
+        ClientState::Kicked(by) => localize.format("net-kicked", &[("address", &client.address), ("by", by)]),
+        ClientState::Broken(error) => localize.format("net-broken", &[("address", &client.address), ("error", error)]),
+        ClientState::Disconnected => localize.format("net-disconnected", &[("address", &client.address)])
+    };
+    egui::Window::new(localize.text("net-title"))
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_TOP, [0.0, 10.0])
+        .show(egui_context.ctx_mut(), |ui| ui.label(text));
+}
+
+fn discover_games(menu: Res<MainMenu>, mut dialog: ResMut<JoinDialog>) {
+    if !menu.joining {
+        dialog.discovery = None;
+        dialog.games.clear();
+        return
+    }
+    if dialog.discovery.is_none() {
+        // only one game on a computer gets the port, others still join by address
+        match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT)).and_then(|socket| socket.set_nonblocking(true).map(|_| socket)) {
+            Ok(socket) => dialog.discovery = Some(socket),
+            Err(error) => {
+                if dialog.error.is_none() {
+                    warn!(target: NET, "Can't look for games on the local network: {}", error);
+                    dialog.error = Some(error.to_string());
+                }
+                return
+            }
+        }
+    }
+    let mut buffer = [0; 256];
+    let mut announced = Vec::new();
+    if let Some(socket) = &dialog.discovery {
+        while let Ok((len, from)) = socket.recv_from(&mut buffer) {
+            match rmp_serde::from_slice::<Announcement>(&buffer[..len]) {
+                Ok(announcement) if announcement.protocol == PROTOCOL_VERSION => announced.push((SocketAddr::new(from.ip(), announcement.port), announcement.players)),
+                Ok(_) => (),
+                Err(error) => debug!(target: NET, "Unreadable announcement from {}: {}", from, error)
+            }
+        }
+    }
+    let now = Instant::now();
+    for (address, players) in announced {
+        dialog.games.retain(|game| game.address != address);
+        dialog.games.push(LanGame { address, players, seen: now });
+    }
+    dialog.games.retain(|game| now.duration_since(game.seen) < LAN_GAME_TIMEOUT);
+    dialog.games.sort_by_key(|game| game.address);
+}
+
+fn show_join_dialog(
+    mut commands: Commands,
+    mut egui_context: ResMut<EguiContext>,
+    mut menu: ResMut<MainMenu>,
+    mut dialog: ResMut<JoinDialog>,
+    mut state: ResMut<State<AppState>>,
+    localize: Res<Localize>)
+{
+    if !menu.joining {
+        return
+    }
+    if dialog.connecting.as_ref().is_some_and(JoinHandle::is_finished) {
+        let address = dialog.address.clone();
+        match dialog.connecting.take().unwrap().join() {
+            Ok(Ok(client)) => {
+                commands.insert_resource(client);
+                menu.joining = false;
+                state.set(AppState::Playing).unwrap();
+                return
+            }
+            Ok(Err(error)) => {
+                warn!(target: NET, "Can't connect to {}: {}", address, error);
+                dialog.error = Some(localize.format("join-failed", &[("address", &address), ("error", &error)]));
+            }
+            Err(_) => dialog.error = Some(localize.format("join-failed", &[("address", &address), ("error", &"panicked")]))
+        }
+    }
+    let mut open = true;
+    let dialog = &mut *dialog;
+    egui::Window::new(localize.text("join-title"))
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(egui_context.ctx_mut(), |ui| {
+            ui.add_enabled_ui(dialog.connecting.is_none(), |ui| {
+                egui::Grid::new("join_game").show(ui, |ui| {
+                    ui.label(localize.text("join-address"));
+                    ui.text_edit_singleline(&mut dialog.address);
+                    ui.end_row();
+                    ui.label(localize.text("join-name"));
+                    ui.text_edit_singleline(&mut dialog.name);
+                    ui.end_row();
+                    ui.label(localize.text("join-password"));
+                    ui.add(egui::TextEdit::singleline(&mut dialog.password).password(true));
+                    ui.end_row();
+                    ui.label("");
+                    ui.checkbox(&mut dialog.observe, localize.text("join-observe"));
+                    ui.end_row();
+                });
+                ui.strong(localize.text("join-lan"));
+                if dialog.games.is_empty() {
+                    ui.label(localize.text("join-lan-empty"));
+                }
+                for game in &dialog.games {
+                    let text = localize.format("join-lan-game", &[("address", &game.address), ("players", &game.players)]);
+                    if ui.selectable_label(dialog.address == game.address.to_string(), text).clicked() {
+                        dialog.address = game.address.to_string();
+                    }
+                }
+                ui.separator();
+                let ready = !dialog.address.trim().is_empty() && !dialog.name.trim().is_empty();
+                if ui.add_enabled(ready, egui::Button::new(localize.text("join-join"))).clicked() {
+                    let (address, name, observe) = (dialog.address.trim().to_string(), dialog.name.trim().to_string(), dialog.observe);
+                    // only admins have one
+                    let password = Some(dialog.password.clone()).filter(|password| !password.is_empty());
+                    dialog.error = None;
+                    dialog.connecting = Some(thread::spawn(move || Client::connect(&address, name, observe, password)));
+                }
+            });
+            if dialog.connecting.is_some() {
+                ui.label(localize.format("net-joining", &[("address", &dialog.address)]));
+            }
+            if let Some(error) = &dialog.error {
+                ui.colored_label(egui::Color32::LIGHT_RED, error);
+            }
+        });
+    // a connection still being made is dropped once it's there
+    if !open {
+        menu.joining = false;
+        dialog.connecting = None;
+        dialog.error = None;
+    }
+}
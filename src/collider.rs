@@ -0,0 +1,49 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+use super::{Prototype, ComponentPrototype, Prototypes};
+use crate::logging::ASSETS;
+
+/// Used when a unit doesn't name a collider, slightly smaller than a tile so units fit between walls
+pub const DEFAULT_UNIT_HALF_SIZE: f32 = 0.499;
+
+/// Physics shape of a unit or structure, dimensions in tiles
+#[derive(Prototype, ComponentPrototype, Deserialize, Clone)]
+#[prot_category(collider)]
+#[component(Collider)]
+pub struct ColliderPrototype {
+    name: String,
+    #[serde(flatten)]
+    shape: ColliderShape
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "shape", rename_all = "kebab-case")]
+pub enum ColliderShape {
+    Cuboid { width: f32, height: f32 },
+    Ball { radius: f32 },
+    // vertical, `height` is the distance between the centers of the caps
+    Capsule { height: f32, radius: f32 },
+    ConvexPolygon { points: Vec<[f32; 2]> }
+}
+
+impl From<&ColliderPrototype> for Collider {
+    fn from(prototype: &ColliderPrototype) -> Self {
+        match &prototype.shape {
+            ColliderShape::Cuboid { width, height } => Collider::cuboid(width / 2.0, height / 2.0),
+            ColliderShape::Ball { radius } => Collider::ball(*radius),
+            ColliderShape::Capsule { height, radius } => Collider::capsule_y(height / 2.0, *radius),
+            ColliderShape::ConvexPolygon { points } => {
+                let points: Vec<Vec2> = points.iter().copied().map(Vec2::from).collect();
+                Collider::convex_hull(&points).unwrap_or_else(|| {
+                    warn!(target: ASSETS, "Collider {} has no convex hull, using the default", prototype.name);
+                    Collider::cuboid(DEFAULT_UNIT_HALF_SIZE, DEFAULT_UNIT_HALF_SIZE)
+                })
+            }
+        }
+    }
+}
+
+/// Collider prototype the entity was spawned with, rapier's `Collider` doesn't keep it
+#[derive(Component, Clone)]
+pub struct ColliderName(pub String);
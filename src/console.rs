@@ -0,0 +1,276 @@
+use std::collections::VecDeque;
+use bevy::{prelude::*, ecs::system::SystemParam};
+use bevy_egui::{EguiContext, egui};
+use super::UnitId;
+use crate::{blueprint::{BlueprintEvent, BlueprintOutcome, BlueprintRequests}, plot::SelectedUnit, locale::Localize};
+use crate::admin::{AdminCommand, AdminEvent, AdminOutcome, AdminResult, ChatEvent, ConsoleMessage};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::save::{SaveEvent, LoadEvent, SaveOutcome, SaveRequests};
+
+const TOGGLE_KEY: KeyCode = KeyCode::Grave;
+const HISTORY_LENGTH: usize = 200;
+
+/// Chat and commands, opened with the backquote key. Lines starting with a slash are commands,
+/// everything else is chat to the other players of a multiplayer game. /spawn, /team, /kick,
+/// /pause and /usage are admin commands, see `AdminPlugin`.
+#[derive(Default)]
+pub struct Console {
+    open: bool,
+    input: String,
+    history: VecDeque<String>
+}
+
+/// Chat and admin commands going out and what comes back of them
+#[derive(SystemParam)]
+pub struct ConsoleRequests<'w, 's> {
+    // admin commands name units by id
+    ids: Query<'w, 's, &'static UnitId>,
+    chat: EventWriter<'w, 's, ChatEvent>,
+    admin: EventWriter<'w, 's, AdminEvent>,
+    outcomes: EventReader<'w, 's, AdminOutcome>,
+    messages: EventReader<'w, 's, ConsoleMessage>
+}
+
+impl Console {
+    fn print(&mut self, line: String) {
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(line);
+    }
+}
+
+enum Command {
+    Spawn { blueprint: String, position: Vec2 },
+    Copy(String),
+    Paste { blueprint: String, position: Vec2 },
+    Export(String),
+    Import { blueprint: String, string: String },
+    SetTeam(u32),
+    Kick(String),
+    Pause,
+    Usage,
+    #[cfg(not(target_arch = "wasm32"))]
+    Save(String),
+    // `None` loads the newest save
+    #[cfg(not(target_arch = "wasm32"))]
+    Load(Option<String>),
+    Help
+}
+
+fn parse_command(line: &str, localize: &Localize) -> Result<Command, String> {
+    let mut words = line.split_whitespace();
+    let number = |word: Option<&str>| word.and_then(|word| word.parse::<f32>().ok());
+    match words.next() {
+        Some("spawn") => match (words.next(), number(words.next()), number(words.next())) {
+            (Some(blueprint), Some(x), Some(y)) => Ok(Command::Spawn { blueprint: blueprint.to_string(), position: Vec2::new(x, y) }),
+            _ => Err(localize.text("console-usage-spawn").to_string())
+        },
+        Some("copy") => match words.next() {
+            Some(blueprint) => Ok(Command::Copy(blueprint.to_string())),
+            None => Err(localize.text("console-usage-copy").to_string())
+        },
+        Some("paste") => match (words.next(), number(words.next()), number(words.next())) {
+            (Some(blueprint), Some(x), Some(y)) => Ok(Command::Paste { blueprint: blueprint.to_string(), position: Vec2::new(x, y) }),
+            _ => Err(localize.text("console-usage-paste").to_string())
+        },
+        Some("export") => match words.next() {
+            Some(blueprint) => Ok(Command::Export(blueprint.to_string())),
+            None => Err(localize.text("console-usage-export").to_string())
+        },
+        // the string is the rest of the line, programs in it can contain spaces
+        Some("import") => match line.trim_start()["import".len()..].trim_start().split_once(char::is_whitespace) {
+            Some((blueprint, string)) if !string.trim().is_empty() => Ok(Command::Import { blueprint: blueprint.to_string(), string: string.trim().to_string() }),
+            _ => Err(localize.text("console-usage-import").to_string())
+        },
+        Some("team") => match words.next().and_then(|word| word.parse().ok()) {
+            Some(team) => Ok(Command::SetTeam(team)),
+            None => Err(localize.text("console-usage-team").to_string())
+        },
+        Some("kick") => match words.next() {
+            Some(name) => Ok(Command::Kick(name.to_string())),
+            None => Err(localize.text("console-usage-kick").to_string())
+        },
+        Some("pause") => Ok(Command::Pause),
+        Some("usage") => Ok(Command::Usage),
+        #[cfg(not(target_arch = "wasm32"))]
+        Some("save") => match words.next() {
+            Some(name) => Ok(Command::Save(name.to_string())),
+            None => Err(localize.text("console-usage-save").to_string())
+        },
+        #[cfg(not(target_arch = "wasm32"))]
+        Some("load") => Ok(Command::Load(words.next().map(str::to_string))),
+        Some("help") => Ok(Command::Help),
+        Some(command) => Err(localize.format("console-unknown-command", &[("command", &command)])),
+        None => Err(localize.text("console-help").to_string())
+    }
+}
+
+pub fn toggle_console(mut egui_context: ResMut<EguiContext>, keys: Res<Input<KeyCode>>, mut console: ResMut<Console>) {
+    if keys.just_pressed(TOGGLE_KEY) && !egui_context.ctx_mut().wants_keyboard_input() {
+        console.open = !console.open;
+    }
+}
+
+pub fn show_console(
+    mut egui_context: ResMut<EguiContext>,
+    mut console: ResMut<Console>,
+    selected: Res<SelectedUnit>,
+    localize: Res<Localize>,
+    mut blueprints: BlueprintRequests,
+    #[cfg(not(target_arch = "wasm32"))] mut saves: SaveRequests,
+    mut requests: ConsoleRequests)
+{
+    // printed even while closed, so the results are there when it's opened again
+    for outcome in blueprints.outcomes.iter() {
+        let line = match outcome {
+            BlueprintOutcome::Copied { name } => localize.format("console-blueprint-copied", &[("blueprint", name)]),
+            BlueprintOutcome::Exported { name, string } => {
+                egui_context.ctx_mut().output().copied_text = string.clone();
+                localize.format("console-blueprint-exported", &[("blueprint", name), ("string", string)])
+            }
+            BlueprintOutcome::Imported { name } => localize.format("console-blueprint-imported", &[("blueprint", name)]),
+            BlueprintOutcome::Failed { name, error } => localize.format("console-blueprint-failed", &[("blueprint", name), ("error", error)]),
+            BlueprintOutcome::Missing { name } => localize.format("console-blueprint-missing", &[("blueprint", name)])
+        };
+        console.print(line);
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    for outcome in saves.outcomes.iter() {
+        let line = match outcome {
+            SaveOutcome::Saved { name } => localize.format("console-save-saved", &[("save", name)]),
+            SaveOutcome::Loaded { name, units, mismatches } => {
+                console.print(localize.format("console-save-loaded", &[("save", name), ("units", units)]));
+                for mismatch in mismatches {
+                    console.print(localize.format("console-load-mismatch", &[("mismatch", mismatch)]));
+                }
+                continue
+            }
+            SaveOutcome::SaveFailed { name, error } => localize.format("console-save-failed", &[("save", name), ("error", error)]),
+            SaveOutcome::LoadFailed { error } => localize.format("console-load-failed", &[("error", error)])
+        };
+        console.print(line);
+    }
+    for AdminOutcome { by, result } in requests.outcomes.iter() {
+        // the players that gave them are told by the server, pausing is for everyone to know
+        if by.is_some() && !matches!(result, AdminResult::Paused(_)) {
+            continue
+        }
+        let line = match result {
+            AdminResult::Kicked { name } => localize.format("console-kicked", &[("player", name)]),
+            AdminResult::NotPlaying { name } => localize.format("console-not-playing", &[("player", name)]),
+            AdminResult::Paused(true) => localize.text("console-paused").to_string(),
+            AdminResult::Paused(false) => localize.text("console-resumed").to_string(),
+            AdminResult::Spawning { name, position } => localize.format("console-spawning", &[("blueprint", name), ("x", &position.x), ("y", &position.y)]),
+            AdminResult::TeamChanged { team, .. } => localize.format("console-team-joined", &[("team", team)]),
+            AdminResult::NoSuchUnit { unit } => localize.format("console-no-unit", &[("unit", unit)]),
+            AdminResult::Usage(teams) if teams.is_empty() => localize.text("console-script-usage-none").to_string(),
+            AdminResult::Usage(teams) => {
+                for usage in teams {
+                    let key = if usage.throttled { "console-script-usage-throttled" } else { "console-script-usage" };
+                    console.print(localize.format(key, &[("team", &usage.team), ("instructions", &(usage.instructions / 1000)), ("memory", &(usage.memory / 1024))]));
+                }
+                continue
+            }
+        };
+        console.print(line);
+    }
+    for message in requests.messages.iter() {
+        let line = match message {
+            ConsoleMessage::Chat { from, text } => localize.format("console-chat-from", &[("player", from), ("text", text)]),
+            ConsoleMessage::Notice(text) => text.clone()
+        };
+        console.print(line);
+    }
+    if !console.open {
+        return
+    }
+    let mut open = true;
+    let mut submitted = None;
+    egui::Window::new(localize.text("console-title")).open(&mut open).default_width(500.0).show(egui_context.ctx_mut(), |ui| {
+        egui::ScrollArea::vertical().max_height(300.0).stick_to_bottom().show(ui, |ui| {
+            for line in &console.history {
+                ui.label(egui::RichText::new(line).monospace());
+            }
+        });
+        ui.separator();
+        let response = ui.text_edit_singleline(&mut console.input);
+        if response.lost_focus() && ui.input().key_pressed(egui::Key::Enter) {
+            submitted = Some(std::mem::take(&mut console.input));
+            response.request_focus();
+        }
+    });
+    if !open {
+        console.open = false;
+    }
+    let line = match submitted {
+        Some(line) if !line.trim().is_empty() => line,
+        _ => return
+    };
+    let command = match line.trim().strip_prefix('/') {
+        Some(command) => command,
+        None => {
+            requests.chat.send(ChatEvent { text: line.trim().to_string() });
+            return console.print(localize.format("console-chat", &[("text", &line.trim())]))
+        }
+    };
+    console.print(format!("> {}", line.trim()));
+    let reply = match parse_command(command, &localize) {
+        Ok(Command::Spawn { blueprint, position }) => {
+            requests.admin.send(AdminEvent { command: AdminCommand::Spawn { name: blueprint, position: position.to_array() }, by: None });
+            return
+        }
+        Ok(Command::Copy(blueprint)) => match selected.0 {
+            Some(unit) => {
+                blueprints.events.send(BlueprintEvent::Copy { unit, name: blueprint });
+                return
+            }
+            None => localize.text("console-select-unit").to_string()
+        },
+        Ok(Command::Paste { blueprint, position }) => {
+            let reply = localize.format("console-spawning", &[("blueprint", &blueprint), ("x", &position.x), ("y", &position.y)]);
+            blueprints.events.send(BlueprintEvent::Paste { name: blueprint, position });
+            reply
+        }
+        Ok(Command::Export(blueprint)) => {
+            blueprints.events.send(BlueprintEvent::Export { name: blueprint });
+            return
+        }
+        Ok(Command::Import { blueprint, string }) => {
+            blueprints.events.send(BlueprintEvent::Import { name: blueprint, string });
+            return
+        }
+        Ok(Command::SetTeam(team)) => match selected.0.and_then(|unit| requests.ids.get(unit).ok()) {
+            Some(id) => {
+                requests.admin.send(AdminEvent { command: AdminCommand::SetTeam { unit: id.0, team }, by: None });
+                return
+            }
+            None => localize.text("console-select-unit").to_string()
+        },
+        Ok(Command::Kick(name)) => {
+            requests.admin.send(AdminEvent { command: AdminCommand::Kick { name }, by: None });
+            return
+        }
+        Ok(Command::Pause) => {
+            requests.admin.send(AdminEvent { command: AdminCommand::Pause, by: None });
+            return
+        }
+        Ok(Command::Usage) => {
+            requests.admin.send(AdminEvent { command: AdminCommand::Usage, by: None });
+            return
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        Ok(Command::Save(name)) => {
+            saves.saves.send(SaveEvent { name });
+            return
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        Ok(Command::Load(name)) => {
+            saves.loads.send(LoadEvent { name });
+            return
+        }
+        Ok(Command::Help) => localize.text("console-help").to_string(),
+        Err(error) => error
+    };
+    console.print(reply);
+}
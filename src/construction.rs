@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+#[cfg(not(target_arch = "wasm32"))]
+use mlua::prelude::*;
+use serde::Deserialize;
+use super::{Prototype, ComponentPrototype, Prototypes, Unit, TICK_RATE};
+use crate::collider::{ColliderPrototype, ColliderName};
+use crate::{inventory::Inventory, health::Health, heat::Heat, circuit::CircuitConnector, logistics::{Logistics, DroneHub}, belt::Belt};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::program::raise_intent;
+
+const GHOST_COLOR: Color = Color::rgba(0.4, 0.7, 1.0, 0.4);
+
+#[derive(Prototype, Deserialize, Clone)]
+#[prot_category(structure)]
+pub struct StructurePrototype {
+    name: String,
+    #[serde(default)]
+    cost: HashMap<String, u32>,
+    build_time: f32, // seconds
+    #[serde(default = "default_size")]
+    size: [f32; 2], // tiles
+    #[serde(default)]
+    texture: Option<String>,
+    #[serde(default)]
+    health: Option<String>,
+    #[serde(default)]
+    circuit_connector: Option<String>,
+    #[serde(default)]
+    inventory: Option<String>,
+    #[serde(default)]
+    logistics: Option<Logistics>,
+    #[serde(default)]
+    drone_hub: Option<DroneHub>,
+    #[serde(default)]
+    belt: Option<String>,
+    // covers `size` when not set
+    #[serde(default)]
+    collider: Option<String>,
+    // units can drive through structures that aren't solid
+    #[serde(default = "default_solid")]
+    solid: bool,
+    // units inside are spared from the weather, only useful for structures that aren't solid
+    #[serde(default)]
+    shelter: bool
+}
+
+fn default_solid() -> bool {
+    true
+}
+
+fn default_size() -> [f32; 2] {
+    [1.0, 1.0]
+}
+
+#[derive(Component, Prototype, ComponentPrototype, Deserialize, Clone)]
+#[prot_category(builder)]
+pub struct Builder {
+    name: String,
+    range: f32, // tiles
+    #[serde(default = "default_build_speed")]
+    build_speed: f32,
+    #[serde(default)]
+    heat: f32, // heat / second while building
+    #[serde(skip)]
+    request: Option<BuildRequest>,
+    #[serde(skip)]
+    site: Option<Entity>
+}
+
+fn default_build_speed() -> f32 {
+    1.0
+}
+
+#[derive(Clone)]
+pub struct BuildRequest {
+    structure: String,
+    position: Vec2,
+    rotation: f32 // degrees clockwise
+}
+
+#[derive(Component)]
+pub struct Structure {
+    pub prototype: String
+}
+
+/// Ghost of a structure being built
+#[derive(Component)]
+pub struct ConstructionSite {
+    structure: String,
+    progress: f32, // seconds of work done
+    builder: Entity
+}
+
+impl ConstructionSite {
+    pub fn structure(&self) -> &str {
+        &self.structure
+    }
+}
+
+pub fn start_construction(
+    mut commands: Commands,
+    mut builders: Query<(Entity, &mut Builder, &Transform, &mut Inventory), With<Unit>>,
+    prototypes: Res<Prototypes>,
+    assets: Res<AssetServer>)
+{
+    for (entity, mut builder, transform, mut inventory) in builders.iter_mut() {
+        let request = match builder.request.take() {
+            Some(request) => request,
+            None => continue
+        };
+        if builder.site.is_some() || transform.translation.truncate().distance(request.position) > builder.range {
+            continue
+        }
+        if let Some(structure) = StructurePrototype::from_pt(&prototypes, &request.structure) {
+            if !inventory.remove_all(&structure.cost) {
+                continue
+            }
+            let site_transform = Transform::from_translation(request.position.extend(0.0))
+                .with_rotation(Quat::from_rotation_z(-request.rotation.to_radians()));
+            let site = commands.spawn_bundle(structure.ghost_bundle(&assets, site_transform))
+                .insert(ConstructionSite { structure: structure.name.clone(), progress: 0.0, builder: entity })
+                .id();
+            builder.site = Some(site);
+        }
+    }
+}
+
+pub fn progress_construction(
+    mut commands: Commands,
+    mut sites: Query<(Entity, &mut ConstructionSite, &Transform)>,
+    mut builders: Query<(&mut Builder, &Transform, Option<&mut Heat>), With<Unit>>,
+    prototypes: Res<Prototypes>,
+    assets: Res<AssetServer>)
+{
+    for (entity, mut site, transform) in sites.iter_mut() {
+        let ((mut builder, builder_transform, mut heat), structure) = match (builders.get_mut(site.builder), StructurePrototype::from_pt(&prototypes, &site.structure)) {
+            (Ok(builder), Some(structure)) => (builder, structure),
+            // abandoned or invalid sites are removed, the spent items are lost
+            _ => {
+                commands.entity(entity).despawn_recursive();
+                continue
+            }
+        };
+        let position = transform.translation.truncate();
+        if builder_transform.translation.truncate().distance(position) > builder.range || heat.as_ref().map_or(false, |heat| heat.is_overheated()) {
+            continue
+        }
+        site.progress += builder.build_speed / TICK_RATE as f32;
+        if let Some(heat) = &mut heat {
+            heat.add(builder.heat / TICK_RATE as f32);
+        }
+        if site.progress >= structure.build_time {
+            commands.entity(entity).despawn_recursive();
+            structure.spawn(&mut commands, &assets, &prototypes, *transform);
+            builder.site = None;
+        }
+    }
+}
+
+impl StructurePrototype {
+    pub fn cost(&self) -> &HashMap<String, u32> {
+        &self.cost
+    }
+
+    pub fn texture(&self) -> Option<&str> {
+        self.texture.as_deref()
+    }
+
+    pub fn size(&self) -> [f32; 2] {
+        self.size
+    }
+
+    pub fn is_shelter(&self) -> bool {
+        self.shelter
+    }
+
+    fn sprite_bundle(&self, assets: &AssetServer, transform: Transform) -> SpriteBundle {
+        SpriteBundle {
+            texture: self.texture.as_ref().map(|texture| assets.load(texture.as_str())).unwrap_or_default(),
+            sprite: Sprite {
+                custom_size: Some(Vec2::from(self.size)),
+                ..default()
+            },
+            transform,
+            ..default()
+        }
+    }
+
+    /// Sprite of the structure's construction site
+    pub fn ghost_bundle(&self, assets: &AssetServer, transform: Transform) -> SpriteBundle {
+        let mut sprite_bundle = self.sprite_bundle(assets, transform);
+        sprite_bundle.sprite.color = GHOST_COLOR;
+        sprite_bundle
+    }
+
+    pub fn spawn(&self, commands: &mut Commands, assets: &AssetServer, prototypes: &Prototypes, transform: Transform) -> Entity {
+        let [width, height] = self.size;
+        let mut structure = commands.spawn();
+        structure
+            .insert(Structure { prototype: self.name.clone() })
+            .insert(RigidBody::Fixed)
+            .insert_bundle(self.sprite_bundle(assets, transform));
+        match self.collider.as_ref().and_then(|collider| Some((collider, ColliderPrototype::component_from_pt(prototypes, collider)?))) {
+            Some((name, collider)) => structure.insert(collider).insert(ColliderName(name.clone())),
+            None => structure.insert(Collider::cuboid(width / 2.0, height / 2.0))
+        };
+        if let Some(health) = self.health.as_ref().and_then(|health| Health::component_from_pt(prototypes, health)) {
+            structure.insert(health);
+        }
+        if let Some(connector) = self.circuit_connector.as_ref().and_then(|connector| CircuitConnector::component_from_pt(prototypes, connector)) {
+            structure.insert(connector);
+        }
+        if let Some(inventory) = self.inventory.as_ref().and_then(|inventory| Inventory::component_from_pt(prototypes, inventory)) {
+            structure.insert(inventory);
+        }
+        if let Some(logistics) = &self.logistics {
+            structure.insert(logistics.clone());
+        }
+        if let Some(drone_hub) = &self.drone_hub {
+            structure.insert(drone_hub.clone());
+        }
+        if let Some(belt) = &self.belt {
+            structure.insert(Belt { prototype: belt.clone() });
+        }
+        if !self.solid {
+            structure.insert(Sensor);
+        }
+        structure.id()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct LuaBuilderHandle<'a> {
+    pub builder: &'a mut Builder
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LuaUserData for LuaBuilderHandle<'_> {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut("build", |lua, lua_handle, (structure, x, y, rotation): (String, f32, f32, Option<f32>)| {
+            raise_intent(lua)?;
+            let rotation = rotation.unwrap_or(0.0);
+            lua_handle.builder.request = Some(BuildRequest { structure, position: Vec2::new(x, y), rotation });
+            Ok(())
+        });
+    }
+
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("range", |_lua, lua_handle| {
+            Ok(lua_handle.builder.range)
+        });
+        fields.add_field_method_get("is_building", |_lua, lua_handle| {
+            Ok(lua_handle.builder.site.is_some())
+        });
+    }
+}
@@ -0,0 +1,166 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+use super::{Prototype, ComponentPrototype, Prototypes, Unit, UnitId, SimTick, SimStepLabel, MovementLabel, EventLabel, TICK_RATE};
+use crate::health::{Health, DamageEvent, DamageType};
+use crate::visual::Visuals;
+use crate::subscriptions::{ScriptEvent, unit_id};
+use crate::data_value::DataValue;
+
+const CREATURE_HALF_SIZE: f32 = 0.35;
+// creatures give up on units that get this many times their aggro range away
+const LEASH_FACTOR: f32 = 1.5;
+// wander targets closer than this count as reached
+const ARRIVAL_DISTANCE: f32 = 0.1;
+
+/// Hostile creatures moved by the engine instead of a program
+pub struct CreaturePlugin;
+
+impl Plugin for CreaturePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(run_creatures.after(MovementLabel).before(EventLabel).with_run_criteria(SimStepLabel));
+    }
+}
+
+#[derive(Prototype, Deserialize)]
+#[prot_category(creature)]
+pub struct CreaturePrototype {
+    name: String,
+    visual: String,
+    health: String,
+    speed: f32, // tiles / second
+    // how far from its spawn point it wanders
+    #[serde(default = "default_wander_radius")]
+    wander_radius: f32,
+    aggro_range: f32,
+    attack_range: f32,
+    attack_damage: f32,
+    #[serde(default = "default_damage_type")]
+    damage_type: DamageType,
+    attack_interval: f32 // seconds
+}
+
+fn default_wander_radius() -> f32 {
+    3.0
+}
+
+fn default_damage_type() -> DamageType {
+    DamageType::Physical
+}
+
+enum CreatureState {
+    Wandering(Option<Vec2>),
+    Chasing(Entity)
+}
+
+#[derive(Component)]
+pub struct Creature {
+    prototype: String,
+    home: Vec2,
+    state: CreatureState,
+    next_attack: u64
+}
+
+impl Creature {
+    pub fn prototype(&self) -> &str {
+        &self.prototype
+    }
+}
+
+impl CreaturePrototype {
+    pub fn spawn(&self, commands: &mut Commands, visuals: &Visuals, prototypes: &Prototypes, position: Vec2) -> Entity {
+        let mut creature = commands.spawn();
+        creature
+            .insert(Creature { prototype: self.name.clone(), home: position, state: CreatureState::Wandering(None), next_attack: 0 })
+            .insert(Collider::cuboid(CREATURE_HALF_SIZE, CREATURE_HALF_SIZE))
+            .insert(RigidBody::KinematicPositionBased);
+        visuals.insert(&mut creature, &self.visual, position);
+        if let Some(health) = Health::component_from_pt(prototypes, &self.health) {
+            creature.insert(health);
+        }
+        creature.id()
+    }
+}
+
+// Derived from the creature and the tick instead of a random generator, so creatures wander the
+// same way on every machine
+fn wander_offset(entity: Entity, tick: u64, radius: f32) -> Vec2 {
+    let mut seed = entity.to_bits().to_le_bytes().to_vec();
+    seed.extend(tick.to_le_bytes());
+    let hash = blake3::hash(&seed);
+    let bytes = hash.as_bytes();
+    let fraction = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as f32 / u32::MAX as f32;
+    Vec2::from_angle(fraction(0) * std::f32::consts::TAU) * fraction(4).sqrt() * radius
+}
+
+// creatures only go after units they can damage
+type Prey = (With<Unit>, With<Health>);
+
+fn run_creatures(
+    mut creatures: Query<(Entity, &mut Creature, &mut Transform, &Collider), Without<Unit>>,
+    units: Query<(Entity, &Transform, &UnitId), Prey>,
+    rapier_context: Res<RapierContext>,
+    sim_tick: Res<SimTick>,
+    prototypes: Res<Prototypes>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut script_events: EventWriter<ScriptEvent>)
+{
+    for (entity, mut creature, mut transform, collider) in creatures.iter_mut() {
+        let prototype = match CreaturePrototype::from_pt(&prototypes, &creature.prototype) {
+            Some(prototype) => prototype,
+            None => continue
+        };
+        let position = transform.translation.truncate();
+        let target_position = |target: Entity| units.get(target).ok().map(|(_, transform, _)| transform.translation.truncate());
+        if let CreatureState::Chasing(target) = creature.state {
+            // the target was destroyed or got away
+            if target_position(target).map_or(true, |target| target.distance(position) > prototype.aggro_range * LEASH_FACTOR) {
+                creature.state = CreatureState::Wandering(None);
+            }
+        }
+        if let CreatureState::Wandering(_) = creature.state {
+            let nearest = units.iter()
+                .map(|(unit, transform, id)| (unit, *id, transform.translation.truncate().distance(position)))
+                .filter(|(_, _, distance)| *distance <= prototype.aggro_range)
+                .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+            if let Some((target, id, _)) = nearest {
+                creature.state = CreatureState::Chasing(target);
+                // creatures aren't saved, their id only tells apart events of different creatures
+                script_events.send(ScriptEvent::new("creature_aggro", position)
+                    .with("creature", DataValue::Integer(entity.to_bits() as i64))
+                    .with("unit", unit_id(id)));
+            }
+        }
+        let destination = match creature.state {
+            CreatureState::Chasing(target) => match target_position(target) {
+                Some(target_position) if target_position.distance(position) <= prototype.attack_range => {
+                    if sim_tick.0 >= creature.next_attack {
+                        damage_events.send(DamageEvent { target, amount: prototype.attack_damage, damage_type: prototype.damage_type });
+                        creature.next_attack = sim_tick.0 + (prototype.attack_interval * TICK_RATE as f32) as u64;
+                    }
+                    None
+                }
+                target_position => target_position
+            },
+            CreatureState::Wandering(Some(destination)) if destination.distance(position) > ARRIVAL_DISTANCE => Some(destination),
+            CreatureState::Wandering(_) => {
+                let destination = creature.home + wander_offset(entity, sim_tick.0, prototype.wander_radius);
+                creature.state = CreatureState::Wandering(Some(destination));
+                Some(destination)
+            }
+        };
+        if let Some(destination) = destination {
+            let delta = (destination - position).clamp_length_max(prototype.speed / TICK_RATE as f32);
+            let rotation = transform.rotation.to_euler(EulerRot::XYZ).2;
+            let filter = QueryFilter::default().exclude_collider(entity).exclude_sensors();
+            if rapier_context.cast_shape(position, rotation, delta, collider, 1.0, filter).is_some() {
+                // blocked wanderers pick somewhere else to go, chasers keep pushing
+                if let CreatureState::Wandering(_) = creature.state {
+                    creature.state = CreatureState::Wandering(None);
+                }
+            } else {
+                transform.translation += delta.extend(0.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use bevy::prelude::*;
+#[cfg(not(feature = "headless"))]
+use std::{collections::VecDeque, fmt::Write, time::Duration};
+#[cfg(not(feature = "headless"))]
+use bevy::{ecs::{schedule::SystemLabel, system::SystemParam}, diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin, EntityCountDiagnosticsPlugin}, utils::Instant};
+#[cfg(not(feature = "headless"))]
+use bevy_prototype_debug_lines::DebugLines;
+#[cfg(not(feature = "headless"))]
+use bevy_rapier2d::prelude::*;
+#[cfg(not(feature = "headless"))]
+use super::{Unit, MovementIntent, UiFont, TICK_RATE};
+#[cfg(not(feature = "headless"))]
+use crate::{storage::Storage, team::ScriptUsage, program::{UnitProgram, ProgramStatus}, checksum::StateChecksum};
+
+#[cfg(not(feature = "headless"))]
+const TRAIL_LENGTH: usize = 120;
+#[cfg(not(feature = "headless"))]
+const PERFORMANCE_KEY: KeyCode = KeyCode::F5;
+#[cfg(not(feature = "headless"))]
+const PHASE_NAMES: [&str; 4] = ["clocks and scripts", "intents", "movement", "world and events"];
+#[cfg(not(feature = "headless"))]
+const SENSOR_DEBUG_COLOR: Color = Color::rgba(0.2, 0.4, 1.0, 1.0);
+
+#[cfg(not(feature = "headless"))]
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DebugMenuLabel;
+
+/// Runtime debug toggles. F1 opens the menu, number keys toggle the entries while it is open. F5
+/// toggles the performance overlay at any time.
+#[cfg(not(feature = "headless"))]
+pub struct DebugSettings {
+    pub menu_open: bool,
+    pub colliders: bool,
+    pub sensors: bool,
+    pub movement_overlay: bool,
+    pub fps: bool,
+    pub entity_count: bool,
+    pub storage_usage: bool,
+    pub labels: bool,
+    pub telemetry: bool,
+    pub log_window: bool,
+    pub script_usage: bool,
+    pub performance: bool
+}
+
+#[cfg(not(feature = "headless"))]
+impl Default for DebugSettings {
+    fn default() -> Self {
+        Self {
+            menu_open: false,
+            colliders: cfg!(feature = "debug"),
+            sensors: cfg!(feature = "debug"),
+            movement_overlay: false,
+            fps: false,
+            entity_count: false,
+            storage_usage: false,
+            labels: true,
+            telemetry: false,
+            log_window: false,
+            script_usage: false,
+            performance: false
+        }
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+#[derive(Component)]
+pub struct DebugText;
+
+#[cfg(not(feature = "headless"))]
+#[derive(Component, Default)]
+pub struct PositionTrail(VecDeque<Vec2>);
+
+/// Last shape cast done by `handle_movement` for this unit
+#[derive(Component, Default, Clone, Copy)]
+pub struct ShapeCastTrace {
+    // only drawn by the movement overlay
+    #[cfg(not(feature = "headless"))]
+    pub origin: Vec2,
+    #[cfg(not(feature = "headless"))]
+    pub delta: Vec2,
+    pub hit: bool
+}
+
+#[cfg(not(feature = "headless"))]
+pub fn spawn_debug_text(mut commands: Commands, font: Res<UiFont>) {
+    let style = TextStyle {
+        font: font.0.clone(),
+        font_size: 16.0,
+        color: Color::WHITE
+    };
+    commands.spawn_bundle(TextBundle::from_section("", style)
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: Val::Px(5.0),
+                left: Val::Px(5.0),
+                ..default()
+            },
+            ..default()
+        }))
+        .insert(DebugText);
+}
+
+/// Wall time from the start of the simulation step to the end of each phase, see `PHASE_NAMES`.
+/// Systems of neighbouring phases can run in parallel with the checkpoints, so it's only a rough
+/// breakdown. Rapier's stages run after the step and aren't included.
+#[cfg(not(feature = "headless"))]
+pub struct PhaseTimings {
+    start: Instant,
+    ends: [Duration; 4]
+}
+
+#[cfg(not(feature = "headless"))]
+impl Default for PhaseTimings {
+    fn default() -> Self {
+        Self { start: Instant::now(), ends: [Duration::ZERO; 4] }
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+impl PhaseTimings {
+    fn phases(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.ends.iter().scan(Duration::ZERO, |previous, end| {
+            let phase = end.saturating_sub(*previous);
+            *previous = *end;
+            Some(phase)
+        })
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+pub fn start_phase_timing(mut timings: ResMut<PhaseTimings>) {
+    timings.start = Instant::now();
+}
+
+// one checkpoint system per phase, `PHASE` indexes `PHASE_NAMES`
+#[cfg(not(feature = "headless"))]
+pub fn end_phase_timing<const PHASE: usize>(mut timings: ResMut<PhaseTimings>) {
+    timings.ends[PHASE] = timings.start.elapsed();
+}
+
+#[cfg(not(feature = "headless"))]
+pub fn handle_debug_menu_input(mut settings: ResMut<DebugSettings>, input: Res<Input<KeyCode>>) {
+    if input.just_pressed(KeyCode::F1) {
+        settings.menu_open = !settings.menu_open;
+    }
+    if input.just_pressed(PERFORMANCE_KEY) {
+        settings.performance = !settings.performance;
+    }
+    if !settings.menu_open {
+        return
+    }
+    for key in input.get_just_pressed() {
+        match key {
+            KeyCode::Key1 => settings.colliders = !settings.colliders,
+            KeyCode::Key2 => settings.sensors = !settings.sensors,
+            KeyCode::Key3 => settings.movement_overlay = !settings.movement_overlay,
+            KeyCode::Key4 => settings.fps = !settings.fps,
+            KeyCode::Key5 => settings.entity_count = !settings.entity_count,
+            KeyCode::Key6 => settings.storage_usage = !settings.storage_usage,
+            KeyCode::Key7 => settings.labels = !settings.labels,
+            KeyCode::Key8 => settings.telemetry = !settings.telemetry,
+            KeyCode::Key9 => settings.log_window = !settings.log_window,
+            KeyCode::Key0 => settings.script_usage = !settings.script_usage,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+pub fn apply_debug_render_settings(
+    mut commands: Commands,
+    settings: Res<DebugSettings>,
+    mut debug_render_context: ResMut<DebugRenderContext>,
+    sensors: Query<Entity, With<Sensor>>,
+    new_sensors: Query<Entity, Added<Sensor>>)
+{
+    let sensor_color = if settings.sensors { SENSOR_DEBUG_COLOR } else { Color::NONE };
+    if settings.is_changed() {
+        debug_render_context.pipeline.mode = if settings.colliders {
+            DebugRenderMode::default()
+        } else {
+            DebugRenderMode::empty()
+        };
+        for sensor in sensors.iter() {
+            commands.entity(sensor).insert(ColliderDebugColor(sensor_color));
+        }
+    } else {
+        for sensor in new_sensors.iter() {
+            commands.entity(sensor).insert(ColliderDebugColor(sensor_color));
+        }
+    }
+}
+
+/// What the performance overlay shows besides the frame rate
+#[cfg(not(feature = "headless"))]
+#[derive(SystemParam)]
+pub struct PerformanceStats<'w, 's> {
+    timings: Res<'w, PhaseTimings>,
+    programs: Query<'w, 's, &'static UnitProgram, With<Unit>>,
+    rapier_context: Res<'w, RapierContext>,
+    checksum: Res<'w, StateChecksum>
+}
+
+#[cfg(not(feature = "headless"))]
+pub fn update_debug_text(
+    settings: Res<DebugSettings>,
+    diagnostics: Res<Diagnostics>,
+    storages: Query<(Entity, &Storage), With<Unit>>,
+    script_usage: Res<ScriptUsage>,
+    performance: PerformanceStats,
+    mut text: Query<&mut Text, With<DebugText>>)
+{
+    let mut text = text.single_mut();
+    let content = &mut text.sections[0].value;
+    content.clear();
+    if settings.menu_open {
+        let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+        writeln!(content, "[1] colliders: {}", on_off(settings.colliders)).unwrap();
+        writeln!(content, "[2] sensors: {}", on_off(settings.sensors)).unwrap();
+        writeln!(content, "[3] movement overlay: {}", on_off(settings.movement_overlay)).unwrap();
+        writeln!(content, "[4] fps: {}", on_off(settings.fps)).unwrap();
+        writeln!(content, "[5] entity count: {}", on_off(settings.entity_count)).unwrap();
+        writeln!(content, "[6] storage usage: {}", on_off(settings.storage_usage)).unwrap();
+        writeln!(content, "[7] unit labels: {}", on_off(settings.labels)).unwrap();
+        writeln!(content, "[8] telemetry recording: {}", on_off(settings.telemetry)).unwrap();
+        writeln!(content, "[9] log window: {}", on_off(settings.log_window)).unwrap();
+        writeln!(content, "[0] script usage: {}", on_off(settings.script_usage)).unwrap();
+        writeln!(content, "[F5] performance: {}", on_off(settings.performance)).unwrap();
+    }
+    if settings.fps {
+        if let Some(fps) = diagnostics.get(FrameTimeDiagnosticsPlugin::FPS).and_then(|fps| fps.average()) {
+            writeln!(content, "FPS: {:.0}", fps).unwrap();
+        }
+    }
+    if settings.entity_count {
+        if let Some(count) = diagnostics.get(EntityCountDiagnosticsPlugin::ENTITY_COUNT).and_then(|count| count.value()) {
+            writeln!(content, "Entities: {}", count).unwrap();
+        }
+    }
+    if settings.storage_usage {
+        for (entity, storage) in storages.iter() {
+            writeln!(content, "Storage {:?}: {}/{} bytes", entity, storage.usage(), storage.quota()).unwrap();
+        }
+    }
+    if settings.script_usage {
+        for (team, usage) in script_usage.iter() {
+            let throttled = if usage.throttled { ", throttled" } else { "" };
+            writeln!(content, "Team {} scripts: {}k instructions/s, {} KiB{}", team.0, usage.instructions / 1000, usage.memory / 1024, throttled).unwrap();
+        }
+    }
+    if settings.performance {
+        if let Some(fps) = diagnostics.get(FrameTimeDiagnosticsPlugin::FPS).and_then(|fps| fps.average()) {
+            writeln!(content, "FPS: {:.0}", fps).unwrap();
+        }
+        let PerformanceStats { timings, programs, rapier_context, checksum } = performance;
+        let total: Duration = timings.phases().sum();
+        writeln!(content, "Step: {:.2} ms", total.as_secs_f64() * 1000.0).unwrap();
+        for (name, phase) in PHASE_NAMES.iter().zip(timings.phases()) {
+            writeln!(content, "  {}: {:.2} ms", name, phase.as_secs_f64() * 1000.0).unwrap();
+        }
+        let running = programs.iter().filter(|program| program.status() == ProgramStatus::Running).count();
+        let memory: usize = programs.iter().map(UnitProgram::memory).sum();
+        writeln!(content, "Units: {}, running scripts: {}", programs.iter().count(), running).unwrap();
+        writeln!(content, "Lua memory: {} KiB", memory / 1024).unwrap();
+        writeln!(content, "Contact pairs: {}", rapier_context.narrow_phase.contact_pairs().count()).unwrap();
+        writeln!(content, "Checksum: {}", &checksum.current().to_hex()[..16]).unwrap();
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+pub fn record_position_trails(mut units: Query<(&mut PositionTrail, &Transform), With<Unit>>) {
+    for (mut trail, transform) in units.iter_mut() {
+        let position = transform.translation.truncate();
+        if trail.0.back() != Some(&position) {
+            if trail.0.len() == TRAIL_LENGTH {
+                trail.0.pop_front();
+            }
+            trail.0.push_back(position);
+        }
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+type OverlayUnit = (&'static Transform, Option<&'static PositionTrail>, Option<&'static MovementIntent>, Option<&'static ShapeCastTrace>);
+
+// Runs before movement is handled, otherwise the intents would already be consumed
+#[cfg(not(feature = "headless"))]
+pub fn draw_debug_overlay(
+    settings: Res<DebugSettings>,
+    mut lines: ResMut<DebugLines>,
+    units: Query<OverlayUnit, With<Unit>>)
+{
+    if !settings.movement_overlay {
+        return
+    }
+    for (transform, trail, movement, trace) in units.iter() {
+        if let Some(trail) = trail {
+            for (start, end) in trail.0.iter().zip(trail.0.iter().skip(1)) {
+                lines.line_colored(start.extend(0.5), end.extend(0.5), 0.0, Color::YELLOW);
+            }
+        }
+        if let Some(movement) = movement {
+            if movement.input_move != Vec2::ZERO {
+                let intent = movement.input_move.rotate(transform.right().truncate());
+                let start = transform.translation.truncate();
+                lines.line_colored(start.extend(0.5), (start + intent).extend(0.5), 0.0, Color::CYAN);
+            }
+        }
+        if let Some(trace) = trace {
+            // per tick deltas are too short to see, so the cast is drawn scaled to a second
+            let color = if trace.hit { Color::RED } else { Color::GREEN };
+            let end = trace.origin + trace.delta * TICK_RATE as f32;
+            lines.line_colored(trace.origin.extend(0.5), end.extend(0.5), 0.0, color);
+        }
+    }
+}
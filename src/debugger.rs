@@ -0,0 +1,262 @@
+use std::{collections::{BTreeMap, HashSet}, io::{self, BufRead, BufReader, ErrorKind, Write}, net::{TcpListener, TcpStream}, sync::{Arc, Mutex}};
+use bevy::prelude::*;
+use mlua::prelude::*;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use super::UnitId;
+use crate::program::UnitProgram;
+use crate::data_value::DataValue;
+use crate::logging::SCRIPT;
+
+/// `scriplets --debug-server <port>` lets a debugger connect on that port of localhost
+pub const DEBUG_SERVER_FLAG: &str = "--debug-server";
+
+// globals every program has, left out of `variables`
+const STANDARD_GLOBALS: [&str; 7] = ["_G", "_VERSION", "coroutine", "math", "string", "table", "utf8"];
+
+/// What the debugger sends, one JSON object per line
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum DebugCommand {
+    Attach { unit: u64 },
+    SetBreakpoints { lines: Vec<i32> },
+    Pause,
+    Continue,
+    Step,
+    Variables,
+    Detach
+}
+
+/// What the game answers with, one JSON object per line
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum DebugMessage {
+    Attached { unit: u64 },
+    Stopped { line: i32, reason: &'static str },
+    Variables { line: i32, globals: BTreeMap<String, Value> },
+    Detached,
+    Error { message: String }
+}
+
+struct Session {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    // bytes of a line that didn't fully arrive yet
+    pending: Vec<u8>,
+    attached: Option<(u64, Entity)>,
+    breakpoints: HashSet<i32>,
+    // stops on the next line
+    stepping: bool,
+    closed: bool
+}
+
+impl Session {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            pending: Vec::new(),
+            attached: None,
+            breakpoints: HashSet::new(),
+            stepping: false,
+            closed: false
+        })
+    }
+
+    fn send(&mut self, message: &DebugMessage) {
+        let line = serde_json::to_string(message).unwrap();
+        if writeln!(self.writer, "{}", line).is_err() {
+            self.closed = true;
+        }
+    }
+
+    fn error(&mut self, message: impl ToString) {
+        self.send(&DebugMessage::Error { message: message.to_string() });
+    }
+
+    /// Next complete command, `None` if there's none yet or the connection is closed
+    fn read_command(&mut self) -> Option<Result<DebugCommand, serde_json::Error>> {
+        match self.reader.read_until(b'\n', &mut self.pending) {
+            Ok(0) => self.closed = true,
+            Ok(_) if self.pending.ends_with(b"\n") => {
+                let command = serde_json::from_slice(&self.pending);
+                self.pending.clear();
+                return Some(command)
+            }
+            Ok(_) => (),
+            Err(error) if error.kind() == ErrorKind::WouldBlock => (),
+            Err(_) => self.closed = true
+        }
+        None
+    }
+
+    // commands that work the same whether the program is stopped or not
+    fn handle(&mut self, command: DebugCommand) {
+        match command {
+            DebugCommand::SetBreakpoints { lines } => self.breakpoints = lines.into_iter().collect(),
+            DebugCommand::Pause | DebugCommand::Step => self.stepping = true,
+            DebugCommand::Continue => self.stepping = false,
+            DebugCommand::Detach => {
+                self.attached = None;
+                self.stepping = false;
+                self.send(&DebugMessage::Detached);
+            }
+            DebugCommand::Attach { .. } => self.error("can't attach while the program is stopped, continue first"),
+            DebugCommand::Variables => self.error("variables can only be read while the program is stopped")
+        }
+    }
+
+    /// Blocks the whole game until the debugger continues, steps, detaches or goes away
+    fn wait(&mut self, lua: &Lua, line: i32) {
+        if self.writer.set_nonblocking(false).is_err() {
+            self.closed = true;
+        }
+        while !self.closed {
+            match self.read_command() {
+                Some(Ok(DebugCommand::Continue)) => break,
+                Some(Ok(DebugCommand::Step)) => {
+                    self.stepping = true;
+                    break
+                }
+                Some(Ok(DebugCommand::Detach)) => {
+                    self.handle(DebugCommand::Detach);
+                    break
+                }
+                Some(Ok(DebugCommand::Variables)) => match globals(lua) {
+                    Ok(globals) => self.send(&DebugMessage::Variables { line, globals }),
+                    Err(error) => self.error(error)
+                },
+                // already stopped
+                Some(Ok(DebugCommand::Pause)) => (),
+                Some(Ok(command)) => self.handle(command),
+                Some(Err(error)) => self.error(error),
+                None => ()
+            }
+        }
+        self.writer.set_nonblocking(true).ok();
+    }
+}
+
+// Only globals can be read, Lua's debug library that could read locals isn't loaded into
+// programs. Values that can be stored are shown as JSON, others like functions as `tostring` does.
+fn globals(lua: &Lua) -> LuaResult<BTreeMap<String, Value>> {
+    let tostring: LuaFunction = lua.globals().get("tostring")?;
+    let mut globals = BTreeMap::new();
+    for pair in lua.globals().pairs::<LuaValue, LuaValue>() {
+        let (name, value) = pair?;
+        let name = match name {
+            LuaValue::String(name) => name.to_str()?.to_string(),
+            _ => continue
+        };
+        if STANDARD_GLOBALS.contains(&name.as_str()) || matches!(value, LuaValue::Function(_)) {
+            continue
+        }
+        let json = match DataValue::from_lua(value.clone(), lua).ok().and_then(|value| serde_json::to_value(value).ok()) {
+            Some(json) => json,
+            None => Value::String(tostring.call(value)?)
+        };
+        globals.insert(name, json);
+    }
+    Ok(globals)
+}
+
+fn line_hook(session: Arc<Mutex<Session>>) -> impl Fn(&Lua, mlua::Debug) -> LuaResult<()> + Send + 'static {
+    move |lua, debug| {
+        let mut session = session.lock().unwrap();
+        let line = debug.curr_line();
+        if session.attached.is_none() || session.closed {
+            return Ok(())
+        }
+        let reason = if session.stepping {
+            "step"
+        } else if session.breakpoints.contains(&line) {
+            "breakpoint"
+        } else {
+            return Ok(())
+        };
+        session.stepping = false;
+        session.send(&DebugMessage::Stopped { line, reason });
+        session.wait(lua, line);
+        Ok(())
+    }
+}
+
+/// Local TCP server external editors attach a debugger to, one at a time. The protocol is
+/// described in the README.
+pub struct DebugServer {
+    listener: TcpListener,
+    session: Option<Arc<Mutex<Session>>>,
+    // unit the line hook is set on
+    hooked: Option<Entity>
+}
+
+impl DebugServer {
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener, session: None, hooked: None })
+    }
+}
+
+/// Accepts debuggers and handles their commands while the program isn't stopped, and keeps the line
+/// hook on the attached unit. It's set again every tick, reloading a program replaces its
+/// interpreter and the hook with it.
+pub fn poll_debug_server(server: Option<ResMut<DebugServer>>, mut programs: Query<&mut UnitProgram>, ids: Query<(Entity, &UnitId)>) {
+    let mut server = match server {
+        Some(server) => server,
+        None => return
+    };
+    match server.listener.accept() {
+        Ok((stream, address)) => match Session::new(stream) {
+            // a new debugger replaces the old one
+            Ok(session) => {
+                info!(target: SCRIPT, "Debugger connected from {}", address);
+                server.session = Some(Arc::new(Mutex::new(session)));
+            }
+            Err(error) => warn!(target: SCRIPT, "Can't set up the debugger connection from {}: {}", address, error)
+        },
+        Err(error) if error.kind() == ErrorKind::WouldBlock => (),
+        Err(error) => warn!(target: SCRIPT, "Can't accept debugger connection: {}", error)
+    }
+    let (mut attached, mut closed) = (None, false);
+    if let Some(session) = server.session.clone() {
+        let mut session = session.lock().unwrap();
+        while let Some(command) = session.read_command() {
+            match command {
+                Ok(DebugCommand::Attach { unit }) => match ids.iter().find(|(_, id)| id.0 == unit) {
+                    Some((entity, _)) => {
+                        session.attached = Some((unit, entity));
+                        session.stepping = false;
+                        session.send(&DebugMessage::Attached { unit });
+                    }
+                    None => session.error(format!("there's no unit {}", unit))
+                },
+                Ok(command) => session.handle(command),
+                Err(error) => session.error(error)
+            }
+        }
+        if let Some((_, entity)) = session.attached.filter(|(_, entity)| programs.get(*entity).is_err()) {
+            debug!(target: SCRIPT, "Debugged unit {:?} is gone", entity);
+            session.attached = None;
+            session.send(&DebugMessage::Detached);
+        }
+        attached = session.attached.map(|(_, entity)| entity).filter(|_| !session.closed);
+        closed = session.closed;
+    }
+    if closed {
+        info!(target: SCRIPT, "Debugger disconnected");
+        server.session = None;
+    }
+    if server.hooked != attached {
+        if let Some(mut program) = server.hooked.and_then(|entity| programs.get_mut(entity).ok()) {
+            program.remove_line_hook();
+        }
+        server.hooked = attached;
+    }
+    if let (Some(entity), Some(session)) = (attached, server.session.clone()) {
+        if let Ok(mut program) = programs.get_mut(entity) {
+            program.set_line_hook(line_hook(session));
+        }
+    }
+}
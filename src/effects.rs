@@ -0,0 +1,104 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+use strum::AsRefStr;
+use super::{Prototype, Prototypes, TICK_RATE};
+use crate::health::{DamageEvent, DamageType};
+use crate::logging::ASSETS;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, AsRefStr)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum EffectKind {
+    Slow, // magnitude is the fraction of speed lost
+    Stun, // program doesn't tick while active
+    Burn, // magnitude is thermal damage / second
+    Boost // magnitude is the fraction of speed gained
+}
+
+#[derive(Prototype, Deserialize, Clone)]
+#[prot_category(status_effect)]
+pub struct StatusEffectPrototype {
+    name: String,
+    kind: EffectKind,
+    duration: f32, // seconds
+    #[serde(default)]
+    magnitude: f32
+}
+
+pub struct ActiveEffect {
+    pub name: String,
+    pub kind: EffectKind,
+    pub remaining_ticks: u32,
+    pub magnitude: f32
+}
+
+#[derive(Component, Default)]
+pub struct StatusEffects(Vec<ActiveEffect>);
+
+impl StatusEffects {
+    /// Reapplying an active effect refreshes its duration
+    pub fn apply(&mut self, prototype: &StatusEffectPrototype) {
+        let remaining_ticks = (prototype.duration * TICK_RATE as f32).round() as u32;
+        if let Some(active) = self.0.iter_mut().find(|active| active.name == prototype.name) {
+            active.remaining_ticks = active.remaining_ticks.max(remaining_ticks);
+        } else {
+            self.0.push(ActiveEffect {
+                name: prototype.name.clone(),
+                kind: prototype.kind,
+                remaining_ticks,
+                magnitude: prototype.magnitude
+            });
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ActiveEffect> {
+        self.0.iter()
+    }
+
+    pub fn speed_multiplier(&self) -> f32 {
+        self.0.iter().fold(1.0, |multiplier, effect| match effect.kind {
+            EffectKind::Slow => multiplier * (1.0 - effect.magnitude).max(0.0),
+            EffectKind::Boost => multiplier * (1.0 + effect.magnitude),
+            _ => multiplier
+        })
+    }
+
+    pub fn is_stunned(&self) -> bool {
+        self.0.iter().any(|effect| effect.kind == EffectKind::Stun)
+    }
+}
+
+pub struct ApplyEffectEvent {
+    pub target: Entity,
+    pub effect: String
+}
+
+pub fn apply_status_effects(
+    mut effect_events: EventReader<ApplyEffectEvent>,
+    mut targets: Query<&mut StatusEffects>,
+    prototypes: Res<Prototypes>)
+{
+    for event in effect_events.iter() {
+        match (targets.get_mut(event.target), StatusEffectPrototype::from_pt(&prototypes, &event.effect)) {
+            (Ok(mut effects), Some(prototype)) => effects.apply(prototype),
+            (_, None) => warn!(target: ASSETS, "Unknown status effect {}", event.effect),
+            _ => {}
+        }
+    }
+}
+
+pub fn tick_status_effects(mut targets: Query<(Entity, &mut StatusEffects)>, mut damage_events: EventWriter<DamageEvent>) {
+    for (entity, mut effects) in targets.iter_mut() {
+        if effects.0.is_empty() {
+            continue
+        }
+        for effect in effects.0.iter_mut() {
+            // burn damage is dealt once a second so armor doesn't swallow tiny per-tick hits
+            if effect.kind == EffectKind::Burn && effect.remaining_ticks.is_multiple_of(TICK_RATE as u32) {
+                damage_events.send(DamageEvent { target: entity, amount: effect.magnitude, damage_type: DamageType::Thermal });
+            }
+            effect.remaining_ticks = effect.remaining_ticks.saturating_sub(1);
+        }
+        effects.0.retain(|effect| effect.remaining_ticks > 0);
+    }
+}
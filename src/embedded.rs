@@ -0,0 +1,78 @@
+use std::path::{Path, PathBuf};
+use bevy::{prelude::*, asset::{AssetIo, AssetIoError, Metadata, FileType, create_platform_default_asset_io}, utils::BoxedFuture};
+use crate::logging::ASSETS;
+
+/// Built into the binary so the game starts without an assets folder. Files on disk with the same
+/// path take precedence.
+const EMBEDDED_ASSETS: [(&str, &[u8]); 5] = [
+    ("prototypes.json", include_bytes!("../embedded/prototypes.json")),
+    ("unit.png", include_bytes!("../embedded/unit.png")),
+    ("wall.png", include_bytes!("../embedded/wall.png")),
+    ("DejaVuSansMono.ttf", include_bytes!("../assets/DejaVuSansMono.ttf")),
+    ("locales/en.ftl", include_bytes!("../assets/locales/en.ftl"))
+];
+
+fn embedded(path: &Path) -> Option<&'static [u8]> {
+    EMBEDDED_ASSETS.iter().find(|(embedded_path, _)| Path::new(embedded_path) == path).map(|(_, bytes)| *bytes)
+}
+
+/// Reads assets from the platform's usual source, falling back to the embedded ones
+struct EmbeddedAssetIo(Box<dyn AssetIo>);
+
+impl AssetIo for EmbeddedAssetIo {
+    fn load_path<'a>(&'a self, path: &'a Path) -> BoxedFuture<'a, Result<Vec<u8>, AssetIoError>> {
+        Box::pin(async move {
+            match (self.0.load_path(path).await, embedded(path)) {
+                (Err(AssetIoError::NotFound(_)), Some(bytes)) => {
+                    info!(target: ASSETS, "Using embedded {}", path.display());
+                    Ok(bytes.to_vec())
+                }
+                (result, _) => result
+            }
+        })
+    }
+
+    fn read_directory(&self, path: &Path) -> Result<Box<dyn Iterator<Item = PathBuf>>, AssetIoError> {
+        self.0.read_directory(path)
+    }
+
+    fn get_metadata(&self, path: &Path) -> Result<Metadata, AssetIoError> {
+        match (self.0.get_metadata(path), embedded(path)) {
+            (Err(AssetIoError::NotFound(_)), Some(_)) => Ok(Metadata::new(FileType::File)),
+            (result, _) => result
+        }
+    }
+
+    fn watch_path_for_changes(&self, path: &Path) -> Result<(), AssetIoError> {
+        self.0.watch_path_for_changes(path)
+    }
+
+    fn watch_for_changes(&self) -> Result<(), AssetIoError> {
+        self.0.watch_for_changes()
+    }
+}
+
+/// Has to be added before `AssetPlugin`, which only creates an asset server if there isn't one yet.
+/// Mods are layered in here too, between the embedded assets and the platform's source, and the
+/// settings they need are read along with the rest of `settings.toml`.
+pub struct EmbeddedAssetsPlugin;
+
+impl Plugin for EmbeddedAssetsPlugin {
+    fn build(&self, app: &mut App) {
+        let asset_io = create_platform_default_asset_io(app);
+        #[cfg(not(target_arch = "wasm32"))]
+        let asset_io = {
+            let settings = crate::settings::SettingsFile::load();
+            let (asset_io, mod_list) = crate::archive::ArchiveAssetIo::new(asset_io, &settings.mods);
+            app
+                .insert_resource(mod_list)
+                .insert_resource(settings.mods)
+                .insert_resource(settings.autosave);
+            // the language is only picked in the settings window
+            #[cfg(not(feature = "headless"))]
+            app.insert_resource(crate::settings::LanguageSetting(settings.language));
+            Box::new(asset_io)
+        };
+        app.insert_resource(AssetServer::new(EmbeddedAssetIo(asset_io)));
+    }
+}
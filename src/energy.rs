@@ -0,0 +1,45 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+use super::{Prototype, ComponentPrototype, Prototypes, TICK_RATE};
+
+/// Energy storage of a unit. Equipment draws from it, the built-in generator slowly refills it.
+#[derive(Component, Prototype, Deserialize, Clone)]
+#[prot_category(energy)]
+pub struct Energy {
+    name: String,
+    pub capacity: f32,
+    #[serde(default)]
+    pub recharge: f32, // energy / second
+    #[serde(skip)]
+    pub stored: f32
+}
+
+impl ComponentPrototype<'_> for Energy {
+    fn to_component(&self) -> Self {
+        Self {
+            stored: self.capacity,
+            ..self.clone()
+        }
+    }
+}
+
+impl Energy {
+    /// Takes up to `amount` energy, returns how much was actually taken
+    pub fn drain(&mut self, amount: f32) -> f32 {
+        let drained = amount.min(self.stored);
+        self.stored -= drained;
+        drained
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stored <= 0.0
+    }
+}
+
+pub fn recharge_energy(mut storages: Query<&mut Energy>) {
+    for mut energy in storages.iter_mut() {
+        if energy.stored < energy.capacity {
+            energy.stored = (energy.stored + energy.recharge / TICK_RATE as f32).min(energy.capacity);
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::VecDeque;
+use bevy::prelude::*;
+#[cfg(not(target_arch = "wasm32"))]
+use mlua::prelude::*;
+use serde::Deserialize;
+use strum::AsRefStr;
+use super::{Prototype, ComponentPrototype, Prototypes, Unit, UnitId};
+use crate::{turret::Turret, inventory::{Inventory, spawn_ground_item}, heat::Radiator, construction::Builder, repair::RepairTool, circuit::CircuitConnector, storage::Storage};
+use crate::subscriptions::{ScriptEvent, unit_id};
+use crate::data_value::DataValue;
+use crate::logging::SCRIPT;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::program::raise_intent;
+
+// changes a unit can have waiting, one is carried out per tick
+const MAX_EQUIPMENT_REQUESTS: usize = 8;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, AsRefStr)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum SlotKind {
+    Sensor,
+    Weapon,
+    Utility
+}
+
+#[derive(Deserialize, Clone)]
+struct SlotPrototype {
+    kind: SlotKind,
+    #[serde(default = "default_size")]
+    size: u32
+}
+
+fn default_size() -> u32 {
+    1
+}
+
+/// Slots of a unit that equipment items can be installed into
+#[derive(Prototype, ComponentPrototype, Deserialize)]
+#[prot_category(equipment_grid)]
+#[component(EquipmentGrid)]
+pub struct EquipmentGridPrototype {
+    name: String,
+    slots: Vec<SlotPrototype>
+}
+
+impl From<&EquipmentGridPrototype> for EquipmentGrid {
+    fn from(prototype: &EquipmentGridPrototype) -> Self {
+        EquipmentGrid {
+            name: prototype.name.clone(),
+            slots: prototype.slots.iter().map(|slot| EquipmentSlot { kind: slot.kind, size: slot.size, installed: None }).collect(),
+            requests: VecDeque::new(),
+            last_error: None
+        }
+    }
+}
+
+/// Component an equipment item gives the unit it's installed in
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EquipmentComponent {
+    Turret,
+    Builder,
+    RepairTool,
+    CircuitConnector,
+    Storage,
+    Radiator
+}
+
+/// Item that can be installed into an equipment slot, named like the item
+#[derive(Prototype, Deserialize)]
+#[prot_category(equipment)]
+pub struct EquipmentPrototype {
+    name: String,
+    slot: SlotKind,
+    #[serde(default = "default_size")]
+    size: u32,
+    component: EquipmentComponent,
+    // prototype of the component, from the category of `component`
+    prototype: String
+}
+
+pub struct EquipmentSlot {
+    kind: SlotKind,
+    size: u32,
+    // name of the installed item
+    installed: Option<String>
+}
+
+pub enum EquipmentRequest {
+    // into the first empty slot that fits if no slot is given
+    Install { item: String, slot: Option<usize> },
+    Uninstall { slot: usize }
+}
+
+#[derive(Component)]
+pub struct EquipmentGrid {
+    name: String,
+    slots: Vec<EquipmentSlot>,
+    requests: VecDeque<EquipmentRequest>,
+    // why the last carried out request failed
+    last_error: Option<String>
+}
+
+impl EquipmentGrid {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Installed items are taken from the unit's inventory, uninstalled ones are put back into it
+    /// or dropped next to the unit. Factories and manipulators can request changes the same way
+    /// scripts do. Requests are carried out in order, one per tick. Fails if too many are waiting.
+    pub fn request(&mut self, request: EquipmentRequest) -> Result<(), EquipmentRequest> {
+        if self.requests.len() >= MAX_EQUIPMENT_REQUESTS {
+            return Err(request)
+        }
+        self.requests.push_back(request);
+        Ok(())
+    }
+}
+
+// the equipment a unit has now is grouped in a nested tuple
+type EquippedUnit = (Entity, &'static UnitId, &'static mut EquipmentGrid, Option<&'static mut Inventory>, &'static Transform, Option<&'static Children>, (Option<&'static Builder>, Option<&'static RepairTool>, Option<&'static CircuitConnector>, Option<&'static Storage>, Option<&'static Radiator>));
+
+pub fn change_equipment(
+    mut commands: Commands,
+    mut units: Query<EquippedUnit, With<Unit>>,
+    turrets: Query<(), With<Turret>>,
+    prototypes: Res<Prototypes>,
+    mut script_events: EventWriter<ScriptEvent>)
+{
+    let mut failures = Vec::new();
+    for (entity, _, mut grid, mut inventory, transform, children, (builder, repair_tool, connector, storage, radiator)) in units.iter_mut() {
+        let request = match grid.requests.pop_front() {
+            Some(request) => request,
+            None => continue
+        };
+        grid.last_error = None;
+        let position = transform.translation.truncate();
+        let turret = children.and_then(|children| children.iter().find(|child| turrets.contains(**child)).copied());
+        match request {
+            EquipmentRequest::Install { item, slot } => {
+                let equipment = match EquipmentPrototype::from_pt(&prototypes, &item) {
+                    Some(equipment) => equipment,
+                    None => {
+                        failures.push((entity, position, format!("{} is not equipment", item)));
+                        continue
+                    }
+                };
+                let fits = |slot: &EquipmentSlot| slot.installed.is_none() && slot.kind == equipment.slot && slot.size >= equipment.size;
+                let slot = match slot {
+                    Some(slot) => grid.slots.get(slot).filter(|slot| fits(slot)).map(|_| slot),
+                    None => grid.slots.iter().position(fits)
+                };
+                let slot = match slot {
+                    Some(slot) => slot,
+                    None => {
+                        failures.push((entity, position, format!("no free {} slot for {}", equipment.slot.as_ref(), item)));
+                        continue
+                    }
+                };
+                // a unit can't have two components of the same kind
+                let present = match equipment.component {
+                    EquipmentComponent::Turret => turret.is_some(),
+                    EquipmentComponent::Builder => builder.is_some(),
+                    EquipmentComponent::RepairTool => repair_tool.is_some(),
+                    EquipmentComponent::CircuitConnector => connector.is_some(),
+                    EquipmentComponent::Storage => storage.is_some(),
+                    EquipmentComponent::Radiator => radiator.is_some()
+                };
+                if present {
+                    failures.push((entity, position, format!("can't install {}, the unit already has that equipment", item)));
+                    continue
+                }
+                if !inventory.as_mut().map_or(false, |inventory| inventory.remove(&item, 1)) {
+                    failures.push((entity, position, format!("there's no {} in the inventory", item)));
+                    continue
+                }
+                let mut unit = commands.entity(entity);
+                let installed = match equipment.component {
+                    EquipmentComponent::Turret => Turret::component_from_pt(&prototypes, &equipment.prototype).map(|turret| {
+                        unit.with_children(|parent| {
+                            parent.spawn_bundle(turret.sprite_bundle()).insert(turret);
+                        });
+                    }),
+                    EquipmentComponent::Builder => Builder::component_from_pt(&prototypes, &equipment.prototype).map(|builder| { unit.insert(builder); }),
+                    EquipmentComponent::RepairTool => RepairTool::component_from_pt(&prototypes, &equipment.prototype).map(|repair_tool| { unit.insert(repair_tool); }),
+                    EquipmentComponent::CircuitConnector => CircuitConnector::component_from_pt(&prototypes, &equipment.prototype).map(|connector| { unit.insert(connector); }),
+                    EquipmentComponent::Storage => Storage::component_from_pt(&prototypes, &equipment.prototype).map(|storage| { unit.insert(storage); }),
+                    EquipmentComponent::Radiator => Radiator::component_from_pt(&prototypes, &equipment.prototype).map(|radiator| { unit.insert(radiator); })
+                };
+                match installed {
+                    Some(()) => grid.slots[slot].installed = Some(item),
+                    None => {
+                        if let Some(inventory) = &mut inventory {
+                            inventory.add(&item, 1);
+                        }
+                        failures.push((entity, position, format!("equipment {} has an unknown prototype {}", item, equipment.prototype)));
+                    }
+                }
+            }
+            EquipmentRequest::Uninstall { slot } => {
+                let item = match grid.slots.get_mut(slot).and_then(|slot| slot.installed.take()) {
+                    Some(item) => item,
+                    None => {
+                        failures.push((entity, position, format!("slot {} is empty", slot.wrapping_add(1))));
+                        continue
+                    }
+                };
+                if let Some(equipment) = EquipmentPrototype::from_pt(&prototypes, &item) {
+                    match equipment.component {
+                        EquipmentComponent::Turret => {
+                            if let Some(turret) = turret {
+                                commands.entity(turret).despawn_recursive();
+                            }
+                        }
+                        EquipmentComponent::Builder => { commands.entity(entity).remove::<Builder>(); }
+                        EquipmentComponent::RepairTool => { commands.entity(entity).remove::<RepairTool>(); }
+                        EquipmentComponent::CircuitConnector => { commands.entity(entity).remove::<CircuitConnector>(); }
+                        EquipmentComponent::Storage => { commands.entity(entity).remove::<Storage>(); }
+                        EquipmentComponent::Radiator => { commands.entity(entity).remove::<Radiator>(); }
+                    }
+                }
+                match &mut inventory {
+                    Some(inventory) => inventory.add(&item, 1),
+                    None => { spawn_ground_item(&mut commands, item, 1, position); }
+                }
+            }
+        }
+    }
+    // scripts find out through `last_error` or by subscribing to `equipment_failed`
+    for (entity, position, error) in failures {
+        warn!(target: SCRIPT, "Equipment change of unit {:?} failed: {}", entity, error);
+        let (_, id, mut grid, ..) = match units.get_mut(entity) {
+            Ok(unit) => unit,
+            Err(_) => continue
+        };
+        grid.last_error = Some(error.clone());
+        script_events.send(ScriptEvent::new("equipment_failed", position)
+            .with("unit", unit_id(*id))
+            .with("error", DataValue::String(error)));
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn too_many_requests() -> LuaError {
+    LuaError::RuntimeError(format!("more than {} equipment changes are waiting", MAX_EQUIPMENT_REQUESTS))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct LuaEquipmentGridHandle<'a> {
+    pub grid: &'a mut EquipmentGrid
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LuaUserData for LuaEquipmentGridHandle<'_> {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        // slots are numbered from 1 like Lua sequences
+        methods.add_method_mut("install", |lua, lua_handle, (item, slot): (String, Option<usize>)| {
+            raise_intent(lua)?;
+            let slot = slot.map(|slot| slot.wrapping_sub(1));
+            lua_handle.grid.request(EquipmentRequest::Install { item, slot }).map_err(|_| too_many_requests())
+        });
+        methods.add_method_mut("uninstall", |lua, lua_handle, slot: usize| {
+            raise_intent(lua)?;
+            lua_handle.grid.request(EquipmentRequest::Uninstall { slot: slot.wrapping_sub(1) }).map_err(|_| too_many_requests())
+        });
+    }
+
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("slots", |lua, lua_handle| {
+            let table = lua.create_table()?;
+            for (i, slot) in lua_handle.grid.slots.iter().enumerate() {
+                let slot_table = lua.create_table()?;
+                slot_table.set("kind", slot.kind.as_ref())?;
+                slot_table.set("size", slot.size)?;
+                slot_table.set("installed", slot.installed.as_deref())?;
+                table.set(i + 1, slot_table)?;
+            }
+            Ok(table)
+        });
+        fields.add_field_method_get("pending", |_lua, lua_handle| {
+            Ok(lua_handle.grid.requests.len())
+        });
+        fields.add_field_method_get("last_error", |_lua, lua_handle| {
+            Ok(lua_handle.grid.last_error.clone())
+        });
+    }
+}
@@ -0,0 +1,45 @@
+use bevy::prelude::*;
+use crate::camera::{MIN_ZOOM, MAX_ZOOM};
+
+const PAN_SPEED: f32 = 1.5; // tiles / second at zoom 1
+const ZOOM_SPEED: f32 = 5.0; // zoom levels / second
+
+/// Right stick pans the camera and the triggers zoom. Manual unit control is in `ManualControlPlugin`.
+pub struct GamepadPlugin;
+
+impl Plugin for GamepadPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(move_and_zoom_camera_with_gamepad);
+    }
+}
+
+// only the first connected gamepad is used
+pub fn active_gamepad(gamepads: &Gamepads) -> Option<Gamepad> {
+    gamepads.iter().next().copied()
+}
+
+pub fn stick(axes: &Axis<GamepadAxis>, gamepad: Gamepad, x: GamepadAxisType, y: GamepadAxisType) -> Vec2 {
+    Vec2::new(
+        axes.get(GamepadAxis::new(gamepad, x)).unwrap_or(0.0),
+        axes.get(GamepadAxis::new(gamepad, y)).unwrap_or(0.0)
+    )
+}
+
+fn move_and_zoom_camera_with_gamepad(
+    mut camera: Query<(&mut OrthographicProjection, &mut Transform), With<Camera2d>>,
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    triggers: Res<Axis<GamepadButton>>,
+    time: Res<Time>)
+{
+    let gamepad = match active_gamepad(&gamepads) {
+        Some(gamepad) => gamepad,
+        None => return
+    };
+    let (mut camera, mut camera_transform) = camera.single_mut();
+    let trigger = |button_type| triggers.get(GamepadButton::new(gamepad, button_type)).unwrap_or(0.0);
+    let zoom = trigger(GamepadButtonType::LeftTrigger2) - trigger(GamepadButtonType::RightTrigger2);
+    camera.scale = (camera.scale + zoom * ZOOM_SPEED * time.delta_seconds()).clamp(MIN_ZOOM, MAX_ZOOM);
+    let pan = stick(&axes, gamepad, GamepadAxisType::RightStickX, GamepadAxisType::RightStickY);
+    camera_transform.translation += (pan * PAN_SPEED * camera.scale * time.delta_seconds()).extend(0.0);
+}
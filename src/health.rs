@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use serde::Deserialize;
+use strum::AsRefStr;
+use super::{Prototype, ComponentPrototype, Prototypes, UnitId, TICK_RATE};
+use crate::{energy::Energy, inventory::Inventory, storage::Storage, program::WakeEvent, subscriptions::{ScriptEvent, unit_id}};
+
+const CORPSE_COLOR: Color = Color::rgb(0.25, 0.25, 0.25);
+// impacts slower than this don't hurt, tiles / second
+const SAFE_IMPACT_SPEED: f32 = 1.0;
+// damage per unit of mass for every tile / second above the safe speed
+const IMPACT_DAMAGE: f32 = 10.0;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, AsRefStr)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum DamageType {
+    Physical,
+    Energy,
+    Explosive,
+    Thermal
+}
+
+#[derive(Component, Prototype, Deserialize, Clone)]
+#[prot_category(health)]
+pub struct Health {
+    name: String,
+    pub max_health: f32,
+    #[serde(default)]
+    pub armor: f32, // flat reduction per hit
+    // fraction of damage ignored, 0.0 - 1.0
+    #[serde(default)]
+    resistances: HashMap<DamageType, f32>,
+    #[serde(default)]
+    pub shield: Option<Shield>,
+    // impact damage is divided by it
+    #[serde(default = "default_toughness")]
+    pub toughness: f32,
+    #[serde(skip)]
+    pub current: f32
+}
+
+fn default_toughness() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Shield {
+    pub max_shield: f32,
+    #[serde(default)]
+    pub regeneration: f32, // shield points / second
+    #[serde(default)]
+    pub energy_per_point: f32,
+    #[serde(skip)]
+    pub current: f32
+}
+
+impl ComponentPrototype<'_> for Health {
+    fn to_component(&self) -> Self {
+        let mut health = self.clone();
+        health.current = health.max_health;
+        if let Some(shield) = &mut health.shield {
+            shield.current = shield.max_shield;
+        }
+        health
+    }
+}
+
+impl Health {
+    pub fn resistance(&self, damage_type: DamageType) -> f32 {
+        self.resistances.get(&damage_type).copied().unwrap_or(0.0).clamp(0.0, 1.0)
+    }
+
+    /// Resistances apply first, then the shield absorbs what it can and armor reduces the rest
+    pub fn apply_damage(&mut self, amount: f32, damage_type: DamageType) {
+        let mut remaining = amount * (1.0 - self.resistance(damage_type));
+        if let Some(shield) = &mut self.shield {
+            let absorbed = remaining.min(shield.current);
+            shield.current -= absorbed;
+            remaining -= absorbed;
+        }
+        if remaining > 0.0 {
+            self.current -= (remaining - self.armor).max(0.0);
+        }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
+}
+
+pub struct DamageEvent {
+    pub target: Entity,
+    pub amount: f32,
+    pub damage_type: DamageType
+}
+
+/// Remains of a destroyed unit, holding whatever it carried until salvaged
+#[derive(Component)]
+pub struct Corpse;
+
+/// Damage of a unit running into something. Both of them take it, each divided by its own
+/// toughness, so fast and heavy units can ram.
+pub fn send_impact_damage(damage_events: &mut EventWriter<DamageEvent>, healths: &Query<&Health>, unit: Entity, obstacle: Entity, speed: f32, mass: f32) {
+    let damage = (speed - SAFE_IMPACT_SPEED).max(0.0) * mass * IMPACT_DAMAGE;
+    if damage <= 0.0 {
+        return
+    }
+    for target in [unit, obstacle] {
+        if let Ok(health) = healths.get(target) {
+            damage_events.send(DamageEvent { target, amount: damage / health.toughness, damage_type: DamageType::Physical });
+        }
+    }
+}
+
+// inventory and storage are moved to the corpse when the target dies
+type DamageTarget = (&'static mut Health, &'static Transform, Option<&'static Inventory>, Option<&'static Storage>, Option<&'static UnitId>);
+
+pub fn apply_damage(
+    mut commands: Commands,
+    mut damage_events: EventReader<DamageEvent>,
+    mut wake_events: EventWriter<WakeEvent>,
+    mut script_events: EventWriter<ScriptEvent>,
+    mut healths: Query<DamageTarget>)
+{
+    for event in damage_events.iter() {
+        if let Ok((mut health, transform, inventory, storage, unit)) = healths.get_mut(event.target) {
+            if health.is_dead() {
+                continue
+            }
+            health.apply_damage(event.amount, event.damage_type);
+            if unit.is_some() {
+                wake_events.send(WakeEvent { unit: event.target });
+            }
+            if health.is_dead() {
+                commands.entity(event.target).despawn_recursive();
+                if let Some(id) = unit {
+                    let position = transform.translation.truncate();
+                    script_events.send(ScriptEvent::new("unit_destroyed", position).with("unit", unit_id(*id)));
+                    spawn_corpse(&mut commands, transform, inventory.cloned().unwrap_or_default(), storage.cloned());
+                }
+            }
+        }
+    }
+}
+
+fn spawn_corpse(commands: &mut Commands, transform: &Transform, inventory: Inventory, storage: Option<Storage>) {
+    let mut corpse = commands.spawn();
+    if let Some(storage) = storage {
+        corpse.insert(storage);
+    }
+    corpse
+        .insert(Corpse)
+        .insert(inventory)
+        .insert(Collider::cuboid(0.45, 0.45))
+        .insert(Sensor)
+        .insert_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: CORPSE_COLOR,
+                custom_size: Some(Vec2::splat(0.9)),
+                ..default()
+            },
+            transform: *transform,
+            ..default()
+        });
+}
+
+pub fn regenerate_shields(mut units: Query<(&mut Health, Option<&mut Energy>)>) {
+    for (mut health, mut energy) in units.iter_mut() {
+        if let Some(shield) = &mut health.shield {
+            let missing = shield.max_shield - shield.current;
+            if missing <= 0.0 || shield.regeneration <= 0.0 {
+                continue
+            }
+            let mut regenerated = (shield.regeneration / TICK_RATE as f32).min(missing);
+            if shield.energy_per_point > 0.0 {
+                regenerated = match &mut energy {
+                    Some(energy) => energy.drain(regenerated * shield.energy_per_point) / shield.energy_per_point,
+                    None => 0.0
+                };
+            }
+            shield.current += regenerated;
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+use super::{Prototype, ComponentPrototype, Prototypes, MovementStats, MovementIntent, Unit, UnitId, TICK_RATE};
+use crate::health::{DamageEvent, DamageType};
+use crate::subscriptions::{ScriptEvent, unit_id};
+
+// an overheated unit has to cool down to this fraction of its capacity before its equipment works
+// again
+const COOLED_DOWN_FRACTION: f32 = 0.5;
+
+/// Heat built up by using equipment. At capacity the unit overheats: turrets, builders, repair
+/// tools and overclocking stop working and it takes thermal damage until it cooled down.
+#[derive(Component, Prototype, ComponentPrototype, Deserialize, Clone)]
+#[prot_category(heat)]
+pub struct Heat {
+    name: String,
+    pub capacity: f32,
+    #[serde(default)]
+    pub dissipation: f32, // heat / second
+    #[serde(default)]
+    overheat_damage: f32, // damage / second
+    #[serde(skip)]
+    pub current: f32,
+    #[serde(skip)]
+    overheated: bool,
+    #[serde(skip)]
+    overheated_ticks: u32
+}
+
+impl Heat {
+    pub fn add(&mut self, amount: f32) {
+        self.current = (self.current + amount).min(self.capacity);
+        if self.current >= self.capacity {
+            self.overheated = true;
+        }
+    }
+
+    pub fn is_overheated(&self) -> bool {
+        self.overheated
+    }
+}
+
+/// Equipment dissipating heat on top of what the unit does on its own
+#[derive(Component, Prototype, ComponentPrototype, Deserialize, Clone)]
+#[prot_category(radiator)]
+pub struct Radiator {
+    name: String,
+    dissipation: f32 // heat / second
+}
+
+type HeatedUnit = (Entity, &'static UnitId, &'static mut Heat, &'static Transform, Option<(&'static MovementStats, &'static MovementIntent)>, Option<&'static Radiator>);
+
+pub fn update_heat(
+    mut units: Query<HeatedUnit, With<Unit>>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut script_events: EventWriter<ScriptEvent>)
+{
+    for (entity, id, mut heat, transform, movement, radiator) in units.iter_mut() {
+        if let Some(overclock) = movement.and_then(|(stats, intent)| stats.overclock(intent)).filter(|_| !heat.overheated) {
+            heat.add(overclock.heat / TICK_RATE as f32);
+        }
+        let dissipation = heat.dissipation + radiator.map_or(0.0, |radiator| radiator.dissipation);
+        heat.current = (heat.current - dissipation / TICK_RATE as f32).max(0.0);
+        if !heat.overheated {
+            continue
+        }
+        if heat.current < heat.capacity * COOLED_DOWN_FRACTION {
+            heat.overheated = false;
+            heat.overheated_ticks = 0;
+            continue
+        }
+        if heat.overheated_ticks == 0 {
+            script_events.send(ScriptEvent::new("overheated", transform.translation.truncate()).with("unit", unit_id(*id)));
+        }
+        // dealt once a second, so armor doesn't swallow it
+        if heat.overheated_ticks.is_multiple_of(TICK_RATE as u32) && heat.overheat_damage > 0.0 {
+            damage_events.send(DamageEvent { target: entity, amount: heat.overheat_damage, damage_type: DamageType::Thermal });
+        }
+        heat.overheated_ticks += 1;
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::{HashMap, VecDeque};
+use bevy::prelude::*;
+use bevy_egui::{EguiContext, egui};
+use bevy_prototype_debug_lines::DebugLines;
+use serde::Serialize;
+use super::{Unit, SimTick, MovementIntent, MovementState};
+use crate::program::{UnitProgram, ProgramStatus};
+use crate::{storage::Storage, energy::Energy, plot::SelectedUnit, locale::Localize};
+use crate::data_value::{DataValue, DataValueHashEq};
+
+const TOGGLE_KEY: KeyCode = KeyCode::F6;
+// ten seconds of ticks
+const HISTORY_TICKS: usize = 600;
+
+/// The selected unit at one tick: what its program saw and what it decided
+struct HistoryFrame {
+    tick: u64,
+    position: Vec2,
+    angle: f32, // radians counterclockwise, like the transform
+    status: ProgramStatus,
+    energy: Option<f32>,
+    intent: Option<MovementIntent>,
+    state: Option<MovementState>,
+    // black box keys written this tick with their new values, `nil` for removed ones
+    storage_changes: Vec<(String, String)>
+}
+
+/// The last ticks of the selected unit, opened with F6. The window scrubs back through them and
+/// marks where the unit was on the map.
+#[derive(Default)]
+pub struct UnitHistory {
+    open: bool,
+    unit: Option<Entity>,
+    frames: VecDeque<HistoryFrame>,
+    // black box contents as of the newest frame
+    storage: HashMap<DataValueHashEq, DataValue>,
+    // tick of the shown frame, `None` follows the newest one
+    pinned: Option<u64>
+}
+
+pub fn toggle_unit_history(keys: Res<Input<KeyCode>>, mut history: ResMut<UnitHistory>) {
+    if keys.just_pressed(TOGGLE_KEY) {
+        history.open = !history.open;
+    }
+}
+
+fn json_text(value: &impl Serialize) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+type RecordedUnit = (&'static Transform, &'static UnitProgram, Option<&'static MovementIntent>, Option<&'static MovementState>, Option<&'static Energy>, Option<&'static Storage>);
+
+// Runs between unit programs and movement, so the frame has the intents the program just made
+// and the move result it saw while making them
+pub fn record_unit_history(
+    selected: Res<SelectedUnit>,
+    sim_tick: Res<SimTick>,
+    mut history: ResMut<UnitHistory>,
+    units: Query<RecordedUnit, With<Unit>>)
+{
+    if history.unit != selected.0 {
+        *history = UnitHistory { open: history.open, unit: selected.0, ..default() };
+    }
+    let (transform, program, intent, state, energy, storage) = match selected.0.and_then(|unit| units.get(unit).ok()) {
+        Some(unit) => unit,
+        None => return
+    };
+    let mut storage_changes = Vec::new();
+    if let Some(storage) = storage {
+        for (key, value) in storage.entries() {
+            if history.storage.get(key) != Some(value) {
+                storage_changes.push((json_text(key), json_text(value)));
+                history.storage.insert(key.clone(), value.clone());
+            }
+        }
+        let removed: Vec<DataValueHashEq> = history.storage.keys().filter(|key| storage.get(key).is_none()).cloned().collect();
+        for key in removed {
+            storage_changes.push((json_text(&key), "nil".to_string()));
+            history.storage.remove(&key);
+        }
+        storage_changes.sort_unstable();
+    }
+    if history.frames.len() == HISTORY_TICKS {
+        history.frames.pop_front();
+    }
+    history.frames.push_back(HistoryFrame {
+        tick: sim_tick.0,
+        position: transform.translation.truncate(),
+        angle: transform.rotation.to_euler(EulerRot::XYZ).2,
+        status: program.status(),
+        energy: energy.map(|energy| energy.stored),
+        intent: intent.cloned(),
+        state: state.cloned(),
+        storage_changes
+    });
+}
+
+fn row(ui: &mut egui::Ui, name: &str, value: String) {
+    ui.label(name);
+    ui.label(egui::RichText::new(value).monospace());
+    ui.end_row();
+}
+
+fn vector_text(vector: Vec2) -> String {
+    format!("{:.2}, {:.2}", vector.x, vector.y)
+}
+
+pub fn show_unit_history(mut egui_context: ResMut<EguiContext>, mut history: ResMut<UnitHistory>, mut lines: ResMut<DebugLines>, localize: Res<Localize>) {
+    if !history.open {
+        return
+    }
+    let history = &mut *history;
+    let newest = history.frames.len().saturating_sub(1);
+    // pinned frames that fell out of the buffer give way to the newest one
+    let mut index = history.pinned.and_then(|tick| history.frames.iter().position(|frame| frame.tick == tick)).unwrap_or(newest);
+    egui::Window::new(localize.text("history-title")).open(&mut history.open).default_width(350.0).show(egui_context.ctx_mut(), |ui| {
+        if history.frames.is_empty() {
+            ui.label(localize.text("history-select-unit"));
+            return
+        }
+        ui.horizontal(|ui| {
+            if ui.add(egui::Slider::new(&mut index, 0..=newest).show_value(false)).changed() {
+                history.pinned = Some(history.frames[index].tick).filter(|_| index != newest);
+            }
+            if ui.button(localize.text("history-live")).clicked() {
+                history.pinned = None;
+                index = newest;
+            }
+        });
+        let frame = &history.frames[index];
+        ui.label(localize.format("history-tick", &[("tick", &frame.tick), ("ago", &(newest - index))]));
+        egui::Grid::new("history_frame").striped(true).show(ui, |ui| {
+            row(ui, localize.text("history-position"), vector_text(frame.position));
+            row(ui, localize.text("history-rotation"), format!("{:.1}", -frame.angle.to_degrees()));
+            row(ui, localize.text("history-status"), format!("{:?}", frame.status));
+            if let Some(energy) = frame.energy {
+                row(ui, localize.text("history-energy"), format!("{:.1}", energy));
+            }
+            if let Some(state) = &frame.state {
+                row(ui, localize.text("history-speed"), format!("{:.2}", state.movement.speed));
+                let blocked_by = match (state.last_move_result.blocked_by_unit, state.last_move_result.blocked_by) {
+                    (Some(id), _) => id.0.to_string(),
+                    (None, Some(_)) => localize.text("history-obstacle").to_string(),
+                    (None, None) => "-".to_string()
+                };
+                row(ui, localize.text("history-blocked-by"), blocked_by);
+            }
+            if let Some(intent) = &frame.intent {
+                row(ui, localize.text("history-move"), vector_text(intent.input_move));
+                row(ui, localize.text("history-rotate"), format!("{:.2}", intent.input_rotation));
+                row(ui, localize.text("history-heading"), intent.target_heading.map_or_else(|| "-".to_string(), |heading| format!("{:.1}", heading)));
+                row(ui, localize.text("history-target-speed"), intent.target_speed.map_or_else(|| "-".to_string(), |speed| format!("{:.2}", speed)));
+                row(ui, localize.text("history-force"), format!("{} / {:.2}", vector_text(intent.input_force), intent.input_torque));
+                row(ui, localize.text("history-hand-brake"), intent.hand_brake.to_string());
+                row(ui, localize.text("history-overclocked"), intent.overclocked.to_string());
+            }
+        });
+        ui.separator();
+        ui.label(localize.text("history-storage"));
+        if frame.storage_changes.is_empty() {
+            ui.weak(localize.text("history-no-changes"));
+        }
+        egui::Grid::new("history_storage").striped(true).show(ui, |ui| {
+            for (key, value) in &frame.storage_changes {
+                row(ui, key, value.clone());
+            }
+        });
+    });
+    // the recorded path, with the shown frame marked and its move intent pointing out of it
+    for (start, end) in history.frames.iter().zip(history.frames.iter().skip(1)) {
+        lines.line_colored(start.position.extend(0.5), end.position.extend(0.5), 0.0, Color::GRAY);
+    }
+    if let Some(frame) = history.frames.get(index) {
+        let (x, y) = (Vec2::new(0.3, 0.0), Vec2::new(0.0, 0.3));
+        lines.line_colored((frame.position - x).extend(0.6), (frame.position + x).extend(0.6), 0.0, Color::FUCHSIA);
+        lines.line_colored((frame.position - y).extend(0.6), (frame.position + y).extend(0.6), 0.0, Color::FUCHSIA);
+        if let Some(intent) = frame.intent.as_ref().filter(|intent| intent.input_move != Vec2::ZERO) {
+            let end = frame.position + intent.input_move.rotate(Vec2::from_angle(frame.angle));
+            lines.line_colored(frame.position.extend(0.6), end.extend(0.6), 0.0, Color::CYAN);
+        }
+    }
+}
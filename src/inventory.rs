@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+use serde::Deserialize;
+use super::{Prototype, ComponentPrototype, Prototypes, Unit, UnitId};
+use crate::subscriptions::{ScriptEvent, unit_id};
+use crate::data_value::DataValue;
+
+const GROUND_ITEM_COLOR: Color = Color::rgb(0.6, 0.65, 0.7);
+const PICK_UP_RANGE: f32 = 0.75; // tiles
+
+/// Item counts carried by a unit or stored in a structure
+#[derive(Component, Prototype, ComponentPrototype, Deserialize, Clone, Default)]
+#[prot_category(inventory)]
+pub struct Inventory {
+    name: String,
+    #[serde(default)]
+    items: HashMap<String, u32>
+}
+
+impl Inventory {
+    pub fn count(&self, item: &str) -> u32 {
+        self.items.get(item).copied().unwrap_or(0)
+    }
+
+    pub fn add(&mut self, item: &str, count: u32) {
+        if count > 0 {
+            *self.items.entry(item.to_string()).or_insert(0) += count;
+        }
+    }
+
+    /// Removes `count` items, or nothing if there aren't enough
+    pub fn remove(&mut self, item: &str, count: u32) -> bool {
+        match self.items.get_mut(item) {
+            Some(stored) if *stored >= count => {
+                *stored -= count;
+                if *stored == 0 {
+                    self.items.remove(item);
+                }
+                true
+            }
+            _ => count == 0
+        }
+    }
+
+    pub fn contains_all(&self, items: &HashMap<String, u32>) -> bool {
+        items.iter().all(|(item, count)| self.count(item) >= *count)
+    }
+
+    /// Removes every listed item, or nothing if any of them is missing
+    pub fn remove_all(&mut self, items: &HashMap<String, u32>) -> bool {
+        if !self.contains_all(items) {
+            return false
+        }
+        for (item, count) in items {
+            self.remove(item, *count);
+        }
+        true
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.items.iter().map(|(item, count)| (item.as_str(), *count))
+    }
+}
+
+/// Stack of items lying on the ground
+#[derive(Component)]
+pub struct GroundItem {
+    pub item: String,
+    pub count: u32
+}
+
+pub enum ItemAction {
+    Drop { item: String, count: u32 },
+    PickUp
+}
+
+pub struct ItemActionEvent {
+    pub unit: Entity,
+    pub action: ItemAction
+}
+
+pub fn spawn_ground_item(commands: &mut Commands, item: String, count: u32, position: Vec2) -> Entity {
+    commands.spawn_bundle(SpriteBundle {
+        sprite: Sprite {
+            color: GROUND_ITEM_COLOR,
+            custom_size: Some(Vec2::splat(0.25)),
+            ..default()
+        },
+        transform: Transform::from_translation(position.extend(0.2)),
+        ..default()
+    }).insert(GroundItem { item, count }).id()
+}
+
+pub fn handle_item_actions(
+    mut commands: Commands,
+    mut item_action_events: EventReader<ItemActionEvent>,
+    mut script_events: EventWriter<ScriptEvent>,
+    mut units: Query<(&mut Inventory, &Transform, &UnitId), With<Unit>>,
+    ground_items: Query<(Entity, &GroundItem, &Transform)>)
+{
+    let mut picked_up = Vec::new();
+    for event in item_action_events.iter() {
+        let (mut inventory, transform, id) = match units.get_mut(event.unit) {
+            Ok(unit) => unit,
+            Err(_) => continue
+        };
+        let position = transform.translation.truncate();
+        match &event.action {
+            ItemAction::Drop { item, count } => {
+                if *count > 0 && inventory.remove(item, *count) {
+                    spawn_ground_item(&mut commands, item.clone(), *count, position);
+                    script_events.send(ScriptEvent::new("item_dropped", position)
+                        .with("unit", unit_id(*id))
+                        .with("item", DataValue::String(item.clone()))
+                        .with("count", DataValue::Integer(*count as i64)));
+                }
+            }
+            ItemAction::PickUp => {
+                for (entity, ground_item, item_transform) in ground_items.iter() {
+                    if !picked_up.contains(&entity) && item_transform.translation.truncate().distance(position) <= PICK_UP_RANGE {
+                        inventory.add(&ground_item.item, ground_item.count);
+                        commands.entity(entity).despawn();
+                        picked_up.push(entity);
+                    }
+                }
+            }
+        }
+    }
+}
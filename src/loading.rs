@@ -0,0 +1,583 @@
+use std::{marker::PhantomData, sync::{Arc, Mutex}};
+use bevy::{prelude::*, asset::{AssetLoader, AssetPath, LoadContext, LoadedAsset, LoadState}, ecs::system::SystemParam, utils::BoxedFuture};
+#[cfg(not(feature = "headless"))]
+use bevy_egui::{EguiContext, egui};
+use strum::AsRefStr;
+use super::{Prototypes, UiFont};
+use crate::{animation::AnimationAtlases, visual::Visuals, logging::ASSETS};
+#[cfg(not(feature = "headless"))]
+use crate::{audio::SoundHandles, template::{ProgramSource, ProgramSourceLoader, ProgramTemplates}};
+use crate::locale::{Translations, TranslationsLoader, Localize, update_translations};
+use crate::migration::{Migration, migrate};
+
+pub const PROTOTYPES_PATH: &str = "prototypes.json";
+pub const UI_FONT_PATH: &str = "DejaVuSansMono.ttf";
+#[cfg(not(feature = "headless"))]
+const FADE_SECONDS: f32 = 0.5;
+/// Format version of `prototypes.json`, bump it and add a migration when changing the format in a
+/// way older files can't be read with
+pub const PROTOTYPES_FORMAT: u32 = 1;
+const FORMAT_VERSION_KEY: &str = "format_version";
+// upgrades from every older format version, see `migrate`
+const PROTOTYPES_MIGRATIONS: &[Migration<serde_json::Value>] = &[];
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum AppState {
+    Loading,
+    #[cfg(not(feature = "headless"))]
+    MainMenu,
+    Playing,
+    Error
+}
+
+/// Loads the prototypes and everything they reference, then switches to `AfterLoading`. The
+/// loading and error screens are part of `UiPlugin`.
+pub struct LoadingPlugin;
+
+impl Plugin for LoadingPlugin {
+    fn build(&self, app: &mut App) {
+        let prototypes_error = PrototypesError::default();
+        let headless = app.world.contains_resource::<Headless>();
+        app
+            .add_asset::<Prototypes>()
+            .insert_resource(prototypes_error.clone())
+            .add_asset_loader(PrototypesLoader { error: prototypes_error, headless })
+            .add_asset::<Translations>()
+            .add_asset_loader(TranslationsLoader)
+            // empty until loading finishes
+            .init_resource::<Prototypes>()
+            .init_resource::<AnimationAtlases>()
+            .init_resource::<Visuals>()
+            .init_resource::<Localize>()
+            // inserted by `EmbeddedAssetsPlugin` when there are mods
+            .init_resource::<ModList>()
+            .init_resource::<AfterLoading>()
+            .add_state(AppState::Loading)
+            .add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
+            .add_system_set(SystemSet::on_update(AppState::Loading).with_system(check_load_assets))
+            .add_system(update_translations);
+        // only played sounds and the new program dialog's templates
+        #[cfg(not(feature = "headless"))]
+        app
+            .init_resource::<SoundHandles>()
+            .add_asset::<ProgramSource>()
+            .add_asset_loader(ProgramSourceLoader)
+            .init_resource::<ProgramTemplates>();
+    }
+}
+
+/// State to switch to once loading finishes, `main` sets it to `AppState::MainMenu` when there's
+/// a main menu to show
+pub struct AfterLoading(pub AppState);
+
+impl Default for AfterLoading {
+    fn default() -> Self {
+        Self(AppState::Playing)
+    }
+}
+
+/// Inserted in apps without a window, like dedicated servers. Loading leaves out the font,
+/// textures, sounds and program templates then, there's nothing to show them with.
+pub struct Headless;
+
+/// Mod in the load order
+#[derive(Clone)]
+pub struct ActiveMod {
+    pub name: String,
+    pub version: String,
+    // the mod's `prototypes.json`, merged into the base prototypes while loading and dropped then
+    pub prototypes: Option<Vec<u8>>
+}
+
+/// Mods that are loaded, in load order, and why others couldn't be. Saves record the active ones
+/// to check they're loaded with the same mods. The game starts without mods that have errors, the
+/// mods settings list them.
+#[derive(Clone, Default)]
+pub struct ModList {
+    pub active: Vec<ActiveMod>,
+    pub errors: Vec<String>,
+    // names of every mod found in the `mods` folder, disabled ones included, for the mods settings
+    pub installed: Vec<String>
+}
+
+/// Last error from parsing `prototypes.json`. The asset server only logs loader errors, this keeps
+/// the text for the error screen.
+#[derive(Clone, Default)]
+pub struct PrototypesError(Arc<Mutex<Option<String>>>);
+
+// Files without a format version are from before it was added, which was the first format
+fn read_prototypes(bytes: &[u8], file: &str) -> anyhow::Result<serde_json::Value> {
+    let mut value: serde_json::Value = serde_json::from_slice(bytes)?;
+    let version = match value.as_object_mut().and_then(|object| object.remove(FORMAT_VERSION_KEY)) {
+        Some(version) => version.as_u64().and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| anyhow::anyhow!("{} has to be a positive integer", FORMAT_VERSION_KEY))?,
+        None => 1
+    };
+    migrate(&mut value, version, PROTOTYPES_FORMAT, PROTOTYPES_MIGRATIONS)?;
+    if version < PROTOTYPES_FORMAT {
+        info!(target: ASSETS, "Migrated {} from format version {} to {}", file, version, PROTOTYPES_FORMAT);
+    }
+    Ok(value)
+}
+
+pub fn parse_prototypes(bytes: &[u8]) -> anyhow::Result<Prototypes> {
+    let value = read_prototypes(bytes, PROTOTYPES_PATH)?;
+    // serde skips unknown categories, which is usually a typo
+    for category in value.as_object().into_iter().flat_map(|object| object.keys()) {
+        if !Prototypes::CATEGORIES.contains(&category.as_str()) {
+            warn!(target: ASSETS, "{} has an unknown prototype category {}", PROTOTYPES_PATH, category);
+        }
+    }
+    let mut prototypes: Prototypes = serde_json::from_value(value.clone())?;
+    prototypes.source = value;
+    Ok(prototypes)
+}
+
+/// Parses the base `prototypes.json`, upgrading it from older format versions first, and registers
+/// every file the prototypes reference as a dependency, so the asset server starts loading them
+/// right away. The mods' prototypes are merged in afterwards, see `merge_mod_prototypes`.
+pub struct PrototypesLoader {
+    pub error: PrototypesError,
+    // textures, sounds and templates aren't dependencies then, see `Headless`
+    pub headless: bool
+}
+
+impl AssetLoader for PrototypesLoader {
+    fn load<'a>(&'a self, bytes: &'a [u8], load_context: &'a mut LoadContext) -> BoxedFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let result = parse_prototypes(bytes);
+            *self.error.0.lock().unwrap() = result.as_ref().err().map(ToString::to_string);
+            let prototypes = result?;
+            let dependencies: Vec<AssetPath> = prototypes.asset_paths().into_iter()
+                .filter(|(kind, _)| !self.headless || !is_only_shown(*kind))
+                .map(|(_, path)| AssetPath::from(path).to_owned())
+                .collect();
+            load_context.set_default_asset(LoadedAsset::new(prototypes).with_dependencies(dependencies));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["json"]
+    }
+}
+
+// Every prototype of a mod has to be valid, otherwise none of them are used
+fn read_mod_prototypes(name: &str, bytes: &[u8]) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+    let value = read_prototypes(bytes, &format!("{}'s {}", name, PROTOTYPES_PATH)).map_err(|error| error.to_string())?;
+    let categories = match value {
+        serde_json::Value::Object(categories) => categories,
+        _ => return Err(format!("{} has to be an object of prototype categories", PROTOTYPES_PATH))
+    };
+    for (category, prototypes) in &categories {
+        Prototypes::validate(category, prototypes)?;
+    }
+    Ok(categories)
+}
+
+// A mod's prototype replaces the one with the same name in its category, others are added
+fn merge_categories(merged: &mut serde_json::Value, categories: serde_json::Map<String, serde_json::Value>) {
+    let merged = match merged.as_object_mut() {
+        Some(merged) => merged,
+        None => return
+    };
+    for (category, prototypes) in categories {
+        let target = merged.entry(category).or_insert_with(|| serde_json::Value::Array(Vec::new()));
+        let (target, prototypes) = match (target.as_array_mut(), prototypes) {
+            (Some(target), serde_json::Value::Array(prototypes)) => (target, prototypes),
+            _ => continue
+        };
+        for prototype in prototypes {
+            let name = prototype.get("name").cloned();
+            match target.iter_mut().find(|existing| name.is_some() && existing.get("name") == name.as_ref()) {
+                Some(existing) => *existing = prototype,
+                None => target.push(prototype)
+            }
+        }
+    }
+}
+
+/// Merges the prototypes of the active mods into the base ones in load order, category by
+/// category. Mods with invalid prototypes are skipped and added to the mod errors.
+fn merge_mod_prototypes(prototypes: &mut Prototypes, mod_list: &mut ModList) -> anyhow::Result<()> {
+    let mut merged = prototypes.source.clone();
+    let mut changed = false;
+    for active in &mut mod_list.active {
+        let bytes = match active.prototypes.take() {
+            Some(bytes) => bytes,
+            None => continue
+        };
+        match read_mod_prototypes(&active.name, &bytes) {
+            Ok(categories) => {
+                merge_categories(&mut merged, categories);
+                changed = true;
+            }
+            Err(error) => {
+                error!(target: ASSETS, "Leaving out the prototypes of mod {}: {}", active.name, error);
+                mod_list.errors.push(format!("{}: {}, its prototypes are left out", active.name, error));
+            }
+        }
+    }
+    if changed {
+        *prototypes = serde_json::from_value(merged.clone())?;
+        prototypes.source = merged;
+    }
+    Ok(())
+}
+
+/// Identifies what a game plays with: the merged prototypes, not how their files are written,
+/// and the active mods in load order, since mods also bring their own assets and scripts.
+pub fn hash_prototypes(prototypes: &Prototypes, mod_list: &ModList) -> blake3::Hash {
+    let mut hasher = blake3::Hasher::new();
+    // objects serialize with sorted keys, so formatting and key order don't change the hash
+    hasher.update(&serde_json::to_vec(&prototypes.source).unwrap());
+    for active in &mod_list.active {
+        hasher.update(format!("{} {}\n", active.name, active.version).as_bytes());
+    }
+    hasher.finalize()
+}
+
+// Maps and mods aren't loaded from files yet, they'll get their own kinds when they are
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, AsRefStr)]
+#[strum(serialize_all = "lowercase")]
+pub enum AssetKind {
+    Prototypes,
+    Font,
+    Texture,
+    Sound,
+    Program,
+    Locale
+}
+
+// what apps without a window leave out, templates are only offered in the new program dialog
+fn is_only_shown(kind: AssetKind) -> bool {
+    matches!(kind, AssetKind::Font | AssetKind::Texture | AssetKind::Sound | AssetKind::Program)
+}
+
+struct TrackedAsset {
+    kind: AssetKind,
+    path: String,
+    handle: HandleUntyped,
+    state: LoadState
+}
+
+/// Load state of everything that has to be loaded before the game starts
+pub struct LoadingTracker {
+    prototypes: Handle<Prototypes>,
+    // files referenced by the prototypes are added once those are parsed
+    assets: Vec<TrackedAsset>,
+    prototypes_parsed: bool
+}
+
+impl LoadingTracker {
+    fn new(prototypes: Handle<Prototypes>, font: Option<&UiFont>) -> Self {
+        let mut tracker = Self { prototypes: prototypes.clone(), assets: Vec::new(), prototypes_parsed: false };
+        tracker.track(AssetKind::Prototypes, PROTOTYPES_PATH, prototypes.clone_untyped());
+        if let Some(font) = font {
+            tracker.track(AssetKind::Font, UI_FONT_PATH, font.0.clone_untyped());
+        }
+        tracker
+    }
+
+    fn track(&mut self, kind: AssetKind, path: &str, handle: HandleUntyped) {
+        self.assets.push(TrackedAsset { kind, path: path.to_string(), handle, state: LoadState::NotLoaded });
+    }
+
+    /// Loaded and total count of each kind of asset
+    #[cfg(not(feature = "headless"))]
+    fn progress(&self) -> Vec<(AssetKind, usize, usize)> {
+        let mut progress: Vec<(AssetKind, usize, usize)> = Vec::new();
+        for asset in &self.assets {
+            let loaded = (asset.state == LoadState::Loaded) as usize;
+            match progress.iter_mut().find(|(kind, _, _)| *kind == asset.kind) {
+                Some((_, kind_loaded, total)) => {
+                    *kind_loaded += loaded;
+                    *total += 1;
+                }
+                None => progress.push((asset.kind, loaded, 1))
+            }
+        }
+        progress.sort_unstable_by_key(|(kind, _, _)| *kind);
+        progress
+    }
+
+    #[cfg(not(feature = "headless"))]
+    fn failed(&self) -> impl Iterator<Item = &TrackedAsset> {
+        self.assets.iter().filter(|asset| asset.state == LoadState::Failed)
+    }
+
+    fn is_done(&self) -> bool {
+        self.prototypes_parsed && self.assets.iter().all(|asset| asset.state == LoadState::Loaded)
+    }
+}
+
+// Prototypes and what they reference are loaded in the background, see `check_load_assets`
+fn load_assets(mut commands: Commands, assets: Res<AssetServer>, headless: Option<Res<Headless>>) {
+    if headless.is_some() {
+        commands.insert_resource(LoadingTracker::new(assets.load(PROTOTYPES_PATH), None));
+        return
+    }
+    let ui_font = UiFont(assets.load(UI_FONT_PATH));
+    commands.insert_resource(LoadingTracker::new(assets.load(PROTOTYPES_PATH), Some(&ui_font)));
+    commands.insert_resource(ui_font);
+}
+
+/// Asset server and the asset storages loading reads from and writes to
+#[derive(SystemParam)]
+pub struct LoadingAssets<'w, 's> {
+    server: Res<'w, AssetServer>,
+    prototypes: ResMut<'w, Assets<Prototypes>>,
+    texture_atlases: ResMut<'w, Assets<TextureAtlas>>,
+    headless: Option<Res<'w, Headless>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>
+}
+
+// The asset server doesn't report dependencies in a handle's load state, so every file is tracked
+// on its own. The prototypes are moved out of their asset into the resource the game uses.
+pub fn check_load_assets(
+    mut commands: Commands,
+    mut loading_assets: LoadingAssets,
+    mut tracker: ResMut<LoadingTracker>,
+    prototypes_error: Res<PrototypesError>,
+    mut mod_list: ResMut<ModList>,
+    after_loading: Res<AfterLoading>,
+    mut state: ResMut<State<AppState>>)
+{
+    let assets = &loading_assets.server;
+    let mut prototypes_failed = false;
+    for asset in tracker.assets.iter_mut() {
+        let load_state = assets.get_load_state(&asset.handle);
+        if load_state == LoadState::Failed && asset.state != LoadState::Failed {
+            error!(target: ASSETS, "Loading {} failed", asset.path);
+            // nothing else can load without the prototypes
+            if asset.kind == AssetKind::Prototypes {
+                let message = prototypes_error.0.lock().unwrap().clone()
+                    .unwrap_or_else(|| format!("{} can't be read, check that it exists in the assets folder", PROTOTYPES_PATH));
+                error!(target: ASSETS, "{}", message);
+                #[cfg(not(feature = "headless"))]
+                commands.insert_resource(AssetError(message));
+                prototypes_failed = true;
+            }
+        }
+        asset.state = load_state;
+    }
+    if prototypes_failed {
+        state.set(AppState::Error).unwrap();
+        return
+    }
+    if !tracker.prototypes_parsed {
+        let prototypes = match loading_assets.prototypes.get_mut(&tracker.prototypes) {
+            Some(prototypes) => prototypes,
+            None => return
+        };
+        if let Err(error) = merge_mod_prototypes(prototypes, &mut mod_list) {
+            // there's no error screen without a window
+            error!(target: ASSETS, "{}", error);
+            #[cfg(not(feature = "headless"))]
+            commands.insert_resource(AssetError(error.to_string()));
+            state.set(AppState::Error).unwrap();
+            return
+        }
+        prototypes.hash = Some(hash_prototypes(prototypes, &mod_list));
+        let headless = loading_assets.headless.is_some();
+        for (kind, path) in prototypes.asset_paths() {
+            if headless && is_only_shown(kind) {
+                continue
+            }
+            tracker.track(kind, path, assets.load_untyped(path));
+        }
+        tracker.prototypes_parsed = true;
+        return
+    }
+    if !tracker.is_done() {
+        return
+    }
+    let prototypes = loading_assets.prototypes.remove(&tracker.prototypes).unwrap();
+    info!(target: ASSETS, "Loaded prototypes, hash {}", prototypes.hash.unwrap().to_hex());
+    let counts: Vec<String> = prototypes.counts().into_iter().filter(|(_, count)| *count > 0).map(|(category, count)| format!("{} {}", count, category)).collect();
+    debug!(target: ASSETS, "Prototypes: {}", counts.join(", "));
+    if !mod_list.active.is_empty() {
+        let mods: Vec<String> = mod_list.active.iter().map(|active| format!("{} {}", active.name, active.version)).collect();
+        info!(target: ASSETS, "Active mods: {}", mods.join(", "));
+    }
+    if loading_assets.headless.is_some() {
+        commands.insert_resource(Visuals::without_textures(&prototypes));
+    } else {
+        let atlases = AnimationAtlases::load(assets, &mut loading_assets.texture_atlases, &prototypes);
+        commands.insert_resource(Visuals::load(assets, &atlases, &prototypes));
+        commands.insert_resource(atlases);
+        #[cfg(not(feature = "headless"))]
+        commands.insert_resource(SoundHandles::load(assets, &prototypes));
+    }
+    #[cfg(not(feature = "headless"))]
+    commands.insert_resource(ProgramTemplates::load(assets, &prototypes));
+    commands.insert_resource(Localize::load(assets, &prototypes));
+    commands.insert_resource(prototypes);
+    state.set(after_loading.0).unwrap();
+}
+
+// shown before the locale files are known, so this and the error screen aren't translated
+#[cfg(not(feature = "headless"))]
+pub fn show_loading_screen(mut egui_context: ResMut<EguiContext>, assets: Res<AssetServer>, tracker: Res<LoadingTracker>) {
+    egui::CentralPanel::default().show(egui_context.ctx_mut(), |ui| {
+        ui.vertical_centered(|ui| {
+            ui.add_space(ui.available_height() / 3.0);
+            ui.heading("Loading");
+            ui.add_space(10.0);
+            let progress = tracker.progress();
+            let (loaded, total) = progress.iter().fold((0, 0), |(loaded, total), (_, kind_loaded, kind_total)| (loaded + kind_loaded, total + kind_total));
+            ui.add(egui::ProgressBar::new(loaded as f32 / total.max(1) as f32).desired_width(400.0).show_percentage());
+            for (kind, kind_loaded, kind_total) in progress {
+                ui.add(egui::ProgressBar::new(kind_loaded as f32 / kind_total as f32)
+                    .desired_width(400.0)
+                    .text(format!("{} {}/{}", kind.as_ref(), kind_loaded, kind_total)));
+            }
+            for asset in tracker.failed() {
+                ui.horizontal(|ui| {
+                    ui.colored_label(egui::Color32::LIGHT_RED, format!("Can't load {} {}", asset.kind.as_ref(), asset.path));
+                    if ui.button("Retry").clicked() {
+                        assets.reload_asset(asset.path.as_str());
+                    }
+                });
+            }
+        });
+    });
+}
+
+/// Why the prototypes can't be loaded, for the error screen
+#[cfg(not(feature = "headless"))]
+pub struct AssetError(String);
+
+#[cfg(not(feature = "headless"))]
+pub fn show_asset_error(
+    mut egui_context: ResMut<EguiContext>,
+    assets: Res<AssetServer>,
+    error: Res<AssetError>,
+    mut state: ResMut<State<AppState>>)
+{
+    egui::CentralPanel::default().show(egui_context.ctx_mut(), |ui| {
+        ui.vertical_centered(|ui| {
+            ui.add_space(ui.available_height() / 3.0);
+            ui.heading(format!("Can't load {}", PROTOTYPES_PATH));
+            ui.add_space(10.0);
+            ui.colored_label(egui::Color32::LIGHT_RED, egui::RichText::new(error.0.as_str()).monospace());
+            ui.add_space(10.0);
+            if ui.button("Reload assets").clicked() {
+                assets.reload_asset(PROTOTYPES_PATH);
+                state.set(AppState::Loading).unwrap();
+            }
+        });
+    });
+}
+
+/// Covers the screen when loading finishes and fades out
+#[cfg(not(feature = "headless"))]
+#[derive(Component)]
+pub struct LoadingFade(Timer);
+
+#[cfg(not(feature = "headless"))]
+pub fn spawn_loading_fade(mut commands: Commands) {
+    commands.spawn_bundle(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+            ..default()
+        },
+        color: UiColor(Color::BLACK),
+        ..default()
+    }).insert(LoadingFade(Timer::from_seconds(FADE_SECONDS, false)));
+}
+
+#[cfg(not(feature = "headless"))]
+pub fn fade_out_loading(mut commands: Commands, mut fades: Query<(Entity, &mut LoadingFade, &mut UiColor)>, time: Res<Time>) {
+    for (entity, mut fade, mut color) in fades.iter_mut() {
+        fade.0.tick(time.delta());
+        color.0.set_a(fade.0.percent_left());
+        if fade.0.finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn movement(name: &str, speed: f32) -> serde_json::Value {
+        json!({
+            "name": name,
+            "movement_type": "omnidirectional",
+            "speed": speed,
+            "max_speed": speed,
+            "max_speed_backwards": speed,
+            "acceleration": 1.0,
+            "braking_acceleration": 1.0,
+            "passive_deceleration": 0.0,
+            "rotation_speed": 90.0
+        })
+    }
+
+    fn base() -> Prototypes {
+        parse_prototypes(json!({ "movement": [movement("default", 1.0), movement("slow", 0.5)] }).to_string().as_bytes()).unwrap()
+    }
+
+    fn active_mod(name: &str, prototypes: serde_json::Value) -> ActiveMod {
+        ActiveMod { name: name.to_string(), version: "1.0.0".to_string(), prototypes: Some(prototypes.to_string().into_bytes()) }
+    }
+
+    #[test]
+    fn mods_replace_prototypes_by_name_and_add_new_ones() {
+        let mut prototypes = base();
+        let mut mod_list = ModList {
+            active: vec![
+                active_mod("fast", json!({ "movement": [movement("default", 3.0), movement("fast", 4.0)] })),
+                // later mods win
+                active_mod("faster", json!({ "movement": [movement("fast", 5.0)] }))
+            ],
+            ..Default::default()
+        };
+        merge_mod_prototypes(&mut prototypes, &mut mod_list).unwrap();
+        assert!(mod_list.errors.is_empty());
+        assert_eq!(prototypes.movement.len(), 3);
+        assert_eq!(prototypes.movement["default"].speed, 3.0);
+        assert_eq!(prototypes.movement["slow"].speed, 0.5);
+        assert_eq!(prototypes.movement["fast"].speed, 5.0);
+        assert!(prototypes.contains("movement", "fast"));
+    }
+
+    #[test]
+    fn invalid_mods_are_left_out_on_their_own() {
+        let mut prototypes = base();
+        let mut mod_list = ModList {
+            active: vec![
+                active_mod("broken", json!({ "movement": [movement("default", 3.0), { "name": "no type" }] })),
+                active_mod("typo", json!({ "movment": [movement("default", 3.0)] })),
+                active_mod("fine", json!({ "movement": [movement("fine", 2.0)] }))
+            ],
+            ..Default::default()
+        };
+        merge_mod_prototypes(&mut prototypes, &mut mod_list).unwrap();
+        assert_eq!(mod_list.errors.len(), 2);
+        assert!(mod_list.errors[0].starts_with("broken: movement:"));
+        assert!(mod_list.errors[1].starts_with("typo: unknown prototype category movment"));
+        // nothing of the broken mod got in
+        assert_eq!(prototypes.movement["default"].speed, 1.0);
+        assert!(prototypes.movement.contains_key("fine"));
+    }
+
+    #[test]
+    fn prototypes_hash_covers_content_and_mods() {
+        let hash = hash_prototypes(&base(), &ModList::default());
+        let reformatted = serde_json::to_string_pretty(&json!({ "movement": [movement("default", 1.0), movement("slow", 0.5)] })).unwrap();
+        assert_eq!(hash_prototypes(&parse_prototypes(reformatted.as_bytes()).unwrap(), &ModList::default()), hash);
+        let mut changed = base();
+        let mut mod_list = ModList { active: vec![active_mod("fast", json!({ "movement": [movement("default", 3.0)] }))], ..Default::default() };
+        merge_mod_prototypes(&mut changed, &mut mod_list).unwrap();
+        assert_ne!(hash_prototypes(&changed, &mod_list), hash);
+        // a mod counts even when it changes no prototypes
+        let mod_list = ModList { active: vec![active_mod("sounds", json!({}))], ..Default::default() };
+        assert_ne!(hash_prototypes(&base(), &mod_list), hash);
+    }
+}
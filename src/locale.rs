@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+#[cfg(not(feature = "headless"))]
+use std::fmt::Display;
+use bevy::{prelude::*, asset::{AssetLoader, LoadContext, LoadedAsset}, reflect::TypeUuid, utils::BoxedFuture};
+use serde::Deserialize;
+use super::{Prototype, Prototypes};
+
+/// Used for keys missing in the selected language
+pub const FALLBACK_LANGUAGE: &str = "en";
+
+/// Language the UI can be shown in
+#[derive(Prototype, Deserialize)]
+#[prot_category(locale)]
+pub struct LocalePrototype {
+    name: String,
+    // shown in the language selector, in the language itself
+    label: String,
+    path: String
+}
+
+impl LocalePrototype {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+/// Translations of one language, from a file in a small subset of Fluent: `key = text` lines,
+/// indented lines continuing the text of the key above, `#` comments and `{ $name }` placeables
+#[derive(TypeUuid)]
+#[uuid = "8d1f4b7e-2c6a-4e93-a5b8-6f0e3d9c1a72"]
+pub struct Translations(HashMap<String, String>);
+
+fn parse_translations(text: &str) -> anyhow::Result<Translations> {
+    let mut translations: HashMap<String, String> = HashMap::new();
+    let mut last_key: Option<String> = None;
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue
+        }
+        if line.starts_with(char::is_whitespace) {
+            match last_key.as_ref().and_then(|key| translations.get_mut(key)) {
+                Some(value) => {
+                    if !value.is_empty() {
+                        value.push('\n');
+                    }
+                    value.push_str(line.trim());
+                }
+                None => anyhow::bail!("line {} is indented but doesn't follow a key", number + 1)
+            }
+            continue
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => anyhow::bail!("line {} has no `=`", number + 1)
+        };
+        translations.insert(key.to_string(), value.to_string());
+        last_key = Some(key.to_string());
+    }
+    Ok(Translations(translations))
+}
+
+pub struct TranslationsLoader;
+
+impl AssetLoader for TranslationsLoader {
+    fn load<'a>(&'a self, bytes: &'a [u8], load_context: &'a mut LoadContext) -> BoxedFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            load_context.set_default_asset(LoadedAsset::new(parse_translations(std::str::from_utf8(bytes)?)?));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ftl"]
+    }
+}
+
+pub struct Language {
+    pub name: String,
+    pub label: String,
+    translations: Handle<Translations>
+}
+
+/// Looks up UI text in the selected language. Keys missing there come from the fallback language,
+/// keys missing in both are shown as they are, so prototypes can use plain text instead of keys.
+/// Mods translate by shipping a locale file with the same path.
+#[derive(Default)]
+pub struct Localize {
+    language: String,
+    /// Sorted by label
+    languages: Vec<Language>,
+    translations: HashMap<String, String>,
+    fallback: HashMap<String, String>,
+    // the translations are copied out of their assets when the language changes or a file is reloaded
+    dirty: bool
+}
+
+impl Localize {
+    pub fn load(assets: &AssetServer, prototypes: &Prototypes) -> Self {
+        let mut languages: Vec<Language> = prototypes.locale.values().map(|locale| Language {
+            name: locale.name.clone(),
+            label: locale.label.clone(),
+            translations: assets.load(locale.path.as_str())
+        }).collect();
+        languages.sort_unstable_by(|a, b| a.label.cmp(&b.label));
+        Self { language: FALLBACK_LANGUAGE.to_string(), languages, translations: HashMap::new(), fallback: HashMap::new(), dirty: true }
+    }
+
+    #[cfg(not(feature = "headless"))]
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    #[cfg(not(feature = "headless"))]
+    pub fn languages(&self) -> &[Language] {
+        &self.languages
+    }
+
+    #[cfg(not(feature = "headless"))]
+    pub fn set_language(&mut self, language: &str) {
+        if self.language != language && self.languages.iter().any(|known| known.name == language) {
+            self.language = language.to_string();
+            self.dirty = true;
+        }
+    }
+
+    #[cfg(not(feature = "headless"))]
+    pub fn text<'a>(&'a self, key: &'a str) -> &'a str {
+        self.translations.get(key).or_else(|| self.fallback.get(key)).map_or(key, String::as_str)
+    }
+
+    #[cfg(not(feature = "headless"))]
+    /// Text with its `{ $name }` placeables replaced
+    pub fn format(&self, key: &str, arguments: &[(&str, &dyn Display)]) -> String {
+        let mut text = self.text(key).to_string();
+        for (name, value) in arguments {
+            let value = value.to_string();
+            text = text.replace(&format!("{{ ${} }}", name), &value).replace(&format!("{{${}}}", name), &value);
+        }
+        text
+    }
+
+    fn translations_of<'a>(&self, language: &str, assets: &'a Assets<Translations>) -> Option<&'a HashMap<String, String>> {
+        let language = self.languages.iter().find(|known| known.name == language)?;
+        assets.get(&language.translations).map(|translations| &translations.0)
+    }
+}
+
+pub fn update_translations(mut localize: ResMut<Localize>, mut events: EventReader<AssetEvent<Translations>>, assets: Res<Assets<Translations>>) {
+    if events.iter().count() == 0 && !localize.dirty {
+        return
+    }
+    let translations = localize.translations_of(&localize.language, &assets).cloned().unwrap_or_default();
+    let fallback = localize.translations_of(FALLBACK_LANGUAGE, &assets).cloned().unwrap_or_default();
+    localize.translations = translations;
+    localize.fallback = fallback;
+    localize.dirty = false;
+}
@@ -0,0 +1,211 @@
+use std::fmt::Write;
+use bevy::prelude::*;
+use tracing_subscriber::{prelude::*, EnvFilter, filter::{LevelFilter, ParseError}};
+#[cfg(not(feature = "headless"))]
+use std::{collections::VecDeque, fmt, sync::{Arc, Mutex}};
+#[cfg(not(feature = "headless"))]
+use bevy_egui::{EguiContext, egui};
+#[cfg(not(feature = "headless"))]
+use tracing::{Event, Level, Subscriber, field::{Field, Visit}};
+#[cfg(not(feature = "headless"))]
+use tracing_subscriber::{Registry, layer::Context, reload};
+#[cfg(not(feature = "headless"))]
+use crate::debug::DebugSettings;
+
+/// Log categories, used as tracing targets: `warn!(target: SCRIPT, ...)`
+pub const SCRIPT: &str = "script";
+pub const PHYSICS: &str = "physics";
+pub const NET: &str = "net";
+pub const ASSETS: &str = "assets";
+const CATEGORIES: [&str; 4] = [SCRIPT, PHYSICS, NET, ASSETS];
+
+#[cfg(not(feature = "headless"))]
+const LOG_WINDOW_CAPACITY: usize = 500;
+// same as bevy's default, the renderer is very noisy otherwise
+const LIBRARY_DIRECTIVES: &str = "wgpu=error,naga=warn";
+#[cfg(not(feature = "headless"))]
+const LEVELS: [LevelFilter; 6] = [LevelFilter::OFF, LevelFilter::ERROR, LevelFilter::WARN, LevelFilter::INFO, LevelFilter::DEBUG, LevelFilter::TRACE];
+
+#[cfg(not(feature = "headless"))]
+struct LogEntry {
+    level: Level,
+    target: String,
+    message: String
+}
+
+/// Keeps the latest log entries for the log window
+#[cfg(not(feature = "headless"))]
+#[derive(Clone, Default)]
+struct LogBuffer(Arc<Mutex<VecDeque<LogEntry>>>);
+
+#[cfg(not(feature = "headless"))]
+impl<S: Subscriber> tracing_subscriber::Layer<S> for LogBuffer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        let mut entries = self.0.lock().unwrap();
+        if entries.len() == LOG_WINDOW_CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(LogEntry {
+            level: *event.metadata().level(),
+            target: event.metadata().target().to_string(),
+            message: visitor.0
+        });
+    }
+}
+
+// The message comes first, other fields are appended as key=value
+#[cfg(not(feature = "headless"))]
+#[derive(Default)]
+struct MessageVisitor(String);
+
+#[cfg(not(feature = "headless"))]
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0.insert_str(0, &format!("{:?}", value));
+        } else {
+            write!(self.0, " {}={:?}", field.name(), value).unwrap();
+        }
+    }
+}
+
+/// Runtime log filter and the log window state. Replaces bevy's `LogPlugin`, which can't be
+/// reconfigured once the app runs.
+#[cfg(not(feature = "headless"))]
+pub struct Logging {
+    filter: reload::Handle<EnvFilter, Registry>,
+    buffer: LogBuffer,
+    default_level: LevelFilter,
+    category_levels: [LevelFilter; 4],
+    // extra filter directives typed in the log window, `RUST_LOG` at startup
+    directives: String,
+    filter_error: Option<String>
+}
+
+/// A headless build has no log window, the filter is only taken from `RUST_LOG`
+#[cfg(feature = "headless")]
+pub struct Logging;
+
+impl Logging {
+    /// Installs the global tracing subscriber, has to be called once before the app is built
+    pub fn init() -> Self {
+        let default_level = LevelFilter::INFO;
+        let category_levels = [LevelFilter::INFO; 4];
+        let directives = std::env::var("RUST_LOG").unwrap_or_default();
+        let filter = build_filter(default_level, category_levels, &directives);
+        let filter_error = filter.as_ref().err().map(ToString::to_string);
+        let filter = filter.unwrap_or_else(|_| EnvFilter::new(default_level.to_string()));
+        #[cfg(feature = "headless")]
+        {
+            tracing_subscriber::registry().with(filter).with(tracing_subscriber::fmt::layer()).init();
+            if let Some(error) = filter_error {
+                warn!("Can't use RUST_LOG, logging at the default levels: {}", error);
+            }
+            Self
+        }
+        #[cfg(not(feature = "headless"))]
+        Self::with_window(filter, default_level, category_levels, directives, filter_error)
+    }
+
+    #[cfg(not(feature = "headless"))]
+    fn with_window(filter: EnvFilter, default_level: LevelFilter, category_levels: [LevelFilter; 4], directives: String, filter_error: Option<String>) -> Self {
+        let (filter, handle) = reload::Layer::new(filter);
+        let buffer = LogBuffer::default();
+        let subscriber = tracing_subscriber::registry().with(filter).with(buffer.clone());
+        #[cfg(not(target_arch = "wasm32"))]
+        subscriber.with(tracing_subscriber::fmt::layer()).init();
+        // there's no stdout in the browser, log to the console and show panics there too
+        #[cfg(target_arch = "wasm32")]
+        {
+            subscriber.with(tracing_wasm::WASMLayer::new(tracing_wasm::WASMLayerConfig::default())).init();
+            console_error_panic_hook::set_once();
+        }
+        Self { filter: handle, buffer, default_level, category_levels, directives, filter_error }
+    }
+
+    #[cfg(not(feature = "headless"))]
+    fn apply_filter(&mut self) {
+        self.filter_error = match build_filter(self.default_level, self.category_levels, &self.directives) {
+            Ok(filter) => self.filter.reload(filter).err().map(|error| error.to_string()),
+            Err(error) => Some(error.to_string())
+        };
+    }
+}
+
+fn build_filter(default_level: LevelFilter, category_levels: [LevelFilter; 4], extra: &str) -> Result<EnvFilter, ParseError> {
+    let mut directives = format!("{},{}", default_level, LIBRARY_DIRECTIVES);
+    for (category, level) in CATEGORIES.iter().zip(category_levels) {
+        write!(directives, ",{}={}", category, level).unwrap();
+    }
+    if !extra.trim().is_empty() {
+        write!(directives, ",{}", extra.trim()).unwrap();
+    }
+    EnvFilter::try_new(directives)
+}
+
+#[cfg(not(feature = "headless"))]
+fn level_color(level: Level) -> egui::Color32 {
+    match level {
+        Level::ERROR => egui::Color32::LIGHT_RED,
+        Level::WARN => egui::Color32::YELLOW,
+        Level::INFO => egui::Color32::WHITE,
+        _ => egui::Color32::GRAY
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+fn level_combo(ui: &mut egui::Ui, label: &str, level: &mut LevelFilter) -> bool {
+    let mut changed = false;
+    egui::ComboBox::from_label(label).selected_text(level.to_string()).show_ui(ui, |ui| {
+        for option in LEVELS {
+            changed |= ui.selectable_value(level, option, option.to_string()).changed();
+        }
+    });
+    changed
+}
+
+#[cfg(not(feature = "headless"))]
+pub fn show_log_window(mut egui_context: ResMut<EguiContext>, mut settings: ResMut<DebugSettings>, mut logging: ResMut<Logging>) {
+    if !settings.log_window {
+        return
+    }
+    let mut open = true;
+    egui::Window::new("Log").open(&mut open).default_width(600.0).show(egui_context.ctx_mut(), |ui| {
+        let logging = &mut *logging;
+        let mut changed = false;
+        ui.horizontal_wrapped(|ui| {
+            changed |= level_combo(ui, "default", &mut logging.default_level);
+            for (category, level) in CATEGORIES.iter().zip(logging.category_levels.iter_mut()) {
+                changed |= level_combo(ui, category, level);
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("filter");
+            let response = ui.text_edit_singleline(&mut logging.directives);
+            if response.lost_focus() && ui.input().key_pressed(egui::Key::Enter) {
+                changed = true;
+            }
+        });
+        if changed {
+            logging.apply_filter();
+        }
+        if let Some(error) = &logging.filter_error {
+            ui.colored_label(egui::Color32::LIGHT_RED, error.as_str());
+        }
+        ui.separator();
+        // copied out so nothing logged while drawing can wait on the buffer lock
+        let lines: Vec<_> = logging.buffer.0.lock().unwrap().iter()
+            .map(|entry| (entry.level, format!("{:>5} {}: {}", entry.level, entry.target, entry.message)))
+            .collect();
+        egui::ScrollArea::vertical().stick_to_bottom().show(ui, |ui| {
+            for (level, text) in lines {
+                ui.colored_label(level_color(level), egui::RichText::new(text).monospace());
+            }
+        });
+    });
+    if !open {
+        settings.log_window = false;
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::{HashMap, VecDeque};
+use bevy::{prelude::*, ecs::schedule::SystemLabel};
+use serde::Deserialize;
+use super::{Prototype, Prototypes, TICK_RATE};
+use crate::inventory::Inventory;
+
+const DRONE_COLOR: Color = Color::rgb(0.9, 0.6, 0.2);
+const DRONE_REACH: f32 = 0.5; // tiles
+
+/// Role of a structure in the drone logistics network
+#[derive(Component, Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum Logistics {
+    Provider,
+    Requester { requests: HashMap<String, u32> }
+}
+
+#[derive(Prototype, Deserialize)]
+#[prot_category(drone)]
+pub struct DronePrototype {
+    name: String,
+    speed: f32, // tiles / second
+    capacity: u32
+}
+
+/// Structure that spawns logistics drones once built
+#[derive(Component, Deserialize, Clone)]
+pub struct DroneHub {
+    drone: String,
+    count: u32
+}
+
+#[derive(Clone)]
+pub struct LogisticsJob {
+    item: String,
+    count: u32,
+    from: Entity,
+    to: Entity
+}
+
+#[derive(Default)]
+pub struct LogisticsJobs(VecDeque<LogisticsJob>);
+
+#[derive(Component)]
+pub struct Drone {
+    prototype: String,
+    job: Option<LogisticsJob>,
+    cargo: u32
+}
+
+/// What drones look like, whatever they carry
+pub fn drone_sprite(position: Vec2) -> SpriteBundle {
+    SpriteBundle {
+        sprite: Sprite {
+            color: DRONE_COLOR,
+            custom_size: Some(Vec2::splat(0.3)),
+            ..default()
+        },
+        transform: Transform::from_translation(position.extend(2.0)),
+        ..default()
+    }
+}
+
+pub fn spawn_hub_drones(mut commands: Commands, hubs: Query<(&DroneHub, &Transform), Added<DroneHub>>) {
+    for (hub, transform) in hubs.iter() {
+        for _ in 0..hub.count {
+            commands.spawn_bundle(drone_sprite(transform.translation.truncate()))
+                .insert(Drone { prototype: hub.drone.clone(), job: None, cargo: 0 });
+        }
+    }
+}
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LogisticsPlanLabel;
+
+pub fn plan_logistics_jobs(
+    mut jobs: ResMut<LogisticsJobs>,
+    structures: Query<(Entity, &Logistics, &Inventory)>,
+    drones: Query<&Drone>)
+{
+    let mut incoming: HashMap<(Entity, &str), u32> = HashMap::new();
+    let mut reserved: HashMap<(Entity, &str), u32> = HashMap::new();
+    for job in jobs.0.iter().chain(drones.iter().filter_map(|drone| drone.job.as_ref())) {
+        *incoming.entry((job.to, job.item.as_str())).or_default() += job.count;
+        *reserved.entry((job.from, job.item.as_str())).or_default() += job.count;
+    }
+    let mut new_jobs = Vec::new();
+    for (requester, logistics, inventory) in structures.iter() {
+        let requests = match logistics {
+            Logistics::Requester { requests } => requests,
+            Logistics::Provider => continue
+        };
+        for (item, requested) in requests {
+            let incoming = incoming.get(&(requester, item.as_str())).copied().unwrap_or(0);
+            let mut missing = requested.saturating_sub(inventory.count(item) + incoming);
+            for (provider, logistics, provider_inventory) in structures.iter() {
+                if missing == 0 {
+                    break
+                }
+                if !matches!(logistics, Logistics::Provider) {
+                    continue
+                }
+                let reserved = reserved.entry((provider, item.as_str())).or_default();
+                let available = provider_inventory.count(item).saturating_sub(*reserved).min(missing);
+                if available > 0 {
+                    *reserved += available;
+                    missing -= available;
+                    new_jobs.push(LogisticsJob { item: item.clone(), count: available, from: provider, to: requester });
+                }
+            }
+        }
+    }
+    jobs.0.extend(new_jobs);
+}
+
+pub fn assign_logistics_jobs(mut jobs: ResMut<LogisticsJobs>, mut drones: Query<&mut Drone>, prototypes: Res<Prototypes>) {
+    for mut drone in drones.iter_mut() {
+        if drone.job.is_some() {
+            continue
+        }
+        let capacity = match DronePrototype::from_pt(&prototypes, &drone.prototype) {
+            Some(prototype) => prototype.capacity,
+            None => continue
+        };
+        if let Some(mut job) = jobs.0.pop_front() {
+            // split jobs that don't fit into a single trip
+            if job.count > capacity {
+                let mut rest = job.clone();
+                rest.count -= capacity;
+                job.count = capacity;
+                jobs.0.push_front(rest);
+            }
+            drone.job = Some(job);
+        } else {
+            break
+        }
+    }
+}
+
+type LogisticsStructure = (With<Logistics>, Without<Drone>);
+
+pub fn fly_drones(
+    mut drones: Query<(&mut Drone, &mut Transform)>,
+    mut structures: Query<(&mut Inventory, &Transform), LogisticsStructure>,
+    prototypes: Res<Prototypes>)
+{
+    for (mut drone, mut transform) in drones.iter_mut() {
+        let (job, speed) = match (drone.job.clone(), DronePrototype::from_pt(&prototypes, &drone.prototype)) {
+            (Some(job), Some(prototype)) => (job, prototype.speed),
+            _ => continue
+        };
+        let target = if drone.cargo == 0 { job.from } else { job.to };
+        let (mut inventory, target_transform) = match structures.get_mut(target) {
+            Ok(target) => target,
+            // the structure is gone, whatever the drone carries is lost
+            Err(_) => {
+                drone.job = None;
+                drone.cargo = 0;
+                continue
+            }
+        };
+        let position = transform.translation.truncate();
+        let offset = target_transform.translation.truncate() - position;
+        if offset.length() > DRONE_REACH {
+            let step = offset.clamp_length_max(speed / TICK_RATE as f32);
+            transform.translation += step.extend(0.0);
+        } else if drone.cargo == 0 {
+            let taken = job.count.min(inventory.count(&job.item));
+            if taken == 0 || !inventory.remove(&job.item, taken) {
+                drone.job = None;
+            } else {
+                drone.cargo = taken;
+            }
+        } else {
+            inventory.add(&job.item, drone.cargo);
+            drone.cargo = 0;
+            drone.job = None;
+        }
+    }
+}
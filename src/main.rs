@@ -1,23 +1,169 @@
-use std::{collections::HashMap, path::PathBuf, fs::File, f32::consts::PI, io::Read};
-use bevy::{prelude::*, window::PresentMode, render::camera::ScalingMode, input::mouse::{MouseWheel, MouseScrollUnit, MouseMotion}, time::Stopwatch, asset::AssetServerSettings};
+use std::{collections::HashMap, time::Duration};
+use bevy::{prelude::*, ecs::{schedule::{SystemLabel, RunCriteriaLabel, ShouldRun}, system::{EntityCommands, SystemParam}, event::Events}, time::{Stopwatch, FixedTimestep}, reflect::TypeUuid, asset::AssetPlugin, app::ScheduleRunnerSettings};
+#[cfg(not(feature = "headless"))]
+use bevy::{log::LogPlugin, window::PresentMode};
 use bevy_rapier2d::prelude::*;
 use serde::{Deserialize, Deserializer};
 use scriplets_derive::{ComponentPrototype, Prototype};
-use strum::AsRefStr;
 use blake3::Hash;
 
 mod program;
-mod data_value;
+mod status;
+mod debug;
+mod audio;
+mod animation;
+mod turret;
+mod energy;
+mod health;
+mod effects;
+mod inventory;
+mod construction;
+mod repair;
+mod circuit;
+mod logistics;
+mod belt;
+mod blueprint;
+mod storage;
+mod subscriptions;
+mod team;
+mod registry;
+mod behavior;
+mod telemetry;
+#[cfg(not(feature = "headless"))]
+mod plot;
+mod logging;
+mod collider;
+mod visual;
+mod loading;
+mod embedded;
+#[cfg(not(feature = "headless"))]
+mod camera;
+mod map;
+#[cfg(not(feature = "headless"))]
+mod ui;
+#[cfg(not(feature = "headless"))]
+mod gamepad;
+mod manual_control;
+mod template;
+mod api;
+mod equipment;
+mod heat;
+mod weather;
+mod creature;
+#[cfg(not(feature = "headless"))]
+mod console;
+mod locale;
+mod settings;
+#[cfg(not(feature = "headless"))]
+mod browser;
+#[cfg(not(feature = "headless"))]
+mod history;
+mod protodiff;
+mod watchdog;
+mod checksum;
+mod admin;
+// Lua doesn't run in the browser yet
+#[cfg(not(target_arch = "wasm32"))]
+mod scenario;
+// Lua doesn't run in the browser yet and there's no TCP there
+#[cfg(not(target_arch = "wasm32"))]
+mod debugger;
+// there's no file system to find mods on in the browser
+#[cfg(not(target_arch = "wasm32"))]
+mod archive;
+// or to save to
+#[cfg(not(target_arch = "wasm32"))]
+mod save;
+// and without saves there's nothing for the main menu to offer
+#[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+mod menu;
+// multiplayer needs sockets, and a window to play in on the client's side
+#[cfg(not(target_arch = "wasm32"))]
+mod net;
+#[cfg(not(target_arch = "wasm32"))]
+mod replication;
+#[cfg(not(target_arch = "wasm32"))]
+mod server;
+#[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+mod client;
 
-use program::{UnitProgram, UnitHandle};
-use data_value::{DataValue, DataValueHashEq};
+use program::{UnitProgram, WakeEvent, ScriptingPlugin, ScriptRandom};
+use status::{spawn_status_indicators, update_status_indicators};
+use debug::ShapeCastTrace;
+#[cfg(not(feature = "headless"))]
+use debug::PositionTrail;
+use audio::AudioPrototype;
+#[cfg(not(feature = "headless"))]
+use audio::SoundEvent;
+use animation::{AnimationPrototype, AnimationStateLabel, update_unit_animation_states, animate_sprites};
+use turret::{Turret, rotate_turrets};
+use energy::{Energy, recharge_energy};
+use health::{Health, DamageEvent, apply_damage, regenerate_shields, send_impact_damage};
+use effects::{StatusEffects, StatusEffectPrototype, ApplyEffectEvent, apply_status_effects, tick_status_effects};
+use inventory::{Inventory, ItemActionEvent, handle_item_actions};
+use construction::{StructurePrototype, Builder, start_construction, progress_construction};
+use repair::{RepairTool, repair, salvage};
+use circuit::{CircuitConnector, connect_circuits, propagate_circuits};
+use logistics::{DronePrototype, LogisticsJobs, LogisticsPlanLabel, spawn_hub_drones, plan_logistics_jobs, assign_logistics_jobs, fly_drones};
+use belt::{BeltPrototype, move_belt_items, apply_belt_unit_effects};
+use blueprint::{Blueprint, Blueprints, UnitSpawnQueue, BlueprintEvent, BlueprintOutcome, handle_blueprint_events};
+use storage::Storage;
+use team::Team;
+use behavior::BehaviorTree;
+use watchdog::Watchdog;
+#[cfg(not(target_arch = "wasm32"))]
+use scenario::Scenario;
+use checksum::{StateChecksum, ChecksumLabel, update_state_checksum};
+use embedded::EmbeddedAssetsPlugin;
+use loading::{AppState, AssetKind, LoadingPlugin};
+use visual::{VisualPrototype, Visuals, VisualName};
+use collider::{ColliderPrototype, ColliderName, DEFAULT_UNIT_HALF_SIZE};
+use logging::{Logging, PHYSICS};
+use registry::UnitLabel;
+use subscriptions::EventSubscriptions;
+#[cfg(not(feature = "headless"))]
+use camera::{CameraPlugin, RESOLUTION};
+use map::MapPlugin;
+use weather::{WeatherPlugin, WeatherPrototype, WeatherSchedulePrototype};
+use creature::{CreaturePlugin, CreaturePrototype};
+#[cfg(not(feature = "headless"))]
+use ui::UiPlugin;
+#[cfg(not(feature = "headless"))]
+use gamepad::GamepadPlugin;
+#[cfg(not(feature = "headless"))]
+use manual_control::ManualControlPlugin;
+use manual_control::{ManualIntents, ManualIntentLabel, apply_manual_intents};
+use template::ProgramTemplatePrototype;
+use locale::LocalePrototype;
+use equipment::{EquipmentGridPrototype, EquipmentPrototype, change_equipment};
+use heat::{Heat, Radiator, update_heat};
+// re-exported so modules keep using `crate::data_value`, `crate::schema` and `crate::migration`
+use scriplets_core::{data_value, schema, migration};
+use scriplets_core::movement::{self, MovementType, Obstacles, Pose, Hit};
 
+#[cfg(not(feature = "headless"))]
 const CLEAR_COLOR: Color = Color::rgb(0.1, 0.1, 0.1);
-const RESOLUTION: f32 = 16.0 / 9.0;
+const DEFAULT_UNIT_VISUAL: &str = "unit";
+const DUMP_LUA_DEFS_FLAG: &str = "--dump-lua-defs";
+const SEED_FLAG: &str = "--seed";
+const LUA_DEFS_PATH: &str = "scriplets.d.lua";
 
 // General TODO list
-// - split into client and server
+// - multiplayer (see server.rs and client.rs)
+//   - desync detection in replays: they'd record the StateChecksum of every tick and stop playing
+//     at the first mismatch, showing both checksums and the units
+//   - UnitId as the one reference to units in replays, like it already is in replication
+//     messages, script events and unit refs
+//   - uploading programs to the server, players can't change their units' programs yet. Uploads
+//     would be stamped with a tick and wait in a buffer like `ManualIntents` do
 // - code editing gui
+// - scripting in the browser build, mlua doesn't compile for wasm32-unknown-unknown. Either a Lua
+//   built with emscripten or an interpreter written in Rust
+// - saving the rest of the world: the map, creatures, items on the ground, corpses and the state
+//   of running programs
+// - mod manager: the settings list the mods in `mods` to enable, disable and reorder them (see
+//   archive.rs and settings.rs), what's missing is applying changes without restarting the game,
+//   the asset io is set up once at startup
 
 // General ideas
 //  Black box: a component that can store data when unit is running and extracted from a unit
@@ -37,12 +183,84 @@ const RESOLUTION: f32 = 16.0 / 9.0;
 #[derive(Component)]
 pub struct Unit;
 
-#[derive(Deserialize)]
-pub struct Prototypes {
-    #[serde(skip)]
-    hash: Option<Hash>,
-    #[serde(deserialize_with = "hashmap_from_sequence")]
-    movement: HashMap<String, Movement>
+// Generates the `Prototypes` table, so a new category is one line here plus its `prot_category`.
+// Categories are lists of prototypes in `prototypes.json`, required ones have to be there.
+macro_rules! prototypes_table {
+    (required { $($required:ident: $required_type:ty),* $(,)? } optional { $($optional:ident: $optional_type:ty),* $(,)? }) => {
+        #[derive(Deserialize, Default, TypeUuid)]
+        #[uuid = "8f5a2e4c-3b1d-4c6e-9a7f-2d0b5e8c1a93"]
+        pub struct Prototypes {
+            // of the merged prototypes and the mods, see `loading::hash_prototypes`
+            #[serde(skip)]
+            hash: Option<Hash>,
+            // the parsed file, for showing prototypes as they were written
+            #[serde(skip)]
+            source: serde_json::Value,
+            $(
+                #[serde(deserialize_with = "hashmap_from_sequence")]
+                $required: HashMap<String, $required_type>,
+            )*
+            $(
+                #[serde(default, deserialize_with = "hashmap_from_sequence")]
+                $optional: HashMap<String, $optional_type>,
+            )*
+        }
+
+        impl Prototypes {
+            /// Names of the categories as they appear in `prototypes.json`
+            pub const CATEGORIES: &'static [&'static str] = &[$(stringify!($required),)* $(stringify!($optional)),*];
+
+            /// Number of prototypes in each category
+            pub fn counts(&self) -> Vec<(&'static str, usize)> {
+                vec![$((stringify!($required), self.$required.len()),)* $((stringify!($optional), self.$optional.len())),*]
+            }
+
+            /// Checks that a category's prototypes, as they appear in `prototypes.json`, can be read
+            pub fn validate(category: &str, prototypes: &serde_json::Value) -> Result<(), String> {
+                let result = match category {
+                    $(stringify!($required) => Vec::<$required_type>::deserialize(prototypes).map(drop),)*
+                    $(stringify!($optional) => Vec::<$optional_type>::deserialize(prototypes).map(drop),)*
+                    _ => return Err(format!("unknown prototype category {}", category))
+                };
+                result.map_err(|error| format!("{}: {}", category, error))
+            }
+        }
+    };
+}
+
+prototypes_table! {
+    required {
+        movement: MovementStats
+    }
+    optional {
+        audio: AudioPrototype,
+        animation: AnimationPrototype,
+        turret: Turret,
+        health: Health,
+        energy: Energy,
+        heat: Heat,
+        radiator: Radiator,
+        status_effect: StatusEffectPrototype,
+        inventory: Inventory,
+        structure: StructurePrototype,
+        builder: Builder,
+        repair_tool: RepairTool,
+        circuit_connector: CircuitConnector,
+        drone: DronePrototype,
+        belt: BeltPrototype,
+        storage: Storage,
+        equipment_grid: EquipmentGridPrototype,
+        equipment: EquipmentPrototype,
+        behavior_tree: BehaviorTree,
+        watchdog: Watchdog,
+        collider: ColliderPrototype,
+        visual: VisualPrototype,
+        program_template: ProgramTemplatePrototype,
+        locale: LocalePrototype,
+        weather: WeatherPrototype,
+        weather_schedule: WeatherSchedulePrototype,
+        creature: CreaturePrototype
+    }
 }
 
 pub trait Prototype<'de>: Deserialize<'de> {
@@ -57,50 +275,92 @@ pub trait ComponentPrototype<'de, T: Component = Self>: Prototype<'de> {
     }
 }
 
+impl Prototypes {
+    /// Prototypes of a category as they appear in `prototypes.json`, after migrating it
+    pub fn source(&self, category: &str) -> &[serde_json::Value] {
+        self.source.get(category).and_then(serde_json::Value::as_array).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn contains(&self, category: &str, name: &str) -> bool {
+        self.source(category).iter().any(|prototype| prototype.get("name").and_then(serde_json::Value::as_str) == Some(name))
+    }
+
+    /// Files referenced by the prototypes, relative to the asset folder
+    fn asset_paths(&self) -> Vec<(AssetKind, &str)> {
+        let mut paths: Vec<(AssetKind, &str)> = self.audio.values().map(|audio| (AssetKind::Sound, audio.path())).collect();
+        paths.extend(self.animation.values().map(|animation| (AssetKind::Texture, animation.texture())));
+        paths.extend(self.visual.values().filter_map(VisualPrototype::texture).map(|texture| (AssetKind::Texture, texture)));
+        paths.extend(self.structure.values().filter_map(StructurePrototype::texture).map(|texture| (AssetKind::Texture, texture)));
+        paths.extend(self.program_template.values().map(|template| (AssetKind::Program, template.path())));
+        paths.extend(self.locale.values().map(|locale| (AssetKind::Locale, locale.path())));
+        paths.sort_unstable_by_key(|(_, path)| *path);
+        paths.dedup_by_key(|(_, path)| *path);
+        paths
+    }
+}
+
 pub fn hashmap_from_sequence<'de, D: Deserializer<'de>, P: Prototype<'de>>(deserializer: D) -> Result<HashMap<String, P>, D::Error> {
     Ok(Vec::<P>::deserialize(deserializer)?.into_iter().map(|p| (p.name().to_string(), p)).collect())
 }
 
-// TODO: reimplement acceleration movement type to support steering around a point
-//  Or make a new movement type which works as stated above
-#[derive(Component, Prototype, ComponentPrototype, Deserialize, Clone)]
-#[prot_category(movement)]
-pub struct Movement {
-    name: String,
-    movement_type: MovementType,
-    // movement characteristics
-    #[serde(default)]
-    speed: f32, // tiles / second
-    #[serde(default)]
-    max_speed: f32,
-    #[serde(default)]
-    max_speed_backwards: Option<f32>,
-    #[serde(default)]
-    acceleration: f32, // tiles / second^2
-    #[serde(default)]
-    braking_acceleration: Option<f32>,
-    #[serde(default)]
-    passive_deceleration: f32,
-    #[serde(default)]
-    rotation_speed: f32, // degrees / second
-    #[serde(default)]
-    rotation_offset: f32,
-    // input 
-    #[serde(skip)]
-    input_move: Vec2,
-    #[serde(skip)]
-    input_rotation: f32,
-    #[serde(skip)]
-    hand_brake: bool
-}
-
-#[derive(Deserialize, Clone, AsRefStr)]
-#[serde(rename_all = "kebab-case")]
-#[strum(serialize_all = "kebab-case")]
-pub enum MovementType {
-    Omnidirectional,
-    AcceleratedSteering,
-    Train
+/// How a unit moves, from its movement prototype. The movement itself is in `scriplets_core`, this
+/// makes it a component.
+#[derive(Component, Deserialize, Clone, Deref)]
+#[serde(transparent)]
+pub struct MovementStats(pub movement::MovementStats);
+
+impl Prototype<'_> for MovementStats {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn from_pt<'a, 'b>(prototypes_table: &'a Prototypes, name: &'b str) -> Option<&'a Self> {
+        prototypes_table.movement.get(name)
+    }
+}
+
+impl ComponentPrototype<'_> for MovementStats {
+    fn to_component(&self) -> Self {
+        self.clone()
+    }
+}
+
+/// What the unit's script or manual control asks the movement to do
+#[derive(Component, Clone, Default, Deref, DerefMut)]
+pub struct MovementIntent(pub movement::MovementIntent);
+
+/// Where the movement is at, the part of it that changes every tick
+#[derive(Component, Clone, Default)]
+pub struct MovementState {
+    pub movement: movement::MovementState,
+    last_move_result: MoveResult
+}
+
+/// What came of the intended move during the last tick
+#[derive(Clone, Copy, Default)]
+pub struct MoveResult {
+    // what the move or turn ran into, and its id if it was a unit
+    pub blocked_by: Option<Entity>,
+    pub blocked_by_unit: Option<UnitId>,
+    pub displacement: Vec2, // tiles
+    // false while turning towards a heading
+    pub heading_reached: bool
+}
+
+impl MovementStats {
+    /// Physics driven units are moved by rapier, the others by `handle_movement`
+    fn insert_body(&self, unit: &mut EntityCommands) {
+        match self.movement_type {
+            MovementType::PhysicsDriven => unit
+                .insert(RigidBody::Dynamic)
+                .insert(ColliderMassProperties::Mass(self.mass))
+                .insert(Damping { linear_damping: self.friction, angular_damping: self.friction })
+                .insert(GravityScale(0.0))
+                .insert(ExternalForce::default())
+                .insert(Velocity::default()),
+            _ => unit.insert(RigidBody::KinematicPositionBased)
+        };
+    }
 }
 
 #[derive(Component)]
@@ -108,271 +368,729 @@ pub struct UnitClock(Stopwatch);
 
 pub struct GameClock(Stopwatch);
 
-pub struct UnitSprite(Handle<Image>);
-pub struct WallSprite(Handle<Image>);
+/// Stable identifier of a unit, counting up from 1 in spawn order. Systems where units can affect
+/// each other process them in this order, so the same inputs give the same results.
+#[derive(Component, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct UnitId(pub u64);
 
-fn spawn_camera(mut commands: Commands) {
-    let mut camera = Camera2dBundle::default();
+/// Id the next spawned unit gets
+pub struct NextUnitId(u64);
 
-    camera.projection.top = 1.0;
-    camera.projection.bottom = -1.0;
-    camera.projection.right = 1.0 * RESOLUTION;
-    camera.projection.left = -1.0 * RESOLUTION;
+impl Default for NextUnitId {
+    fn default() -> Self {
+        Self(1)
+    }
+}
 
-    camera.projection.scaling_mode = ScalingMode::None;
+impl NextUnitId {
+    fn take(&mut self) -> UnitId {
+        self.0 += 1;
+        UnitId(self.0 - 1)
+    }
+}
 
-    commands.spawn_bundle(camera);
+/// Units sorted by their id, query iteration order changes whenever components are added or removed
+pub fn units_in_order(ids: &Query<(Entity, &UnitId)>) -> Vec<Entity> {
+    let mut units: Vec<(UnitId, Entity)> = ids.iter().map(|(entity, id)| (*id, entity)).collect();
+    units.sort_unstable();
+    units.into_iter().map(|(_, entity)| entity).collect()
 }
 
-fn move_and_zoom_camera(
-    mut camera: Query<(&mut OrthographicProjection, &mut Transform), With<Camera2d>>,
-    input: Res<Input<MouseButton>>,
-    mut mouse_scroll_evr: EventReader<MouseWheel>,
-    mut mouse_move_evr: EventReader<MouseMotion>)
+/// Seeds everything random scripts can see, set with `--seed`
+#[derive(Default)]
+pub struct WorldSeed(pub u64);
+
+pub use scriplets_core::TICK_RATE;
+
+fn tick_duration() -> Duration {
+    Duration::from_secs_f64(1.0 / TICK_RATE as f64)
+}
+
+/// Number of simulation steps done since the game finished loading. Use this instead of clocks for anything that
+/// has to come out the same on every machine.
+#[derive(Default)]
+pub struct SimTick(pub u64);
+
+/// Whether the simulation is stopped by the `/pause` admin command
+#[derive(Default)]
+pub struct Paused(pub bool);
+
+pub struct UiFont(Handle<Font>);
+
+// Every simulation step runs in `CoreStage::Update` in this order:
+//  clocks -> unit programs -> intents -> movement, then the rest of the world -> script events
+// Unit programs only record what they want to do, so every program in a step sees the world as
+// the previous step left it.
+// Steps run `TICK_RATE` times a second, so a frame can have none or several of them. Everything
+// that's part of a step uses the `SimStepLabel` run criteria, everything else runs once per frame.
+
+/// Run criteria of the simulation step, systems join it with `.with_run_criteria(SimStepLabel)`
+#[derive(RunCriteriaLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SimStepLabel;
+
+// Ticks are counted from the end of loading, how long loading takes mustn't change what happens.
+// Clients of a multiplayer game don't step at all, their world is what the server sends them.
+fn while_playing(
+    In(should_run): In<ShouldRun>,
+    state: Res<State<AppState>>,
+    paused: Res<Paused>,
+    #[cfg(not(any(target_arch = "wasm32", feature = "headless")))] client: Option<Res<client::Client>>) -> ShouldRun
 {
-    let (mut camera, mut camera_transform) = camera.single_mut();
-    for scroll_event in mouse_scroll_evr.iter() {
-        match scroll_event.unit {
-            MouseScrollUnit::Line => camera.scale = (camera.scale - 0.5 * scroll_event.y).clamp(1.0, 20.0),
-            MouseScrollUnit::Pixel => camera.scale = (camera.scale - 0.1 * scroll_event.y).clamp(1.0, 20.0)
-        }
+    #[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+    if client.is_some() {
+        return ShouldRun::No
     }
-    for move_event in mouse_move_evr.iter() {
-        if input.pressed(MouseButton::Middle) {
-            let mut delta = move_event.delta * 0.0025 * camera.scale;
-            delta.x = -delta.x;
-            camera_transform.translation += delta.extend(0.0);
-        }
+    if *state.current() == AppState::Playing && !paused.0 { should_run } else { ShouldRun::No }
+}
+
+// rapier steps on its own, it stops and goes on along with the simulation. Clients never pause,
+// their physics stay off.
+fn pause_physics(paused: Res<Paused>, mut rapier: ResMut<RapierConfiguration>) {
+    if paused.is_changed() && !paused.is_added() {
+        rapier.physics_pipeline_active = !paused.0;
+    }
+}
+
+pub trait AddSimEvent {
+    /// Like `add_event`, but the events are kept for two steps instead of two frames. Frames
+    /// without a step would drop events sent by the step before otherwise.
+    fn add_sim_event<T: Send + Sync + 'static>(&mut self) -> &mut Self;
+}
+
+impl AddSimEvent for App {
+    fn add_sim_event<T: Send + Sync + 'static>(&mut self) -> &mut Self {
+        self.init_resource::<Events<T>>()
+            .add_system(Events::<T>::update_system.with_run_criteria(SimStepLabel).before(ClockLabel))
     }
 }
 
-fn spawn_unit(
-    mut commands: Commands,
-    unit_sprite: Res<UnitSprite>,
-    component_prototypes: Res<Prototypes>)
+/// Clocks, the simulation tick and waking units up
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ClockLabel;
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UnitTickLabel;
+
+/// Systems carrying out what unit programs asked for, except movement
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IntentLabel;
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MovementLabel;
+
+/// Script events are delivered and the unit registry is updated once the world is done changing
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EventLabel;
+
+// scenarios spawn their own units, clients get theirs from the server and on servers without a
+// window every player gets one when joining
+fn spawn_default_unit(
+    mut spawn_queue: ResMut<UnitSpawnQueue>,
+    headless: Option<Res<loading::Headless>>,
+    #[cfg(not(target_arch = "wasm32"))] scenario: Option<Res<Scenario>>,
+    #[cfg(not(any(target_arch = "wasm32", feature = "headless")))] client: Option<Res<client::Client>>)
 {
-    let unit_program = UnitProgram::new_lua_with_program(r#"
+    #[cfg(not(target_arch = "wasm32"))]
+    if scenario.is_some() {
+        return
+    }
+    #[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+    if client.is_some() {
+        return
+    }
+    if headless.is_some() {
+        return
+    }
+    spawn_queue.0.push((default_blueprint(), Vec2::ZERO));
+}
+
+/// The unit a new game starts with, and every player joining a multiplayer game
+pub fn default_blueprint() -> Blueprint {
+    let default_prototype = || Some("default".to_string());
+    let program = r#"
         function on_tick(handle)
             handle:move(1, 1)
         end
-    "#.as_bytes());
-    let movement = Movement::component_from_pt(&component_prototypes, "default").unwrap();
-    commands.spawn()
+    "#;
+    Blueprint {
+        program: program.to_string(),
+        tick_interval: 1,
+        movement: "default".to_string(),
+        turret: default_prototype(),
+        health: default_prototype(),
+        energy: default_prototype(),
+        heat: default_prototype(),
+        inventory: default_prototype(),
+        builder: default_prototype(),
+        repair_tool: default_prototype(),
+        circuit_connector: default_prototype(),
+        storage: default_prototype(),
+        equipment_grid: default_prototype(),
+        behavior_tree: None,
+        watchdog: default_prototype(),
+        collider: None,
+        visual: None
+    }
+}
+
+/// Everything `spawn_unit` needs, for systems spawning units or creatures right away instead of
+/// queueing them
+#[derive(SystemParam)]
+pub struct UnitSpawner<'w, 's> {
+    pub commands: Commands<'w, 's>,
+    next_id: ResMut<'w, NextUnitId>,
+    pub visuals: Res<'w, Visuals>,
+    pub prototypes: Res<'w, Prototypes>
+}
+
+impl UnitSpawner<'_, '_> {
+    /// Unit id the next spawned unit will get
+    pub fn next_id(&self) -> u64 {
+        self.next_id.0
+    }
+
+    pub fn spawn(&mut self, blueprint: &Blueprint, position: Vec2) -> Option<Entity> {
+        spawn_unit(&mut self.commands, &mut self.next_id, blueprint, position, &self.visuals, &self.prototypes)
+    }
+}
+
+fn spawn_queued_units(mut spawner: UnitSpawner, mut spawn_queue: ResMut<UnitSpawnQueue>) {
+    for (blueprint, position) in spawn_queue.0.drain(..) {
+        spawner.spawn(&blueprint, position);
+    }
+}
+
+fn spawn_unit(commands: &mut Commands, next_id: &mut NextUnitId, blueprint: &Blueprint, position: Vec2, visuals: &Visuals, component_prototypes: &Prototypes) -> Option<Entity> {
+    let mut unit_program = UnitProgram::new_lua_with_program(blueprint.program.as_bytes());
+    unit_program.set_tick_interval(blueprint.tick_interval);
+    let movement = MovementStats::component_from_pt(component_prototypes, &blueprint.movement)?;
+    let turret = blueprint.turret.as_ref().and_then(|turret| Turret::component_from_pt(component_prototypes, turret));
+    let mut unit = commands.spawn();
+    movement.insert_body(&mut unit);
+    #[cfg(not(feature = "headless"))]
+    unit.insert(PositionTrail::default());
+    unit
         .insert(Unit)
+        .insert(next_id.take())
         .insert(UnitClock(Stopwatch::default()))
+        .insert(MovementIntent::default())
+        .insert(MovementState { movement: movement.initial_state(), ..default() })
         .insert(movement)
         .insert(unit_program)
-        .insert(Collider::cuboid(0.499, 0.499))
-        .insert(RigidBody::KinematicPositionBased)
-        .insert_bundle(SpriteBundle {
-            texture: unit_sprite.0.clone(),
-            sprite: Sprite {
-                custom_size: Some(Vec2::splat(1.0)),
-                ..default()
-            },
-            ..default()});
-}
-
-fn spawn_walls(mut commands: Commands, wall_sprite: Res<WallSprite>) {
-    for i in 1..=5 {
-        spawn_wall(&mut commands, i as f32, 5.0, &wall_sprite.0)
+        .insert(ShapeCastTrace::default())
+        .insert(StatusEffects::default())
+        .insert(EventSubscriptions::default())
+        .insert(Team::default())
+        .insert(UnitLabel::default())
+        .insert(ScriptRandom::default());
+    match &blueprint.visual {
+        Some(visual) => {
+            visuals.insert(&mut unit, visual, position);
+            unit.insert(VisualName(visual.clone()));
+        }
+        None => visuals.insert(&mut unit, DEFAULT_UNIT_VISUAL, position)
+    }
+    if let Some(health) = blueprint.health.as_ref().and_then(|health| Health::component_from_pt(component_prototypes, health)) {
+        unit.insert(health);
+    }
+    if let Some(energy) = blueprint.energy.as_ref().and_then(|energy| Energy::component_from_pt(component_prototypes, energy)) {
+        unit.insert(energy);
     }
-    for j in 0..=4 {
-        spawn_wall(&mut commands, 5.0, j as f32, &wall_sprite.0)
+    if let Some(heat) = blueprint.heat.as_ref().and_then(|heat| Heat::component_from_pt(component_prototypes, heat)) {
+        unit.insert(heat);
     }
-    spawn_wall(&mut commands, -1.0, 5.0, &wall_sprite.0)
+    if let Some(inventory) = blueprint.inventory.as_ref().and_then(|inventory| Inventory::component_from_pt(component_prototypes, inventory)) {
+        unit.insert(inventory);
+    }
+    if let Some(builder) = blueprint.builder.as_ref().and_then(|builder| Builder::component_from_pt(component_prototypes, builder)) {
+        unit.insert(builder);
+    }
+    if let Some(repair_tool) = blueprint.repair_tool.as_ref().and_then(|repair_tool| RepairTool::component_from_pt(component_prototypes, repair_tool)) {
+        unit.insert(repair_tool);
+    }
+    if let Some(connector) = blueprint.circuit_connector.as_ref().and_then(|connector| CircuitConnector::component_from_pt(component_prototypes, connector)) {
+        unit.insert(connector);
+    }
+    if let Some(storage) = blueprint.storage.as_ref().and_then(|storage| Storage::component_from_pt(component_prototypes, storage)) {
+        unit.insert(storage);
+    }
+    if let Some(grid) = blueprint.equipment_grid.as_ref().and_then(|grid| EquipmentGridPrototype::component_from_pt(component_prototypes, grid)) {
+        unit.insert(grid);
+    }
+    if let Some(watchdog) = blueprint.watchdog.as_ref().and_then(|watchdog| Watchdog::component_from_pt(component_prototypes, watchdog)) {
+        unit.insert(watchdog);
+    }
+    match blueprint.collider.as_ref().and_then(|collider| Some((collider, ColliderPrototype::component_from_pt(component_prototypes, collider)?))) {
+        Some((name, collider)) => unit.insert(collider).insert(ColliderName(name.clone())),
+        None => unit.insert(Collider::cuboid(DEFAULT_UNIT_HALF_SIZE, DEFAULT_UNIT_HALF_SIZE))
+    };
+    if let Some(behavior_tree) = blueprint.behavior_tree.as_ref().and_then(|behavior_tree| BehaviorTree::component_from_pt(component_prototypes, behavior_tree)) {
+        unit.insert(behavior_tree);
+    }
+    if let Some(turret) = turret {
+        unit.with_children(|parent| {
+            parent.spawn_bundle(turret.sprite_bundle()).insert(turret);
+        });
+    }
+    Some(unit.id())
 }
 
-fn spawn_wall(commands: &mut Commands, x: f32, y: f32, sprite: &Handle<Image>) {
-    let transform = TransformBundle::from(Transform::from_xyz(x, y, 0.0));
-    commands.spawn()
-        .insert(Collider::cuboid(0.5, 0.5))
-        .insert(RigidBody::Fixed)
-        .insert_bundle(SpriteBundle {
-            texture: sprite.clone(),
-            transform: transform.local,
-            global_transform: transform.global,
-            sprite: Sprite {
-                custom_size: Some(Vec2::splat(1.0)),
-                ..default()
-            },
-            ..default()
-        });
+type MovingUnit = (Entity, &'static MovementStats, &'static mut MovementIntent, &'static mut MovementState, &'static mut Transform, &'static Collider, Option<&'static mut ShapeCastTrace>, Option<&'static StatusEffects>, Option<&'static Heat>, Option<&'static mut ExternalForce>, Option<&'static Velocity>);
+
+/// Rapier's view of the world for the unit with `collider`, which doesn't block itself
+struct RapierObstacles<'a> {
+    context: &'a RapierContext,
+    collider: &'a Collider,
+    filter: QueryFilter<'a>
+}
+
+impl Obstacles for RapierObstacles<'_> {
+    type Obstacle = Entity;
+
+    fn cast(&self, pose: Pose, delta: Vec2) -> Option<Hit<Entity>> {
+        self.context.cast_shape(pose.position, pose.rotation, delta, self.collider, 1.0, self.filter).map(|(obstacle, toi)| Hit {
+            obstacle,
+            toi: toi.toi,
+            normal: Vec2::from_angle(pose.rotation).rotate(toi.normal1)
+        })
+    }
+
+    fn overlap(&self, pose: Pose) -> Option<Entity> {
+        self.context.intersection_with_shape(pose.position, pose.rotation, self.collider, self.filter)
+    }
 }
 
 fn handle_movement(
-    mut units: Query<(Entity, &mut Movement, &mut Transform, &Collider), With<Unit>>,
-    rapier_context: Res<RapierContext>)
+    mut units: Query<MovingUnit, With<Unit>>,
+    ids: Query<(Entity, &UnitId)>,
+    rapier_context: Res<RapierContext>,
+    healths: Query<&Health>,
+    #[cfg(not(feature = "headless"))] mut sound_events: EventWriter<SoundEvent>,
+    mut wake_events: EventWriter<WakeEvent>,
+    mut damage_events: EventWriter<DamageEvent>)
 {
-    for (entity, mut movement, mut transform, collider) in units.iter_mut() {
+    // earlier units move first and can block later ones
+    for entity in units_in_order(&ids) {
+        let (entity, movement, mut intent, mut state, mut transform, collider, mut trace, effects, heat, external_force, velocity) = match units.get_mut(entity) {
+            Ok(unit) => unit,
+            Err(_) => continue
+        };
+        let mut speed_multiplier = effects.map_or(1.0, StatusEffects::speed_multiplier);
+        if let Some(overclock) = movement.overclock(&intent).filter(|_| !heat.map_or(false, Heat::is_overheated)) {
+            speed_multiplier *= overclock.speed_multiplier;
+        }
+        let start = transform.translation.truncate();
+        let pose = Pose { position: start, rotation: transform.rotation.to_euler(EulerRot::XYZ).2 };
+        let mut blocked_by = None;
         match movement.movement_type {
-            MovementType::Omnidirectional => {
-                if !movement.hand_brake {
-                    if movement.input_rotation != 0.0 {
-                        let rotation = Quat::from_rotation_z(-(movement.rotation_speed * movement.input_rotation.clamp(-1.0, 1.0) * PI) / (180.0 * 60.0));
-                        transform.rotation *= rotation;
-                    }
-                    if movement.input_move != Vec2::ZERO {
-                        let unrotated_move = movement.input_move.clamp_length_max(1.0) * (movement.speed / 60.0);
-                        let delta = unrotated_move.rotate(transform.right().truncate());
-                        let shape_pos = transform.translation.truncate();
-                        let shape_rot = transform.rotation.to_euler(EulerRot::XYZ).2;
-                        let max_toi = 1.0;
-                        let filter = QueryFilter::default()
-                            .exclude_collider(entity)
-                            .exclude_sensors();
-                        if rapier_context.cast_shape(shape_pos, shape_rot, delta, collider, max_toi, filter).is_none() {
-                            transform.translation += delta.extend(0.0);
-                        }
-                        movement.input_move = Vec2::ZERO;
-                    }
+            MovementType::PhysicsDriven => {
+                let (force, torque) = movement.physics_forces(&mut intent, pose, speed_multiplier);
+                if let Some(mut external_force) = external_force {
+                    external_force.force = force;
+                    external_force.torque = torque;
                 }
-            },
-            MovementType::AcceleratedSteering => {
-                let input_move_vec = movement.input_move.clamp(Vec2::NEG_X + Vec2::NEG_Y, Vec2::X + Vec2::Y);
-                let max_speed = movement.max_speed;
-                let max_speed_backwards = -movement.max_speed_backwards.unwrap_or(max_speed);
-                let acceleration = movement.acceleration;
-                let braking_acceleration = -movement.braking_acceleration.unwrap_or(acceleration);
-                let passive_deceleration = movement.passive_deceleration;
-                let is_moving_forward = movement.speed > 0.0;
-                let is_moving_backwards = movement.speed < 0.0;
-                let new_speed = {
-                    let acceleration = {
-                        if movement.hand_brake {
-                            if movement.speed > 0.0 {
-                                braking_acceleration
-                            } else {
-                                -braking_acceleration
-                            }
-                        } else if (movement.speed > 0.0 && input_move_vec.x > 0.0) || (movement.speed < 0.0 && input_move_vec.x < 0.0) {
-                            acceleration
-                        } else if (movement.speed > 0.0 && input_move_vec.x < 0.0) || (movement.speed < 0.0 && input_move_vec.x > 0.0) {
-                            braking_acceleration
-                        } else if movement.speed != 0.0 {
-                            -passive_deceleration
-                        } else {
-                            acceleration
-                        }
-                        
-                    };
-                    let new_speed_uncapped = (movement.speed + acceleration * input_move_vec.x / 60.0).clamp(max_speed_backwards, max_speed);
-                    if is_moving_forward {
-                        new_speed_uncapped.clamp(0.0, f32::MAX)
-                    } else if is_moving_backwards {
-                        new_speed_uncapped.clamp(f32::MIN, 0.0)
-                    } else {
-                        new_speed_uncapped
-                    }
-                };
-                movement.speed = new_speed;
-                if movement.speed != 0.0 {
-                    let linear_delta = movement.speed / 60.0;
-                    let starting_translation = transform.translation.truncate() + transform.up().truncate() * movement.rotation_offset;
-                    let mut rot_angle = (movement.rotation_speed * PI / (60.0 * 180.0)) * input_move_vec.y;
-                    if movement.speed < 0.0 {
-                        rot_angle = -rot_angle;
-                    }
-                    let result_rotation = transform.rotation * Quat::from_rotation_z(-rot_angle);
-                    let turning_scale = linear_delta / rot_angle;
-                    let rot_vec_normalized = Vec2::from_angle(rot_angle);
-                    let turning_radius = transform.right().truncate() + transform.up().truncate() * movement.rotation_offset * turning_scale;
-                    let turning_origin = starting_translation - turning_radius;
-                    let result_translation = turning_radius.rotate(rot_vec_normalized) + turning_origin - transform.up().truncate() * movement.rotation_offset;
-                    
-                    let delta = result_translation - starting_translation;
-                    let shape_pos = result_translation;
-                    let shape_rot = result_rotation.to_euler(EulerRot::XYZ).2;
-                    let max_toi = 1.0;
-                    let filter = QueryFilter::default()
-                        .exclude_collider(entity)
-                        .exclude_sensors();
-                    if rapier_context.cast_shape(shape_pos, shape_rot, delta, collider, max_toi, filter).is_none() {
-                        transform.translation = result_translation.extend(0.0);
-                        transform.rotation = result_rotation;
+                if let Some(velocity) = velocity {
+                    state.movement.speed = velocity.linvel.length();
+                }
+            }
+            _ => {
+                let filter = QueryFilter::default()
+                    .exclude_collider(entity)
+                    .exclude_sensors();
+                let obstacles = RapierObstacles { context: &rapier_context, collider, filter };
+                let step = movement.step(&mut intent, &mut state.movement, pose, speed_multiplier, &obstacles);
+                if let (Some(cast), Some(trace)) = (step.cast, &mut trace) {
+                    if let Some(obstacle) = cast.hit.filter(|_| !trace.hit) {
+                        debug!(target: PHYSICS, "Unit {:?} collided at {}", entity, cast.origin);
+                        #[cfg(not(feature = "headless"))]
+                        sound_events.send(SoundEvent { name: "collision".to_string(), position: cast.origin });
+                        wake_events.send(WakeEvent { unit: entity });
+                        send_impact_damage(&mut damage_events, &healths, entity, obstacle, cast.speed, movement.mass);
                     }
-                    movement.input_move = Vec2::ZERO
+                    **trace = ShapeCastTrace {
+                        #[cfg(not(feature = "headless"))]
+                        origin: cast.origin,
+                        #[cfg(not(feature = "headless"))]
+                        delta: cast.delta,
+                        hit: cast.hit.is_some()
+                    };
+                }
+                if step.pose.position != pose.position {
+                    transform.translation = step.pose.position.extend(transform.translation.z);
+                }
+                if step.pose.rotation != pose.rotation {
+                    transform.rotation = Quat::from_rotation_z(step.pose.rotation);
                 }
+                blocked_by = step.blocked_by;
             }
-            _ => {}
         }
+        let displacement = match velocity.filter(|_| matches!(movement.movement_type, MovementType::PhysicsDriven)) {
+            // dynamic bodies are moved by the physics step, this is how far the last one took them
+            Some(velocity) => velocity.linvel / TICK_RATE as f32,
+            None => transform.translation.truncate() - start
+        };
+        let heading_reached = intent.target_heading.is_none();
+        let blocked_by_unit = blocked_by.and_then(|obstacle| ids.get(obstacle).ok()).map(|(_, id)| *id);
+        state.last_move_result = MoveResult { blocked_by, blocked_by_unit, displacement, heading_reached };
     }
 }
 
-fn unit_tick(
-    mut units: Query<(&mut UnitProgram, Option<&mut Movement>, &mut UnitClock, &Transform), With<Unit>>,
-    game_clock: Res<GameClock>) 
-{
-    for (mut unit_program, mut movement, clock, transform) in units.iter_mut() {
-        let handle = UnitHandle {
-            movement: movement.as_deref_mut(),
-            transform,
-            clock: &clock,
-            game_clock: &game_clock
-        };
-        unit_program.tick(handle)
-    }
+fn tick_units_clocks(mut units: Query<&mut UnitClock, With<Unit>>) {
+    units.iter_mut().for_each(|mut unit| {unit.0.tick(tick_duration());})
 }
 
-fn tick_units_clocks(mut units: Query<&mut UnitClock, With<Unit>>, time: Res<Time>) {
-    units.iter_mut().for_each(|mut unit| {unit.0.tick(time.delta());})
+fn game_clock_tick(mut clock: ResMut<GameClock>) {
+    clock.0.tick(tick_duration());
 }
 
-fn game_clock_tick(mut clock: ResMut<GameClock>, time: Res<Time>) {
-    clock.0.tick(time.delta());
+fn advance_sim_tick(mut sim_tick: ResMut<SimTick>) {
+    sim_tick.0 += 1;
 }
 
-fn print_units_positions(units: Query<&Transform, With<Unit>>) {
-    for (i, unit) in units.iter().enumerate() {
-        println!("Unit #{}: x {}, y {}", i, unit.translation.x, unit.translation.y)
+/// Units and their equipment: spawning, movement and everything that acts on the intents unit
+/// programs record
+pub struct UnitPlugin;
+
+impl Plugin for UnitPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(32.0))
+            // rapier runs in its own stages, this keeps it stepping at the simulation's rate
+            .insert_resource(RapierConfiguration {
+                timestep_mode: TimestepMode::Interpolated { dt: 1.0 / TICK_RATE as f32, time_scale: 1.0, substeps: 1 },
+                ..default()
+            })
+            .insert_resource(GameClock(Stopwatch::default()))
+            .init_resource::<SimTick>()
+            .init_resource::<Paused>()
+            .add_system(pause_physics)
+            .init_resource::<UnitSpawnQueue>()
+            .init_resource::<NextUnitId>()
+            .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(spawn_default_unit))
+            .add_sim_event::<DamageEvent>()
+            .add_sim_event::<ApplyEffectEvent>()
+            .add_sim_event::<ItemActionEvent>()
+            .add_sim_event::<BlueprintEvent>()
+            .add_event::<BlueprintOutcome>()
+            .init_resource::<LogisticsJobs>()
+            .init_resource::<Blueprints>()
+            .add_system_set(SystemSet::new()
+                .label(ClockLabel)
+                .with_run_criteria(FixedTimestep::steps_per_second(TICK_RATE as f64).chain(while_playing).label(SimStepLabel))
+                .with_system(tick_units_clocks)
+                .with_system(advance_sim_tick)
+                .with_system(game_clock_tick))
+            .add_system_set(SystemSet::new()
+                .label(IntentLabel)
+                .after(UnitTickLabel)
+                .with_run_criteria(SimStepLabel)
+                .with_system(rotate_turrets)
+                .with_system(start_construction)
+                .with_system(repair)
+                .with_system(salvage)
+                .with_system(connect_circuits)
+                .with_system(handle_item_actions)
+                .with_system(change_equipment)
+                .with_system(handle_blueprint_events)
+                .with_system(plan_logistics_jobs.label(LogisticsPlanLabel))
+                .with_system(assign_logistics_jobs.after(LogisticsPlanLabel)))
+            .init_resource::<ManualIntents>()
+            .add_system(apply_manual_intents.label(ManualIntentLabel).after(UnitTickLabel).before(MovementLabel).with_run_criteria(SimStepLabel))
+            .add_system(handle_movement.label(MovementLabel).after(IntentLabel).with_run_criteria(SimStepLabel))
+            .add_system_set(SystemSet::new()
+                .after(MovementLabel)
+                .before(EventLabel)
+                .with_run_criteria(SimStepLabel)
+                .with_system(apply_damage)
+                .with_system(regenerate_shields)
+                .with_system(recharge_energy)
+                .with_system(update_heat)
+                .with_system(apply_status_effects)
+                .with_system(tick_status_effects)
+                .with_system(progress_construction)
+                .with_system(propagate_circuits)
+                .with_system(spawn_hub_drones)
+                .with_system(fly_drones)
+                .with_system(move_belt_items)
+                .with_system(apply_belt_unit_effects)
+                .with_system(spawn_queued_units)
+                .with_system(spawn_status_indicators)
+                .with_system(update_status_indicators))
+            .init_resource::<StateChecksum>()
+            .add_system(update_state_checksum.label(ChecksumLabel).after(EventLabel).with_run_criteria(SimStepLabel))
+            .add_system(update_unit_animation_states.label(AnimationStateLabel).after(MovementLabel))
+            .add_system(animate_sprites.after(AnimationStateLabel));
+        // played by `CameraPlugin`, a headless build has nothing to hear them with
+        #[cfg(not(feature = "headless"))]
+        app.add_event::<SoundEvent>();
     }
 }
 
-fn load_assets(
-    mut commands: Commands,
-    assets: Res<AssetServer>,
-    asset_settings: Res<AssetServerSettings>)
-{
-    let unit_sprite = assets.load("unit.png");
-    commands.insert_resource(UnitSprite(unit_sprite));
-    let wall_sprite = assets.load("wall.png");
-    commands.insert_resource(WallSprite(wall_sprite));
-    let prototypes_path = PathBuf::from(&asset_settings.asset_folder).join("prototypes.json");
-    let mut prototypes_file = File::open(prototypes_path).unwrap();
-    let mut prototypes_file_data = Vec::new();
-    prototypes_file.read_to_end(&mut prototypes_file_data).unwrap();
-    let hash = blake3::hash(&prototypes_file_data);
-    let mut prototypes: Prototypes = serde_json::from_slice(&prototypes_file_data).unwrap();
-    prototypes.hash = Some(hash);
-    commands.insert_resource(prototypes)
+// The world and everything acting on it, the same in the game and on servers without a window
+fn add_simulation(app: &mut App) {
+    app
+        .add_plugin(LoadingPlugin)
+        .add_plugin(UnitPlugin)
+        .add_plugin(ScriptingPlugin)
+        .add_plugin(MapPlugin)
+        .add_plugin(WeatherPlugin)
+        .add_plugin(CreaturePlugin)
+        .add_plugin(admin::AdminPlugin);
+    #[cfg(not(target_arch = "wasm32"))]
+    app
+        .add_plugin(server::ServerPlugin)
+        .add_plugin(save::SavePlugin);
+}
+
+/// App of a server without a window, run by `--server` and in a background thread by
+/// `--local-server`
+#[cfg(not(target_arch = "wasm32"))]
+fn server_app(server: server::Server, seed: u64) -> App {
+    let mut app = windowless_app(seed);
+    app.insert_resource(server);
+    add_simulation(&mut app);
+    app
+}
+
+// Without a window, the `headless` build runs like servers do. Assets the simulation spawns things
+// with are stored but never drawn.
+#[cfg(any(not(target_arch = "wasm32"), feature = "headless"))]
+fn windowless_app(seed: u64) -> App {
+    let mut app = App::new();
+    app
+        .insert_resource(WorldSeed(seed))
+        .insert_resource(loading::Headless)
+        // steps are timed by the simulation's own run criteria, this only keeps the loop from spinning
+        .insert_resource(ScheduleRunnerSettings::run_loop(tick_duration() / 2))
+        .add_plugins(MinimalPlugins)
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_plugin(EmbeddedAssetsPlugin)
+        .add_plugin(AssetPlugin)
+        .add_asset::<Image>()
+        .add_asset::<TextureAtlas>()
+        .add_asset::<Mesh>()
+        .add_asset::<ColorMaterial>()
+        .add_asset::<Font>()
+        .add_asset::<AudioSource>();
+    app
 }
 
 fn main() {
-    let height = 900.0;
+    if std::env::args().any(|arg| arg == DUMP_LUA_DEFS_FLAG) {
+        match std::fs::write(LUA_DEFS_PATH, api::lua_definitions()) {
+            Ok(()) => println!("Wrote {}", LUA_DEFS_PATH),
+            Err(error) => eprintln!("Can't write {}: {}", LUA_DEFS_PATH, error)
+        }
+        return
+    }
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some(protodiff::PROTODIFF_COMMAND) {
+        match (args.get(2), args.get(3)) {
+            (Some(a), Some(b)) => match protodiff::protodiff(a, b) {
+                Ok(diff) => print!("{}", diff),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }
+            },
+            _ => {
+                eprintln!("usage: scriplets {} <a.json> <b.json>", protodiff::PROTODIFF_COMMAND);
+                std::process::exit(2);
+            }
+        }
+        return
+    }
+    let seed = match args.iter().position(|arg| arg == SEED_FLAG).map(|index| args.get(index + 1).and_then(|seed| seed.parse().ok())) {
+        Some(Some(seed)) => seed,
+        Some(None) => {
+            eprintln!("usage: scriplets {} <number>", SEED_FLAG);
+            std::process::exit(2);
+        }
+        None => 0
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    match args.iter().position(|arg| arg == server::SERVER_FLAG).map(|index| args.get(index + 1).and_then(|port| port.parse().ok())) {
+        Some(Some(port)) => {
+            let logging = Logging::init();
+            match server::Server::bind(port) {
+                Ok(server) => {
+                    server_app(server, seed).insert_resource(logging).run();
+                    return
+                }
+                Err(error) => {
+                    eprintln!("Can't host on port {}: {}", port, error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(None) => {
+            eprintln!("usage: scriplets {} <port>", server::SERVER_FLAG);
+            std::process::exit(2);
+        }
+        None => ()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    let debug_server = match args.iter().position(|arg| arg == debugger::DEBUG_SERVER_FLAG).map(|index| args.get(index + 1).and_then(|port| port.parse().ok())) {
+        Some(Some(port)) => match debugger::DebugServer::bind(port) {
+            Ok(server) => Some(server),
+            Err(error) => {
+                eprintln!("Can't start the debug server on port {}: {}", port, error);
+                std::process::exit(1);
+            }
+        },
+        Some(None) => {
+            eprintln!("usage: scriplets {} <port>", debugger::DEBUG_SERVER_FLAG);
+            std::process::exit(2);
+        }
+        None => None
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    let scenario = match args.iter().position(|arg| arg == scenario::SCENARIO_FLAG).map(|index| args.get(index + 1)) {
+        Some(Some(path)) => match scenario::Scenario::load(path) {
+            Ok(scenario) => Some(scenario),
+            Err(error) => {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        },
+        Some(None) => {
+            eprintln!("usage: scriplets {} <file.lua>", scenario::SCENARIO_FLAG);
+            std::process::exit(2);
+        }
+        None => None
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    let server = match args.iter().position(|arg| arg == server::HOST_FLAG).map(|index| args.get(index + 1).and_then(|port| port.parse().ok())) {
+        Some(Some(port)) => match server::Server::bind(port) {
+            Ok(server) => Some(server),
+            Err(error) => {
+                eprintln!("Can't host on port {}: {}", port, error);
+                std::process::exit(1);
+            }
+        },
+        Some(None) => {
+            eprintln!("usage: scriplets {} <port>", server::HOST_FLAG);
+            std::process::exit(2);
+        }
+        None => None
+    };
+    #[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+    let client = match args.iter().position(|arg| arg == client::JOIN_FLAG).map(|index| args.get(index + 1)) {
+        Some(Some(address)) => {
+            let name = match args.iter().position(|arg| arg == client::NAME_FLAG).map(|index| args.get(index + 1)) {
+                Some(Some(name)) => name.clone(),
+                Some(None) => {
+                    eprintln!("usage: scriplets {} <address> {} <name>", client::JOIN_FLAG, client::NAME_FLAG);
+                    std::process::exit(2);
+                }
+                None => net::default_name()
+            };
+            let password = match args.iter().position(|arg| arg == client::PASSWORD_FLAG).map(|index| args.get(index + 1)) {
+                Some(Some(password)) => Some(password.clone()),
+                Some(None) => {
+                    eprintln!("usage: scriplets {} <address> {} <password>", client::JOIN_FLAG, client::PASSWORD_FLAG);
+                    std::process::exit(2);
+                }
+                None => None
+            };
+            if server.is_some() || scenario.is_some() {
+                eprintln!("{} plays on another game, it can't be combined with {} or {}", client::JOIN_FLAG, server::HOST_FLAG, scenario::SCENARIO_FLAG);
+                std::process::exit(2);
+            }
+            match client::Client::connect(address, name, args.iter().any(|arg| arg == client::OBSERVE_FLAG), password) {
+                Ok(client) => Some(client),
+                Err(error) => {
+                    eprintln!("Can't connect to {}: {}", address, error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(None) => {
+            eprintln!("usage: scriplets {} <address>", client::JOIN_FLAG);
+            std::process::exit(2);
+        }
+        None => None
+    };
+    #[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+    let local_server = args.iter().any(|arg| arg == server::LOCAL_SERVER_FLAG);
+    #[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+    if local_server && (client.is_some() || server.is_some() || scenario.is_some()) {
+        eprintln!("{} can't be combined with {}, {} or {}", server::LOCAL_SERVER_FLAG, client::JOIN_FLAG, server::HOST_FLAG, scenario::SCENARIO_FLAG);
+        std::process::exit(2);
+    }
+    let logging = Logging::init();
+    // started once logging is, the server logs from its own thread
+    #[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+    let client = if local_server {
+        let mut local = server::Server::local();
+        let transport = local.connect_local();
+        std::thread::spawn(move || server_app(local, seed).run());
+        Some(client::Client::local(transport, net::default_name()))
+    } else {
+        client
+    };
+    // a headless build runs the simulation without a window or anything to look at it with
+    #[cfg(feature = "headless")]
+    let mut app = windowless_app(seed);
+    #[cfg(not(feature = "headless"))]
     let mut app = App::new();
-    app.insert_resource(ClearColor(CLEAR_COLOR))
+    #[cfg(not(feature = "headless"))]
+    let height = 900.0;
+    #[cfg(not(feature = "headless"))]
+    app
+        .insert_resource(ClearColor(CLEAR_COLOR))
+        .insert_resource(WorldSeed(seed))
         .insert_resource(WindowDescriptor {
             title: "Scriplets".to_string(),
             present_mode: PresentMode::Fifo,
             height,
             width: height * RESOLUTION,
             resizable: false,
+            // only used in the browser
+            canvas: Some("#scriplets".to_string()),
+            fit_canvas_to_parent: true,
             ..default()
         })
-        .add_plugins(DefaultPlugins)
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(32.0))
-        .insert_resource(GameClock(Stopwatch::default()))
-        .add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
-        .add_startup_system(spawn_walls)
-        .add_startup_system(spawn_unit)
-        .add_startup_system(spawn_camera)
-        .add_system_to_stage(CoreStage::First, tick_units_clocks)
-        .add_system_to_stage(CoreStage::PreUpdate, unit_tick)
-        .add_system(print_units_positions)
-        .add_system(game_clock_tick)
-        .add_system(handle_movement)
-        .add_system(move_and_zoom_camera);
-    #[cfg(feature = "debug")]
-    app.add_plugin(RapierDebugRenderPlugin::default());
+        .add_plugins_with(DefaultPlugins, |group| group.disable::<LogPlugin>().add_before::<AssetPlugin, _>(EmbeddedAssetsPlugin));
+    app.insert_resource(logging);
+    add_simulation(&mut app);
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(server) = debug_server {
+        app.insert_resource(server);
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(server) = server {
+        app.insert_resource(server);
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let continue_game = args.iter().any(|arg| arg == save::CONTINUE_FLAG);
+        if continue_game {
+            app.insert_resource(save::ContinueGame);
+        }
+        // the main menu would only ask what the flags already said
+        #[cfg(not(feature = "headless"))]
+        if !continue_game && scenario.is_none() && client.is_none() {
+            app.insert_resource(loading::AfterLoading(AppState::MainMenu));
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(scenario) = scenario {
+        app.insert_resource(scenario);
+    }
+    #[cfg(not(feature = "headless"))]
+    app
+        .add_plugin(CameraPlugin)
+        .add_plugin(UiPlugin)
+        .add_plugin(GamepadPlugin)
+        .add_plugin(ManualControlPlugin);
+    #[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+    {
+        app.add_plugin(client::ClientPlugin);
+        if let Some(client) = client {
+            app.insert_resource(client);
+        }
+    }
     app.run()
 }
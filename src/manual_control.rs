@@ -0,0 +1,171 @@
+use bevy::{prelude::*, ecs::schedule::SystemLabel};
+use super::{MovementIntent, SimTick};
+#[cfg(not(feature = "headless"))]
+use std::marker::PhantomData;
+#[cfg(not(feature = "headless"))]
+use bevy::ecs::system::SystemParam;
+#[cfg(not(feature = "headless"))]
+use bevy_egui::EguiContext;
+#[cfg(not(feature = "headless"))]
+use super::{Unit, SimStepLabel, UnitTickLabel};
+#[cfg(not(feature = "headless"))]
+use crate::{gamepad::{active_gamepad, stick}, plot::SelectedUnit};
+
+#[cfg(not(feature = "headless"))]
+const TOGGLE_KEY: KeyCode = KeyCode::M;
+#[cfg(not(feature = "headless"))]
+const TOGGLE_BUTTON: GamepadButtonType = GamepadButtonType::Select;
+#[cfg(not(feature = "headless"))]
+const HAND_BRAKE_BUTTON: GamepadButtonType = GamepadButtonType::East;
+// intents made for a tick this long ago are dropped instead of applied late
+const MAX_INTENT_AGE: u64 = 30;
+
+/// M or the gamepad's select button toggles manual control of the selected unit. It's moved with
+/// WASD or the left stick, rotated with Q/E or the shoulder buttons and braked with space or the
+/// east button.
+#[cfg(not(feature = "headless"))]
+pub struct ManualControlPlugin;
+
+#[cfg(not(feature = "headless"))]
+impl Plugin for ManualControlPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_system(toggle_manual_control)
+            .add_system(read_manual_input.after(UnitTickLabel).before(ManualIntentLabel).with_run_criteria(SimStepLabel));
+    }
+}
+
+/// Applying manual intents, part of `UnitPlugin` since remote players' intents arrive without a
+/// local player
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ManualIntentLabel;
+
+/// Manual driving input, stamped with the simulation tick it was made for
+pub struct ManualIntent {
+    pub tick: u64,
+    pub unit: Entity,
+    pub input_move: Vec2,
+    pub input_rotation: f32,
+    pub hand_brake: bool
+}
+
+/// Manual driving intents waiting for their tick. Local input is made for the current tick, intents
+/// of remote players can arrive ahead of theirs or late.
+#[derive(Default)]
+pub struct ManualIntents(pub Vec<ManualIntent>);
+
+/// Unit driven by the player. Its program keeps running, but its movement intents are replaced.
+#[derive(Component)]
+pub struct ManualControl;
+
+/// Keyboard and gamepad state manual control reads
+#[cfg(not(feature = "headless"))]
+#[derive(SystemParam)]
+pub struct ControlInput<'w, 's> {
+    egui_context: ResMut<'w, EguiContext>,
+    keys: Res<'w, Input<KeyCode>>,
+    gamepads: Res<'w, Gamepads>,
+    axes: Res<'w, Axis<GamepadAxis>>,
+    buttons: Res<'w, Input<GamepadButton>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>
+}
+
+#[cfg(not(feature = "headless"))]
+impl ControlInput<'_, '_> {
+    // keys typed into a text field don't control units
+    fn keyboard_free(&mut self) -> bool {
+        !self.egui_context.ctx_mut().wants_keyboard_input()
+    }
+
+    /// Movement, rotation and hand brake the player asks for, the same for every controlled unit
+    pub fn read(&mut self) -> (Vec2, f32, bool) {
+        let mut input_move = Vec2::ZERO;
+        let mut input_rotation = 0.0;
+        let mut hand_brake = false;
+        if self.keyboard_free() {
+            let keys = &self.keys;
+            let axis = |negative, positive| keys.pressed(positive) as i32 as f32 - keys.pressed(negative) as i32 as f32;
+            input_move += Vec2::new(axis(KeyCode::A, KeyCode::D), axis(KeyCode::S, KeyCode::W));
+            input_rotation += axis(KeyCode::Q, KeyCode::E);
+            hand_brake |= keys.pressed(KeyCode::Space);
+        }
+        if let Some(gamepad) = active_gamepad(&self.gamepads) {
+            let pressed = |button_type| self.buttons.pressed(GamepadButton::new(gamepad, button_type));
+            input_move += stick(&self.axes, gamepad, GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY);
+            input_rotation += pressed(GamepadButtonType::RightTrigger) as i32 as f32 - pressed(GamepadButtonType::LeftTrigger) as i32 as f32;
+            hand_brake |= pressed(HAND_BRAKE_BUTTON);
+        }
+        (input_move.clamp_length_max(1.0), input_rotation, hand_brake)
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+fn toggle_manual_control(
+    mut commands: Commands,
+    mut input: ControlInput,
+    selected: Res<SelectedUnit>,
+    controlled: Query<Entity, With<ManualControl>>,
+    units: Query<(), With<Unit>>)
+{
+    // control is handed back to the program once another unit is selected
+    for entity in controlled.iter() {
+        if selected.0 != Some(entity) {
+            commands.entity(entity).remove::<ManualControl>();
+            info!("Unit {:?} is running its program again", entity);
+        }
+    }
+    let key_pressed = input.keys.just_pressed(TOGGLE_KEY) && input.keyboard_free();
+    let button_pressed = active_gamepad(&input.gamepads).map_or(false, |gamepad| input.buttons.just_pressed(GamepadButton::new(gamepad, TOGGLE_BUTTON)));
+    if !key_pressed && !button_pressed {
+        return
+    }
+    if let Some(entity) = selected.0.filter(|entity| units.contains(*entity)) {
+        if controlled.contains(entity) {
+            commands.entity(entity).remove::<ManualControl>();
+            info!("Unit {:?} is running its program again", entity);
+        } else {
+            commands.entity(entity).insert(ManualControl);
+            info!("Unit {:?} is controlled manually", entity);
+        }
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+fn read_manual_input(
+    mut intents: ResMut<ManualIntents>,
+    units: Query<Entity, With<ManualControl>>,
+    sim_tick: Res<SimTick>,
+    mut input: ControlInput)
+{
+    let (input_move, input_rotation, hand_brake) = input.read();
+    for unit in units.iter() {
+        intents.0.push(ManualIntent { tick: sim_tick.0, unit, input_move, input_rotation, hand_brake });
+    }
+}
+
+// Writes the same inputs `handle:move`, `handle:rotate`, `handle:apply_force`, `handle:apply_torque`
+// and `handle:toggle_hand_brake` do, after the program ran so they replace whatever it asked for.
+// Late intents are applied on the current tick, the simulation can't go back to theirs.
+pub fn apply_manual_intents(mut intents: ResMut<ManualIntents>, mut units: Query<&mut MovementIntent, With<ManualControl>>, sim_tick: Res<SimTick>) {
+    let tick = sim_tick.0;
+    intents.0.retain(|intent| {
+        if intent.tick > tick {
+            return true
+        }
+        if tick - intent.tick > MAX_INTENT_AGE {
+            debug!("Dropped manual intent for unit {:?} made for tick {}, {} ticks late", intent.unit, intent.tick, tick - intent.tick);
+            return false
+        }
+        if let Ok(mut movement_intent) = units.get_mut(intent.unit) {
+            movement_intent.input_move = intent.input_move;
+            movement_intent.input_rotation = intent.input_rotation;
+            // headings the program set would keep turning the unit
+            movement_intent.target_heading = None;
+            movement_intent.input_force = intent.input_move;
+            movement_intent.input_torque = intent.input_rotation;
+            movement_intent.hand_brake = intent.hand_brake;
+        }
+        false
+    });
+}
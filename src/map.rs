@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use super::{Prototype, Prototypes};
+use crate::loading::AppState;
+use crate::visual::Visuals;
+use crate::creature::CreaturePrototype;
+use crate::logging::ASSETS;
+
+const WALL_VISUAL: &str = "wall";
+// tiles along each side of a chunk, every chunk's walls are drawn as one mesh
+const CHUNK_SIZE: i32 = 16;
+const CREATURE_SPAWNS: [(&str, [f32; 2]); 3] = [("crawler", [-6.0, -4.0]), ("crawler", [9.0, -3.0]), ("crawler", [2.0, 10.0])];
+
+/// Level geometry and creatures. Maps aren't loaded from files yet, both are hardcoded.
+pub struct MapPlugin;
+
+impl Plugin for MapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_enter(AppState::Playing)
+            .with_system(spawn_walls)
+            .with_system(spawn_creatures));
+    }
+}
+
+fn wall_tiles() -> Vec<IVec2> {
+    let mut tiles: Vec<IVec2> = (1..=5).map(|i| IVec2::new(i, 5)).collect();
+    tiles.extend((0..=4).map(|j| IVec2::new(5, j)));
+    tiles.push(IVec2::new(-1, 5));
+    tiles
+}
+
+/// Covers the tiles with as few rectangles as a greedy merge finds: runs of tiles in a row first,
+/// then runs spanning the same columns in the rows above each other. Returns each rectangle's
+/// lowest and highest tile.
+fn merge_tiles(mut tiles: Vec<IVec2>) -> Vec<(IVec2, IVec2)> {
+    tiles.sort_unstable_by_key(|tile| (tile.y, tile.x));
+    tiles.dedup();
+    let mut runs: Vec<(IVec2, IVec2)> = Vec::new();
+    for tile in tiles {
+        match runs.last_mut() {
+            Some((_, end)) if end.y == tile.y && end.x + 1 == tile.x => end.x = tile.x,
+            _ => runs.push((tile, tile))
+        }
+    }
+    // rectangles that can still grow, by their columns
+    let mut open: HashMap<(i32, i32), (IVec2, IVec2)> = HashMap::new();
+    let mut rectangles = Vec::new();
+    for (start, end) in runs {
+        match open.remove(&(start.x, end.x)) {
+            Some((low, high)) if high.y + 1 == start.y => {
+                open.insert((start.x, end.x), (low, end));
+            }
+            closed => {
+                rectangles.extend(closed);
+                open.insert((start.x, end.x), (start, end));
+            }
+        }
+    }
+    rectangles.extend(open.into_values());
+    // hash map order would change which collider is which entity between runs
+    rectangles.sort_unstable_by_key(|(low, _)| (low.y, low.x));
+    rectangles
+}
+
+// Walls are only colliders, one per rectangle of neighbouring tiles so rapier has fewer of them to
+// check. Their sprites are batched per chunk, only walls with an animated visual get a sprite each.
+fn spawn_walls(mut commands: Commands, visuals: Res<Visuals>, mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<ColorMaterial>>) {
+    let tiles = wall_tiles();
+    for (low, high) in merge_tiles(tiles.clone()) {
+        let (low, high) = (low.as_vec2() - 0.5, high.as_vec2() + 0.5);
+        let half_size = (high - low) / 2.0;
+        commands.spawn()
+            .insert(Collider::cuboid(half_size.x, half_size.y))
+            .insert(RigidBody::Fixed)
+            .insert_bundle(TransformBundle::from(Transform::from_translation((low + half_size).extend(0.0))));
+    }
+    let mut chunks: HashMap<IVec2, Vec<Vec2>> = HashMap::new();
+    for tile in tiles {
+        chunks.entry(IVec2::new(tile.x.div_euclid(CHUNK_SIZE), tile.y.div_euclid(CHUNK_SIZE))).or_default().push(tile.as_vec2());
+    }
+    for positions in chunks.values() {
+        match visuals.tiles_mesh(WALL_VISUAL, positions, &mut meshes, &mut materials) {
+            Some(mesh) => {
+                // bevy only bounds entities with a `Handle<Mesh>`, without one the chunk is never culled
+                let bounds = meshes.get(&mesh.mesh.0).and_then(Mesh::compute_aabb);
+                let mut chunk = commands.spawn_bundle(mesh);
+                if let Some(bounds) = bounds {
+                    chunk.insert(bounds);
+                }
+            }
+            None => {
+                for position in positions {
+                    visuals.insert(&mut commands.spawn(), WALL_VISUAL, *position);
+                }
+            }
+        }
+    }
+}
+
+// clients are sent the server's creatures, theirs would stand still
+fn spawn_creatures(
+    mut commands: Commands,
+    visuals: Res<Visuals>,
+    prototypes: Res<Prototypes>,
+    #[cfg(not(any(target_arch = "wasm32", feature = "headless")))] client: Option<Res<crate::client::Client>>)
+{
+    #[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+    if client.is_some() {
+        return
+    }
+    for (name, position) in CREATURE_SPAWNS {
+        match CreaturePrototype::from_pt(&prototypes, name) {
+            Some(creature) => {
+                creature.spawn(&mut commands, &visuals, &prototypes, Vec2::from(position));
+            }
+            None => warn!(target: ASSETS, "Unknown creature {}", name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every tile of the rectangles, sorted like merge_tiles sorts them
+    fn covered(rectangles: &[(IVec2, IVec2)]) -> Vec<IVec2> {
+        let mut tiles: Vec<IVec2> = rectangles.iter()
+            .flat_map(|(low, high)| (low.y..=high.y).flat_map(move |y| (low.x..=high.x).map(move |x| IVec2::new(x, y))))
+            .collect();
+        tiles.sort_unstable_by_key(|tile| (tile.y, tile.x));
+        tiles
+    }
+
+    #[test]
+    fn blocks_of_tiles_become_one_rectangle() {
+        let tiles: Vec<IVec2> = (0..3).flat_map(|y| (-2..2).map(move |x| IVec2::new(x, y))).rev().collect();
+        assert_eq!(merge_tiles(tiles), vec![(IVec2::new(-2, 0), IVec2::new(1, 2))]);
+    }
+
+    #[test]
+    fn rectangles_cover_every_tile_once() {
+        let mut tiles = wall_tiles();
+        let rectangles = merge_tiles(tiles.clone());
+        assert_eq!(rectangles, vec![
+            (IVec2::new(5, 0), IVec2::new(5, 4)),
+            (IVec2::new(-1, 5), IVec2::new(-1, 5)),
+            (IVec2::new(1, 5), IVec2::new(5, 5))
+        ]);
+        tiles.sort_unstable_by_key(|tile| (tile.y, tile.x));
+        assert_eq!(covered(&rectangles), tiles);
+    }
+
+    #[test]
+    fn only_rows_right_above_each_other_merge() {
+        let tiles = vec![IVec2::new(0, 0), IVec2::new(1, 0), IVec2::new(0, 2), IVec2::new(1, 2), IVec2::new(0, 2)];
+        assert_eq!(merge_tiles(tiles), vec![(IVec2::new(0, 0), IVec2::new(1, 0)), (IVec2::new(0, 2), IVec2::new(1, 2))]);
+    }
+}
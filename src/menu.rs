@@ -0,0 +1,59 @@
+use bevy::{prelude::*, app::AppExit};
+use bevy_egui::{EguiContext, egui};
+use crate::loading::{AppState, ModList};
+use crate::locale::Localize;
+use crate::save::{LoadEvent, newest_save};
+
+/// Main menu shown once loading finishes, unless `--continue` or a scenario says what to play.
+/// "Continue" loads the newest save, "New game" starts with the default unit and "Join" opens the
+/// client's join dialog.
+#[derive(Default)]
+pub struct MainMenu {
+    // looked up when the menu opens, `None` without saves
+    newest_save: Option<String>,
+    pub joining: bool
+}
+
+pub fn open_main_menu(mut menu: ResMut<MainMenu>) {
+    menu.newest_save = newest_save().ok();
+}
+
+pub fn show_main_menu(
+    mut egui_context: ResMut<EguiContext>,
+    mut menu: ResMut<MainMenu>,
+    localize: Res<Localize>,
+    mod_list: Res<ModList>,
+    mut state: ResMut<State<AppState>>,
+    mut load_events: EventWriter<LoadEvent>,
+    mut exit: EventWriter<AppExit>)
+{
+    egui::CentralPanel::default().show(egui_context.ctx_mut(), |ui| {
+        ui.vertical_centered(|ui| {
+            ui.add_space(ui.available_height() / 3.0);
+            ui.heading("Scriplets");
+            ui.add_space(10.0);
+            let continue_button = ui.add_enabled(menu.newest_save.is_some(), egui::Button::new(localize.text("menu-continue")));
+            if let Some(save) = &menu.newest_save {
+                if continue_button.on_hover_text(save.as_str()).clicked() {
+                    // spawning the default unit on entering the game doesn't matter, loading replaces all units
+                    load_events.send(LoadEvent { name: Some(save.clone()) });
+                    state.set(AppState::Playing).unwrap();
+                }
+            }
+            if ui.button(localize.text("menu-new-game")).clicked() {
+                state.set(AppState::Playing).unwrap();
+            }
+            if ui.button(localize.text("menu-join")).clicked() {
+                menu.joining = true;
+            }
+            if ui.button(localize.text("menu-quit")).clicked() {
+                exit.send(AppExit);
+            }
+            // the game runs without them, the mods settings say what's wrong
+            if !mod_list.errors.is_empty() {
+                ui.add_space(10.0);
+                ui.colored_label(egui::Color32::LIGHT_RED, localize.format("menu-mod-errors", &[("count", &mod_list.errors.len())]));
+            }
+        });
+    });
+}
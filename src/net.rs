@@ -0,0 +1,362 @@
+use std::{io::{self, ErrorKind, Read, Write}, net::TcpStream, marker::PhantomData};
+#[cfg(any(test, not(feature = "headless")))]
+use std::sync::{Mutex, mpsc::{self, Sender, Receiver, TryRecvError}};
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use crate::replication::Update;
+use crate::admin::AdminCommand;
+use crate::Prototypes;
+use crate::loading::ModList;
+use crate::save::{active_mods, describe_mods};
+
+/// Version of the messages below, clients and servers with different ones refuse each other
+pub const PROTOCOL_VERSION: u32 = 1;
+/// UDP port servers broadcast an `Announcement` to, for finding games on the local network
+pub const DISCOVERY_PORT: u16 = 47_810;
+// frames are a little endian u32 length and that many bytes of MessagePack
+const LENGTH_LEN: usize = 4;
+const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+// a peer that doesn't read what it's sent for this long is dropped instead of buffering forever
+const MAX_OUTGOING_LEN: usize = 256 * 1024 * 1024;
+
+/// What a client sends to the server
+#[derive(Serialize, Deserialize)]
+pub enum ClientMessage {
+    Hello(Hello),
+    /// Manual control input for one of the player's units at the client's tick, see `ManualIntent`
+    Drive { unit: u64, tick: u64, input_move: [f32; 2], input_rotation: f32, hand_brake: bool },
+    /// The unit runs its program again
+    Release { unit: u64 },
+    Chat { text: String },
+    /// Only carried out for admins
+    Admin(AdminCommand)
+}
+
+/// What a client joins as
+#[derive(Serialize, Deserialize)]
+pub struct Hello {
+    pub protocol: u32,
+    pub name: String,
+    /// Observers get no team and see every unit
+    pub observer: bool,
+    /// Admins need their password from `server.toml`
+    pub password: Option<String>,
+    pub content: GameContent
+}
+
+/// The prototypes and mods a game runs with, players only join games running with the same
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct GameContent {
+    /// See `loading::hash_prototypes`
+    pub prototypes_hash: String,
+    /// Active mods as "name version" in load order
+    pub mods: Vec<String>
+}
+
+impl GameContent {
+    pub fn of(prototypes: &Prototypes, mod_list: &ModList) -> Self {
+        Self { prototypes_hash: prototypes.hash.map(|hash| hash.to_hex().to_string()).unwrap_or_default(), mods: active_mods(mod_list) }
+    }
+
+    /// Why a player with `theirs` can't join a server with `self`, `None` if they can
+    pub fn mismatch(&self, theirs: &GameContent) -> Option<String> {
+        if self.mods != theirs.mods {
+            Some(format!("the server runs with {}, you with {}", describe_mods(&self.mods), describe_mods(&theirs.mods)))
+        } else if self.prototypes_hash != theirs.prototypes_hash {
+            Some(format!("the server's prototypes differ from yours, their hashes are {} and {}", short_hash(&self.prototypes_hash), short_hash(&theirs.prototypes_hash)))
+        } else {
+            None
+        }
+    }
+}
+
+// enough to tell hashes apart in messages
+fn short_hash(hash: &str) -> &str {
+    &hash[..hash.len().min(16)]
+}
+
+/// What the server sends to a client
+#[derive(Serialize, Deserialize)]
+pub enum ServerMessage {
+    /// `team` is `None` for observers
+    Welcome { team: Option<u32> },
+    Refused { reason: String },
+    /// Part of the world as `encode_save` writes it, sent after `Welcome` before any update.
+    /// `len` is the length of the whole save.
+    World {
+        len: u64,
+        #[serde(with = "serde_bytes")]
+        part: Vec<u8>
+    },
+    Update(Update),
+    /// Chat of another player
+    Chat { from: String, text: String },
+    /// What came of the player's admin commands and what happened to the game
+    Notice { text: String },
+    /// Sent before the server drops the connection
+    Kicked { by: String }
+}
+
+/// What servers broadcast every second, the address it came from and `port` is where to join
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Announcement {
+    pub protocol: u32,
+    pub port: u16,
+    pub players: u32
+}
+
+/// Who players are without `--name`, the user's name. Hosts chat as that too.
+pub fn default_name() -> String {
+    std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_else(|_| "player".to_string())
+}
+
+/// Moves whole frames between two ends of a connection without blocking
+pub trait Transport: Send + Sync {
+    /// Queues the frame, it's sent as soon as the other end takes it
+    fn send(&mut self, frame: Vec<u8>);
+    /// Next complete frame, `None` if there's none yet
+    fn receive(&mut self) -> Option<Vec<u8>>;
+    fn is_closed(&self) -> bool;
+}
+
+/// TCP with length prefixed frames
+pub struct TcpTransport {
+    stream: TcpStream,
+    // bytes of frames that didn't fully arrive or weren't taken yet
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+    closed: bool
+}
+
+impl TcpTransport {
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        // updates are small and sent every tick, waiting to fill packets only delays them
+        stream.set_nodelay(true)?;
+        Ok(Self { stream, incoming: Vec::new(), outgoing: Vec::new(), closed: false })
+    }
+
+    fn flush(&mut self) {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => self.closed = true,
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                    continue
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => (),
+                Err(_) => self.closed = true
+            }
+            break
+        }
+        if self.outgoing.len() > MAX_OUTGOING_LEN {
+            self.closed = true;
+        }
+    }
+
+    fn fill(&mut self) {
+        let mut buffer = [0; 16 * 1024];
+        while !self.closed {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => self.closed = true,
+                Ok(read) => self.incoming.extend_from_slice(&buffer[..read]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(_) => self.closed = true
+            }
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, frame: Vec<u8>) {
+        if self.closed {
+            return
+        }
+        self.outgoing.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        self.outgoing.extend(frame);
+        self.flush();
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        self.flush();
+        if self.incoming.len() < LENGTH_LEN {
+            self.fill();
+        }
+        let len = u32::from_le_bytes(self.incoming.get(..LENGTH_LEN)?.try_into().unwrap()) as usize;
+        if len > MAX_FRAME_LEN {
+            self.closed = true;
+            return None
+        }
+        if self.incoming.len() < LENGTH_LEN + len {
+            self.fill();
+            if self.incoming.len() < LENGTH_LEN + len {
+                return None
+            }
+        }
+        let frame = self.incoming[LENGTH_LEN..LENGTH_LEN + len].to_vec();
+        self.incoming.drain(..LENGTH_LEN + len);
+        Some(frame)
+    }
+
+    // frames that arrived before the other end went away can still be received
+    fn is_closed(&self) -> bool {
+        self.closed && self.incoming.len() < LENGTH_LEN
+    }
+}
+
+/// Frames between two ends in the same process, for a server running in a background thread. It
+/// carries the same frames as `TcpTransport`, the server can't tell them apart.
+#[cfg(any(test, not(feature = "headless")))]
+pub struct MemoryTransport {
+    sender: Sender<Vec<u8>>,
+    // only ever used through `&mut self`, the mutex just makes it `Sync`
+    receiver: Mutex<Receiver<Vec<u8>>>,
+    closed: bool
+}
+
+#[cfg(any(test, not(feature = "headless")))]
+impl MemoryTransport {
+    pub fn pair() -> (Self, Self) {
+        let (a_sender, b_receiver) = mpsc::channel();
+        let (b_sender, a_receiver) = mpsc::channel();
+        (
+            Self { sender: a_sender, receiver: Mutex::new(a_receiver), closed: false },
+            Self { sender: b_sender, receiver: Mutex::new(b_receiver), closed: false }
+        )
+    }
+}
+
+#[cfg(any(test, not(feature = "headless")))]
+impl Transport for MemoryTransport {
+    fn send(&mut self, frame: Vec<u8>) {
+        if self.sender.send(frame).is_err() {
+            self.closed = true;
+        }
+    }
+
+    // frames sent before the other end went away arrive before the disconnection
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        match self.receiver.get_mut().unwrap().try_recv() {
+            Ok(frame) => Some(frame),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                self.closed = true;
+                None
+            }
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
+}
+
+/// Messages over a transport, `S` is what this end sends and `R` what it receives
+pub struct Connection<S, R> {
+    transport: Box<dyn Transport>,
+    marker: PhantomData<fn(S) -> R>
+}
+
+impl<S: Serialize, R: DeserializeOwned> Connection<S, R> {
+    pub fn new(transport: impl Transport + 'static) -> Self {
+        Self { transport: Box::new(transport), marker: PhantomData }
+    }
+
+    pub fn send(&mut self, message: &S) {
+        self.transport.send(rmp_serde::to_vec(message).unwrap());
+    }
+
+    /// Next message, `None` if there's none yet or the connection is closed
+    pub fn receive(&mut self) -> Option<Result<R, rmp_serde::decode::Error>> {
+        self.transport.receive().map(|frame| rmp_serde::from_slice(&frame))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.transport.is_closed()
+    }
+}
+
+/// The server's end of a connection
+pub type ClientConnection = Connection<ServerMessage, ClientMessage>;
+/// A client's end of a connection
+#[cfg(any(test, not(feature = "headless")))]
+pub type ServerConnection = Connection<ClientMessage, ServerMessage>;
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use super::*;
+
+    fn connected() -> (ClientConnection, ServerConnection) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (Connection::new(TcpTransport::new(server).unwrap()), Connection::new(TcpTransport::new(client).unwrap()))
+    }
+
+    // the other end is a socket away, this waits for what's in flight
+    fn receive<S: Serialize, R: DeserializeOwned>(connection: &mut Connection<S, R>) -> R {
+        for _ in 0..1000 {
+            if let Some(message) = connection.receive() {
+                return message.unwrap()
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        panic!("nothing arrived")
+    }
+
+    #[test]
+    fn sends_messages_both_ways_in_order() {
+        let (mut server, mut client) = connected();
+        let content = GameContent { prototypes_hash: "0123".to_string(), mods: Vec::new() };
+        client.send(&ClientMessage::Hello(Hello { protocol: PROTOCOL_VERSION, name: "ada".to_string(), observer: false, password: None, content: content.clone() }));
+        client.send(&ClientMessage::Release { unit: 7 });
+        assert!(matches!(receive(&mut server), ClientMessage::Hello(hello) if hello.name == "ada" && !hello.observer && hello.content == content));
+        assert!(matches!(receive(&mut server), ClientMessage::Release { unit: 7 }));
+        server.send(&ServerMessage::Welcome { team: Some(3) });
+        assert!(matches!(receive(&mut client), ServerMessage::Welcome { team: Some(3) }));
+    }
+
+    #[test]
+    fn frames_larger_than_a_read_arrive_whole() {
+        let (mut server, mut client) = connected();
+        let reason = "x".repeat(200_000);
+        server.send(&ServerMessage::Refused { reason: reason.clone() });
+        for _ in 0..1000 {
+            // what the socket didn't take right away is sent whenever the server polls
+            assert!(server.receive().is_none());
+            if let Some(message) = client.receive() {
+                assert!(matches!(message.unwrap(), ServerMessage::Refused { reason: received } if received == reason));
+                return
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        panic!("nothing arrived");
+    }
+
+    #[test]
+    fn memory_transports_carry_the_same_messages() {
+        let (server, client) = MemoryTransport::pair();
+        let (mut server, mut client): (ClientConnection, ServerConnection) = (Connection::new(server), Connection::new(client));
+        client.send(&ClientMessage::Release { unit: 7 });
+        assert!(matches!(receive(&mut server), ClientMessage::Release { unit: 7 }));
+        server.send(&ServerMessage::Welcome { team: Some(3) });
+        drop(server);
+        assert!(matches!(receive(&mut client), ServerMessage::Welcome { team: Some(3) }));
+        assert!(client.receive().is_none());
+        assert!(client.is_closed());
+    }
+
+    #[test]
+    fn notices_closed_connections() {
+        let (server, mut client) = connected();
+        drop(server);
+        for _ in 0..1000 {
+            if client.receive().is_none() && client.is_closed() {
+                return
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        panic!("the connection still looks open");
+    }
+}
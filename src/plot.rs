@@ -0,0 +1,151 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use bevy::prelude::*;
+use bevy_egui::{EguiContext, egui::{self, plot::{Plot, Line, Legend, Value, Values}}};
+use super::Unit;
+use crate::{energy::Energy, telemetry::Telemetry, registry::UnitLabel};
+
+const SELECT_RADIUS: f32 = 0.75;
+const MAX_PLOT_SECONDS: f64 = 60.0;
+
+/// Unit picked with the left mouse button, shown in the metrics panel
+#[derive(Default)]
+pub struct SelectedUnit(pub Option<Entity>);
+
+/// Recent samples of the selected unit's metrics, as seconds since startup and value
+pub struct MetricPlots {
+    unit: Option<Entity>,
+    last_position: Option<Vec2>,
+    series: BTreeMap<String, VecDeque<(f64, f64)>>,
+    shown: HashSet<String>,
+    seconds: f64
+}
+
+impl Default for MetricPlots {
+    fn default() -> Self {
+        Self {
+            unit: None,
+            last_position: None,
+            series: BTreeMap::new(),
+            shown: ["speed", "energy"].into_iter().map(String::from).collect(),
+            seconds: 10.0
+        }
+    }
+}
+
+impl MetricPlots {
+    fn push(&mut self, name: &str, time: f64, value: f64) {
+        let samples = self.series.entry(name.to_string()).or_default();
+        samples.push_back((time, value));
+        while samples.front().map_or(false, |(sample_time, _)| time - sample_time > MAX_PLOT_SECONDS) {
+            samples.pop_front();
+        }
+    }
+}
+
+/// Where the cursor points in the world, `None` while it's outside the window
+pub fn cursor_world_position(windows: &Windows, camera: &Query<(&Camera, &GlobalTransform), With<Camera2d>>) -> Option<Vec2> {
+    let window = windows.primary();
+    let cursor = window.cursor_position()?;
+    let (camera, camera_transform) = camera.single();
+    let window_size = Vec2::new(window.width(), window.height());
+    let ndc = (cursor / window_size) * 2.0 - Vec2::ONE;
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
+    Some(ndc_to_world.project_point3(ndc.extend(-1.0)).truncate())
+}
+
+pub fn select_units(
+    mut selected: ResMut<SelectedUnit>,
+    mut egui_context: ResMut<EguiContext>,
+    input: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    camera: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
+    units: Query<(Entity, &Transform), With<Unit>>)
+{
+    if !input.just_pressed(MouseButton::Left) || egui_context.ctx_mut().wants_pointer_input() {
+        return
+    }
+    if let Some(cursor) = cursor_world_position(&windows, &camera) {
+        selected.0 = units.iter()
+            .map(|(entity, transform)| (entity, transform.translation.truncate().distance(cursor)))
+            .filter(|(_, distance)| *distance < SELECT_RADIUS)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(entity, _)| entity);
+    }
+}
+
+// Runs before telemetry is written, which consumes the metrics scripts emitted this frame
+pub fn sample_metric_plots(
+    selected: Res<SelectedUnit>,
+    mut plots: ResMut<MetricPlots>,
+    mut telemetry: ResMut<Telemetry>,
+    time: Res<Time>,
+    units: Query<(&Transform, Option<&Energy>), With<Unit>>)
+{
+    if plots.unit != selected.0 {
+        let shown = std::mem::take(&mut plots.shown);
+        *plots = MetricPlots { unit: selected.0, shown, seconds: plots.seconds, ..default() };
+    }
+    telemetry.plotted_unit = selected.0;
+    let unit = if let Some(unit) = selected.0 { unit } else { return };
+    let now = time.seconds_since_startup();
+    if let Ok((transform, energy)) = units.get(unit) {
+        let position = transform.translation.truncate();
+        if let Some(last_position) = plots.last_position {
+            if time.delta_seconds() > 0.0 {
+                plots.push("speed", now, (position.distance(last_position) / time.delta_seconds()) as f64);
+            }
+        }
+        plots.last_position = Some(position);
+        if let Some(energy) = energy {
+            plots.push("energy", now, energy.stored as f64);
+        }
+    }
+    for metric in telemetry.metrics().iter().filter(|metric| metric.unit == unit) {
+        plots.push(&metric.name, now, metric.value);
+    }
+}
+
+pub fn show_metric_plots(
+    mut egui_context: ResMut<EguiContext>,
+    mut selected: ResMut<SelectedUnit>,
+    mut plots: ResMut<MetricPlots>,
+    time: Res<Time>,
+    labels: Query<&UnitLabel>)
+{
+    let unit = if let Some(unit) = selected.0 { unit } else { return };
+    let title = match labels.get(unit).ok().and_then(|label| label.0.as_ref()) {
+        Some(label) => format!("Metrics: {}", label),
+        None => format!("Metrics: unit {}", unit.to_bits())
+    };
+    let now = time.seconds_since_startup();
+    let mut open = true;
+    egui::Window::new(title).id(egui::Id::new("metric_plots")).open(&mut open).show(egui_context.ctx_mut(), |ui| {
+        let plots = &mut *plots;
+        ui.add(egui::Slider::new(&mut plots.seconds, 1.0..=MAX_PLOT_SECONDS).text("seconds"));
+        ui.horizontal_wrapped(|ui| {
+            for name in plots.series.keys() {
+                let mut shown = plots.shown.contains(name);
+                if ui.checkbox(&mut shown, name.as_str()).changed() {
+                    if shown {
+                        plots.shown.insert(name.clone());
+                    } else {
+                        plots.shown.remove(name);
+                    }
+                }
+            }
+        });
+        // time is shown relative to now, so the newest sample is at zero
+        Plot::new("metric_plot").height(200.0).legend(Legend::default()).include_x(-plots.seconds).include_x(0.0).show(ui, |plot_ui| {
+            for (name, samples) in plots.series.iter().filter(|(name, _)| plots.shown.contains(*name)) {
+                let values = samples.iter()
+                    .filter(|(sample_time, _)| now - sample_time <= plots.seconds)
+                    .map(|(sample_time, value)| Value::new(sample_time - now, *value))
+                    .collect();
+                plot_ui.line(Line::new(Values::from_values(values)).name(name));
+            }
+        });
+    });
+    if !open {
+        selected.0 = None;
+    }
+}
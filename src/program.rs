@@ -1,61 +1,663 @@
-use mlua::prelude::*;
-use bevy::prelude::*;
-use super::{Movement, UnitClock, GameClock};
-use std::{sync::Mutex, f32::consts::PI};
+// Lua doesn't build for the browser yet, programs there get a stub runtime
+#[cfg(not(target_arch = "wasm32"))]
+use mlua::{prelude::*, DebugEvent};
+use bevy::{prelude::*, ecs::system::SystemParam};
+use super::{Unit, MovementStats, MovementIntent, MovementState, UnitClock, GameClock, SimTick, WorldSeed, UnitId, units_in_order, SimStepLabel, AddSimEvent, ClockLabel, UnitTickLabel, MovementLabel, EventLabel};
+#[cfg(not(feature = "headless"))]
+use super::IntentLabel;
+use crate::turret::Turret;
+use crate::{health::Health, energy::Energy, heat::Heat, effects::StatusEffects, inventory::{Inventory, ItemAction, ItemActionEvent}};
+#[cfg(not(feature = "headless"))]
+use crate::audio::SoundEvent;
+use crate::construction::Builder;
+use crate::repair::RepairTool;
+use crate::circuit::CircuitConnector;
+use crate::storage::{Storage, KeySalt};
+use crate::equipment::EquipmentGrid;
+use crate::team::{Team, TeamBlackboards, Blackboard, ScriptUsage, notify_blackboard_changes, account_script_usage};
+use crate::telemetry::{Telemetry, TelemetryMetric, TelemetryLabel, record_telemetry};
+use crate::logging::SCRIPT;
+use crate::registry::{UnitLabel, UnitRegistry, UnitDirectory, update_unit_registry, update_unit_directory};
+use crate::weather::Weather;
+use crate::behavior::BehaviorTree;
+use crate::subscriptions::{EventSubscriptions, ScriptEvent, deliver_script_events, emit_low_power_events};
+use crate::watchdog::run_watchdogs;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{debugger::poll_debug_server, scenario::run_scenario, checksum::ChecksumLabel};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{turret::LuaTurretHandle, construction::LuaBuilderHandle, repair::LuaRepairToolHandle, circuit::LuaCircuitHandle, storage::{LuaStorageHandle, LuaCryptoHandle}, equipment::LuaEquipmentGridHandle, team::LuaBlackboardHandle, telemetry::LuaTelemetryHandle};
+#[cfg(not(target_arch = "wasm32"))]
+use std::f32::consts::PI;
+#[cfg(not(target_arch = "wasm32"))]
+use super::{MovementType, TICK_RATE};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{registry::{UnitView, UnitRef}, subscriptions::SCRIPT_EVENT_NAMES, data_value::{DataValue, DataValueLimits}, api::{ApiKind, is_documented, check_registered}};
+use std::{io::{Read, Write}, panic::{self, AssertUnwindSafe}, any::Any};
+use serde::{Serialize, Deserialize};
+use flate2::{Compression, read::DeflateDecoder, write::DeflateEncoder};
+use thiserror::Error;
+use scriplets_core::program::ProgramSchedule;
+pub use scriplets_core::program::ProgramStatus;
+#[cfg(target_arch = "wasm32")]
+use bevy_egui::{EguiContext, egui};
+#[cfg(target_arch = "wasm32")]
+use crate::locale::Localize;
+
+const SHARED_PROGRAM_PREFIX: &str = "scriplets-program-1:";
+// decompressed size of a shared program string, hash and metadata included. Strings come from
+// other players, so a small string inflating to gigabytes has to be refused.
+const MAX_SHARED_PROGRAM_LEN: u64 = 1024 * 1024;
+// registry flag set when the program does anything that affects the world during a tick
+#[cfg(not(target_arch = "wasm32"))]
+const INTENT_FLAG: &str = "scriplets_intent";
+// Lua instructions between two calls of the count hook, usage is counted in steps of this
+#[cfg(not(target_arch = "wasm32"))]
+const INSTRUCTION_COUNT_STEP: u32 = 1000;
+
+// Unit handle names have to be documented in `UNIT_HANDLE_API`, which is checked while compiling.
+// Names pushed to `registered` are checked the other way around by `check_registered`.
+#[cfg(not(target_arch = "wasm32"))]
+macro_rules! documented {
+    ($kind:ident, $name:literal) => {{
+        const _: () = assert!(is_documented(ApiKind::$kind, $name), concat!("undocumented unit handle API: ", $name));
+        $name
+    }};
+    ($registered:ident, $kind:ident, $name:literal) => {{
+        $registered.push($name);
+        documented!($kind, $name)
+    }};
+}
+
+// Methods that only exist when the unit has what they act on are registered under a hidden name,
+// see `gated_methods`
+#[cfg(not(target_arch = "wasm32"))]
+macro_rules! gated {
+    ($registered:ident, $name:literal) => {{
+        documented!($registered, Method, $name);
+        concat!("\0", $name)
+    }};
+}
+
+/// Running unit programs and everything scripts can read or send: events, team blackboards, the
+/// unit registry and telemetry
+pub struct ScriptingPlugin;
+
+impl Plugin for ScriptingPlugin {
+    fn build(&self, app: &mut App) {
+        // programs are shared in the new program dialog
+        #[cfg(not(feature = "headless"))]
+        app
+            .add_sim_event::<ProgramShareEvent>()
+            .add_event::<ProgramShareOutcome>()
+            .add_system(handle_program_share_events.label(IntentLabel).after(UnitTickLabel).with_run_criteria(SimStepLabel));
+        app
+            .add_sim_event::<WakeEvent>()
+            .add_sim_event::<ScriptEvent>()
+            .init_resource::<TeamBlackboards>()
+            .init_resource::<KeySalt>()
+            .init_resource::<ScriptUsage>()
+            .init_resource::<UnitRegistry>()
+            .init_resource::<UnitDirectory>()
+            .init_resource::<Telemetry>()
+            .add_system(wake_units.label(ClockLabel).with_run_criteria(SimStepLabel))
+            .add_system(unit_tick.label(UnitTickLabel).after(ClockLabel).with_run_criteria(SimStepLabel))
+            .add_system(account_script_usage.after(UnitTickLabel).with_run_criteria(SimStepLabel))
+            .add_system(run_watchdogs.after(UnitTickLabel).before(EventLabel).with_run_criteria(SimStepLabel))
+            .add_system_set(SystemSet::new()
+                .after(MovementLabel)
+                .before(EventLabel)
+                .with_run_criteria(SimStepLabel)
+                .with_system(emit_low_power_events)
+                .with_system(notify_blackboard_changes))
+            .add_system_set(SystemSet::new()
+                .label(EventLabel)
+                .after(MovementLabel)
+                .with_run_criteria(SimStepLabel)
+                .with_system(deliver_script_events)
+                .with_system(update_unit_registry)
+                .with_system(update_unit_directory)
+                .with_system(record_telemetry.label(TelemetryLabel)));
+        #[cfg(not(target_arch = "wasm32"))]
+        app
+            .add_system(run_scenario.after(EventLabel).after(ChecksumLabel).with_run_criteria(SimStepLabel))
+            .add_system(poll_debug_server.after(ClockLabel).before(UnitTickLabel));
+    }
+}
 
 #[derive(Component)]
 pub struct UnitProgram {
     state: UnitProgramState,
-    pub program: Box<[u8]>
+    pub program: Box<[u8]>,
+    schedule: ProgramSchedule,
+    // bytes used by the interpreter after the last run
+    memory: usize
 }
 
 impl UnitProgram {
-    pub fn tick(&mut self, handle: UnitHandle<'_>) {
-        self.state.tick(handle)
-    } 
+    /// Runs the program once, returns how many Lua instructions that took
+    pub fn tick(&mut self, handle: UnitHandle<'_>) -> u64 {
+        if !self.schedule.can_run() {
+            return 0
+        }
+        let tick = handle.tick;
+        self.schedule.start_run(tick);
+        // the state is thrown away after a panic, so whatever it was left in doesn't matter
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.state.tick(handle)));
+        let instructions = self.state.take_instructions();
+        match result {
+            Ok(result) => {
+                if let Err(error) = &result {
+                    warn!(target: SCRIPT, "Program faulted: {}", error);
+                }
+                self.memory = self.state.used_memory();
+                let intent = self.state.take_intent();
+                self.schedule.finish_run(tick, result.map_err(|error| error.to_string()), intent);
+            }
+            Err(payload) => self.crash(payload)
+        }
+        instructions
+    }
+
+    /// Stops running the program for `ticks` ticks, or until woken up if `None`
+    pub fn hibernate(&mut self, ticks: Option<u64>, tick: u64) {
+        self.schedule.hibernate(ticks, tick);
+    }
+
+    pub fn wake_up(&mut self) {
+        self.schedule.wake_up();
+    }
+
+    pub fn is_hibernating(&self, tick: u64) -> bool {
+        self.schedule.is_hibernating(tick)
+    }
 
     pub fn reload(&mut self) {
-        self.state.reload(self.program.as_ref())
+        debug!(target: SCRIPT, "Loading program, hash {}", self.hash().to_hex());
+        self.schedule.reload();
+        if let Err(error) = self.state.reload(self.program.as_ref()) {
+            warn!(target: SCRIPT, "Program faulted: {}", error);
+            self.schedule.fault(error.to_string());
+        }
+    }
+
+    /// Identifies the program source, the same way the prototypes hash identifies the merged prototypes
+    pub fn hash(&self) -> blake3::Hash {
+        blake3::hash(&self.program)
+    }
+
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    pub fn status(&self) -> ProgramStatus {
+        self.schedule.status()
+    }
+
+    pub fn error_log(&self) -> &[String] {
+        self.schedule.error_log()
+    }
+
+    /// Calls `hook` before every line the program runs, until it's removed or the program reloaded
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_line_hook(&mut self, hook: impl Fn(&Lua, mlua::Debug) -> LuaResult<()> + Send + 'static) {
+        match &mut self.state {
+            UnitProgramState::Lua(lua) => {
+                // a state has only one hook, so it keeps counting instructions too
+                let triggers = LuaHookTriggers { every_line: true, every_nth_instruction: Some(INSTRUCTION_COUNT_STEP), ..default() };
+                let result = lua.get_mut().set_hook(triggers, move |lua, debug| match debug.event() {
+                    DebugEvent::Count => {
+                        count_instructions(lua);
+                        Ok(())
+                    }
+                    _ => hook(lua, debug)
+                });
+                if let Err(error) = result {
+                    warn!(target: SCRIPT, "Can't set line hook: {}", error);
+                }
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn remove_line_hook(&mut self) {
+        match &mut self.state {
+            UnitProgramState::Lua(lua) => set_count_hook(lua.get_mut())
+        }
+    }
+
+    fn crash(&mut self, payload: Box<dyn Any + Send>) {
+        let message = payload.downcast_ref::<&str>().copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        error!(target: SCRIPT, "Program crashed: {}", message);
+        self.schedule.crash(message);
+        self.state = self.state.resetted();
     }
 
     pub fn new_lua() -> Self {
         UnitProgram {
             state: UnitProgramState::new_lua(),
-            program: Box::new([])
+            program: Box::new([]),
+            schedule: ProgramSchedule::default(),
+            memory: 0
         }
     }
 
+    pub fn tick_interval(&self) -> u64 {
+        self.schedule.tick_interval()
+    }
+
+    pub fn set_tick_interval(&mut self, tick_interval: u64) {
+        self.schedule.set_tick_interval(tick_interval);
+    }
+
+    /// Ticks since the program last ran, see `ProgramSchedule::delta_ticks`
+    pub fn delta_ticks(&self, tick: u64) -> u64 {
+        self.schedule.delta_ticks(tick)
+    }
+
+    pub fn is_due(&self, tick: u64, unit_id: UnitId, throttle: u64) -> bool {
+        self.schedule.is_due(tick, unit_id.0, throttle)
+    }
+
     pub fn new_lua_with_program(program: &[u8]) -> Self {
-        UnitProgram {
-            state: UnitProgramState::new_lua_with_program(program),
-            program: program.into()
+        let mut result = Self::new_lua();
+        result.program = program.into();
+        result.reload();
+        result
+    }
+
+    pub fn export(&self, name: String, description: String) -> String {
+        SharedProgram {
+            name,
+            description,
+            language: self.state.language().to_string(),
+            program: String::from_utf8_lossy(&self.program).into_owned()
+        }.encode()
+    }
+
+    /// Replaces the program with an imported one and reloads it
+    pub fn import(&mut self, string: &str) -> Result<SharedProgram, SharedProgramError> {
+        let shared = SharedProgram::decode(string)?;
+        if shared.language != self.state.language() {
+            return Err(SharedProgramError::Language(shared.language))
+        }
+        self.set_program(shared.program.as_bytes());
+        Ok(shared)
+    }
+
+    /// Replaces the program, reloads it and wakes the unit up
+    pub fn set_program(&mut self, program: &[u8]) {
+        self.program = program.into();
+        self.reload();
+        self.wake_up();
+    }
+}
+
+/// Program with its metadata, in a form that can be passed between players as a string
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SharedProgram {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub language: String,
+    pub program: String
+}
+
+impl SharedProgram {
+    // The JSON is prefixed with its blake3 hash so damaged strings are rejected, then deflated and
+    // base64 encoded.
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap();
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(blake3::hash(&json).as_bytes()).unwrap();
+        encoder.write_all(&json).unwrap();
+        format!("{}{}", SHARED_PROGRAM_PREFIX, base64::encode(encoder.finish().unwrap()))
+    }
+
+    pub fn decode(string: &str) -> Result<Self, SharedProgramError> {
+        let encoded = string.trim().strip_prefix(SHARED_PROGRAM_PREFIX).ok_or(SharedProgramError::Prefix)?;
+        let mut data = Vec::new();
+        // one byte more than allowed tells whether the limit was hit
+        DeflateDecoder::new(base64::decode(encoded)?.as_slice()).take(MAX_SHARED_PROGRAM_LEN + 1).read_to_end(&mut data)?;
+        if data.len() as u64 > MAX_SHARED_PROGRAM_LEN {
+            return Err(SharedProgramError::TooLarge)
+        }
+        if data.len() < blake3::OUT_LEN {
+            return Err(SharedProgramError::Hash)
+        }
+        let (hash, json) = data.split_at(blake3::OUT_LEN);
+        if blake3::hash(json) != blake3::Hash::from(<[u8; blake3::OUT_LEN]>::try_from(hash).unwrap()) {
+            return Err(SharedProgramError::Hash)
+        }
+        Ok(serde_json::from_slice(json)?)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SharedProgramError {
+    #[error("not a shared program string")]
+    Prefix,
+    #[error("invalid base64: {0}")]
+    Base64(#[from] base64::DecodeError),
+    #[error("invalid compressed data: {0}")]
+    Decompress(#[from] std::io::Error),
+    #[error("the program is larger than {} bytes", MAX_SHARED_PROGRAM_LEN)]
+    TooLarge,
+    #[error("hash doesn't match the program, the string is damaged")]
+    Hash,
+    #[error("invalid program data: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("program is written in {0}, which this unit can't run")]
+    Language(String)
+}
+
+// movement and equipment are grouped in nested tuples, queries only take up to 15 elements
+type UnitTickQuery<'w, 's> = Query<'w, 's, (&'static mut UnitProgram, (Option<&'static MovementStats>, Option<&'static mut MovementIntent>, Option<&'static MovementState>), &'static mut UnitClock, &'static Transform, Option<&'static Children>, Option<&'static Health>, Option<&'static Energy>, Option<&'static StatusEffects>, (Option<&'static mut Builder>, Option<&'static mut RepairTool>, Option<&'static mut CircuitConnector>, Option<&'static mut Storage>, Option<&'static mut EquipmentGrid>, Option<&'static Heat>, Option<&'static BehaviorTree>), Option<&'static Inventory>, Option<&'static mut EventSubscriptions>, Option<&'static Team>, Option<&'static mut UnitLabel>, &'static mut ScriptRandom, Entity), With<Unit>>;
+
+/// What programs can read besides their own unit
+#[derive(SystemParam)]
+struct ScriptWorld<'w, 's> {
+    ids: Query<'w, 's, (Entity, &'static UnitId)>,
+    game_clock: Res<'w, GameClock>,
+    sim_tick: Res<'w, SimTick>,
+    world_seed: Res<'w, WorldSeed>,
+    key_salt: Res<'w, KeySalt>,
+    registry: Res<'w, UnitRegistry>,
+    directory: Res<'w, UnitDirectory>,
+    weather: Res<'w, Weather>
+}
+
+/// What programs change besides their own unit
+#[derive(SystemParam)]
+struct ScriptOutputs<'w, 's> {
+    blackboards: ResMut<'w, TeamBlackboards>,
+    script_usage: ResMut<'w, ScriptUsage>,
+    telemetry: ResMut<'w, Telemetry>,
+    // nothing plays them in a headless build
+    #[cfg(not(feature = "headless"))]
+    sound_events: EventWriter<'w, 's, SoundEvent>,
+    item_action_events: EventWriter<'w, 's, ItemActionEvent>
+}
+
+fn unit_tick(mut units: UnitTickQuery, mut turrets: Query<&mut Turret>, world: ScriptWorld, mut outputs: ScriptOutputs) {
+    // scripts can affect each other through pickups, radio and the team blackboard, so the order matters
+    for entity in units_in_order(&world.ids) {
+        let (mut unit_program, (movement, mut movement_intent, movement_state), clock, transform, children, health, energy, effects, (mut builder, mut repair_tool, mut circuit, mut storage, mut equipment_grid, heat, behavior_tree), inventory, mut subscriptions, team, label, mut random, entity) = match units.get_mut(entity) {
+            Ok(unit) => unit,
+            Err(_) => continue
+        };
+        let unit_id = world.ids.get(entity).map_or(UnitId(0), |(_, id)| *id);
+        let throttle = team.map_or(1, |team| outputs.script_usage.throttle(*team));
+        if !unit_program.is_due(world.sim_tick.0, unit_id, throttle) || unit_program.is_hibernating(world.sim_tick.0) || effects.map_or(false, StatusEffects::is_stunned) {
+            continue
+        }
+        let turret_entity = children.and_then(|children| children.iter().find(|child| turrets.get(**child).is_ok()).copied());
+        let mut turret = turret_entity.and_then(|entity| turrets.get_mut(entity).ok());
+        let mut sounds = Vec::new();
+        let mut item_actions = Vec::new();
+        let mut hibernate = None;
+        let handle = UnitHandle {
+            entity,
+            unit_id: unit_id.0,
+            world_seed: world.world_seed.0,
+            key_salt: &world.key_salt,
+            random: &mut random,
+            movement,
+            movement_intent: movement_intent.as_deref_mut(),
+            movement_state,
+            transform,
+            clock: &clock,
+            game_clock: &world.game_clock,
+            tick: world.sim_tick.0,
+            delta_ticks: unit_program.delta_ticks(world.sim_tick.0),
+            sounds: &mut sounds,
+            turret: turret.as_deref_mut(),
+            health,
+            energy,
+            heat,
+            effects,
+            builder: builder.as_deref_mut(),
+            inventory,
+            repair_tool: repair_tool.as_deref_mut(),
+            circuit: circuit.as_deref_mut(),
+            storage: storage.as_deref_mut(),
+            equipment_grid: equipment_grid.as_deref_mut(),
+            subscriptions: subscriptions.as_deref_mut(),
+            team_storage: team.map(|team| outputs.blackboards.0.entry(*team).or_default()),
+            team: team.copied(),
+            label,
+            registry: &world.registry,
+            directory: &world.directory,
+            weather: &world.weather,
+            behavior_tree,
+            telemetry: outputs.telemetry.metrics_mut(entity),
+            item_actions: &mut item_actions,
+            hibernate: &mut hibernate
+        };
+        let instructions = unit_program.tick(handle);
+        if let Some(team) = team {
+            outputs.script_usage.record(*team, instructions);
+        }
+        if let Some(ticks) = hibernate {
+            unit_program.hibernate(ticks, world.sim_tick.0);
+        }
+        #[cfg(not(feature = "headless"))]
+        for name in sounds {
+            outputs.sound_events.send(SoundEvent { name, position: transform.translation.truncate() });
+        }
+        for action in item_actions {
+            outputs.item_action_events.send(ItemActionEvent { unit: entity, action });
+        }
+    }
+}
+
+/// Random numbers for scripts, hashed from the world seed, the unit id and how many were drawn
+/// before, so they come out the same in every run with the same seed
+#[derive(Component, Default)]
+pub struct ScriptRandom {
+    drawn: u64
+}
+
+impl ScriptRandom {
+    pub fn next(&mut self, world_seed: u64, unit_id: u64) -> u64 {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&world_seed.to_le_bytes());
+        hasher.update(&unit_id.to_le_bytes());
+        hasher.update(&self.drawn.to_le_bytes());
+        self.drawn += 1;
+        u64::from_le_bytes(hasher.finalize().as_bytes()[..8].try_into().unwrap())
+    }
+}
+
+/// Wakes a hibernating unit up, sent when something happens to it
+pub struct WakeEvent {
+    pub unit: Entity
+}
+
+pub fn wake_units(mut wake_events: EventReader<WakeEvent>, mut programs: Query<&mut UnitProgram>) {
+    for event in wake_events.iter() {
+        if let Ok(mut program) = programs.get_mut(event.unit) {
+            program.wake_up();
+        }
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+pub enum ProgramShareEvent {
+    Export { unit: Entity, name: String, description: String },
+    Import { unit: Entity, string: String }
+}
+
+/// What came of a `ProgramShareEvent`, for the new program dialog to show
+#[cfg(not(feature = "headless"))]
+pub enum ProgramShareOutcome {
+    Exported { string: String },
+    Imported { name: String },
+    Failed { error: String }
+}
+
+#[cfg(not(feature = "headless"))]
+pub fn handle_program_share_events(mut share_events: EventReader<ProgramShareEvent>, mut outcomes: EventWriter<ProgramShareOutcome>, mut programs: Query<&mut UnitProgram>) {
+    for event in share_events.iter() {
+        match event {
+            ProgramShareEvent::Export { unit, name, description } => {
+                if let Ok(program) = programs.get(*unit) {
+                    let string = program.export(name.clone(), description.clone());
+                    info!(target: SCRIPT, "Program {}: {}", name, string);
+                    outcomes.send(ProgramShareOutcome::Exported { string });
+                }
+            }
+            ProgramShareEvent::Import { unit, string } => {
+                if let Ok(mut program) = programs.get_mut(*unit) {
+                    match program.import(string) {
+                        Ok(shared) => {
+                            info!(target: SCRIPT, "Imported program {}, hash {}", shared.name, program.hash().to_hex());
+                            outcomes.send(ProgramShareOutcome::Imported { name: shared.name });
+                        }
+                        Err(error) => {
+                            warn!(target: SCRIPT, "Can't import program: {}", error);
+                            outcomes.send(ProgramShareOutcome::Failed { error: error.to_string() });
+                        }
+                    }
+                }
+            }
         }
     }
 }
 
 pub enum UnitProgramState {
-    Lua(Mutex<Lua>),
-    // wasm TODO
+    #[cfg(not(target_arch = "wasm32"))]
+    Lua(Exclusive<Lua>),
+    // stands in for Lua in the browser build, see the stub `impl UnitProgramState`
+    #[cfg(target_arch = "wasm32")]
+    Unsupported
 }
 
+/// Lua instructions run since the last `take_instructions`. Counted by a hook instead of measuring
+/// time, so throttling comes out the same on every machine.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct InstructionCount(u64);
+
+#[cfg(not(target_arch = "wasm32"))]
+fn count_instructions(lua: &Lua) {
+    if let Some(mut count) = lua.app_data_mut::<InstructionCount>() {
+        count.0 += INSTRUCTION_COUNT_STEP as u64;
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn set_count_hook(lua: &Lua) {
+    let triggers = LuaHookTriggers { every_nth_instruction: Some(INSTRUCTION_COUNT_STEP), ..default() };
+    let result = lua.set_hook(triggers, |lua, _debug| {
+        count_instructions(lua);
+        Ok(())
+    });
+    if let Err(error) = result {
+        warn!(target: SCRIPT, "Can't set instruction count hook: {}", error);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 impl UnitProgramState {
-    pub fn tick(&mut self, handle: UnitHandle<'_>) { // TODO: error handling?
+    /// Returns `false` if the program has neither an `on_tick` function nor a behavior tree
+    pub fn tick(&mut self, mut handle: UnitHandle<'_>) -> LuaResult<bool> {
         match self {
             Self::Lua(lua) => {
-                let lua = lua.get_mut().unwrap();
-                if let Some(on_tick_fn) = lua.globals().get::<_, Option<LuaFunction>>("on_tick").unwrap() {
+                let lua = lua.get_mut();
+                let on_tick_fn = lua.globals().get::<_, Option<LuaFunction>>("on_tick")?;
+                let behavior_tree = handle.behavior_tree;
+                if on_tick_fn.is_some() || behavior_tree.is_some() {
+                    lua.set_app_data(UnitView::new(handle.directory, handle.team, handle.transform.translation.truncate(), handle.weather.visibility()));
                     lua.scope(|s| {
+                        // equipment handles are separate userdata, reachable as fields of the unit handle
+                        let equipment = lua.create_table()?;
+                        if let Some(turret) = handle.turret.take() {
+                            equipment.set(documented!(Equipment, "turret"), s.create_nonstatic_userdata(LuaTurretHandle{turret})?)?;
+                        }
+                        if let Some(builder) = handle.builder.take() {
+                            equipment.set(documented!(Equipment, "builder"), s.create_nonstatic_userdata(LuaBuilderHandle{builder})?)?;
+                        }
+                        if let Some(repair_tool) = handle.repair_tool.take() {
+                            equipment.set(documented!(Equipment, "repair_tool"), s.create_nonstatic_userdata(LuaRepairToolHandle{repair_tool})?)?;
+                        }
+                        if let Some(connector) = handle.circuit.take() {
+                            equipment.set(documented!(Equipment, "circuit"), s.create_nonstatic_userdata(LuaCircuitHandle{connector})?)?;
+                        }
+                        if let Some(storage) = handle.storage.take() {
+                            equipment.set(documented!(Equipment, "storage"), s.create_nonstatic_userdata(LuaStorageHandle{storage})?)?;
+                        }
+                        if let Some(grid) = handle.equipment_grid.take() {
+                            equipment.set(documented!(Equipment, "equipment_grid"), s.create_nonstatic_userdata(LuaEquipmentGridHandle{grid})?)?;
+                        }
+                        if let Some(blackboard) = handle.team_storage.take() {
+                            let team_storage = LuaBlackboardHandle {
+                                blackboard,
+                                unit: UnitId(handle.unit_id),
+                                position: handle.transform.translation.truncate()
+                            };
+                            equipment.set(documented!(Equipment, "team_storage"), s.create_nonstatic_userdata(team_storage)?)?;
+                        }
+                        for name in gated_methods(&handle) {
+                            equipment.set(name, gated_method(lua, name)?)?;
+                        }
+                        let crypto = LuaCryptoHandle::new(handle.key_salt, handle.unit_id, handle.tick);
+                        equipment.set(documented!(Equipment, "crypto"), lua.create_userdata(crypto)?)?;
+                        let telemetry = LuaTelemetryHandle { metrics: handle.telemetry.take(), unit: handle.entity };
+                        equipment.set(documented!(Equipment, "telemetry"), s.create_nonstatic_userdata(telemetry)?)?;
                         let lua_handle = s.create_nonstatic_userdata(LuaUnitHandle{handle})?;
-                        on_tick_fn.call(lua_handle)?;
+                        lua_handle.set_user_value(equipment)?;
+                        match (behavior_tree, on_tick_fn) {
+                            (Some(behavior_tree), _) => {
+                                behavior_tree.run(lua, &lua_handle)?;
+                            }
+                            (None, Some(on_tick_fn)) => on_tick_fn.call(lua_handle)?,
+                            (None, None) => {}
+                        }
                         Ok(())
-                    }).unwrap();
-                };
+                    })?;
+                    Ok(true)
+                } else {
+                    Ok(false)
+                }
             }
         }
     }
 
-    pub fn reload(&mut self, program: &[u8]) {
-        *self = self.new_with_program(program);
+    pub fn reload(&mut self, program: &[u8]) -> LuaResult<()> {
+        *self = self.new_with_program(program)?;
+        Ok(())
+    }
+
+    pub fn used_memory(&mut self) -> usize {
+        match self {
+            Self::Lua(lua) => lua.get_mut().used_memory()
+        }
+    }
+
+    pub fn take_instructions(&mut self) -> u64 {
+        match self {
+            Self::Lua(lua) => lua.get_mut().app_data_mut::<InstructionCount>().map_or(0, |mut count| std::mem::take(&mut count.0))
+        }
+    }
+
+    /// Returns whether an intent was raised since the last call
+    pub fn take_intent(&mut self) -> bool {
+        match self {
+            Self::Lua(lua) => {
+                let lua = lua.get_mut();
+                let intent = lua.named_registry_value::<_, bool>(INTENT_FLAG).unwrap_or(false);
+                lua.unset_named_registry_value(INTENT_FLAG).ok();
+                intent
+            }
+        }
+    }
+
+    pub fn language(&self) -> &'static str {
+        match self {
+            Self::Lua(_) => "lua"
+        }
     }
 
     pub fn resetted(&mut self) -> Self {
@@ -64,71 +666,369 @@ impl UnitProgramState {
         }
     }
 
+    // Programs are shared as strings, so they get nothing that reaches files or processes: no io,
+    // os or package library and no dofile or loadfile
     pub fn new_lua() -> Self {
-        Self::Lua(Mutex::new(Lua::new()))
+        let libs = LuaStdLib::COROUTINE | LuaStdLib::TABLE | LuaStdLib::STRING | LuaStdLib::UTF8 | LuaStdLib::MATH;
+        let lua = Lua::new_with(libs, LuaOptions::default()).expect("only safe libraries are loaded");
+        for name in ["dofile", "loadfile"] {
+            lua.globals().raw_set(name, LuaNil).expect("globals can always be cleared");
+        }
+        lua.set_app_data(DataValueLimits::default());
+        lua.set_app_data(InstructionCount::default());
+        set_count_hook(&lua);
+        Self::Lua(Exclusive(lua))
     }
 
-    pub fn new_with_program(&self, program: &[u8]) -> Self {
+    pub fn new_with_program(&self, program: &[u8]) -> LuaResult<Self> {
         match self {
             Self::Lua(_) => Self::new_lua_with_program(program)
         }
     }
 
-    pub fn new_lua_with_program(program: &[u8]) -> Self {
+    pub fn new_lua_with_program(program: &[u8]) -> LuaResult<Self> {
         let mut result = Self::new_lua();
-        match result {
-            Self::Lua(ref lua) => {
-                let lua = lua.lock().unwrap();
-                lua.load(program).exec().unwrap();
-            }
+        match &mut result {
+            Self::Lua(lua) => lua.get_mut().load(program).exec()?
         };
-        result
+        Ok(result)
+    }
+}
+
+// Same methods as the Lua runtime. Programs are kept but never run, every unit stays idle and
+// `show_scripting_unavailable` says why once instead of every unit faulting.
+#[cfg(target_arch = "wasm32")]
+impl UnitProgramState {
+    pub fn tick(&mut self, _handle: UnitHandle<'_>) -> Result<bool, String> {
+        Ok(false)
+    }
+
+    pub fn reload(&mut self, _program: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+
+    pub fn used_memory(&mut self) -> usize {
+        0
+    }
+
+    pub fn take_instructions(&mut self) -> u64 {
+        0
+    }
+
+    pub fn take_intent(&mut self) -> bool {
+        false
+    }
+
+    pub fn language(&self) -> &'static str {
+        "lua"
+    }
+
+    pub fn resetted(&mut self) -> Self {
+        Self::Unsupported
+    }
+
+    pub fn new_lua() -> Self {
+        Self::Unsupported
+    }
+}
+
+/// Banner across the top of the screen in the browser build, where unit programs don't run
+#[cfg(target_arch = "wasm32")]
+pub fn show_scripting_unavailable(mut egui_context: ResMut<EguiContext>, localize: Res<Localize>) {
+    egui::TopBottomPanel::top("scripting_unavailable").show(egui_context.ctx_mut(), |ui| {
+        ui.label(localize.text("scripting-unavailable"));
+    });
+}
+
+/// Makes a `Send` value `Sync` by only giving access to it through `&mut`. `Lua` isn't `Sync`, but
+/// components have to be, and programs are only ever run from `unit_tick` with exclusive access,
+/// so a lock would never be contended.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Exclusive<T>(T);
+
+// SAFETY: no `&T` can be obtained from `&Exclusive<T>`, so sharing it between threads can't
+// share the value
+#[cfg(not(target_arch = "wasm32"))]
+unsafe impl<T: Send> Sync for Exclusive<T> {}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<T> Exclusive<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.0
     }
 }
 
 pub struct UnitHandle<'a> {
-    pub movement: Option<&'a mut Movement>,
+    pub entity: Entity,
+    pub unit_id: u64,
+    pub world_seed: u64,
+    pub key_salt: &'a KeySalt,
+    pub random: &'a mut ScriptRandom,
+    pub movement: Option<&'a MovementStats>,
+    pub movement_intent: Option<&'a mut MovementIntent>,
+    pub movement_state: Option<&'a MovementState>,
     pub transform: &'a Transform,
     pub clock: &'a UnitClock,
-    pub game_clock: &'a GameClock
+    pub game_clock: &'a GameClock,
+    pub tick: u64,
+    pub delta_ticks: u64,
+    pub sounds: &'a mut Vec<String>,
+    pub turret: Option<&'a mut Turret>,
+    pub health: Option<&'a Health>,
+    pub energy: Option<&'a Energy>,
+    pub heat: Option<&'a Heat>,
+    pub effects: Option<&'a StatusEffects>,
+    pub builder: Option<&'a mut Builder>,
+    pub inventory: Option<&'a Inventory>,
+    pub repair_tool: Option<&'a mut RepairTool>,
+    pub circuit: Option<&'a mut CircuitConnector>,
+    pub storage: Option<&'a mut Storage>,
+    pub equipment_grid: Option<&'a mut EquipmentGrid>,
+    pub subscriptions: Option<&'a mut EventSubscriptions>,
+    pub team_storage: Option<&'a mut Blackboard>,
+    pub team: Option<Team>,
+    // kept as `Mut` so the label only counts as changed when the program sets it
+    pub label: Option<Mut<'a, UnitLabel>>,
+    pub registry: &'a UnitRegistry,
+    pub directory: &'a UnitDirectory,
+    pub weather: &'a Weather,
+    pub behavior_tree: Option<&'a BehaviorTree>,
+    pub telemetry: Option<&'a mut Vec<TelemetryMetric>>,
+    pub item_actions: &'a mut Vec<ItemAction>,
+    pub hibernate: &'a mut Option<Option<u64>>
+}
+
+/// Names of the gated methods the unit has what they need for
+#[cfg(not(target_arch = "wasm32"))]
+fn gated_methods(handle: &UnitHandle) -> Vec<&'static str> {
+    let groups: [(bool, &[&'static str]); 9] = [
+        (handle.movement.is_some(), &["move", "rotate", "toggle_hand_brake"]),
+        (handle.movement.as_ref().map_or(false, |movement| movement.overclock.is_some()), &["set_overclock"]),
+        (handle.movement.as_ref().map_or(false, |movement| matches!(movement.movement_type, MovementType::Omnidirectional)), &["set_heading"]),
+        (handle.movement.as_ref().map_or(false, |movement| matches!(movement.movement_type, MovementType::AcceleratedSteering)), &["set_target_speed"]),
+        (handle.movement.as_ref().map_or(false, |movement| matches!(movement.movement_type, MovementType::PhysicsDriven)), &["apply_force", "apply_torque"]),
+        (handle.inventory.is_some(), &["drop_item", "pick_up_items"]),
+        (handle.label.is_some(), &["set_label"]),
+        (handle.team.is_some(), &["find_unit"]),
+        (handle.subscriptions.is_some(), &["subscribe", "unsubscribe", "poll_events"])
+    ];
+    groups.into_iter().filter(|(available, _)| *available).flat_map(|(_, names)| names.iter().copied()).collect()
+}
+
+// Gated methods are reached through the handle's `__index` fallback, which finds them in the
+// equipment table. Calling one forwards to the method under its hidden name.
+#[cfg(not(target_arch = "wasm32"))]
+fn gated_method<'lua>(lua: &'lua Lua, name: &str) -> LuaResult<LuaFunction<'lua>> {
+    let key = format!("scriplets_gated_{}", name);
+    if let Ok(function) = lua.named_registry_value::<_, LuaFunction>(&key) {
+        return Ok(function)
+    }
+    let function: LuaFunction = lua.load(&format!("return function(self, ...) return self[\"\\0{}\"](self, ...) end", name)).eval()?;
+    lua.set_named_registry_value(&key, function.clone())?;
+    Ok(function)
+}
+
+/// Marks the current run as having affected the world, units whose runs don't for a while hibernate
+/// on their own. Handle methods that only read don't call it.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn raise_intent(lua: &Lua) -> LuaResult<()> {
+    lua.set_named_registry_value(INTENT_FLAG, true)
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 pub struct LuaUnitHandle<'a> {
     handle: UnitHandle<'a>
 }
 
 // TODO: after making a planet map, methods for getting nearest transition tile or a tile adjacent
 //  to transition tile
+#[cfg(not(target_arch = "wasm32"))]
 impl LuaUserData for LuaUnitHandle<'_> {
     fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
-        methods.add_method_mut("move", |_lua, lua_handle, args: (f32, f32)| {
-            if let Some(movement) = &mut lua_handle.handle.movement {
-                movement.input_move = Vec2::from(args);
+        let mut registered = Vec::new();
+        methods.add_method_mut(gated!(registered, "move"), |lua, lua_handle, args: (f32, f32)| {
+            raise_intent(lua)?;
+            if let Some(intent) = &mut lua_handle.handle.movement_intent {
+                intent.input_move = Vec2::from(args);
             };
             Ok(())
         });
-        methods.add_method_mut("rotate", |_lua, lua_handle, rot: f32| {
-            if let Some(movement) = &mut lua_handle.handle.movement {
-                movement.input_rotation = rot;
+        methods.add_method_mut(gated!(registered, "rotate"), |lua, lua_handle, rot: f32| {
+            raise_intent(lua)?;
+            if let Some(intent) = &mut lua_handle.handle.movement_intent {
+                intent.input_rotation = rot;
+                intent.target_heading = None;
             }
             Ok(())
         });
-        methods.add_method_mut("toggle_hand_brake", |_lua, lua_handle, ()| {
-            if let Some(movement) = &mut lua_handle.handle.movement {
-                movement.hand_brake = !movement.hand_brake;
+        methods.add_method_mut(gated!(registered, "set_heading"), |lua, lua_handle, degrees: f32| {
+            raise_intent(lua)?;
+            if let Some(intent) = &mut lua_handle.handle.movement_intent {
+                intent.target_heading = Some(degrees);
+            }
+            Ok(())
+        });
+        methods.add_method_mut(gated!(registered, "set_target_speed"), |lua, lua_handle, speed: Option<f32>| {
+            raise_intent(lua)?;
+            if let Some(intent) = &mut lua_handle.handle.movement_intent {
+                intent.target_speed = speed;
+            }
+            Ok(())
+        });
+        methods.add_method_mut(gated!(registered, "toggle_hand_brake"), |lua, lua_handle, ()| {
+            raise_intent(lua)?;
+            if let Some(intent) = &mut lua_handle.handle.movement_intent {
+                intent.hand_brake = !intent.hand_brake;
+            }
+            Ok(())
+        });
+        methods.add_method_mut(gated!(registered, "apply_force"), |lua, lua_handle, args: (f32, f32)| {
+            raise_intent(lua)?;
+            if let Some(intent) = &mut lua_handle.handle.movement_intent {
+                intent.input_force = Vec2::from(args);
+            }
+            Ok(())
+        });
+        methods.add_method_mut(gated!(registered, "apply_torque"), |lua, lua_handle, torque: f32| {
+            raise_intent(lua)?;
+            if let Some(intent) = &mut lua_handle.handle.movement_intent {
+                intent.input_torque = torque;
+            }
+            Ok(())
+        });
+        methods.add_method_mut(gated!(registered, "set_overclock"), |lua, lua_handle, enabled: bool| {
+            raise_intent(lua)?;
+            if let Some(intent) = &mut lua_handle.handle.movement_intent {
+                intent.overclocked = enabled;
+            }
+            Ok(())
+        });
+        methods.add_method_mut(documented!(registered, Method, "play_sound"), |lua, lua_handle, name: String| {
+            raise_intent(lua)?;
+            lua_handle.handle.sounds.push(name);
+            Ok(())
+        });
+        methods.add_method_mut(gated!(registered, "drop_item"), |lua, lua_handle, (item, count): (String, Option<u32>)| {
+            raise_intent(lua)?;
+            lua_handle.handle.item_actions.push(ItemAction::Drop { item, count: count.unwrap_or(1) });
+            Ok(())
+        });
+        methods.add_method_mut(gated!(registered, "pick_up_items"), |lua, lua_handle, ()| {
+            raise_intent(lua)?;
+            lua_handle.handle.item_actions.push(ItemAction::PickUp);
+            Ok(())
+        });
+        methods.add_method_mut(gated!(registered, "set_label"), |_lua, lua_handle, label: Option<String>| {
+            if let Some(unit_label) = lua_handle.handle.label.as_mut().filter(|unit_label| unit_label.0 != label) {
+                unit_label.0 = label;
+            }
+            Ok(())
+        });
+        methods.add_method(documented!(registered, Method, "unit_ref"), |_lua, _lua_handle, id: LuaInteger| {
+            let id = u64::try_from(id).map_err(|_| LuaError::RuntimeError(format!("invalid unit id {}", id)))?;
+            Ok(UnitRef(UnitId(id)))
+        });
+        // only units of the same team can be found
+        methods.add_method(gated!(registered, "find_unit"), |_lua, lua_handle, label: String| {
+            let position = lua_handle.handle.team.and_then(|team| lua_handle.handle.registry.find(team, &label));
+            Ok(position.map(<[f32; 2]>::from))
+        });
+        methods.add_method_mut(gated!(registered, "subscribe"), |_lua, lua_handle, (name, filter): (String, Option<DataValue>)| {
+            if !SCRIPT_EVENT_NAMES.contains(&name.as_str()) {
+                return Err(LuaError::RuntimeError(format!("unknown event {}", name)))
+            }
+            let filter = match filter {
+                None | Some(DataValue::Nil) => Default::default(),
+                Some(DataValue::Table(filter)) => filter,
+                Some(DataValue::Sequence(sequence)) if sequence.is_empty() => Default::default(),
+                Some(_) => return Err(LuaError::RuntimeError("event filter must be a table".into()))
+            };
+            if let Some(subscriptions) = &mut lua_handle.handle.subscriptions {
+                subscriptions.subscriptions.insert(name, filter);
+            }
+            Ok(())
+        });
+        methods.add_method_mut(gated!(registered, "unsubscribe"), |_lua, lua_handle, name: String| {
+            if let Some(subscriptions) = &mut lua_handle.handle.subscriptions {
+                subscriptions.subscriptions.remove(&name);
+            }
+            Ok(())
+        });
+        methods.add_method_mut(gated!(registered, "poll_events"), |lua, lua_handle, ()| {
+            let events = match &mut lua_handle.handle.subscriptions {
+                Some(subscriptions) => subscriptions.queue.drain(..).collect(),
+                None => Vec::new()
+            };
+            let table = lua.create_table()?;
+            for (index, event) in events.into_iter().enumerate() {
+                let event = event.to_lua(lua)?;
+                // events keep plain ids so filters can match them, scripts get refs
+                if let LuaValue::Table(event) = &event {
+                    if let LuaValue::Integer(id) = event.raw_get("unit")? {
+                        event.raw_set("unit", u64::try_from(id).ok().map(|id| UnitRef(UnitId(id))))?;
+                    }
+                }
+                table.raw_set(index + 1, event)?;
+            }
+            Ok(table)
+        });
+        methods.add_method_mut(documented!(registered, Method, "random"), |_lua, lua_handle, ()| {
+            let handle = &mut lua_handle.handle;
+            // the top 53 bits fill a double's mantissa
+            Ok((handle.random.next(handle.world_seed, handle.unit_id) >> 11) as f64 / (1u64 << 53) as f64)
+        });
+        methods.add_method_mut(documented!(registered, Method, "random_int"), |_lua, lua_handle, (min, max): (LuaInteger, LuaInteger)| {
+            if min > max {
+                return Err(LuaError::RuntimeError(format!("random_int needs min <= max, got {} and {}", min, max)))
             }
+            let handle = &mut lua_handle.handle;
+            let value = handle.random.next(handle.world_seed, handle.unit_id);
+            // the range is 0 when it covers every integer
+            let range = (max.wrapping_sub(min) as u64).wrapping_add(1);
+            Ok(match range {
+                0 => value as LuaInteger,
+                range => min.wrapping_add((value % range) as LuaInteger)
+            })
+        });
+        methods.add_method_mut(documented!(registered, Method, "hibernate"), |lua, lua_handle, ticks: Option<u64>| {
+            raise_intent(lua)?;
+            *lua_handle.handle.hibernate = Some(ticks);
             Ok(())
-        })
+        });
+        methods.add_meta_function(LuaMetaMethod::Index, |_lua, (lua_handle, key): (LuaAnyUserData, LuaValue)| {
+            lua_handle.get_user_value::<LuaTable>()?.get::<_, LuaValue>(key)
+        });
+        check_registered(ApiKind::Method, &registered);
     }
 
     fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
-        fields.add_field_method_get("time_since_start", |_lua, lua_handle| {
+        let mut registered = Vec::new();
+        fields.add_field_method_get(documented!(registered, Field, "id"), |_lua, lua_handle| {
+            Ok(lua_handle.handle.unit_id)
+        });
+        fields.add_field_method_get(documented!(registered, Field, "label"), |_lua, lua_handle| {
+            Ok(lua_handle.handle.label.as_ref().and_then(|label| label.0.clone()))
+        });
+        fields.add_field_method_get(documented!(registered, Field, "time_since_start"), |_lua, lua_handle| {
             Ok(lua_handle.handle.clock.0.elapsed_secs())
         });
-        fields.add_field_method_get("global_time", |_lua, lua_handle| {
+        fields.add_field_method_get(documented!(registered, Field, "global_time"), |_lua, lua_handle| {
             Ok(lua_handle.handle.game_clock.0.elapsed_secs())
         });
-        fields.add_field_method_get("gps", |lua, lua_handle| {
+        fields.add_field_method_get(documented!(registered, Field, "tick"), |_lua, lua_handle| {
+            Ok(lua_handle.handle.tick)
+        });
+        fields.add_field_method_get(documented!(registered, Field, "delta_ticks"), |_lua, lua_handle| {
+            Ok(lua_handle.handle.delta_ticks)
+        });
+        fields.add_field_method_get(documented!(registered, Field, "tick_rate"), |_lua, _lua_handle| {
+            Ok(TICK_RATE)
+        });
+        fields.add_field_method_get(documented!(registered, Field, "gps"), |lua, lua_handle| {
             let position: [f32; 2] = lua_handle.handle.transform.translation.truncate().into();
             let rotation_radians = lua_handle.handle.transform.rotation.to_euler(EulerRot::XYZ).2;
             let rotation_degrees = -(rotation_radians * 180.0) / PI;
@@ -137,17 +1037,89 @@ impl LuaUserData for LuaUnitHandle<'_> {
             table.set("rotation", rotation_degrees)?;
             Ok(table)
         });
-        fields.add_field_method_get("movement", |lua, lua_handle| {
-            if let Some(movement) = &lua_handle.handle.movement {
-                let movement_type = movement.movement_type.as_ref();
-                let speed = movement.speed;
+        fields.add_field_method_get(documented!(registered, Field, "health"), |lua, lua_handle| {
+            if let Some(health) = lua_handle.handle.health {
+                let table = lua.create_table()?;
+                table.set("current", health.current)?;
+                table.set("max", health.max_health)?;
+                table.set("armor", health.armor)?;
+                Ok(LuaValue::Table(table))
+            } else {
+                Ok(LuaValue::Nil)
+            }
+        });
+        fields.add_field_method_get(documented!(registered, Field, "shield"), |lua, lua_handle| {
+            if let Some(shield) = lua_handle.handle.health.and_then(|health| health.shield.as_ref()) {
+                let table = lua.create_table()?;
+                table.set("current", shield.current)?;
+                table.set("max", shield.max_shield)?;
+                Ok(LuaValue::Table(table))
+            } else {
+                Ok(LuaValue::Nil)
+            }
+        });
+        fields.add_field_method_get(documented!(registered, Field, "energy"), |lua, lua_handle| {
+            if let Some(energy) = lua_handle.handle.energy {
+                let table = lua.create_table()?;
+                table.set("stored", energy.stored)?;
+                table.set("capacity", energy.capacity)?;
+                Ok(LuaValue::Table(table))
+            } else {
+                Ok(LuaValue::Nil)
+            }
+        });
+        fields.add_field_method_get(documented!(registered, Field, "heat"), |lua, lua_handle| {
+            if let Some(heat) = lua_handle.handle.heat {
+                let table = lua.create_table()?;
+                table.set("current", heat.current)?;
+                table.set("capacity", heat.capacity)?;
+                table.set("overheated", heat.is_overheated())?;
+                Ok(LuaValue::Table(table))
+            } else {
+                Ok(LuaValue::Nil)
+            }
+        });
+        fields.add_field_method_get(documented!(registered, Field, "weather"), |lua, lua_handle| {
+            let weather = lua_handle.handle.weather;
+            let table = lua.create_table()?;
+            table.set("current", weather.current())?;
+            table.set("visibility", weather.visibility())?;
+            table.set("sheltered", weather.is_sheltered(lua_handle.handle.entity))?;
+            Ok(table)
+        });
+        fields.add_field_method_get(documented!(registered, Field, "status_effects"), |lua, lua_handle| {
+            let table = lua.create_table()?;
+            if let Some(effects) = lua_handle.handle.effects {
+                for (i, effect) in effects.iter().enumerate() {
+                    let effect_table = lua.create_table()?;
+                    effect_table.set("name", effect.name.as_str())?;
+                    effect_table.set("kind", effect.kind.as_ref())?;
+                    effect_table.set("remaining", effect.remaining_ticks as f32 / TICK_RATE as f32)?;
+                    table.set(i + 1, effect_table)?;
+                }
+            }
+            Ok(table)
+        });
+        fields.add_field_method_get(documented!(registered, Field, "inventory"), |lua, lua_handle| {
+            if let Some(inventory) = lua_handle.handle.inventory {
+                Ok(LuaValue::Table(lua.create_table_from(inventory.iter())?))
+            } else {
+                Ok(LuaValue::Nil)
+            }
+        });
+        fields.add_field_method_get(documented!(registered, Field, "movement"), |lua, lua_handle| {
+            if let (Some(movement), Some(intent), Some(state)) = (lua_handle.handle.movement, &lua_handle.handle.movement_intent, lua_handle.handle.movement_state) {
+                let movement_type = movement.movement_type.as_str();
+                let speed = state.movement.speed;
                 let max_speed = movement.max_speed;
                 let max_speed_backwards = movement.max_speed_backwards;
                 let acceleration = movement.acceleration;
                 let braking_acceleration = movement.acceleration;
                 let passive_deceleration = movement.passive_deceleration;
                 let rotation_speed = movement.rotation_speed;
-                let hand_brake = movement.hand_brake;
+                let hand_brake = intent.hand_brake;
+                let blocked = state.last_move_result.blocked_by.is_some();
+                let target_speed = intent.target_speed;
                 let table = lua.create_table()?;
                 table.set("movement_type", movement_type)?;
                 table.set("speed", speed)?;
@@ -158,10 +1130,107 @@ impl LuaUserData for LuaUnitHandle<'_> {
                 table.set("passive_deceleration", passive_deceleration)?;
                 table.set("rotation_speed", rotation_speed)?;
                 table.set("is_hand_brake_pulled", hand_brake)?;
+                table.set("blocked", blocked)?;
+                table.set("target_speed", target_speed)?;
+                Ok(LuaValue::Table(table))
+            } else {
+                Ok(LuaValue::Nil)
+            }
+        });
+        fields.add_field_method_get(documented!(registered, Field, "last_move_result"), |lua, lua_handle| {
+            if let Some(state) = lua_handle.handle.movement_state {
+                let result = state.last_move_result;
+                let displacement: [f32; 2] = result.displacement.into();
+                let table = lua.create_table()?;
+                table.set("success", result.blocked_by.is_none())?;
+                table.set("blocked_by", result.blocked_by_unit.map(UnitRef))?;
+                table.set("displacement", displacement)?;
+                table.set("heading_reached", result.heading_reached)?;
                 Ok(LuaValue::Table(table))
             } else {
                 Ok(LuaValue::Nil)
             }
-        })
+        });
+        check_registered(ApiKind::Field, &registered);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shared() -> SharedProgram {
+        SharedProgram {
+            name: "miner".to_string(),
+            description: "digs until the inventory is full".to_string(),
+            language: "lua".to_string(),
+            program: "function on_tick(handle)\n    handle:move(1, 0)\nend\n".to_string()
+        }
+    }
+
+    // like `encode`, but with whatever hash and data the test wants
+    fn encode_raw(hash: &[u8], data: &[u8]) -> String {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(hash).unwrap();
+        encoder.write_all(data).unwrap();
+        format!("{}{}", SHARED_PROGRAM_PREFIX, base64::encode(encoder.finish().unwrap()))
+    }
+
+    #[test]
+    fn programs_reach_no_files_or_processes() {
+        let program = b"assert(io == nil and os == nil and package == nil and require == nil)
+            assert(dofile == nil and loadfile == nil)
+            assert(coroutine and table and string and utf8 and math)";
+        assert!(UnitProgramState::new_lua_with_program(program).is_ok());
+    }
+
+    #[test]
+    fn round_trips_programs() {
+        let decoded = SharedProgram::decode(&shared().encode()).unwrap();
+        assert_eq!(decoded.name, "miner");
+        assert_eq!(decoded.description, "digs until the inventory is full");
+        assert_eq!(decoded.language, "lua");
+        assert_eq!(decoded.program, shared().program);
+    }
+
+    #[test]
+    fn ignores_surrounding_whitespace() {
+        let string = format!("  {}\n", shared().encode());
+        assert_eq!(SharedProgram::decode(&string).unwrap().program, shared().program);
+    }
+
+    #[test]
+    fn rejects_malformed_strings() {
+        assert!(matches!(SharedProgram::decode("function on_tick() end"), Err(SharedProgramError::Prefix)));
+        assert!(matches!(SharedProgram::decode(&format!("{}not base64!", SHARED_PROGRAM_PREFIX)), Err(SharedProgramError::Base64(_))));
+        assert!(matches!(SharedProgram::decode(&format!("{}{}", SHARED_PROGRAM_PREFIX, base64::encode(b"not deflated"))), Err(SharedProgramError::Decompress(_))));
+        assert!(matches!(SharedProgram::decode(&encode_raw(b"short", b"")), Err(SharedProgramError::Hash)));
+        let json = b"{\"name\": 1}";
+        assert!(matches!(SharedProgram::decode(&encode_raw(blake3::hash(json).as_bytes(), json)), Err(SharedProgramError::Json(_))));
+    }
+
+    #[test]
+    fn rejects_damaged_strings() {
+        let json = serde_json::to_vec(&shared()).unwrap();
+        let mut damaged = json.clone();
+        damaged[json.len() / 2] ^= 1;
+        assert!(matches!(SharedProgram::decode(&encode_raw(blake3::hash(&json).as_bytes(), &damaged)), Err(SharedProgramError::Hash)));
+    }
+
+    #[test]
+    fn rejects_strings_inflating_past_the_limit() {
+        // zeros compress to almost nothing, the string is small but its content isn't
+        let data = vec![0; MAX_SHARED_PROGRAM_LEN as usize];
+        let string = encode_raw(blake3::hash(&data).as_bytes(), &data);
+        assert!(string.len() < 8 * 1024);
+        assert!(matches!(SharedProgram::decode(&string), Err(SharedProgramError::TooLarge)));
+    }
+
+    #[test]
+    fn accepts_strings_at_the_limit() {
+        let mut program = shared();
+        let json_len = serde_json::to_vec(&program).unwrap().len();
+        program.program.push_str(&" ".repeat(MAX_SHARED_PROGRAM_LEN as usize - blake3::OUT_LEN - json_len));
+        assert_eq!(SharedProgram::decode(&program.encode()).unwrap().program.len(), program.program.len());
     }
 }
@@ -0,0 +1,66 @@
+use std::{collections::BTreeMap, fmt::Write};
+use serde_json::Value;
+use super::Prototypes;
+use crate::loading::parse_prototypes;
+
+/// `scriplets protodiff a.json b.json`
+pub const PROTODIFF_COMMAND: &str = "protodiff";
+
+fn load(path: &str) -> Result<Prototypes, String> {
+    let bytes = std::fs::read(path).map_err(|error| format!("can't read {}: {}", path, error))?;
+    parse_prototypes(&bytes).map_err(|error| format!("can't load {}: {}", path, error))
+}
+
+fn by_name(prototypes: &[Value]) -> BTreeMap<&str, &Value> {
+    prototypes.iter().map(|prototype| (prototype.get("name").and_then(Value::as_str).unwrap_or_default(), prototype)).collect()
+}
+
+fn fields(prototype: &Value) -> BTreeMap<&str, &Value> {
+    prototype.as_object().into_iter().flatten().map(|(field, value)| (field.as_str(), value)).collect()
+}
+
+fn field_text(value: Option<&&Value>) -> String {
+    value.map_or_else(|| "(unset)".to_string(), ToString::to_string)
+}
+
+/// Prototypes added (`+`), removed (`-`) and changed (`~`) from `a` to `b`, with the changed
+/// fields. Both are loaded like the game loads them, so invalid files are errors, but fields are
+/// compared as written, defaults aren't filled in.
+pub fn protodiff(a: &str, b: &str) -> Result<String, String> {
+    let (old, new) = (load(a)?, load(b)?);
+    let mut diff = String::new();
+    for category in Prototypes::CATEGORIES {
+        let (old_prototypes, new_prototypes) = (by_name(old.source(category)), by_name(new.source(category)));
+        let mut lines = Vec::new();
+        for (name, old_prototype) in &old_prototypes {
+            match new_prototypes.get(name) {
+                None => lines.push(format!("  - {}", name)),
+                Some(new_prototype) if new_prototype != old_prototype => {
+                    lines.push(format!("  ~ {}", name));
+                    let (old_fields, new_fields) = (fields(old_prototype), fields(new_prototype));
+                    let mut names: Vec<&str> = old_fields.keys().chain(new_fields.keys()).copied().collect();
+                    names.sort_unstable();
+                    names.dedup();
+                    for field in names {
+                        let (old_value, new_value) = (old_fields.get(field), new_fields.get(field));
+                        if old_value != new_value {
+                            lines.push(format!("      {}: {} -> {}", field, field_text(old_value), field_text(new_value)));
+                        }
+                    }
+                }
+                Some(_) => ()
+            }
+        }
+        lines.extend(new_prototypes.keys().filter(|name| !old_prototypes.contains_key(*name)).map(|name| format!("  + {}", name)));
+        if !lines.is_empty() {
+            writeln!(diff, "{}", category).unwrap();
+            for line in lines {
+                writeln!(diff, "{}", line).unwrap();
+            }
+        }
+    }
+    if diff.is_empty() {
+        diff.push_str("no differences\n");
+    }
+    Ok(diff)
+}
@@ -0,0 +1,182 @@
+use std::{collections::HashMap, sync::Arc};
+use bevy::prelude::*;
+#[cfg(not(target_arch = "wasm32"))]
+use mlua::prelude::*;
+use super::{Unit, UnitId};
+use crate::team::Team;
+#[cfg(not(feature = "headless"))]
+use super::UiFont;
+#[cfg(not(feature = "headless"))]
+use crate::{debug::DebugSettings, camera::DetailLevel};
+
+// how far units see units of other teams in clear weather, tiles
+#[cfg(not(target_arch = "wasm32"))]
+const SIGHT_RANGE: f32 = 10.0;
+#[cfg(not(feature = "headless"))]
+const LABEL_OFFSET: f32 = 1.0;
+#[cfg(not(feature = "headless"))]
+const LABEL_FONT_SIZE: f32 = 32.0;
+#[cfg(not(feature = "headless"))]
+const LABEL_SCALE: f32 = 0.01;
+
+/// Name given to a unit by its program, used to find it from other units
+#[derive(Component, Default)]
+pub struct UnitLabel(pub Option<String>);
+
+#[cfg(not(feature = "headless"))]
+#[derive(Component)]
+pub struct LabelText;
+
+/// Last known position of every labeled unit, by team and label. Entries of destroyed units stay
+/// until the label is taken by another unit of their team, units that are renamed or change teams
+/// lose their old entry.
+#[derive(Default)]
+pub struct UnitRegistry(HashMap<(Team, String), (Entity, Vec2)>);
+
+impl UnitRegistry {
+    pub fn find(&self, team: Team, label: &str) -> Option<Vec2> {
+        self.0.get(&(team, label.to_string())).map(|(_, position)| *position)
+    }
+}
+
+pub fn update_unit_registry(
+    mut registry: ResMut<UnitRegistry>,
+    units: Query<(Entity, &UnitLabel, &Team, &Transform), With<Unit>>)
+{
+    // drop entries of units that were renamed or moved to another team
+    registry.0.retain(|(team, label), (entity, _)| {
+        units.get(*entity).map_or(true, |(_, unit_label, unit_team, _)| unit_label.0.as_ref() == Some(label) && unit_team == team)
+    });
+    for (entity, label, team, transform) in units.iter() {
+        if let Some(label) = &label.0 {
+            registry.0.insert((*team, label.clone()), (entity, transform.translation.truncate()));
+        }
+    }
+}
+
+/// Position and team of every unit at the end of the last tick. Programs can't reach the world
+/// while they run, `UnitRef`s are resolved against this instead.
+#[derive(Default)]
+pub struct UnitDirectory(Arc<HashMap<UnitId, (Vec2, Team)>>);
+
+pub fn update_unit_directory(mut directory: ResMut<UnitDirectory>, units: Query<(&UnitId, &Transform, &Team), With<Unit>>) {
+    directory.0 = Arc::new(units.iter().map(|(id, transform, team)| (*id, (transform.translation.truncate(), *team))).collect());
+}
+
+/// What the running unit can see, Lua app data replaced before every run. Servers send players
+/// what their units see the same way.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct UnitView {
+    units: Arc<HashMap<UnitId, (Vec2, Team)>>,
+    team: Option<Team>,
+    position: Vec2,
+    // multiplier of the sight range from the weather
+    visibility: f32
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl UnitView {
+    pub fn new(directory: &UnitDirectory, team: Option<Team>, position: Vec2, visibility: f32) -> Self {
+        Self { units: directory.0.clone(), team, position, visibility }
+    }
+
+    /// Sees every unit regardless of team and distance, for observers of a multiplayer game
+    pub fn observer(directory: &UnitDirectory) -> Self {
+        Self { units: directory.0.clone(), team: None, position: Vec2::ZERO, visibility: f32::INFINITY }
+    }
+
+    // units of the same team are always visible, others only within sight range
+    pub(crate) fn visible(&self, id: UnitId) -> Option<(Vec2, Team)> {
+        let (position, team) = *self.units.get(&id)?;
+        (self.team == Some(team) || self.sees(position)).then_some((position, team))
+    }
+
+    /// Whether the position is within sight range, for everything that isn't a unit
+    pub(crate) fn sees(&self, position: Vec2) -> bool {
+        position.distance(self.position) <= SIGHT_RANGE * self.visibility
+    }
+}
+
+/// Reference to another unit that scripts can keep between runs. It's resolved whenever it's
+/// used, so it notices destroyed units and never reveals units out of sight.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Copy)]
+pub struct UnitRef(pub UnitId);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LuaUserData for UnitRef {
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("id", |_lua, unit_ref| Ok(unit_ref.0.0 as LuaInteger));
+    }
+
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("is_alive", |lua, unit_ref, ()| {
+            // units out of sight could be destroyed as far as the script knows
+            Ok(lua.app_data_ref::<UnitView>().map_or(false, |view| view.visible(unit_ref.0).is_some()))
+        });
+        methods.add_method("position", |lua, unit_ref, ()| {
+            let visible = lua.app_data_ref::<UnitView>().and_then(|view| view.visible(unit_ref.0));
+            Ok(visible.map(|(position, _)| <[f32; 2]>::from(position)))
+        });
+        methods.add_method("team", |lua, unit_ref, ()| {
+            let visible = lua.app_data_ref::<UnitView>().and_then(|view| view.visible(unit_ref.0));
+            Ok(visible.map(|(_, team)| team.0))
+        });
+        methods.add_meta_method(LuaMetaMethod::Eq, |_lua, unit_ref, other: LuaAnyUserData| {
+            Ok(other.borrow::<UnitRef>().map_or(false, |other| other.0 == unit_ref.0))
+        });
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+pub fn spawn_unit_labels(mut commands: Commands, units: Query<Entity, Added<UnitLabel>>, font: Res<UiFont>) {
+    for entity in units.iter() {
+        commands.entity(entity).with_children(|parent| {
+            let style = TextStyle {
+                font: font.0.clone(),
+                font_size: LABEL_FONT_SIZE,
+                color: Color::WHITE
+            };
+            parent.spawn_bundle(Text2dBundle {
+                text: Text::from_section("", style).with_alignment(TextAlignment::CENTER),
+                transform: Transform::from_xyz(0.0, LABEL_OFFSET, 1.0).with_scale(Vec3::splat(LABEL_SCALE)),
+                ..default()
+            }).insert(LabelText);
+        });
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+type LabeledUnit = (&'static UnitLabel, &'static Transform, &'static Children, ChangeTrackers<UnitLabel>, ChangeTrackers<Transform>, ChangeTrackers<Children>);
+
+// Like status indicators, labels counter the unit's rotation to stay upright above it. All of them
+// are updated when the labels are toggled or the camera zooms past the detail level, otherwise
+// only the ones of changed units.
+#[cfg(not(feature = "headless"))]
+pub fn update_unit_labels(
+    settings: Res<DebugSettings>,
+    detail: Res<DetailLevel>,
+    mut labels: Query<(&mut Text, &mut Visibility, &mut Transform), With<LabelText>>,
+    units: Query<LabeledUnit, Without<LabelText>>)
+{
+    for (label, unit_transform, children, label_tracker, transform_tracker, children_tracker) in units.iter() {
+        if !settings.is_changed() && !detail.is_changed() && !label_tracker.is_changed() && !transform_tracker.is_changed() && !children_tracker.is_changed() {
+            continue
+        }
+        for child in children.iter() {
+            let (mut text, mut visibility, mut transform) = match labels.get_mut(*child) {
+                Ok(label_text) => label_text,
+                Err(_) => continue
+            };
+            visibility.is_visible = settings.labels && label.0.is_some() && !detail.zoomed_out;
+            if let Some(label) = &label.0 {
+                if text.sections[0].value != *label {
+                    text.sections[0].value = label.clone();
+                }
+            }
+            let inverse_rotation = unit_transform.rotation.inverse();
+            transform.rotation = inverse_rotation;
+            transform.translation = inverse_rotation * Vec3::new(0.0, LABEL_OFFSET, 1.0);
+        }
+    }
+}
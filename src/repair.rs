@@ -0,0 +1,153 @@
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+#[cfg(not(target_arch = "wasm32"))]
+use mlua::prelude::*;
+use serde::Deserialize;
+use super::{Prototype, ComponentPrototype, Prototypes, Unit, TICK_RATE};
+use crate::{health::{Health, Corpse}, energy::Energy, heat::Heat, inventory::Inventory, construction::{Structure, StructurePrototype}};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::program::raise_intent;
+
+#[derive(Component, Prototype, ComponentPrototype, Deserialize, Clone)]
+#[prot_category(repair_tool)]
+pub struct RepairTool {
+    name: String,
+    range: f32, // tiles
+    repair_rate: f32, // health / second
+    #[serde(default)]
+    energy_per_point: f32,
+    #[serde(default)]
+    heat_per_point: f32,
+    // fraction of a structure's cost returned when salvaging it
+    #[serde(default = "default_salvage_return")]
+    salvage_return: f32,
+    #[serde(skip)]
+    repair_target: Option<Vec2>,
+    #[serde(skip)]
+    salvage_target: Option<Vec2>
+}
+
+fn default_salvage_return() -> f32 {
+    0.5
+}
+
+fn entity_at_point(rapier_context: &RapierContext, point: Vec2, exclude: Entity) -> Option<Entity> {
+    let mut found = None;
+    let filter = QueryFilter::default().exclude_collider(exclude);
+    rapier_context.intersections_with_point(point, filter, |entity| {
+        found = Some(entity);
+        false
+    });
+    found
+}
+
+type Repairer = (Entity, &'static mut RepairTool, &'static Transform, Option<&'static mut Energy>, Option<&'static mut Heat>);
+
+pub fn repair(
+    mut repairers: Query<Repairer, With<Unit>>,
+    mut healths: Query<&mut Health>,
+    rapier_context: Res<RapierContext>)
+{
+    for (entity, mut repair_tool, transform, mut energy, mut heat) in repairers.iter_mut() {
+        if heat.as_ref().map_or(false, |heat| heat.is_overheated()) {
+            continue
+        }
+        let target_point = match repair_tool.repair_target {
+            Some(point) if transform.translation.truncate().distance(point) <= repair_tool.range => point,
+            _ => continue
+        };
+        let mut health = match entity_at_point(&rapier_context, target_point, entity).and_then(|target| healths.get_mut(target).ok()) {
+            Some(health) => health,
+            None => {
+                repair_tool.repair_target = None;
+                continue
+            }
+        };
+        let missing = health.max_health - health.current;
+        if missing <= 0.0 {
+            repair_tool.repair_target = None;
+            continue
+        }
+        let mut repaired = (repair_tool.repair_rate / TICK_RATE as f32).min(missing);
+        if repair_tool.energy_per_point > 0.0 {
+            repaired = match &mut energy {
+                Some(energy) => energy.drain(repaired * repair_tool.energy_per_point) / repair_tool.energy_per_point,
+                None => 0.0
+            };
+        }
+        health.current += repaired;
+        if let Some(heat) = &mut heat {
+            heat.add(repaired * repair_tool.heat_per_point);
+        }
+    }
+}
+
+// corpses give back their inventory, structures part of their build cost
+type Salvageable = (Or<(With<Structure>, With<Corpse>)>, Without<Unit>);
+
+pub fn salvage(
+    mut commands: Commands,
+    mut salvagers: Query<(Entity, &mut RepairTool, &Transform, &mut Inventory), With<Unit>>,
+    salvageables: Query<(Option<&Structure>, Option<&Inventory>), Salvageable>,
+    rapier_context: Res<RapierContext>,
+    prototypes: Res<Prototypes>)
+{
+    for (entity, mut repair_tool, transform, mut inventory) in salvagers.iter_mut() {
+        let target_point = match repair_tool.salvage_target.take() {
+            Some(point) if transform.translation.truncate().distance(point) <= repair_tool.range => point,
+            _ => continue
+        };
+        let target = match entity_at_point(&rapier_context, target_point, entity) {
+            Some(target) => target,
+            None => continue
+        };
+        if let Ok((structure, contents)) = salvageables.get(target) {
+            if let Some(contents) = contents {
+                for (item, count) in contents.iter() {
+                    inventory.add(item, count);
+                }
+            }
+            if let Some(prototype) = structure.and_then(|structure| StructurePrototype::from_pt(&prototypes, &structure.prototype)) {
+                for (item, count) in prototype.cost() {
+                    inventory.add(item, (*count as f32 * repair_tool.salvage_return).floor() as u32);
+                }
+            }
+            commands.entity(target).despawn_recursive();
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct LuaRepairToolHandle<'a> {
+    pub repair_tool: &'a mut RepairTool
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LuaUserData for LuaRepairToolHandle<'_> {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut("repair", |lua, lua_handle, (x, y): (f32, f32)| {
+            raise_intent(lua)?;
+            lua_handle.repair_tool.repair_target = Some(Vec2::new(x, y));
+            Ok(())
+        });
+        methods.add_method_mut("stop", |lua, lua_handle, ()| {
+            raise_intent(lua)?;
+            lua_handle.repair_tool.repair_target = None;
+            Ok(())
+        });
+        methods.add_method_mut("salvage", |lua, lua_handle, (x, y): (f32, f32)| {
+            raise_intent(lua)?;
+            lua_handle.repair_tool.salvage_target = Some(Vec2::new(x, y));
+            Ok(())
+        });
+    }
+
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("range", |_lua, lua_handle| {
+            Ok(lua_handle.repair_tool.range)
+        });
+        fields.add_field_method_get("is_repairing", |_lua, lua_handle| {
+            Ok(lua_handle.repair_tool.repair_target.is_some())
+        });
+    }
+}
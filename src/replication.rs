@@ -0,0 +1,320 @@
+use std::{collections::BTreeMap, f32::consts::TAU};
+use bevy::prelude::*;
+use serde::{Serialize, Deserialize};
+
+// positions are sent in 1/256 tiles and rotations in 1/65536 turns
+const POSITION_STEPS: f32 = 256.0;
+const ROTATION_STEPS: f32 = 65536.0;
+
+/// Position and rotation of a unit as they're sent to clients
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct QuantizedTransform {
+    x: i32,
+    y: i32,
+    rotation: u16
+}
+
+impl QuantizedTransform {
+    pub fn new(position: Vec2, rotation: f32) -> Self {
+        let turns = (rotation / TAU).rem_euclid(1.0);
+        Self {
+            x: (position.x * POSITION_STEPS).round() as i32,
+            y: (position.y * POSITION_STEPS).round() as i32,
+            // a full turn wraps around to 0
+            rotation: (turns * ROTATION_STEPS).round() as u32 as u16
+        }
+    }
+
+    pub fn of(transform: &Transform) -> Self {
+        Self::new(transform.translation.truncate(), transform.rotation.to_euler(EulerRot::XYZ).2)
+    }
+
+    pub fn position(self) -> Vec2 {
+        Vec2::new(self.x as f32, self.y as f32) / POSITION_STEPS
+    }
+
+    #[cfg(any(test, not(feature = "headless")))]
+    pub fn rotation(self) -> f32 {
+        self.rotation as f32 / ROTATION_STEPS * TAU
+    }
+
+    /// Position and rotation `alpha` of the way from `self` to `to`, turning the short way around
+    #[cfg(any(test, not(feature = "headless")))]
+    pub fn lerp(self, to: Self, alpha: f32) -> (Vec2, f32) {
+        let turn = (to.rotation.wrapping_sub(self.rotation) as i16) as f32 / ROTATION_STEPS * TAU;
+        (self.position().lerp(to.position(), alpha), self.rotation() + turn * alpha)
+    }
+}
+
+/// What clients know of a unit besides its blueprint
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ReplicatedUnit {
+    pub transform: QuantizedTransform,
+    pub team: u32,
+    pub health: Option<f32>,
+    pub energy: Option<f32>,
+    pub label: Option<String>
+}
+
+/// One changed component of a unit
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum UnitChange {
+    Transform(QuantizedTransform),
+    Team(u32),
+    Health(Option<f32>),
+    Energy(Option<f32>),
+    Label(Option<String>)
+}
+
+impl ReplicatedUnit {
+    fn changes(&self, next: &ReplicatedUnit) -> Vec<UnitChange> {
+        let mut changes = Vec::new();
+        if self.transform != next.transform {
+            changes.push(UnitChange::Transform(next.transform));
+        }
+        if self.team != next.team {
+            changes.push(UnitChange::Team(next.team));
+        }
+        if self.health != next.health {
+            changes.push(UnitChange::Health(next.health));
+        }
+        if self.energy != next.energy {
+            changes.push(UnitChange::Energy(next.energy));
+        }
+        if self.label != next.label {
+            changes.push(UnitChange::Label(next.label.clone()));
+        }
+        changes
+    }
+
+    #[cfg(any(test, not(feature = "headless")))]
+    pub fn apply(&mut self, change: &UnitChange) {
+        match change {
+            UnitChange::Transform(transform) => self.transform = *transform,
+            UnitChange::Team(team) => self.team = *team,
+            UnitChange::Health(health) => self.health = *health,
+            UnitChange::Energy(energy) => self.energy = *energy,
+            UnitChange::Label(label) => self.label = label.clone()
+        }
+    }
+}
+
+/// What an object is, clients spawn it from the same prototype the server did
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum ObjectKind {
+    Creature(String),
+    /// Structures, belts included
+    Structure(String),
+    /// Ghost of the structure being built
+    ConstructionSite(String),
+    GroundItem { item: String, count: u32 },
+    Drone
+}
+
+/// Creature, structure or anything else clients are shown but don't simulate. Objects are sent
+/// whole whenever something about them changed, only creatures, drones and items on belts move.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ReplicatedObject {
+    pub kind: ObjectKind,
+    pub transform: QuantizedTransform
+}
+
+/// Unit the client didn't know of, or any unit in a snapshot
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Spawn {
+    pub id: u64,
+    /// `Blueprint::export` of the unit, what clients spawn it from
+    pub blueprint: String,
+    pub unit: ReplicatedUnit
+}
+
+/// What the server sends every tick. Snapshots carry every unit the client is sent as a spawn,
+/// deltas only what changed since the previous update.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Update {
+    pub tick: u64,
+    pub snapshot: bool,
+    pub spawned: Vec<Spawn>,
+    pub changed: Vec<(u64, Vec<UnitChange>)>,
+    pub despawned: Vec<u64>,
+    /// Objects that are new or changed, by id
+    pub objects: Vec<(u64, ReplicatedObject)>,
+    pub removed_objects: Vec<u64>,
+    /// `WorldView::checksum` of the view the update leads to
+    pub checksum: [u8; 32]
+}
+
+/// Units and objects a client knows of at a tick, by id. The server keeps the one it last sent
+/// each client to diff the next one against, clients keep theirs to apply updates to.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct WorldView {
+    pub tick: u64,
+    pub units: BTreeMap<u64, ReplicatedUnit>,
+    pub objects: BTreeMap<u64, ReplicatedObject>
+}
+
+impl WorldView {
+    /// Update with every unit of this view, `blueprint` gives their blueprints by id
+    pub fn snapshot(&self, blueprint: impl Fn(u64) -> String) -> Update {
+        Update {
+            tick: self.tick,
+            snapshot: true,
+            spawned: self.units.iter().map(|(id, unit)| Spawn { id: *id, blueprint: blueprint(*id), unit: unit.clone() }).collect(),
+            changed: Vec::new(),
+            despawned: Vec::new(),
+            objects: self.objects.iter().map(|(id, object)| (*id, object.clone())).collect(),
+            removed_objects: Vec::new(),
+            checksum: self.checksum()
+        }
+    }
+
+    /// Update turning this view into `next`, `blueprint` gives the blueprints of new units by id
+    pub fn delta(&self, next: &WorldView, blueprint: impl Fn(u64) -> String) -> Update {
+        let mut update = Update {
+            tick: next.tick,
+            snapshot: false,
+            spawned: Vec::new(),
+            changed: Vec::new(),
+            despawned: Vec::new(),
+            objects: next.objects.iter().filter(|(id, object)| self.objects.get(id) != Some(object)).map(|(id, object)| (*id, object.clone())).collect(),
+            removed_objects: self.objects.keys().filter(|id| !next.objects.contains_key(id)).copied().collect(),
+            checksum: next.checksum()
+        };
+        for (id, unit) in &next.units {
+            match self.units.get(id) {
+                Some(old) => {
+                    let changes = old.changes(unit);
+                    if !changes.is_empty() {
+                        update.changed.push((*id, changes));
+                    }
+                }
+                None => update.spawned.push(Spawn { id: *id, blueprint: blueprint(*id), unit: unit.clone() })
+            }
+        }
+        update.despawned = self.units.keys().filter(|id| !next.units.contains_key(id)).copied().collect();
+        update
+    }
+
+    /// Hash of the tick and every unit. The world only steps on the server, so clients can't check
+    /// the `StateChecksum`, they compare this with the one of each update instead.
+    pub fn checksum(&self) -> [u8; 32] {
+        let bytes = rmp_serde::to_vec(&(self.tick, &self.units, &self.objects)).expect("views always serialize");
+        *blake3::hash(&bytes).as_bytes()
+    }
+
+    #[cfg(any(test, not(feature = "headless")))]
+    pub fn apply(&mut self, update: &Update) {
+        self.tick = update.tick;
+        if update.snapshot {
+            self.units.clear();
+            self.objects.clear();
+        }
+        for spawn in &update.spawned {
+            self.units.insert(spawn.id, spawn.unit.clone());
+        }
+        for (id, changes) in &update.changed {
+            if let Some(unit) = self.units.get_mut(id) {
+                changes.iter().for_each(|change| unit.apply(change));
+            }
+        }
+        for id in &update.despawned {
+            self.units.remove(id);
+        }
+        self.objects.extend(update.objects.iter().cloned());
+        for id in &update.removed_objects {
+            self.objects.remove(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(x: f32, health: f32) -> ReplicatedUnit {
+        ReplicatedUnit {
+            transform: QuantizedTransform::new(Vec2::new(x, 0.0), 0.0),
+            team: 0,
+            health: Some(health),
+            energy: None,
+            label: None
+        }
+    }
+
+    fn view(tick: u64, units: Vec<(u64, ReplicatedUnit)>) -> WorldView {
+        WorldView { tick, units: units.into_iter().collect(), objects: BTreeMap::new() }
+    }
+
+    fn object(kind: ObjectKind, x: f32) -> ReplicatedObject {
+        ReplicatedObject { kind, transform: QuantizedTransform::new(Vec2::new(x, 0.0), 0.0) }
+    }
+
+    #[test]
+    fn deltas_only_carry_what_changed() {
+        let old = view(1, vec![(1, unit(0.0, 10.0)), (2, unit(5.0, 10.0)), (3, unit(9.0, 10.0))]);
+        let new = view(2, vec![(1, unit(0.0, 10.0)), (2, unit(5.5, 10.0)), (4, unit(1.0, 3.0))]);
+        let delta = old.delta(&new, |id| format!("blueprint {}", id));
+        assert_eq!(delta.changed, vec![(2, vec![UnitChange::Transform(new.units[&2].transform)])]);
+        assert_eq!(delta.spawned, vec![Spawn { id: 4, blueprint: "blueprint 4".to_string(), unit: unit(1.0, 3.0) }]);
+        assert_eq!(delta.despawned, vec![3]);
+        let mut applied = old;
+        applied.apply(&delta);
+        assert_eq!(applied, new);
+        assert_eq!(applied.checksum(), delta.checksum);
+    }
+
+    #[test]
+    fn checksums_tell_views_apart() {
+        let view = view(3, vec![(1, unit(0.0, 10.0)), (2, unit(5.0, 10.0))]);
+        let mut hurt = view.clone();
+        hurt.units.get_mut(&2).unwrap().health = Some(9.0);
+        let mut later = view.clone();
+        later.tick = 4;
+        assert_eq!(view.checksum(), view.clone().checksum());
+        assert_ne!(view.checksum(), hurt.checksum());
+        assert_ne!(view.checksum(), later.checksum());
+    }
+
+    #[test]
+    fn deltas_carry_changed_objects_whole() {
+        let mut old = view(1, Vec::new());
+        old.objects.insert(1, object(ObjectKind::Creature("crawler".to_string()), 0.0));
+        old.objects.insert(2, object(ObjectKind::Structure("wall".to_string()), 3.0));
+        old.objects.insert(3, object(ObjectKind::Drone, 4.0));
+        let mut new = view(2, Vec::new());
+        new.objects.insert(1, object(ObjectKind::Creature("crawler".to_string()), 0.5));
+        new.objects.insert(2, object(ObjectKind::Structure("wall".to_string()), 3.0));
+        new.objects.insert(4, object(ObjectKind::GroundItem { item: "iron".to_string(), count: 2 }, 1.0));
+        let delta = old.delta(&new, |id| format!("blueprint {}", id));
+        assert_eq!(delta.objects, vec![(1, new.objects[&1].clone()), (4, new.objects[&4].clone())]);
+        assert_eq!(delta.removed_objects, vec![3]);
+        let mut applied = old;
+        applied.apply(&delta);
+        assert_eq!(applied, new);
+    }
+
+    #[test]
+    fn snapshots_drop_units_the_client_still_had() {
+        let mut client = view(1, vec![(1, unit(0.0, 10.0)), (2, unit(5.0, 10.0))]);
+        let server = view(5, vec![(2, unit(6.0, 4.0))]);
+        client.apply(&server.snapshot(|id| format!("blueprint {}", id)));
+        assert_eq!(client, server);
+    }
+
+    #[test]
+    fn transforms_round_to_a_256th_of_a_tile() {
+        let transform = QuantizedTransform::new(Vec2::new(-3.1234, 1000.001), -1.0);
+        assert!(transform.position().distance(Vec2::new(-3.1234, 1000.001)) < 1.0 / 256.0);
+        assert!((transform.rotation() - (TAU - 1.0)).abs() < 0.001);
+        assert_eq!(QuantizedTransform::new(Vec2::ZERO, TAU - 0.00001).rotation, 0);
+    }
+
+    #[test]
+    fn interpolation_turns_the_short_way() {
+        let from = QuantizedTransform::new(Vec2::ZERO, TAU - 0.1);
+        let to = QuantizedTransform::new(Vec2::new(2.0, 0.0), 0.1);
+        let (position, rotation) = from.lerp(to, 0.5);
+        assert_eq!(position, Vec2::new(1.0, 0.0));
+        assert!((rotation - TAU).abs() < 0.001);
+    }
+}
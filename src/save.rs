@@ -0,0 +1,589 @@
+use std::{fs, io::Read, path::PathBuf, time::SystemTime, marker::PhantomData};
+use bevy::{prelude::*, ecs::system::SystemParam};
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use scriplets_core::migration::{Migration, MigrationError, migrate};
+use super::{Unit, UnitId, NextUnitId, SimTick, WorldSeed, UnitSpawner, Prototypes, TICK_RATE, units_in_order, SimStepLabel, EventLabel};
+use crate::blueprint::{Blueprint, BlueprintSources, UnitSpawnQueue};
+use crate::{team::Team, health::Health, energy::Energy, storage::{Storage, StorageContents, KeySalt}};
+use crate::settings::AutosaveSettings;
+use crate::loading::{AppState, ModList};
+use crate::logging::ASSETS;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::replication::{WorldView, ReplicatedUnit, QuantizedTransform};
+
+const SAVES_DIR: &str = "saves";
+const SAVE_EXTENSION: &str = "save";
+// Save files start with this and the format version, then comes the zstd compressed JSON and the
+// blake3 hash of everything before it. Files without it are plain JSON of the first format.
+const SAVE_MAGIC: &[u8] = b"SCRIPSAV";
+/// Bump when changing the layout of `WorldSave` and add a migration from the old one, newer saves
+/// are refused
+pub const SAVE_FORMAT: u32 = 2;
+// upgrades from every older format version, see `migrate`
+const SAVE_MIGRATIONS: &[Migration<serde_json::Value>] = &[
+    Migration { from: 1, description: "prototypes hash of the merged prototypes and mods", migrate: drop_file_prototypes_hash }
+];
+// decompressed size, saves can be shared like programs and a small file mustn't inflate to gigabytes
+const MAX_SAVE_LEN: u64 = 256 * 1024 * 1024;
+// autosaves go into autosave-1, autosave-2, ... up to the number of slots in the settings
+const AUTOSAVE_PREFIX: &str = "autosave-";
+/// `scriplets --continue` loads the newest save once the game has started, skipping the main menu
+/// like its "Continue" entry
+pub const CONTINUE_FLAG: &str = "--continue";
+
+/// Saving and loading the world into the `saves` folder. Only units are saved for now: their
+/// blueprint, transform, id, team, health, energy and black box, along with the tick, the world
+/// seed, the key salt and the next unit id. Programs start over from their source after loading,
+/// the map, creatures and items on the ground are the ones of the running game.
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<SaveEvent>()
+            .add_event::<LoadEvent>()
+            .add_event::<SaveOutcome>()
+            .init_resource::<AutosaveSettings>()
+            .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(continue_game))
+            .add_system(autosave.after(EventLabel).with_run_criteria(SimStepLabel))
+            .add_system_set(SystemSet::on_update(AppState::Playing)
+                .with_system(save_world)
+                .with_system(load_world)
+                .with_system(apply_restored_state));
+    }
+}
+
+/// Inserted by `--continue`
+pub struct ContinueGame;
+
+pub struct SaveEvent {
+    pub name: String
+}
+
+pub struct LoadEvent {
+    // `None` loads the newest save
+    pub name: Option<String>
+}
+
+/// What came of a `SaveEvent` or `LoadEvent`, for the console to show
+// a headless build has no console, it only logs them
+#[cfg_attr(feature = "headless", allow(dead_code))]
+pub enum SaveOutcome {
+    Saved { name: String },
+    // `mismatches` says what differs from when the save was made
+    Loaded { name: String, units: usize, mismatches: Vec<String> },
+    SaveFailed { name: String, error: String },
+    LoadFailed { error: String }
+}
+
+/// Save and load events and what came of them, for the console
+#[cfg(not(feature = "headless"))]
+#[derive(SystemParam)]
+pub struct SaveRequests<'w, 's> {
+    pub saves: EventWriter<'w, 's, SaveEvent>,
+    pub loads: EventWriter<'w, 's, LoadEvent>,
+    pub outcomes: EventReader<'w, 's, SaveOutcome>
+}
+
+// Hashes are hex, they're missing in saves from before they were added
+#[derive(Serialize, Deserialize)]
+pub(crate) struct WorldSave {
+    tick: u64,
+    seed: u64,
+    next_unit_id: u64,
+    #[serde(default)]
+    prototypes_hash: Option<String>,
+    // active mods as "name version" in load order, missing in saves from before they were recorded
+    #[serde(default)]
+    mods: Option<Vec<String>>,
+    // see `KeySalt`, saves from before it was added keep the salt of the running game
+    #[serde(default)]
+    key_salt: Option<[u8; 32]>,
+    pub(crate) units: Vec<SavedUnit>
+}
+
+pub(crate) fn active_mods(mod_list: &ModList) -> Vec<String> {
+    mod_list.active.iter().map(|active| format!("{} {}", active.name, active.version)).collect()
+}
+
+pub(crate) fn describe_mods(mods: &[String]) -> String {
+    if mods.is_empty() { "no mods".to_string() } else { mods.join(", ") }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct SavedUnit {
+    id: u64,
+    // see `Blueprint::export`
+    blueprint: String,
+    // of the blueprint's program, the same as `UnitProgram::hash` of the loaded unit
+    #[serde(default)]
+    program_hash: Option<String>,
+    translation: [f32; 3],
+    rotation: [f32; 4],
+    team: u32,
+    health: Option<f32>,
+    energy: Option<f32>,
+    storage: Option<StorageContents>
+}
+
+#[derive(Debug, Error)]
+pub enum SaveError {
+    #[error("save names can only contain letters, digits, - and _")]
+    Name,
+    #[error("there are no saves")]
+    NoSaves,
+    #[error("the save is damaged, its hash doesn't match its contents")]
+    Damaged,
+    #[error("{0}")]
+    Version(#[from] MigrationError),
+    #[error("the save is larger than {} bytes", MAX_SAVE_LEN)]
+    TooLarge,
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid save data: {0}")]
+    Json(#[from] serde_json::Error)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl WorldSave {
+    /// What a player of `team` is sent when joining: the units they see, without other teams'
+    /// programs and black boxes, and no key salt. Observers have no team.
+    pub(crate) fn redacted(&self, team: Option<Team>, visible: impl Fn(u64) -> bool) -> WorldSave {
+        let units = self.units.iter().filter(|unit| visible(unit.id)).filter_map(|unit| {
+            if Some(Team(unit.team)) == team {
+                return Some(unit.clone())
+            }
+            let blueprint = Blueprint { program: String::new(), ..Blueprint::import(&unit.blueprint).ok()? };
+            Some(SavedUnit { blueprint: blueprint.export(), program_hash: None, storage: None, ..unit.clone() })
+        }).collect();
+        WorldSave {
+            tick: self.tick,
+            seed: self.seed,
+            next_unit_id: self.next_unit_id,
+            prototypes_hash: self.prototypes_hash.clone(),
+            mods: self.mods.clone(),
+            key_salt: None,
+            units
+        }
+    }
+
+    /// The units as replication sees them, what a joining player's deltas start from
+    pub(crate) fn view(&self) -> WorldView {
+        let units = self.units.iter().map(|unit| {
+            let transform = Transform::from_translation(Vec3::from(unit.translation)).with_rotation(Quat::from_array(unit.rotation));
+            (unit.id, ReplicatedUnit {
+                transform: QuantizedTransform::of(&transform),
+                team: unit.team,
+                health: unit.health,
+                energy: unit.energy,
+                // labels are set by programs, the first delta brings them
+                label: None
+            })
+        }).collect();
+        // objects aren't saved, the first delta brings them
+        WorldView { tick: self.tick, units, objects: Default::default() }
+    }
+}
+
+pub(crate) fn encode_save(save: &WorldSave) -> Result<Vec<u8>, SaveError> {
+    let mut bytes = SAVE_MAGIC.to_vec();
+    bytes.extend_from_slice(&SAVE_FORMAT.to_le_bytes());
+    bytes.extend(zstd::encode_all(serde_json::to_vec(save)?.as_slice(), zstd::DEFAULT_COMPRESSION_LEVEL)?);
+    let hash = blake3::hash(&bytes);
+    bytes.extend_from_slice(hash.as_bytes());
+    Ok(bytes)
+}
+
+pub(crate) fn decode_save(bytes: &[u8]) -> Result<WorldSave, SaveError> {
+    if !bytes.starts_with(SAVE_MAGIC) {
+        return parse_save(bytes, 1)
+    }
+    let header_len = SAVE_MAGIC.len() + 4;
+    if bytes.len() < header_len + blake3::OUT_LEN {
+        return Err(SaveError::Damaged)
+    }
+    let (content, hash) = bytes.split_at(bytes.len() - blake3::OUT_LEN);
+    if blake3::hash(content).as_bytes() != hash {
+        return Err(SaveError::Damaged)
+    }
+    let version = u32::from_le_bytes(content[SAVE_MAGIC.len()..header_len].try_into().unwrap());
+    if version > SAVE_FORMAT {
+        return Err(MigrationError::TooNew { found: version, current: SAVE_FORMAT }.into())
+    }
+    let mut json = Vec::new();
+    // one byte more than allowed tells whether the limit was hit
+    zstd::Decoder::new(&content[header_len..])?.take(MAX_SAVE_LEN + 1).read_to_end(&mut json)?;
+    if json.len() as u64 > MAX_SAVE_LEN {
+        return Err(SaveError::TooLarge)
+    }
+    parse_save(&json, version)
+}
+
+// Older formats are upgraded as JSON, before they have to fit `WorldSave`
+fn parse_save(json: &[u8], version: u32) -> Result<WorldSave, SaveError> {
+    let mut value: serde_json::Value = serde_json::from_slice(json)?;
+    migrate(&mut value, version, SAVE_FORMAT, SAVE_MIGRATIONS)?;
+    if version < SAVE_FORMAT {
+        info!(target: ASSETS, "Migrated a save from format version {} to {}", version, SAVE_FORMAT);
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+// Format 1 hashed the bytes of the base prototypes file, which can't be compared with the hash of
+// the merged prototypes, so those saves load like ones from before hashes were added
+fn drop_file_prototypes_hash(save: &mut serde_json::Value) -> Result<(), String> {
+    save.as_object_mut().ok_or("a save has to be an object")?.remove("prototypes_hash");
+    Ok(())
+}
+
+fn save_path(name: &str) -> Result<PathBuf, SaveError> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(SaveError::Name)
+    }
+    Ok(PathBuf::from(SAVES_DIR).join(format!("{}.{}", name, SAVE_EXTENSION)))
+}
+
+// Written next to the old save first, a crash while writing leaves the old one intact
+fn write_save(name: &str, save: &WorldSave) -> Result<(), SaveError> {
+    let path = save_path(name)?;
+    let temporary = path.with_extension("tmp");
+    fs::create_dir_all(SAVES_DIR)?;
+    fs::write(&temporary, encode_save(save)?)?;
+    fs::rename(&temporary, &path)?;
+    Ok(())
+}
+
+fn read_save(name: &str) -> Result<WorldSave, SaveError> {
+    decode_save(&fs::read(save_path(name)?)?)
+}
+
+fn modified(name: &str) -> Option<SystemTime> {
+    fs::metadata(save_path(name).ok()?).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Name of the most recently written save, autosaves included, what "Continue" and `--continue` load
+pub fn newest_save() -> Result<String, SaveError> {
+    let entries = match fs::read_dir(SAVES_DIR) {
+        Ok(entries) => entries,
+        Err(_) => return Err(SaveError::NoSaves)
+    };
+    entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != SAVE_EXTENSION {
+                return None
+            }
+            let name = path.file_stem()?.to_str()?.to_string();
+            Some((modified(&name)?, name))
+        })
+        .max()
+        .map(|(_, name)| name)
+        .ok_or(SaveError::NoSaves)
+}
+
+// The first free slot, or the one written longest ago
+fn autosave_slot(slots: u32) -> String {
+    (1..=slots.max(1))
+        .map(|slot| format!("{}{}", AUTOSAVE_PREFIX, slot))
+        .min_by_key(|name| modified(name))
+        .unwrap()
+}
+
+fn continue_game(continue_game: Option<Res<ContinueGame>>, mut load_events: EventWriter<LoadEvent>) {
+    if continue_game.is_some() {
+        load_events.send(LoadEvent { name: None });
+    }
+}
+
+fn autosave(sim_tick: Res<SimTick>, settings: Res<AutosaveSettings>, mut save_events: EventWriter<SaveEvent>) {
+    let interval = settings.interval_minutes as u64 * 60 * TICK_RATE;
+    if interval != 0 && sim_tick.0 != 0 && sim_tick.0.is_multiple_of(interval) {
+        save_events.send(SaveEvent { name: autosave_slot(settings.slots) });
+    }
+}
+
+type SavedUnitQuery = (&'static Transform, &'static Team, Option<&'static Health>, Option<&'static Energy>, Option<&'static Storage>);
+
+/// Everything written to a save
+#[derive(SystemParam)]
+pub(crate) struct WorldState<'w, 's> {
+    ids: Query<'w, 's, (Entity, &'static UnitId)>,
+    units: Query<'w, 's, SavedUnitQuery, With<Unit>>,
+    blueprints: BlueprintSources<'w, 's>,
+    prototypes: Res<'w, Prototypes>,
+    mod_list: Res<'w, ModList>,
+    sim_tick: Res<'w, SimTick>,
+    world_seed: Res<'w, WorldSeed>,
+    key_salt: Res<'w, KeySalt>,
+    next_id: Res<'w, NextUnitId>
+}
+
+impl WorldState<'_, '_> {
+    pub(crate) fn save(&self) -> WorldSave {
+        let units = units_in_order(&self.ids).into_iter().filter_map(|entity| {
+            let (_, id) = self.ids.get(entity).ok()?;
+            let (transform, team, health, energy, storage) = self.units.get(entity).ok()?;
+            let blueprint = Blueprint::of_unit(entity, &self.blueprints)?;
+            Some(SavedUnit {
+                id: id.0,
+                blueprint: blueprint.export(),
+                program_hash: Some(blake3::hash(blueprint.program.as_bytes()).to_hex().to_string()),
+                translation: transform.translation.to_array(),
+                rotation: transform.rotation.to_array(),
+                team: team.0,
+                health: health.map(|health| health.current),
+                energy: energy.map(|energy| energy.stored),
+                storage: storage.map(Storage::contents)
+            })
+        }).collect();
+        WorldSave {
+            tick: self.sim_tick.0,
+            seed: self.world_seed.0,
+            next_unit_id: self.next_id.0,
+            prototypes_hash: self.prototypes.hash.map(|hash| hash.to_hex().to_string()),
+            mods: Some(active_mods(&self.mod_list)),
+            key_salt: Some(self.key_salt.0),
+            units
+        }
+    }
+}
+
+fn save_world(mut save_events: EventReader<SaveEvent>, mut outcomes: EventWriter<SaveOutcome>, world: WorldState) {
+    for event in save_events.iter() {
+        let outcome = match write_save(&event.name, &world.save()) {
+            Ok(()) => {
+                info!(target: ASSETS, "Saved the world as {}", event.name);
+                SaveOutcome::Saved { name: event.name.clone() }
+            }
+            Err(error) => {
+                warn!(target: ASSETS, "Can't save {}: {}", event.name, error);
+                SaveOutcome::SaveFailed { name: event.name.clone(), error: error.to_string() }
+            }
+        };
+        outcomes.send(outcome);
+    }
+}
+
+/// Saved state applied to a loaded unit once the components it was spawned with are there
+#[derive(Component)]
+struct RestoredState {
+    health: Option<f32>,
+    energy: Option<f32>,
+    storage: Option<StorageContents>
+}
+
+/// What a save replaces besides the units, and what it's checked against
+#[derive(SystemParam)]
+struct LoadTarget<'w, 's> {
+    sim_tick: ResMut<'w, SimTick>,
+    world_seed: ResMut<'w, WorldSeed>,
+    key_salt: ResMut<'w, KeySalt>,
+    mod_list: Res<'w, ModList>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>
+}
+
+fn load_world(
+    mut load_events: EventReader<LoadEvent>,
+    mut outcomes: EventWriter<SaveOutcome>,
+    mut spawner: UnitSpawner,
+    mut spawn_queue: ResMut<UnitSpawnQueue>,
+    mut target: LoadTarget,
+    units: Query<Entity, With<Unit>>)
+{
+    for event in load_events.iter() {
+        let name = match event.name.clone().map_or_else(newest_save, Ok) {
+            Ok(name) => name,
+            Err(error) => {
+                warn!(target: ASSETS, "Can't load the newest save: {}", error);
+                outcomes.send(SaveOutcome::LoadFailed { error: error.to_string() });
+                continue
+            }
+        };
+        let save = match read_save(&name) {
+            Ok(save) => save,
+            Err(error) => {
+                warn!(target: ASSETS, "Can't load {}: {}", name, error);
+                outcomes.send(SaveOutcome::LoadFailed { error: format!("{}: {}", name, error) });
+                continue
+            }
+        };
+        // units of other prototypes or mods could be missing or play differently, so those saves are refused
+        if let Some(mismatch) = content_mismatch(&save, &spawner.prototypes, &target.mod_list) {
+            warn!(target: ASSETS, "Can't load {}: {}", name, mismatch);
+            outcomes.send(SaveOutcome::LoadFailed { error: format!("{}: {}", name, mismatch) });
+            continue
+        }
+        let mut mismatches = Vec::new();
+        spawn_queue.0.clear();
+        for entity in units.iter() {
+            spawner.commands.entity(entity).despawn_recursive();
+        }
+        let loaded = spawn_saved_units(save.units, &mut spawner, &format!("save {}", name), &mut mismatches).len();
+        *spawner.next_id = NextUnitId(save.next_unit_id);
+        target.sim_tick.0 = save.tick;
+        target.world_seed.0 = save.seed;
+        if let Some(salt) = save.key_salt {
+            target.key_salt.0 = salt;
+        }
+        info!(target: ASSETS, "Loaded {}, {} units", name, loaded);
+        for mismatch in &mismatches {
+            warn!(target: ASSETS, "Loaded {}, but {}", name, mismatch);
+        }
+        outcomes.send(SaveOutcome::Loaded { name, units: loaded, mismatches });
+    }
+}
+
+// Why a save can't be loaded into the running game, saves from before the hash or the mods were
+// recorded aren't checked for them
+fn content_mismatch(save: &WorldSave, prototypes: &Prototypes, mod_list: &ModList) -> Option<String> {
+    let mods = active_mods(mod_list);
+    let prototypes_hash = prototypes.hash.map(|hash| hash.to_hex().to_string());
+    match (&save.mods, &save.prototypes_hash) {
+        (Some(saved_mods), _) if *saved_mods != mods =>
+            Some(format!("the save was made with {}, the game runs with {}", describe_mods(saved_mods), describe_mods(&mods))),
+        (_, Some(_)) if save.prototypes_hash != prototypes_hash => Some("the prototypes changed since the save was made".to_string()),
+        _ => None
+    }
+}
+
+/// Spawns saved units with their id, transform and team, returning their ids and entities. The
+/// rest of their state is restored once they're spawned. Programs that don't match their hash are
+/// added to `mismatches`, `source` says where the units came from in warnings.
+pub(crate) fn spawn_saved_units(units: Vec<SavedUnit>, spawner: &mut UnitSpawner, source: &str, mismatches: &mut Vec<String>) -> Vec<(u64, Entity)> {
+    let mut spawned = Vec::new();
+    for unit in units {
+        let blueprint = match Blueprint::import(&unit.blueprint) {
+            Ok(blueprint) => blueprint,
+            Err(error) => {
+                warn!(target: ASSETS, "Unit {} in {}: {}", unit.id, source, error);
+                continue
+            }
+        };
+        if unit.program_hash.as_ref().map_or(false, |hash| *hash != blake3::hash(blueprint.program.as_bytes()).to_hex().as_str()) {
+            mismatches.push(format!("the program of unit {} doesn't match its hash, it was edited outside the game", unit.id));
+        }
+        let entity = match spawner.spawn(&blueprint, Vec2::new(unit.translation[0], unit.translation[1])) {
+            Some(entity) => entity,
+            None => {
+                warn!(target: ASSETS, "Unit {} in {}: unknown movement prototype {}", unit.id, source, blueprint.movement);
+                continue
+            }
+        };
+        // replaces what the unit was spawned with
+        spawner.commands.entity(entity)
+            .insert(UnitId(unit.id))
+            .insert(Transform::from_translation(Vec3::from(unit.translation)).with_rotation(Quat::from_array(unit.rotation)))
+            .insert(Team(unit.team))
+            .insert(RestoredState { health: unit.health, energy: unit.energy, storage: unit.storage });
+        spawned.push((unit.id, entity));
+    }
+    spawned
+}
+
+type RestoredUnit = (Entity, &'static UnitId, &'static mut RestoredState, Option<&'static mut Health>, Option<&'static mut Energy>, Option<&'static mut Storage>);
+
+fn apply_restored_state(mut commands: Commands, mut units: Query<RestoredUnit>) {
+    for (entity, id, mut restored, health, energy, storage) in units.iter_mut() {
+        if let (Some(mut health), Some(current)) = (health, restored.health) {
+            health.current = current;
+        }
+        if let (Some(mut energy), Some(stored)) = (energy, restored.energy) {
+            energy.stored = stored;
+        }
+        if let (Some(mut storage), Some(contents)) = (storage, restored.storage.take()) {
+            match storage.restore(contents) {
+                Ok(()) if !storage.verify() => warn!(target: ASSETS, "Black box of unit {} doesn't match its hash, it was edited outside the game", id.0),
+                Ok(()) => (),
+                Err(error) => warn!(target: ASSETS, "Can't restore the black box of unit {}: {}", id.0, error)
+            }
+        }
+        commands.entity(entity).remove::<RestoredState>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world_save() -> WorldSave {
+        WorldSave {
+            tick: 120,
+            seed: 7,
+            next_unit_id: 3,
+            prototypes_hash: Some("ab".repeat(32)),
+            mods: Some(vec!["walls 1.0.0".to_string()]),
+            key_salt: Some([5; 32]),
+            units: vec![SavedUnit {
+                id: 2,
+                blueprint: "blueprint".to_string(),
+                program_hash: None,
+                translation: [1.0, 2.0, 0.0],
+                rotation: [0.0, 0.0, 0.0, 1.0],
+                team: 1,
+                health: Some(40.0),
+                energy: None,
+                storage: None
+            }]
+        }
+    }
+
+    fn json(save: &WorldSave) -> serde_json::Value {
+        serde_json::to_value(save).unwrap()
+    }
+
+    // a save of this format version around any JSON, with a valid hash
+    fn framed(version: u32, json: impl Read) -> Vec<u8> {
+        let mut bytes = SAVE_MAGIC.to_vec();
+        bytes.extend_from_slice(&version.to_le_bytes());
+        zstd::stream::copy_encode(json, &mut bytes, zstd::DEFAULT_COMPRESSION_LEVEL).unwrap();
+        let hash = blake3::hash(&bytes);
+        bytes.extend_from_slice(hash.as_bytes());
+        bytes
+    }
+
+    #[test]
+    fn saves_decode_to_what_was_encoded() {
+        let save = world_save();
+        let decoded = decode_save(&encode_save(&save).unwrap()).unwrap();
+        assert_eq!(json(&decoded), json(&save));
+    }
+
+    #[test]
+    fn changed_bytes_are_damage() {
+        let bytes = encode_save(&world_save()).unwrap();
+        for index in [SAVE_MAGIC.len(), bytes.len() / 2, bytes.len() - 1] {
+            let mut damaged = bytes.clone();
+            damaged[index] ^= 1;
+            assert!(matches!(decode_save(&damaged), Err(SaveError::Damaged)), "byte {}", index);
+        }
+        assert!(matches!(decode_save(&bytes[..SAVE_MAGIC.len() + 4]), Err(SaveError::Damaged)));
+    }
+
+    #[test]
+    fn saves_inflating_past_the_limit_are_refused() {
+        let padding = std::io::repeat(b' ').take(MAX_SAVE_LEN + 1);
+        let bytes = framed(SAVE_FORMAT, padding);
+        assert!((bytes.len() as u64) < MAX_SAVE_LEN / 1000);
+        assert!(matches!(decode_save(&bytes), Err(SaveError::TooLarge)));
+        let save = serde_json::to_vec(&world_save()).unwrap();
+        assert!(decode_save(&framed(SAVE_FORMAT, save.as_slice())).is_ok());
+    }
+
+    #[test]
+    fn plain_json_saves_are_migrated_from_the_first_format() {
+        let mut old = json(&world_save());
+        old["prototypes_hash"] = serde_json::Value::String("hash of prototypes.json".to_string());
+        let decoded = decode_save(&serde_json::to_vec(&old).unwrap()).unwrap();
+        // the old hash can't be compared with the merged prototypes, everything else is kept
+        assert_eq!(decoded.prototypes_hash, None);
+        assert_eq!(json(&decoded), json(&WorldSave { prototypes_hash: None, ..world_save() }));
+    }
+
+    #[test]
+    fn saves_of_newer_formats_are_refused() {
+        let bytes = framed(SAVE_FORMAT + 1, b"{}".as_slice());
+        assert!(matches!(decode_save(&bytes), Err(SaveError::Version(MigrationError::TooNew { .. }))));
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+use mlua::prelude::*;
+use super::{Prototype, Prototypes, MovementStats, UnitId, SimTick, UnitSpawner};
+use crate::checksum::StateChecksum;
+use crate::blueprint::Blueprint;
+use crate::program::Exclusive;
+use crate::data_value::{DataValue, DataValueLimits};
+use crate::logging::SCRIPT;
+
+/// `scriplets --scenario <file.lua>` runs a scenario instead of the default setup and exits with
+/// whether it passed
+pub const SCENARIO_FLAG: &str = "--scenario";
+// scenarios still running after this many ticks fail, unless they set their own timeout
+const DEFAULT_TIMEOUT: u64 = 36000;
+
+enum Assertion {
+    Reaches { unit: u64, center: Vec2, radius: f32, by_tick: u64 },
+    Alive { unit: u64, until_tick: u64 },
+    // hex checksum from an earlier run that's known to be right
+    Checksum { tick: u64, expected: String }
+}
+
+/// Lua script setting up units and checking what they do. `setup(scenario)` is called once when
+/// the game starts, `on_tick(scenario)` every tick after. The scenario passes when it calls
+/// `scenario:pass()`, or when its last assertion held and it has no `on_tick`. It fails on the
+/// first failed assertion, on `scenario:fail(message)`, on errors and when it times out. Ticks are
+/// counted from the start of the scenario.
+pub struct Scenario {
+    path: String,
+    lua: Exclusive<Lua>,
+    start_tick: Option<u64>,
+    timeout: u64,
+    assertions: Vec<Assertion>
+}
+
+impl Scenario {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let source = std::fs::read(path).map_err(|error| format!("can't read {}: {}", path, error))?;
+        let lua = Lua::new();
+        lua.set_app_data(DataValueLimits::default());
+        lua.load(&source).exec().map_err(|error| format!("can't load {}: {}", path, error))?;
+        Ok(Self { path: path.to_string(), lua: Exclusive::new(lua), start_tick: None, timeout: DEFAULT_TIMEOUT, assertions: Vec::new() })
+    }
+}
+
+/// What scenario scripts get as `scenario`
+struct ScenarioHandle<'a> {
+    tick: u64,
+    checksum: String,
+    // units still alive by unit id
+    positions: &'a HashMap<u64, Vec2>,
+    prototypes: &'a Prototypes,
+    // unit id the next spawned unit will get
+    next_id: u64,
+    spawns: &'a mut Vec<(Blueprint, Vec2)>,
+    assertions: &'a mut Vec<Assertion>,
+    timeout: &'a mut u64,
+    failures: &'a mut Vec<String>,
+    passed: &'a mut bool
+}
+
+impl ScenarioHandle<'_> {
+    // units are spawned after the call, the id is the one they'll get
+    fn queue_spawn(&mut self, blueprint: Blueprint, position: Vec2) -> u64 {
+        let id = self.next_id + self.spawns.len() as u64;
+        self.spawns.push((blueprint, position));
+        id
+    }
+}
+
+impl LuaUserData for ScenarioHandle<'_> {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        // takes a blueprint as a table, like the ones `/spawn` pastes, and returns the unit id
+        methods.add_method_mut("spawn", |_lua, handle, (blueprint, x, y): (DataValue, f32, f32)| {
+            let blueprint = Blueprint::try_from(blueprint).map_err(|error| LuaError::RuntimeError(error.to_string()))?;
+            // units without a movement aren't spawned at all, which would throw off the ids
+            if MovementStats::from_pt(handle.prototypes, &blueprint.movement).is_none() {
+                return Err(LuaError::RuntimeError(format!("unknown movement prototype {}", blueprint.movement)))
+            }
+            Ok(handle.queue_spawn(blueprint, Vec2::new(x, y)))
+        });
+        // builds the unit from prototypes like `/spawn` does, see `Blueprint::from_prototypes`
+        methods.add_method_mut("spawn_unit", |_lua, handle, (prototype, x, y, program): (String, f32, f32, Option<String>)| {
+            let mut blueprint = Blueprint::from_prototypes(handle.prototypes, &prototype)
+                .ok_or_else(|| LuaError::RuntimeError(format!("unknown movement prototype {}", prototype)))?;
+            blueprint.program = program.unwrap_or_default();
+            Ok(handle.queue_spawn(blueprint, Vec2::new(x, y)))
+        });
+        methods.add_method("position", |_lua, handle, unit: u64| {
+            Ok(handle.positions.get(&unit).map(|position| <[f32; 2]>::from(*position)))
+        });
+        methods.add_method_mut("assert_unit_reaches", |_lua, handle, (unit, zone, by_tick): (u64, LuaTable, u64)| {
+            let center = Vec2::new(zone.get("x")?, zone.get("y")?);
+            let radius = zone.get::<_, Option<f32>>("radius")?.unwrap_or(0.5);
+            handle.assertions.push(Assertion::Reaches { unit, center, radius, by_tick });
+            Ok(())
+        });
+        // without `until_tick` the unit only has to be alive now
+        methods.add_method_mut("assert_alive", |_lua, handle, (unit, until_tick): (u64, Option<u64>)| {
+            match until_tick {
+                Some(until_tick) => handle.assertions.push(Assertion::Alive { unit, until_tick }),
+                // units spawned this tick are there from the next one on
+                None if !handle.positions.contains_key(&unit) && !(handle.next_id..handle.next_id + handle.spawns.len() as u64).contains(&unit) => handle.failures.push(format!("unit {} isn't alive at tick {}", unit, handle.tick)),
+                None => ()
+            }
+            Ok(())
+        });
+        // catches changes to the simulation that aren't supposed to change what happens
+        methods.add_method_mut("assert_checksum", |_lua, handle, (tick, expected): (u64, String)| {
+            handle.assertions.push(Assertion::Checksum { tick, expected: expected.to_lowercase() });
+            Ok(())
+        });
+        methods.add_method_mut("fail", |_lua, handle, message: String| {
+            handle.failures.push(message);
+            Ok(())
+        });
+        methods.add_method_mut("pass", |_lua, handle, ()| {
+            *handle.passed = true;
+            Ok(())
+        });
+        methods.add_method_mut("set_timeout", |_lua, handle, ticks: u64| {
+            *handle.timeout = ticks;
+            Ok(())
+        });
+    }
+
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("tick", |_lua, handle| Ok(handle.tick));
+        fields.add_field_method_get("checksum", |_lua, handle| Ok(handle.checksum.clone()));
+    }
+}
+
+// Exiting right away is the only way to get an exit code out, bevy's runner always ends the
+// process with success
+fn finish(scenario: &Scenario, tick: u64, failure: Option<String>) -> ! {
+    match failure {
+        None => {
+            info!(target: SCRIPT, "Scenario {} passed at tick {}", scenario.path, tick);
+            std::process::exit(0)
+        }
+        Some(failure) => {
+            error!(target: SCRIPT, "Scenario {} failed at tick {}: {}", scenario.path, tick, failure);
+            std::process::exit(1)
+        }
+    }
+}
+
+/// Runs at the end of every tick, so assertions see where units ended up
+pub fn run_scenario(
+    mut spawner: UnitSpawner,
+    scenario: Option<ResMut<Scenario>>,
+    sim_tick: Res<SimTick>,
+    checksum: Res<StateChecksum>,
+    units: Query<(&UnitId, &Transform)>)
+{
+    let mut scenario = match scenario {
+        Some(scenario) => scenario,
+        None => return
+    };
+    let scenario = &mut *scenario;
+    let function = if scenario.start_tick.is_some() { "on_tick" } else { "setup" };
+    let start_tick = *scenario.start_tick.get_or_insert(sim_tick.0);
+    let tick = sim_tick.0 - start_tick;
+    let positions: HashMap<u64, Vec2> = units.iter().map(|(id, transform)| (id.0, transform.translation.truncate())).collect();
+    let mut failures = Vec::new();
+    // assertions made this tick are first checked on the next one, once their units are spawned
+    scenario.assertions.retain(|assertion| match *assertion {
+        Assertion::Reaches { unit, center, radius, by_tick } => match positions.get(&unit) {
+            Some(position) if position.distance(center) <= radius => false,
+            Some(_) if tick >= by_tick => {
+                failures.push(format!("unit {} didn't reach {}, {} by tick {}", unit, center.x, center.y, by_tick));
+                false
+            }
+            Some(_) => true,
+            None => {
+                failures.push(format!("unit {} was destroyed before reaching {}, {}", unit, center.x, center.y));
+                false
+            }
+        },
+        Assertion::Alive { unit, until_tick } => {
+            if !positions.contains_key(&unit) {
+                failures.push(format!("unit {} was destroyed before tick {}", unit, until_tick));
+            }
+            positions.contains_key(&unit) && tick < until_tick
+        }
+        Assertion::Checksum { tick: at, ref expected } if at <= tick => {
+            match checksum.at(start_tick + at).map(|hash| hash.to_hex().to_string()) {
+                Some(actual) if actual == *expected => (),
+                Some(actual) => failures.push(format!("desync at tick {}: checksum is {}, expected {}", at, actual, expected)),
+                None => failures.push(format!("checksum of tick {} is too old to check", at))
+            }
+            false
+        }
+        Assertion::Checksum { .. } => true
+    });
+    let mut spawns = Vec::new();
+    let mut passed = false;
+    let lua = scenario.lua.get_mut();
+    let result = lua.globals().get::<_, Option<LuaFunction>>(function).and_then(|function| match function {
+        Some(function) => lua.scope(|s| {
+            let handle = ScenarioHandle {
+                tick,
+                checksum: checksum.current().to_hex().to_string(),
+                positions: &positions,
+                prototypes: &spawner.prototypes,
+                next_id: spawner.next_id(),
+                spawns: &mut spawns,
+                assertions: &mut scenario.assertions,
+                timeout: &mut scenario.timeout,
+                failures: &mut failures,
+                passed: &mut passed
+            };
+            function.call::<_, ()>(s.create_nonstatic_userdata(handle)?)
+        }),
+        None => Ok(())
+    });
+    if let Err(error) = result {
+        failures.push(error.to_string());
+    }
+    for (blueprint, position) in spawns {
+        spawner.spawn(&blueprint, position);
+    }
+    let has_on_tick = matches!(lua.globals().get::<_, Option<LuaFunction>>("on_tick"), Ok(Some(_)));
+    if let Some(failure) = failures.into_iter().next() {
+        finish(scenario, tick, Some(failure));
+    }
+    if passed || (scenario.assertions.is_empty() && !has_on_tick) {
+        finish(scenario, tick, None);
+    }
+    if tick >= scenario.timeout {
+        finish(scenario, tick, Some(format!("timed out after {} ticks", scenario.timeout)));
+    }
+}
@@ -0,0 +1,699 @@
+use std::{collections::{HashMap, HashSet}, fs, io, net::{TcpListener, UdpSocket, Ipv4Addr}};
+use bevy::{prelude::*, ecs::system::SystemParam, time::FixedTimestep};
+use serde::Deserialize;
+use super::{Unit, UnitId, SimTick, UnitSpawner, SimStepLabel, ClockLabel, Paused, TICK_RATE, default_blueprint};
+use crate::net::{ClientConnection, ClientMessage, ServerMessage, Hello, GameContent, Announcement, TcpTransport, Connection, PROTOCOL_VERSION, DISCOVERY_PORT, default_name};
+use crate::admin::{AdminEvent, AdminOutcome, AdminResult, ChatEvent};
+#[cfg(not(feature = "headless"))]
+use crate::admin::ConsoleMessage;
+#[cfg(any(test, not(feature = "headless")))]
+use crate::net::MemoryTransport;
+use crate::replication::{WorldView, ReplicatedUnit, ReplicatedObject, ObjectKind, QuantizedTransform};
+use crate::blueprint::{Blueprint, BlueprintSources};
+use crate::manual_control::{ManualControl, ManualIntent, ManualIntents};
+use crate::checksum::ChecksumLabel;
+use crate::loading::{AppState, ModList};
+use crate::save::{WorldState, encode_save};
+use crate::registry::{UnitLabel, UnitView, UnitDirectory};
+use crate::weather::Weather;
+use crate::creature::Creature;
+use crate::construction::{Structure, ConstructionSite};
+use crate::{inventory::GroundItem, logistics::Drone};
+use crate::{team::Team, health::Health, energy::Energy, logging::NET};
+
+/// `scriplets --host <port>` lets other players join the game on that port
+pub const HOST_FLAG: &str = "--host";
+/// `scriplets --server <port>` runs a dedicated server without a window, see `server_app`
+pub const SERVER_FLAG: &str = "--server";
+/// `scriplets --local-server` plays alone on a server in a background thread, like a player joining
+/// a dedicated one
+#[cfg(any(test, not(feature = "headless")))]
+pub const LOCAL_SERVER_FLAG: &str = "--local-server";
+// full snapshots go out this often, deltas of what changed every tick in between
+const SNAPSHOT_INTERVAL: u64 = 10 * TICK_RATE;
+// tiles between the starting units of players
+const START_SPACING: f32 = 3.0;
+// joining players are sent the world in parts of this many bytes
+const WORLD_PART_LEN: usize = 64 * 1024;
+// seconds between announcements to the local network
+const ANNOUNCE_INTERVAL: f64 = 1.0;
+const CONFIG_PATH: &str = "server.toml";
+
+/// Hosts a multiplayer game when there's a `Server`. Every player gets a team of their own with a
+/// unit to start with, observers get neither. Joining players are sent the world as a save, then
+/// what changed every tick, both only with the units they see. The host plays on the world itself
+/// and announces the game to the local network. Chat goes to every player, admin commands are only
+/// taken from the host and the admins in `server.toml`.
+pub struct ServerPlugin;
+
+impl Plugin for ServerPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            // players joining while loading wait, there's nothing to spawn their unit from yet
+            .add_system_set(SystemSet::on_update(AppState::Playing)
+                .before(ClockLabel)
+                .with_system(accept_players)
+                .with_system(receive_player_messages.after(accept_players))
+                .with_system(send_console_output))
+            .add_system(send_updates.after(ChecksumLabel).with_run_criteria(SimStepLabel))
+            .add_system_to_stage(CoreStage::PostUpdate, track_removals)
+            .add_system(announce.with_run_criteria(FixedTimestep::step(ANNOUNCE_INTERVAL)));
+    }
+}
+
+struct Player {
+    connection: ClientConnection,
+    // `None` until the player said hello
+    name: Option<String>,
+    // `None` for observers
+    team: Option<Team>,
+    // what the player was sent last, the next delta is made against it. `None` until they were
+    // sent the world
+    view: Option<WorldView>,
+    // units the player drives, they run their programs again when the player leaves
+    driving: HashSet<Entity>,
+    admin: bool,
+    // dropped along with players that left
+    kicked: bool
+}
+
+impl Player {
+    fn new(connection: ClientConnection, admin: bool) -> Self {
+        Self { connection, name: None, team: None, view: None, driving: HashSet::new(), admin, kicked: false }
+    }
+}
+
+/// What's kept in `server.toml`, read when the server starts
+#[derive(Deserialize, Default)]
+struct ServerConfig {
+    // players allowed to give admin commands, by name with their password
+    #[serde(default)]
+    admins: HashMap<String, String>
+}
+
+impl ServerConfig {
+    fn load() -> Self {
+        let text = match fs::read_to_string(CONFIG_PATH) {
+            Ok(text) => text,
+            Err(_) => return Self::default()
+        };
+        toml::from_str(&text).unwrap_or_else(|error| {
+            warn!(target: NET, "Can't read {}, nobody but the host is an admin: {}", CONFIG_PATH, error);
+            Self::default()
+        })
+    }
+}
+
+/// Players connected to the game, inserted by `--host`, `--server` and `--local-server`
+pub struct Server {
+    // `None` on local servers, their one player is connected from the start
+    listener: Option<TcpListener>,
+    // broadcasts announcements, `None` if the network doesn't allow it
+    discovery: Option<UdpSocket>,
+    players: Vec<Player>,
+    // players joining again get their team back instead of a new one with another unit
+    teams: HashMap<String, Team>,
+    // `Blueprint::export` of every unit sent so far, with and without the program
+    blueprints: HashMap<UnitId, (String, String)>,
+    // the whole world as of the last update, only what changed since is looked at again
+    view: WorldView,
+    // ids of the units in `view`, despawned units are only known by their entity
+    unit_ids: HashMap<Entity, u64>,
+    // units and objects despawned since the last update, see `track_removals`
+    removed: Vec<Entity>,
+    config: ServerConfig,
+    // who the host chats as
+    host_name: String
+}
+
+impl Server {
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        listener.set_nonblocking(true)?;
+        info!(target: NET, "Hosting on port {}", port);
+        let discovery = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+            .and_then(|socket| socket.set_broadcast(true).and(socket.set_nonblocking(true)).map(|_| socket))
+            .map_err(|error| warn!(target: NET, "Can't announce the game to the local network: {}", error))
+            .ok();
+        Ok(Self::new(Some(listener), discovery, ServerConfig::load()))
+    }
+
+    /// Server only players in the same process can join, through `connect_local`
+    #[cfg(any(test, not(feature = "headless")))]
+    pub fn local() -> Self {
+        Self::new(None, None, ServerConfig::default())
+    }
+
+    fn new(listener: Option<TcpListener>, discovery: Option<UdpSocket>, config: ServerConfig) -> Self {
+        Self {
+            listener,
+            discovery,
+            players: Vec::new(),
+            teams: HashMap::new(),
+            blueprints: HashMap::new(),
+            view: WorldView::default(),
+            unit_ids: HashMap::new(),
+            removed: Vec::new(),
+            config,
+            host_name: default_name()
+        }
+    }
+
+    /// Connects a player in the same process, returning their end of the connection. It's their
+    /// game, so they're an admin.
+    #[cfg(any(test, not(feature = "headless")))]
+    pub fn connect_local(&mut self) -> MemoryTransport {
+        let (server, client) = MemoryTransport::pair();
+        self.players.push(Player::new(Connection::new(server), true));
+        client
+    }
+
+    /// Disconnects the player of that name, whether there was one
+    pub fn kick(&mut self, name: &str, by: &str) -> bool {
+        match self.players.iter_mut().find(|player| player.name.as_deref() == Some(name)) {
+            Some(player) => {
+                info!(target: NET, "{} kicked {}", by, name);
+                player.connection.send(&ServerMessage::Kicked { by: by.to_string() });
+                player.kicked = true;
+                true
+            }
+            None => false
+        }
+    }
+
+    // chat and notices only go to players that joined
+    fn broadcast(&mut self, message: &ServerMessage, except: Option<usize>) {
+        for (_, player) in self.players.iter_mut().enumerate().filter(|(index, player)| player.name.is_some() && Some(*index) != except) {
+            player.connection.send(message);
+        }
+    }
+
+    // the host plays as team 0
+    fn next_team(&self) -> Team {
+        Team(self.teams.values().map(|team| team.0).max().unwrap_or(0) + 1)
+    }
+}
+
+fn accept_players(server: Option<ResMut<Server>>) {
+    let mut server = match server {
+        Some(server) => server,
+        None => return
+    };
+    let server = &mut *server;
+    let listener = match &server.listener {
+        Some(listener) => listener,
+        None => return
+    };
+    loop {
+        match listener.accept() {
+            Ok((stream, address)) => match TcpTransport::new(stream) {
+                Ok(transport) => {
+                    info!(target: NET, "{} connected", address);
+                    server.players.push(Player::new(Connection::new(transport), false));
+                }
+                Err(error) => warn!(target: NET, "Can't set up the connection of {}: {}", address, error)
+            },
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+            Err(error) => {
+                warn!(target: NET, "Can't accept players: {}", error);
+                break
+            }
+        }
+    }
+}
+
+/// Whether the player can join as they asked, the team they play as and whether it's new. Admins'
+/// names are taken only with their password, and players have to play with the server's `content`.
+fn hello(server: &mut Server, player: usize, hello: &Hello, content: &GameContent) -> Result<(Option<Team>, bool), String> {
+    let Hello { protocol, name, observer, password, .. } = hello;
+    if *protocol != PROTOCOL_VERSION {
+        return Err(format!("the server speaks protocol version {}, you {}", PROTOCOL_VERSION, protocol))
+    }
+    if let Some(mismatch) = content.mismatch(&hello.content) {
+        return Err(mismatch)
+    }
+    if name.trim().is_empty() {
+        return Err("players need a name".to_string())
+    }
+    if server.players.iter().enumerate().any(|(index, other)| index != player && other.name.as_ref() == Some(name)) {
+        return Err(format!("{} is already playing", name))
+    }
+    if server.config.admins.get(name).is_some_and(|expected| password.as_ref() != Some(expected)) {
+        return Err(format!("{} is an admin, joining as them takes their password", name))
+    }
+    if *observer {
+        return Ok((None, false))
+    }
+    match server.teams.get(name) {
+        Some(team) => Ok((Some(*team), false)),
+        None => {
+            let team = server.next_team();
+            server.teams.insert(name.clone(), team);
+            Ok((Some(team), true))
+        }
+    }
+}
+
+/// Where players' messages go besides the server and their units
+#[derive(SystemParam)]
+struct PlayerRequests<'w, 's> {
+    intents: ResMut<'w, ManualIntents>,
+    admin_events: EventWriter<'w, 's, AdminEvent>,
+    #[cfg(not(feature = "headless"))]
+    console: EventWriter<'w, 's, ConsoleMessage>
+}
+
+fn receive_player_messages(
+    server: Option<ResMut<Server>>,
+    mut spawner: UnitSpawner,
+    mut requests: PlayerRequests,
+    sim_tick: Res<SimTick>,
+    mod_list: Res<ModList>,
+    units: Query<(Entity, &UnitId, &Team), With<Unit>>)
+{
+    let mut server = match server {
+        Some(server) => server,
+        None => return
+    };
+    let content = GameContent::of(&spawner.prototypes, &mod_list);
+    // a player's unit by its id, others' units can't be driven and observers have none
+    let find = |id: u64, team: Option<Team>| units.iter().find(|(_, unit_id, unit_team)| unit_id.0 == id && Some(**unit_team) == team).map(|(entity, ..)| entity);
+    for index in 0..server.players.len() {
+        while let Some(message) = server.players[index].connection.receive() {
+            let player = &mut server.players[index];
+            match message {
+                Ok(ClientMessage::Hello(joining)) if player.name.is_none() => match hello(&mut server, index, &joining, &content) {
+                    Ok((team, new)) => {
+                        let name = joining.name;
+                        match team {
+                            Some(team) => info!(target: NET, "{} joined as team {}", name, team.0),
+                            None => info!(target: NET, "{} is observing", name)
+                        }
+                        if let Some(team) = team.filter(|_| new) {
+                            let position = Vec2::new(team.0 as f32 * START_SPACING, -START_SPACING);
+                            if let Some(unit) = spawner.spawn(&default_blueprint(), position) {
+                                spawner.commands.entity(unit).insert(team);
+                            }
+                        }
+                        let admin = server.config.admins.contains_key(&name);
+                        let player = &mut server.players[index];
+                        player.admin |= admin;
+                        player.name = Some(name);
+                        player.team = team;
+                        player.connection.send(&ServerMessage::Welcome { team: team.map(|team| team.0) });
+                    }
+                    Err(reason) => {
+                        info!(target: NET, "Refused {}: {}", joining.name, reason);
+                        server.players[index].connection.send(&ServerMessage::Refused { reason });
+                    }
+                },
+                Ok(ClientMessage::Drive { unit, tick, input_move, input_rotation, hand_brake }) if player.name.is_some() => {
+                    if let Some(entity) = find(unit, player.team) {
+                        if player.driving.insert(entity) {
+                            spawner.commands.entity(entity).insert(ManualControl);
+                        }
+                        // clients only know ticks the server sent, later ones would wait in the buffer forever
+                        requests.intents.0.push(ManualIntent { tick: tick.min(sim_tick.0), unit: entity, input_move: Vec2::from(input_move), input_rotation, hand_brake });
+                    }
+                }
+                Ok(ClientMessage::Release { unit }) if player.name.is_some() => {
+                    if let Some(entity) = find(unit, player.team).filter(|entity| player.driving.remove(entity)) {
+                        spawner.commands.entity(entity).remove::<ManualControl>();
+                    }
+                }
+                Ok(ClientMessage::Chat { text }) if player.name.is_some() => {
+                    let from = player.name.clone().unwrap_or_default();
+                    server.broadcast(&ServerMessage::Chat { from: from.clone(), text: text.clone() }, Some(index));
+                    #[cfg(not(feature = "headless"))]
+                    requests.console.send(ConsoleMessage::Chat { from, text });
+                }
+                Ok(ClientMessage::Admin(command)) if player.name.is_some() => {
+                    let name = player.name.clone().unwrap_or_default();
+                    if player.admin {
+                        info!(target: NET, "{} gave the admin command {:?}", name, command);
+                        requests.admin_events.send(AdminEvent { command, by: Some(name) });
+                    } else {
+                        info!(target: NET, "Refused the admin command {:?} of {}, they aren't an admin", command, name);
+                        player.connection.send(&ServerMessage::Notice { text: "only admins can do that".to_string() });
+                    }
+                }
+                Ok(_) => warn!(target: NET, "Unexpected message from {}", player.name.as_deref().unwrap_or("a new player")),
+                Err(error) => warn!(target: NET, "Can't read a message from {}: {}", player.name.as_deref().unwrap_or("a new player"), error)
+            }
+        }
+    }
+    server.players.retain(|player| {
+        if !player.connection.is_closed() && !player.kicked {
+            return true
+        }
+        info!(target: NET, "{} left", player.name.as_deref().unwrap_or("A player that didn't join"));
+        for entity in player.driving.iter().filter(|entity| units.contains(**entity)) {
+            spawner.commands.entity(*entity).remove::<ManualControl>();
+        }
+        false
+    });
+}
+
+type ReplicatedQuery = (&'static UnitId, &'static Transform, &'static Team, Option<&'static Health>, Option<&'static Energy>, &'static UnitLabel);
+type ObjectQuery = (Option<&'static Creature>, Option<&'static Structure>, Option<&'static ConstructionSite>, Option<&'static GroundItem>, Option<&'static Drone>);
+type ObjectFilter = (Or<(With<Creature>, With<Structure>, With<ConstructionSite>, With<GroundItem>, With<Drone>)>, Without<Unit>);
+type ChangedUnits = (With<Unit>, Or<(Changed<Transform>, Changed<Health>, Changed<Energy>, Changed<UnitLabel>, Changed<Team>)>);
+// the kind of an object never changes, only how many items lie in a pile
+type ChangedObjects = (ObjectFilter, Or<(Changed<Transform>, Changed<GroundItem>)>);
+
+/// What changed of what's replicated and what decides who sees it
+#[derive(SystemParam)]
+struct ReplicatedWorld<'w, 's> {
+    units: Query<'w, 's, (Entity, ReplicatedQuery), ChangedUnits>,
+    objects: Query<'w, 's, (Entity, &'static Transform, ObjectQuery), ChangedObjects>,
+    blueprint_sources: BlueprintSources<'w, 's>,
+    directory: Res<'w, UnitDirectory>,
+    weather: Res<'w, Weather>
+}
+
+// Removals are only reported in the frame they happened, sim steps can skip frames
+fn track_removals(
+    server: Option<ResMut<Server>>,
+    units: RemovedComponents<Unit>,
+    creatures: RemovedComponents<Creature>,
+    structures: RemovedComponents<Structure>,
+    sites: RemovedComponents<ConstructionSite>,
+    items: RemovedComponents<GroundItem>,
+    drones: RemovedComponents<Drone>)
+{
+    if let Some(mut server) = server {
+        server.removed.extend(units.iter().chain(creatures.iter()).chain(structures.iter()).chain(sites.iter()).chain(items.iter()).chain(drones.iter()));
+    }
+}
+
+// Players are sent what their units see, like their programs do, and the objects within their
+// sight range, observers everything. The world is only looked at where it changed since the last
+// update, each player's view is then filtered from it. Blueprints are exported once per unit,
+// players of other teams and observers get them without the program.
+fn send_updates(
+    server: Option<ResMut<Server>>,
+    sim_tick: Res<SimTick>,
+    replicated: ReplicatedWorld,
+    world: WorldState)
+{
+    let mut server = match server {
+        Some(server) => server,
+        None => return
+    };
+    let server = &mut *server;
+    for entity in server.removed.drain(..) {
+        if let Some(id) = server.unit_ids.remove(&entity) {
+            server.view.units.remove(&id);
+            server.blueprints.remove(&UnitId(id));
+        }
+        server.view.objects.remove(&entity.to_bits());
+    }
+    let view = &mut server.view;
+    view.tick = sim_tick.0;
+    for (entity, (id, transform, team, health, energy, label)) in replicated.units.iter() {
+        if !server.blueprints.contains_key(id) {
+            // tried again the next time the unit changes
+            let blueprint = match Blueprint::of_unit(entity, &replicated.blueprint_sources) {
+                Some(blueprint) => blueprint,
+                None => continue
+            };
+            let without_program = Blueprint { program: String::new(), ..blueprint.clone() };
+            server.blueprints.insert(*id, (blueprint.export(), without_program.export()));
+        }
+        server.unit_ids.insert(entity, id.0);
+        view.units.insert(id.0, ReplicatedUnit {
+            transform: QuantizedTransform::of(transform),
+            team: team.0,
+            health: health.map(|health| health.current),
+            energy: energy.map(|energy| energy.stored),
+            label: label.0.clone()
+        });
+    }
+    for (entity, transform, object) in replicated.objects.iter() {
+        let kind = match object {
+            (Some(creature), ..) => ObjectKind::Creature(creature.prototype().to_string()),
+            (_, Some(structure), ..) => ObjectKind::Structure(structure.prototype.clone()),
+            (_, _, Some(site), ..) => ObjectKind::ConstructionSite(site.structure().to_string()),
+            (_, _, _, Some(item), _) => ObjectKind::GroundItem { item: item.item.clone(), count: item.count },
+            _ => ObjectKind::Drone
+        };
+        // entities aren't saved, their ids only have to last while the server runs
+        view.objects.insert(entity.to_bits(), ReplicatedObject { kind, transform: QuantizedTransform::of(transform) });
+    }
+    let view = &server.view;
+    let blueprints = &server.blueprints;
+    let mut save = None;
+    for player in server.players.iter_mut().filter(|player| player.name.is_some()) {
+        let sight: Vec<UnitView> = match player.team {
+            Some(team) => view.units.values()
+                .filter(|unit| unit.team == team.0)
+                .map(|unit| UnitView::new(&replicated.directory, Some(team), unit.transform.position(), replicated.weather.visibility()))
+                .collect(),
+            None => vec![UnitView::observer(&replicated.directory)]
+        };
+        let sees = |id: u64| sight.iter().any(|unit_view| unit_view.visible(UnitId(id)).is_some());
+        let sees_object = |object: &ReplicatedObject| sight.iter().any(|unit_view| unit_view.sees(object.transform.position()));
+        if player.view.is_none() {
+            // through the save pipeline, deltas go on from the units in the save
+            let redacted = save.get_or_insert_with(|| world.save()).redacted(player.team, sees);
+            match encode_save(&redacted) {
+                Ok(bytes) => {
+                    for part in bytes.chunks(WORLD_PART_LEN) {
+                        player.connection.send(&ServerMessage::World { len: bytes.len() as u64, part: part.to_vec() });
+                    }
+                    player.view = Some(redacted.view());
+                }
+                Err(error) => warn!(target: NET, "Can't send the world to {}: {}", player.name.as_deref().unwrap_or_default(), error)
+            }
+            continue
+        }
+        let blueprint = |id: u64| {
+            let (with_program, without_program) = &blueprints[&UnitId(id)];
+            if Some(Team(view.units[&id].team)) == player.team { with_program.clone() } else { without_program.clone() }
+        };
+        let seen = WorldView {
+            tick: view.tick,
+            units: view.units.iter().filter(|(id, _)| sees(**id)).map(|(id, unit)| (*id, unit.clone())).collect(),
+            objects: view.objects.iter().filter(|(_, object)| sees_object(object)).map(|(id, object)| (*id, object.clone())).collect()
+        };
+        let update = match &player.view {
+            Some(previous) if !sim_tick.0.is_multiple_of(SNAPSHOT_INTERVAL) => previous.delta(&seen, blueprint),
+            _ => seen.snapshot(blueprint)
+        };
+        player.connection.send(&ServerMessage::Update(update));
+        player.view = Some(seen);
+    }
+}
+
+// The host's chat, what came of admin commands given by players and pausing
+fn send_console_output(
+    server: Option<ResMut<Server>>,
+    mut chat: EventReader<ChatEvent>,
+    mut outcomes: EventReader<AdminOutcome>,
+    paused: Res<Paused>)
+{
+    let mut server = match server {
+        Some(server) => server,
+        None => return
+    };
+    for ChatEvent { text } in chat.iter() {
+        let from = server.host_name.clone();
+        server.broadcast(&ServerMessage::Chat { from, text: text.clone() }, None);
+    }
+    for AdminOutcome { by, result } in outcomes.iter() {
+        // everyone's told about pausing below
+        if matches!(result, AdminResult::Paused(_)) {
+            continue
+        }
+        if let Some(player) = by.as_ref().and_then(|by| server.players.iter_mut().find(|player| player.name.as_ref() == Some(by))) {
+            player.connection.send(&ServerMessage::Notice { text: result.describe() });
+        }
+    }
+    if paused.is_changed() && !paused.is_added() {
+        server.broadcast(&ServerMessage::Notice { text: AdminResult::Paused(paused.0).describe() }, None);
+    }
+}
+
+fn announce(server: Option<Res<Server>>) {
+    let server = match server {
+        Some(server) => server,
+        None => return
+    };
+    let (socket, port) = match (&server.discovery, server.listener.as_ref().map(TcpListener::local_addr)) {
+        (Some(socket), Some(Ok(address))) => (socket, address.port()),
+        _ => return
+    };
+    let players = server.players.iter().filter(|player| player.name.is_some()).count() as u32;
+    let announcement = rmp_serde::to_vec(&Announcement { protocol: PROTOCOL_VERSION, port, players }).unwrap();
+    // fails without a network, nobody's there to find the game then
+    if let Err(error) = socket.send_to(&announcement, (Ipv4Addr::BROADCAST, DISCOVERY_PORT)) {
+        debug!(target: NET, "Can't announce the game: {}", error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use super::*;
+    use crate::net::ServerConnection;
+    use crate::save::decode_save;
+    use crate::replication::Update;
+    use crate::admin::AdminCommand;
+    use crate::Prototypes;
+
+    // what a player with the server's prototypes and mods says, those are there once loading finished
+    fn greeting(app: &mut App, name: &str, observer: bool) -> ClientMessage {
+        let start = Instant::now();
+        while *app.world.resource::<State<AppState>>().current() == AppState::Loading {
+            assert!(start.elapsed() < Duration::from_secs(30), "loading didn't finish");
+            app.update();
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        let content = GameContent::of(app.world.resource::<Prototypes>(), app.world.resource::<ModList>());
+        ClientMessage::Hello(Hello { protocol: PROTOCOL_VERSION, name: name.to_string(), observer, password: None, content })
+    }
+
+    // the world, updates until there are `count` and the team of a player that just said hello
+    fn join(app: &mut App, client: &mut ServerConnection, count: usize) -> (Vec<u8>, Vec<Update>, Option<u32>) {
+        let (mut world, mut updates, mut welcome) = (Vec::new(), Vec::new(), None);
+        let start = Instant::now();
+        // loading and the steps take real time
+        while updates.len() < count {
+            assert!(start.elapsed() < Duration::from_secs(30), "no updates arrived");
+            app.update();
+            while let Some(message) = client.receive() {
+                match message.unwrap() {
+                    ServerMessage::Welcome { team } => welcome = Some(team),
+                    ServerMessage::Refused { reason } => panic!("refused: {}", reason),
+                    ServerMessage::World { part, .. } => {
+                        assert!(updates.is_empty(), "the world arrived after an update");
+                        world.extend(part);
+                    }
+                    ServerMessage::Update(update) => updates.push(update),
+                    ServerMessage::Chat { .. } | ServerMessage::Notice { .. } => (),
+                    ServerMessage::Kicked { by } => panic!("kicked by {}", by)
+                }
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        (world, updates, welcome.expect("no welcome arrived"))
+    }
+
+    #[test]
+    fn joining_players_get_the_world_then_deltas() {
+        let mut server = Server::local();
+        let mut client: ServerConnection = Connection::new(server.connect_local());
+        let mut app = crate::server_app(server, 0);
+        client.send(&greeting(&mut app, "ada", false));
+        let (world, updates, team) = join(&mut app, &mut client, 3);
+        assert_eq!(team, Some(1));
+        let mut view = decode_save(&world).unwrap().view();
+        for update in &updates {
+            assert!(!update.snapshot);
+            view.apply(update);
+        }
+        // spawned after the world was sent, a delta brings it
+        assert!(view.units.values().any(|unit| unit.team == 1));
+    }
+
+    #[test]
+    fn despawned_units_leave_the_view() {
+        let mut server = Server::local();
+        let mut client: ServerConnection = Connection::new(server.connect_local());
+        let mut app = crate::server_app(server, 0);
+        client.send(&greeting(&mut app, "ada", false));
+        let (world, updates, _) = join(&mut app, &mut client, 3);
+        let mut view = decode_save(&world).unwrap().view();
+        updates.iter().for_each(|update| view.apply(update));
+        let (entity, id) = app.world.query::<(Entity, &UnitId)>().iter(&app.world).find(|(_, id)| view.units.contains_key(&id.0)).unwrap();
+        let id = id.0;
+        app.world.despawn(entity);
+        let start = Instant::now();
+        loop {
+            assert!(start.elapsed() < Duration::from_secs(30), "no update arrived");
+            app.update();
+            if let Some(Ok(ServerMessage::Update(update))) = client.receive() {
+                assert!(update.despawned.contains(&id));
+                break
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn observers_see_units_of_every_team() {
+        let mut server = Server::local();
+        let mut player: ServerConnection = Connection::new(server.connect_local());
+        let mut observer: ServerConnection = Connection::new(server.connect_local());
+        let mut app = crate::server_app(server, 0);
+        player.send(&greeting(&mut app, "ada", false));
+        join(&mut app, &mut player, 1);
+        observer.send(&greeting(&mut app, "bob", true));
+        let (world, updates, team) = join(&mut app, &mut observer, 1);
+        assert_eq!(team, None);
+        let mut view = decode_save(&world).unwrap().view();
+        updates.iter().for_each(|update| view.apply(update));
+        assert!(view.units.values().any(|unit| unit.team == 1));
+    }
+
+    // the next notice, other messages are skipped
+    fn notice(app: &mut App, client: &mut ServerConnection) -> String {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(30) {
+            app.update();
+            while let Some(message) = client.receive() {
+                if let ServerMessage::Notice { text } = message.unwrap() {
+                    return text
+                }
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        panic!("no notice arrived")
+    }
+
+    #[test]
+    fn only_admins_can_pause() {
+        let mut server = Server::local();
+        let mut admin: ServerConnection = Connection::new(server.connect_local());
+        let (transport, other) = MemoryTransport::pair();
+        server.players.push(Player::new(Connection::new(transport), false));
+        let mut other: ServerConnection = Connection::new(other);
+        let mut app = crate::server_app(server, 0);
+        admin.send(&greeting(&mut app, "ada", false));
+        other.send(&greeting(&mut app, "bob", false));
+        join(&mut app, &mut admin, 1);
+        join(&mut app, &mut other, 1);
+        other.send(&ClientMessage::Admin(AdminCommand::Pause));
+        assert_eq!(notice(&mut app, &mut other), "only admins can do that");
+        assert!(!app.world.resource::<Paused>().0);
+        admin.send(&ClientMessage::Admin(AdminCommand::Pause));
+        assert_eq!(notice(&mut app, &mut other), "the game is paused");
+        assert!(app.world.resource::<Paused>().0);
+    }
+
+    #[test]
+    fn players_with_other_content_are_refused() {
+        let mut server = Server::local();
+        let mut client: ServerConnection = Connection::new(server.connect_local());
+        let mut app = crate::server_app(server, 0);
+        let mut hello = match greeting(&mut app, "ada", false) {
+            ClientMessage::Hello(hello) => hello,
+            _ => unreachable!()
+        };
+        hello.content.mods.push("fast 1.0.0".to_string());
+        client.send(&ClientMessage::Hello(hello));
+        let start = Instant::now();
+        loop {
+            assert!(start.elapsed() < Duration::from_secs(30), "no answer arrived");
+            app.update();
+            match client.receive().map(Result::unwrap) {
+                Some(ServerMessage::Refused { reason }) => {
+                    assert_eq!(reason, "the server runs with no mods, you with fast 1.0.0");
+                    break
+                }
+                Some(ServerMessage::Welcome { .. }) => panic!("joined with other mods"),
+                _ => std::thread::sleep(Duration::from_millis(5))
+            }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+use bevy::prelude::*;
+#[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+use bevy::app::AppExit;
+#[cfg(not(feature = "headless"))]
+use bevy_egui::{EguiContext, egui};
+#[cfg(not(target_arch = "wasm32"))]
+use serde::{Serialize, Deserialize};
+#[cfg(not(feature = "headless"))]
+use crate::locale::Localize;
+#[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+use crate::loading::ModList;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::logging::ASSETS;
+
+#[cfg(not(feature = "headless"))]
+const TOGGLE_KEY: KeyCode = KeyCode::F3;
+#[cfg(not(target_arch = "wasm32"))]
+const SETTINGS_PATH: &str = "settings.toml";
+
+/// Settings window, opened with F3. Changes are saved into `settings.toml` right away, the browser
+/// build has no file system and starts with the defaults every time.
+#[cfg(not(feature = "headless"))]
+#[derive(Default)]
+pub struct SettingsWindow {
+    open: bool
+}
+
+/// Language from `settings.toml`, selected once the locales are loaded
+#[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+pub struct LanguageSetting(pub Option<String>);
+
+/// How often the world is saved on its own and into how many rotating slots, see `save::autosave`
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct AutosaveSettings {
+    pub interval_minutes: u32, // 0 turns autosaves off
+    pub slots: u32
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for AutosaveSettings {
+    fn default() -> Self {
+        Self {
+            interval_minutes: 5,
+            slots: 3
+        }
+    }
+}
+
+/// What's kept in `settings.toml`. It's read once at startup, before the mods are opened.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Serialize, Deserialize, Default)]
+pub struct SettingsFile {
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub autosave: AutosaveSettings,
+    #[serde(default)]
+    pub mods: ModSettings
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl SettingsFile {
+    pub fn load() -> Self {
+        let text = match fs::read_to_string(SETTINGS_PATH) {
+            Ok(text) => text,
+            Err(_) => return Self::default()
+        };
+        toml::from_str(&text).unwrap_or_else(|error| {
+            warn!(target: ASSETS, "Can't read {}, using the default settings: {}", SETTINGS_PATH, error);
+            Self::default()
+        })
+    }
+
+    #[cfg(not(feature = "headless"))]
+    fn save(&self) {
+        let result = toml::to_string(self)
+            .map_err(|error| error.to_string())
+            .and_then(|text| fs::write(SETTINGS_PATH, text).map_err(|error| error.to_string()));
+        if let Err(error) = result {
+            warn!(target: ASSETS, "Can't write {}: {}", SETTINGS_PATH, error);
+        }
+    }
+}
+
+/// Load order and disabled mods by name. The mods are opened once at startup, see
+/// `archive::ArchiveAssetIo`, so changing them takes a restart.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ModSettings {
+    #[serde(default)]
+    pub order: Vec<String>,
+    #[serde(default)]
+    pub disabled: Vec<String>,
+    // changed since startup, the settings ask for a restart then
+    #[cfg(not(feature = "headless"))]
+    #[serde(skip)]
+    changed: bool,
+    // index of the mod being dragged to another place in the load order
+    #[cfg(not(feature = "headless"))]
+    #[serde(skip)]
+    dragging: Option<usize>
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ModSettings {
+    pub fn is_enabled(&self, name: &str) -> bool {
+        !self.disabled.iter().any(|disabled| disabled == name)
+    }
+
+    /// The `installed` mods in load order, ones missing from it come last in the order given
+    pub fn ordered(&self, installed: &[String]) -> Vec<String> {
+        let mut ordered: Vec<String> = self.order.iter().filter(|name| installed.contains(name)).cloned().collect();
+        ordered.extend(installed.iter().filter(|name| !self.order.contains(name)).cloned());
+        ordered
+    }
+}
+
+// The locales are only known once loading is done, then the setting is used up
+#[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+pub fn select_saved_language(mut commands: Commands, language: Option<Res<LanguageSetting>>, localize: Option<ResMut<Localize>>) {
+    if let (Some(language), Some(mut localize)) = (language, localize) {
+        if let Some(language) = &language.0 {
+            localize.set_language(language);
+        }
+        commands.remove_resource::<LanguageSetting>();
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+pub fn toggle_settings(keys: Res<Input<KeyCode>>, mut settings: ResMut<SettingsWindow>) {
+    if keys.just_pressed(TOGGLE_KEY) {
+        settings.open = !settings.open;
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+pub fn show_settings(
+    mut egui_context: ResMut<EguiContext>,
+    mut settings: ResMut<SettingsWindow>,
+    mut localize: ResMut<Localize>,
+    // saves and mods need a file system, the browser build has none
+    #[cfg(not(target_arch = "wasm32"))] mut autosave: ResMut<AutosaveSettings>,
+    #[cfg(not(target_arch = "wasm32"))] mut mod_settings: ResMut<ModSettings>,
+    #[cfg(not(target_arch = "wasm32"))] mod_list: Res<ModList>,
+    #[cfg(not(target_arch = "wasm32"))] mut exit: EventWriter<AppExit>)
+{
+    if !settings.open {
+        return
+    }
+    let mut language = localize.language().to_string();
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut changed = false;
+    egui::Window::new(localize.text("settings-title")).open(&mut settings.open).show(egui_context.ctx_mut(), |ui| {
+        ui.horizontal(|ui| {
+            ui.label(localize.text("settings-language"));
+            let selected = localize.languages().iter().find(|known| known.name == language).map_or(language.clone(), |known| known.label.clone());
+            egui::ComboBox::from_id_source("language").selected_text(selected).show_ui(ui, |ui| {
+                for known in localize.languages() {
+                    ui.selectable_value(&mut language, known.name.clone(), known.label.as_str());
+                }
+            });
+        });
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            ui.horizontal(|ui| {
+                ui.label(localize.text("settings-autosave-interval"));
+                changed |= ui.add(egui::DragValue::new(&mut autosave.interval_minutes).clamp_range(0..=120)).changed();
+            });
+            ui.horizontal(|ui| {
+                ui.label(localize.text("settings-autosave-slots"));
+                changed |= ui.add(egui::DragValue::new(&mut autosave.slots).clamp_range(1..=20)).changed();
+            });
+            changed |= show_mod_settings(ui, &localize, &mut mod_settings, &mod_list, &mut exit);
+        }
+    });
+    #[cfg(not(target_arch = "wasm32"))]
+    if changed || language != localize.language() {
+        let file = SettingsFile { language: Some(language.clone()), autosave: autosave.clone(), mods: mod_settings.clone() };
+        file.save();
+    }
+    localize.set_language(&language);
+}
+
+// Mods are dragged by their handle to another place in the load order, dependencies still load
+// first whatever the order. Returns whether anything changed.
+#[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+fn show_mod_settings(ui: &mut egui::Ui, localize: &Localize, settings: &mut ModSettings, mod_list: &ModList, exit: &mut EventWriter<AppExit>) -> bool {
+    ui.separator();
+    ui.label(localize.text("settings-mods"));
+    for error in &mod_list.errors {
+        ui.colored_label(egui::Color32::LIGHT_RED, error.as_str());
+    }
+    if mod_list.installed.is_empty() {
+        ui.weak(localize.text("settings-mods-none"));
+        return false
+    }
+    let mut order = settings.ordered(&mod_list.installed);
+    let mut toggled = None;
+    let mut dropped = false;
+    let mut rows = Vec::new();
+    for (index, name) in order.iter().enumerate() {
+        let row = ui.horizontal(|ui| {
+            let handle = ui.add(egui::Label::new("☰").sense(egui::Sense::drag()));
+            if handle.hovered() || handle.dragged() {
+                ui.output().cursor_icon = egui::CursorIcon::Grab;
+            }
+            if handle.drag_started() {
+                settings.dragging = Some(index);
+            }
+            dropped |= handle.drag_released();
+            let mut enabled = settings.is_enabled(name);
+            if ui.checkbox(&mut enabled, name.as_str()).changed() {
+                toggled = Some((name.clone(), enabled));
+            }
+        });
+        rows.push(row.response.rect);
+    }
+    if settings.dragging.is_some() && !dropped && !ui.input().pointer.any_down() {
+        // the drag ended while the window was closed
+        settings.dragging = None;
+    }
+    let mut moved = false;
+    if let (Some(from), Some(pointer)) = (settings.dragging, ui.input().pointer.interact_pos()) {
+        // the mod goes in front of the first row whose middle is below the pointer
+        let to = rows.iter().position(|rect| pointer.y < rect.center().y).unwrap_or(rows.len());
+        let y = rows.get(to).map_or_else(|| rows[rows.len() - 1].bottom(), |rect| rect.top());
+        ui.painter().hline(rows[0].x_range(), y, ui.visuals().selection.stroke);
+        if dropped {
+            let to = if to > from { to - 1 } else { to };
+            if to != from && from < order.len() {
+                let name = order.remove(from);
+                order.insert(to, name);
+                moved = true;
+            }
+        }
+    }
+    if dropped {
+        settings.dragging = None;
+    }
+    let changed = toggled.is_some() || moved;
+    if let Some((name, enabled)) = toggled {
+        settings.disabled.retain(|disabled| *disabled != name);
+        if !enabled {
+            settings.disabled.push(name);
+        }
+    }
+    if changed {
+        settings.order = order;
+        settings.changed = true;
+    }
+    if settings.changed {
+        ui.colored_label(egui::Color32::YELLOW, localize.text("settings-mods-restart"));
+        if ui.button(localize.text("settings-mods-apply")).clicked() {
+            restart(exit);
+        }
+    }
+    changed
+}
+
+// Starts the game again with the same arguments and closes this one, the running game isn't saved
+#[cfg(not(any(target_arch = "wasm32", feature = "headless")))]
+fn restart(exit: &mut EventWriter<AppExit>) {
+    let started = std::env::current_exe().and_then(|exe| std::process::Command::new(exe).args(std::env::args_os().skip(1)).spawn());
+    match started {
+        Ok(_) => exit.send(AppExit),
+        Err(error) => warn!(target: ASSETS, "Can't restart the game: {}", error)
+    }
+}
@@ -0,0 +1,69 @@
+use bevy::prelude::*;
+use super::Unit;
+use crate::program::{UnitProgram, ProgramStatus};
+use crate::{energy::Energy, heat::Heat};
+
+const INDICATOR_SIZE: f32 = 0.25;
+const INDICATOR_OFFSET: f32 = 0.7;
+const OUT_OF_POWER_COLOR: Color = Color::rgb(0.9, 0.8, 0.1);
+const OVERHEATED_COLOR: Color = Color::rgb(1.0, 0.45, 0.1);
+
+#[derive(Component)]
+pub struct StatusIndicator;
+
+fn indicator_color(status: ProgramStatus) -> Color {
+    match status {
+        ProgramStatus::Running => Color::rgb(0.2, 0.8, 0.2),
+        ProgramStatus::Idle => Color::rgb(0.6, 0.6, 0.6),
+        ProgramStatus::Hibernating => Color::rgb(0.3, 0.4, 0.9),
+        ProgramStatus::Faulted => Color::rgb(0.9, 0.1, 0.1),
+        ProgramStatus::Crashed => Color::rgb(0.6, 0.0, 0.6)
+    }
+}
+
+type NewUnit = (With<Unit>, Added<UnitProgram>);
+
+pub fn spawn_status_indicators(mut commands: Commands, units: Query<(Entity, &UnitProgram), NewUnit>) {
+    for (entity, unit_program) in units.iter() {
+        commands.entity(entity).with_children(|parent| {
+            parent.spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: indicator_color(unit_program.status()),
+                    custom_size: Some(Vec2::splat(INDICATOR_SIZE)),
+                    ..default()
+                },
+                transform: Transform::from_xyz(0.0, INDICATOR_OFFSET, 1.0),
+                ..default()
+            }).insert(StatusIndicator);
+        });
+    }
+}
+
+type IndicatedUnit = (&'static UnitProgram, &'static Transform, Option<&'static Energy>, Option<&'static Heat>, &'static Children);
+type IndicatorChanged = (With<Unit>, Without<StatusIndicator>, Or<(Changed<UnitProgram>, Changed<Transform>, Changed<Energy>, Changed<Heat>, Changed<Children>)>);
+
+// Indicators are children of the unit, so they have to counter the unit's rotation to stay above it.
+// Only units where something the indicator shows changed are updated, most stand still and idle.
+pub fn update_status_indicators(
+    mut indicators: Query<(&mut Sprite, &mut Transform), With<StatusIndicator>>,
+    units: Query<IndicatedUnit, IndicatorChanged>)
+{
+    for (unit_program, unit_transform, energy, heat, children) in units.iter() {
+        for child in children.iter() {
+            let (mut sprite, mut transform) = match indicators.get_mut(*child) {
+                Ok(indicator) => indicator,
+                Err(_) => continue
+            };
+            sprite.color = if heat.map_or(false, Heat::is_overheated) {
+                OVERHEATED_COLOR
+            } else if energy.map_or(false, Energy::is_empty) {
+                OUT_OF_POWER_COLOR
+            } else {
+                indicator_color(unit_program.status())
+            };
+            let inverse_rotation = unit_transform.rotation.inverse();
+            transform.rotation = inverse_rotation;
+            transform.translation = inverse_rotation * Vec3::new(0.0, INDICATOR_OFFSET, 1.0);
+        }
+    }
+}
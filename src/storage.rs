@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+use blake3::{Hash, Hasher};
+#[cfg(not(target_arch = "wasm32"))]
+use mlua::prelude::*;
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use super::{Prototype, ComponentPrototype, Prototypes};
+use crate::data_value::{DataValue, DataValueHashEq, DataValueDecodeError};
+use crate::schema::{Schema, SchemaError};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::program::raise_intent;
+
+/// Black box of a unit. Keeps data written by the program, survives the unit as part of its corpse.
+#[derive(Component, Prototype, ComponentPrototype, Deserialize, Clone)]
+#[prot_category(storage)]
+pub struct Storage {
+    name: String,
+    quota: usize, // bytes
+    // a hash of the contents is recorded on every write and checked on every read. Saves keep the
+    // recorded one, so contents edited in a save file don't match it after loading.
+    #[serde(default)]
+    tamper_evident: bool,
+    #[serde(skip)]
+    data: HashMap<DataValueHashEq, DataValue>,
+    #[serde(skip)]
+    usage: usize,
+    #[serde(skip)]
+    lock: Option<String>,
+    #[serde(skip)]
+    hash: Option<Hash>
+}
+
+// Entries are measured by their binary encoding, the same form they would be saved in
+pub fn entry_size(key: &DataValueHashEq, value: &DataValue) -> usize {
+    DataValue::from(key.clone()).to_binary().len() + value.to_binary().len()
+}
+
+impl Storage {
+    pub fn get(&self, key: &DataValueHashEq) -> Option<&DataValue> {
+        self.data.get(key)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&DataValueHashEq, &DataValue)> {
+        self.data.iter()
+    }
+
+    /// Writing nil removes the entry. Fails without changing anything if the quota would be exceeded.
+    pub fn set(&mut self, key: DataValueHashEq, value: DataValue) -> Result<(), usize> {
+        let old_size = self.data.get(&key).map_or(0, |old| entry_size(&key, old));
+        let new_size = if value == DataValue::Nil { 0 } else { entry_size(&key, &value) };
+        let usage = self.usage - old_size + new_size;
+        // a storage restored over its quota can still be cleaned up, only growing it is refused
+        if new_size > old_size && usage > self.quota {
+            return Err(usage)
+        }
+        // writing to contents that don't match their hash doesn't make them trustworthy again
+        let intact = self.verify();
+        self.usage = usage;
+        if value == DataValue::Nil {
+            self.data.remove(&key);
+        } else {
+            self.data.insert(key, value);
+        }
+        if self.tamper_evident && intact {
+            self.hash = Some(self.contents_hash());
+        }
+        Ok(())
+    }
+
+    // Entries are hashed in the order of their encoded keys, so the result doesn't depend on the
+    // iteration order of the map
+    fn contents_hash(&self) -> Hash {
+        let mut entries: Vec<(Vec<u8>, Vec<u8>)> = self.data.iter()
+            .map(|(key, value)| (DataValue::from(key.clone()).to_binary(), value.to_binary()))
+            .collect();
+        entries.sort();
+        let mut hasher = Hasher::new();
+        for (key, value) in entries {
+            hasher.update(&key);
+            hasher.update(&value);
+        }
+        hasher.finalize()
+    }
+
+    /// Always `true` for storages that aren't tamper-evident
+    pub fn verify(&self) -> bool {
+        !self.tamper_evident || self.hash.unwrap_or_else(|| Hasher::new().finalize()) == self.contents_hash()
+    }
+
+    /// Empties the black box and removes its lock
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.usage = 0;
+        self.lock = None;
+        if self.tamper_evident {
+            self.hash = Some(self.contents_hash());
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_some()
+    }
+
+    pub fn usage(&self) -> usize {
+        self.usage
+    }
+
+    pub fn quota(&self) -> usize {
+        self.quota
+    }
+
+    pub fn contents(&self) -> StorageContents {
+        StorageContents {
+            data: base64::encode(DataValue::Table(self.data.clone()).to_binary()),
+            lock: self.lock.clone(),
+            hash: self.hash.map(|hash| *hash.as_bytes())
+        }
+    }
+
+    /// Replaces the data, the lock and the recorded hash with saved ones. The hash isn't
+    /// recomputed, check `verify` afterwards. The quota isn't checked, storages saved before their
+    /// prototype's quota was lowered keep their data but can't grow.
+    pub fn restore(&mut self, contents: StorageContents) -> Result<(), StorageContentsError> {
+        let data = DataValue::from_binary(&base64::decode(contents.data)?)?;
+        // any key and value a script could have written
+        data.validate(&Schema::Table { fields: Vec::new(), other: Some(Box::new(Schema::Any)) })?;
+        let data = match data {
+            DataValue::Table(table) => table,
+            // an empty sequence, the only other thing the schema lets through
+            _ => HashMap::new()
+        };
+        self.usage = data.iter().map(|(key, value)| entry_size(key, value)).sum();
+        self.data = data;
+        self.lock = contents.lock;
+        self.hash = contents.hash.map(Hash::from);
+        Ok(())
+    }
+}
+
+/// Black box contents as they're written to save files
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StorageContents {
+    // the entries as a table in the binary encoding, base64 encoded
+    data: String,
+    lock: Option<String>,
+    // missing in saves from before hashes were saved, their tamper-evident contents don't verify
+    #[serde(default)]
+    hash: Option<[u8; 32]>
+}
+
+#[derive(Debug, Error)]
+pub enum StorageContentsError {
+    #[error("invalid base64: {0}")]
+    Base64(#[from] base64::DecodeError),
+    #[error(transparent)]
+    Decode(#[from] DataValueDecodeError),
+    #[error("invalid storage data, {0}")]
+    Invalid(#[from] SchemaError)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct LuaStorageHandle<'a> {
+    pub storage: &'a mut Storage
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LuaStorageHandle<'_> {
+    fn check_access(&self) -> LuaResult<()> {
+        if self.storage.is_locked() {
+            Err(LuaError::RuntimeError("storage is locked".into()))
+        } else if !self.storage.verify() {
+            Err(LuaError::RuntimeError("storage contents don't match their hash, it was tampered with".into()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LuaUserData for LuaStorageHandle<'_> {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("read", |lua, lua_handle, key: DataValueHashEq| {
+            lua_handle.check_access()?;
+            lua_handle.storage.get(&key).cloned().unwrap_or(DataValue::Nil).to_lua(lua)
+        });
+        methods.add_method_mut("write", |lua, lua_handle, (key, value): (DataValueHashEq, DataValue)| {
+            raise_intent(lua)?;
+            lua_handle.check_access()?;
+            let quota = lua_handle.storage.quota;
+            lua_handle.storage.set(key, value).map_err(|usage| {
+                LuaError::RuntimeError(format!("storage quota exceeded, {} of {} bytes", usage, quota))
+            })
+        });
+        methods.add_method("keys", |lua, lua_handle, ()| {
+            lua_handle.check_access()?;
+            let keys: Vec<DataValue> = lua_handle.storage.data.keys().cloned().map(Into::into).collect();
+            DataValue::Sequence(keys).to_lua(lua)
+        });
+        methods.add_method_mut("lock", |lua, lua_handle, key: String| {
+            raise_intent(lua)?;
+            if lua_handle.storage.is_locked() {
+                return Err(LuaError::RuntimeError("storage is already locked".into()))
+            }
+            lua_handle.storage.lock = Some(key);
+            Ok(())
+        });
+        methods.add_method_mut("unlock", |lua, lua_handle, key: String| {
+            raise_intent(lua)?;
+            match &lua_handle.storage.lock {
+                Some(lock) if *lock != key => Err(LuaError::RuntimeError("wrong key".into())),
+                _ => {
+                    lua_handle.storage.lock = None;
+                    Ok(())
+                }
+            }
+        });
+    }
+
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("usage", |_lua, lua_handle| {
+            Ok(lua_handle.storage.usage)
+        });
+        fields.add_field_method_get("quota", |_lua, lua_handle| {
+            Ok(lua_handle.storage.quota)
+        });
+        fields.add_field_method_get("is_locked", |_lua, lua_handle| {
+            Ok(lua_handle.storage.is_locked())
+        });
+        fields.add_field_method_get("is_tamper_evident", |_lua, lua_handle| {
+            Ok(lua_handle.storage.tamper_evident)
+        });
+    }
+}
+
+/// Secret mixed into the keys scripts generate, so a script can't work out another unit's keys
+/// from its id and the tick. Random for every new world and kept in its saves, scripts never see it.
+pub struct KeySalt(pub [u8; 32]);
+
+impl Default for KeySalt {
+    fn default() -> Self {
+        let mut salt = [0; 32];
+        getrandom::getrandom(&mut salt).expect("the operating system has no random numbers");
+        Self(salt)
+    }
+}
+
+/// Key generation for locking storages. Keys are only compared, nothing is actually encrypted.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct LuaCryptoHandle {
+    seed: Hash,
+    generated: u64
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LuaCryptoHandle {
+    pub fn new(salt: &KeySalt, unit_id: u64, tick: u64) -> Self {
+        let mut hasher = Hasher::new();
+        hasher.update(&salt.0);
+        hasher.update(&unit_id.to_le_bytes());
+        hasher.update(&tick.to_le_bytes());
+        Self { seed: hasher.finalize(), generated: 0 }
+    }
+
+    fn new_key(&mut self) -> String {
+        let mut hasher = Hasher::new();
+        hasher.update(self.seed.as_bytes());
+        hasher.update(&self.generated.to_le_bytes());
+        self.generated += 1;
+        hasher.finalize().to_hex()[..32].to_string()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LuaUserData for LuaCryptoHandle {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut("new_key", |_lua, lua_handle, ()| {
+            Ok(lua_handle.new_key())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage(quota: usize) -> Storage {
+        serde_json::from_value(serde_json::json!({ "name": "box", "quota": quota })).unwrap()
+    }
+
+    fn key(name: &str) -> DataValueHashEq {
+        DataValueHashEq::String(name.into())
+    }
+
+    #[test]
+    fn counts_entries_by_their_encoded_size() {
+        let mut storage = storage(1024);
+        let value = DataValue::String("depot".into());
+        storage.set(key("target"), value.clone()).unwrap();
+        assert_eq!(storage.usage(), entry_size(&key("target"), &value));
+    }
+
+    #[test]
+    fn refuses_writes_over_quota() {
+        let mut storage = storage(32);
+        storage.set(key("a"), DataValue::Integer(1)).unwrap();
+        let usage = storage.usage();
+        let too_long = DataValue::String("x".repeat(64));
+        assert_eq!(storage.set(key("b"), too_long.clone()), Err(usage + entry_size(&key("b"), &too_long)));
+        // nothing changed
+        assert_eq!(storage.usage(), usage);
+        assert!(storage.get(&key("b")).is_none());
+        assert_eq!(storage.get(&key("a")), Some(&DataValue::Integer(1)));
+    }
+
+    #[test]
+    fn accepts_writes_up_to_the_quota() {
+        let value = DataValue::String("x".repeat(16));
+        let mut storage = storage(entry_size(&key("a"), &value));
+        storage.set(key("a"), value).unwrap();
+        assert_eq!(storage.usage(), storage.quota());
+    }
+
+    #[test]
+    fn overwrites_replace_the_old_size() {
+        let long = DataValue::String("x".repeat(40));
+        let short = DataValue::String("x".into());
+        // too small for both values at once
+        let mut storage = storage(entry_size(&key("a"), &long) + 1);
+        storage.set(key("a"), long.clone()).unwrap();
+        storage.set(key("a"), short.clone()).unwrap();
+        assert_eq!(storage.usage(), entry_size(&key("a"), &short));
+        // fits only because the old value's size is freed first
+        storage.set(key("a"), long.clone()).unwrap();
+        assert_eq!(storage.usage(), entry_size(&key("a"), &long));
+    }
+
+    #[test]
+    fn writing_nil_frees_the_entry() {
+        let mut storage = storage(64);
+        storage.set(key("a"), DataValue::Integer(1)).unwrap();
+        storage.set(key("a"), DataValue::Nil).unwrap();
+        assert_eq!(storage.usage(), 0);
+        assert!(storage.get(&key("a")).is_none());
+    }
+
+    #[test]
+    fn storages_over_quota_can_shrink() {
+        let long = DataValue::String("x".repeat(40));
+        let mut saved = storage(256);
+        saved.set(key("a"), long.clone()).unwrap();
+        saved.set(key("b"), long.clone()).unwrap();
+        // restoring doesn't check the quota, so a smaller one leaves it over
+        let mut restored = storage(entry_size(&key("a"), &long));
+        restored.restore(saved.contents()).unwrap();
+        assert!(restored.usage() > restored.quota());
+        restored.set(key("a"), DataValue::String("x".into())).unwrap();
+        restored.set(key("b"), DataValue::Nil).unwrap();
+        assert!(restored.usage() <= restored.quota());
+        // growing is refused again while it's over
+        assert!(restored.set(key("c"), long.clone()).is_err());
+    }
+
+    #[test]
+    fn restoring_recounts_usage() {
+        let mut saved = storage(64);
+        saved.set(key("a"), DataValue::Integer(1)).unwrap();
+        saved.set(key("b"), DataValue::Boolean(true)).unwrap();
+        let mut restored = storage(64);
+        restored.restore(saved.contents()).unwrap();
+        assert_eq!(restored.usage(), saved.usage());
+        assert!(restored.verify());
+    }
+
+    #[test]
+    fn keys_depend_on_the_salt() {
+        let salt = KeySalt([1; 32]);
+        let mut crypto = LuaCryptoHandle::new(&salt, 3, 100);
+        let first = crypto.new_key();
+        assert_ne!(crypto.new_key(), first);
+        // the same world gives the same keys, a script in another world can't reproduce them
+        assert_eq!(LuaCryptoHandle::new(&salt, 3, 100).new_key(), first);
+        assert_ne!(LuaCryptoHandle::new(&KeySalt([2; 32]), 3, 100).new_key(), first);
+    }
+
+    #[test]
+    fn new_worlds_get_different_salts() {
+        assert_ne!(KeySalt::default().0, KeySalt::default().0);
+    }
+}
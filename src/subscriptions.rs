@@ -0,0 +1,99 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use bevy::prelude::*;
+use super::{Unit, UnitId, units_in_order};
+use crate::{energy::Energy, program::WakeEvent};
+use crate::data_value::{DataValue, DataValueHashEq};
+
+const EVENT_QUEUE_CAPACITY: usize = 64;
+const LOW_POWER_FRACTION: f32 = 0.2;
+pub const SCRIPT_EVENT_NAMES: [&str; 11] = ["unit_destroyed", "item_dropped", "low_power", "overheated", "team_storage_changed", "weather_changed", "meteor_incoming", "meteor_strike", "creature_aggro", "program_faulted", "equipment_failed"];
+
+/// World event that scripts can subscribe to
+pub struct ScriptEvent {
+    pub name: &'static str,
+    pub data: HashMap<DataValueHashEq, DataValue>
+}
+
+impl ScriptEvent {
+    pub fn new(name: &'static str, position: Vec2) -> Self {
+        let mut event = Self { name, data: HashMap::new() };
+        event.set("event", DataValue::String(name.to_string()));
+        event.set("x", DataValue::Number(position.x as f64));
+        event.set("y", DataValue::Number(position.y as f64));
+        event
+    }
+
+    pub fn with(mut self, key: &str, value: DataValue) -> Self {
+        self.set(key, value);
+        self
+    }
+
+    fn set(&mut self, key: &str, value: DataValue) {
+        self.data.insert(DataValueHashEq::String(key.to_string()), value);
+    }
+}
+
+/// Units are passed in events by their `UnitId`, the same as `handle.id`, so filters can match
+/// them. `poll_events` hands them to scripts as `UnitRef`s.
+pub fn unit_id(id: UnitId) -> DataValue {
+    DataValue::Integer(id.0 as i64)
+}
+
+/// Events a unit's program subscribed to, with a filter table each, and the ones waiting to be read
+#[derive(Component, Default)]
+pub struct EventSubscriptions {
+    pub subscriptions: HashMap<String, HashMap<DataValueHashEq, DataValue>>,
+    pub queue: VecDeque<DataValue>
+}
+
+impl EventSubscriptions {
+    // every field of the filter has to be equal in the event
+    fn matches(&self, event: &ScriptEvent) -> bool {
+        self.subscriptions.get(event.name).map_or(false, |filter| {
+            filter.iter().all(|(key, value)| event.data.get(key) == Some(value))
+        })
+    }
+}
+
+pub fn deliver_script_events(
+    mut script_events: EventReader<ScriptEvent>,
+    mut subscribers: Query<(Entity, &mut EventSubscriptions)>,
+    ids: Query<(Entity, &UnitId)>,
+    mut wake_events: EventWriter<WakeEvent>)
+{
+    let order = units_in_order(&ids);
+    for event in script_events.iter() {
+        for unit in &order {
+            let (entity, mut subscriptions) = match subscribers.get_mut(*unit) {
+                Ok(subscriber) => subscriber,
+                Err(_) => continue
+            };
+            if !subscriptions.matches(event) {
+                continue
+            }
+            if subscriptions.queue.len() == EVENT_QUEUE_CAPACITY {
+                subscriptions.queue.pop_front();
+            }
+            subscriptions.queue.push_back(DataValue::Table(event.data.clone()));
+            wake_events.send(WakeEvent { unit: entity });
+        }
+    }
+}
+
+type EnergyChanged = (With<Unit>, Changed<Energy>);
+
+pub fn emit_low_power_events(
+    units: Query<(Entity, &Energy, &Transform, &UnitId), EnergyChanged>,
+    mut low: Local<HashSet<Entity>>,
+    mut script_events: EventWriter<ScriptEvent>)
+{
+    for (entity, energy, transform, id) in units.iter() {
+        if energy.stored < energy.capacity * LOW_POWER_FRACTION {
+            if low.insert(entity) {
+                script_events.send(ScriptEvent::new("low_power", transform.translation.truncate()).with("unit", unit_id(*id)));
+            }
+        } else {
+            low.remove(&entity);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+#[cfg(not(target_arch = "wasm32"))]
+use mlua::prelude::*;
+use super::{SimTick, UnitId, TICK_RATE};
+use crate::subscriptions::{ScriptEvent, unit_id};
+use crate::data_value::{DataValue, DataValueHashEq};
+use crate::program::UnitProgram;
+use crate::storage::entry_size;
+use crate::logging::SCRIPT;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::program::raise_intent;
+
+// what a team's scripts may use per second of simulation, instructions are counted instead of time
+// so every machine throttles the same units
+const TEAM_INSTRUCTION_BUDGET: u64 = 20_000_000;
+const TEAM_MEMORY_BUDGET: usize = 64 * 1024 * 1024; // bytes
+// units of a team over its budget run this many times less often
+const THROTTLE_FACTOR: u64 = 4;
+const BLACKBOARD_QUOTA: usize = 256 * 1024; // bytes per team, measured like unit storages
+
+#[derive(Component, Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+pub struct Team(pub u32);
+
+/// Data shared by all units of a team. Writes take effect immediately, so the last unit to write a
+/// key during a tick wins.
+#[derive(Default)]
+pub struct Blackboard {
+    entries: HashMap<DataValueHashEq, DataValue>,
+    usage: usize,
+    // key, writer, writer position
+    changes: Vec<(DataValueHashEq, UnitId, Vec2)>
+}
+
+impl Blackboard {
+    pub fn get(&self, key: &DataValueHashEq) -> Option<&DataValue> {
+        self.entries.get(key)
+    }
+
+    /// Writing nil removes the key. Fails without changing anything if the team's quota would be
+    /// exceeded.
+    pub fn set(&mut self, key: DataValueHashEq, value: DataValue, writer: UnitId, position: Vec2) -> Result<(), usize> {
+        let old_size = self.entries.get(&key).map_or(0, |old| entry_size(&key, old));
+        let new_size = if value == DataValue::Nil { 0 } else { entry_size(&key, &value) };
+        let usage = self.usage - old_size + new_size;
+        if usage > BLACKBOARD_QUOTA {
+            return Err(usage)
+        }
+        self.usage = usage;
+        let changed = match value {
+            DataValue::Nil => self.entries.remove(&key).is_some(),
+            value => self.entries.insert(key.clone(), value.clone()) != Some(value)
+        };
+        if changed {
+            self.changes.push((key, writer, position));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct TeamBlackboards(pub HashMap<Team, Blackboard>);
+
+pub fn notify_blackboard_changes(mut blackboards: ResMut<TeamBlackboards>, mut script_events: EventWriter<ScriptEvent>) {
+    for (team, blackboard) in blackboards.0.iter_mut() {
+        for (key, writer, position) in blackboard.changes.drain(..) {
+            script_events.send(ScriptEvent::new("team_storage_changed", position)
+                .with("team", DataValue::Integer(team.0 as i64))
+                .with("key", key.into())
+                .with("unit", unit_id(writer)));
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct LuaBlackboardHandle<'a> {
+    pub blackboard: &'a mut Blackboard,
+    pub unit: UnitId,
+    pub position: Vec2
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LuaUserData for LuaBlackboardHandle<'_> {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("read", |lua, lua_handle, key: DataValueHashEq| {
+            lua_handle.blackboard.get(&key).cloned().unwrap_or(DataValue::Nil).to_lua(lua)
+        });
+        methods.add_method_mut("write", |lua, lua_handle, (key, value): (DataValueHashEq, DataValue)| {
+            raise_intent(lua)?;
+            lua_handle.blackboard.set(key, value, lua_handle.unit, lua_handle.position).map_err(|usage| {
+                LuaError::RuntimeError(format!("team storage quota exceeded, {} of {} bytes", usage, BLACKBOARD_QUOTA))
+            })
+        });
+        methods.add_method("keys", |lua, lua_handle, ()| {
+            let keys: Vec<DataValue> = lua_handle.blackboard.entries.keys().cloned().map(Into::into).collect();
+            DataValue::Sequence(keys).to_lua(lua)
+        });
+    }
+
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("usage", |_lua, lua_handle| {
+            Ok(lua_handle.blackboard.usage)
+        });
+        fields.add_field_method_get("quota", |_lua, _lua_handle| {
+            Ok(BLACKBOARD_QUOTA)
+        });
+    }
+}
+
+/// Script instructions and memory used by each team's units. Teams over their budget get their units
+/// throttled, so one player's scripts don't slow down everyone else.
+#[derive(Default)]
+pub struct ScriptUsage(HashMap<Team, TeamScriptUsage>);
+
+#[derive(Default)]
+pub struct TeamScriptUsage {
+    pub instructions: u64, // Lua instructions during the last second
+    pub memory: usize, // bytes, as of the last second
+    pub throttled: bool,
+    running_instructions: u64
+}
+
+impl ScriptUsage {
+    pub fn record(&mut self, team: Team, instructions: u64) {
+        self.0.entry(team).or_default().running_instructions += instructions;
+    }
+
+    /// How many times less often the team's units run
+    pub fn throttle(&self, team: Team) -> u64 {
+        if self.0.get(&team).map_or(false, |usage| usage.throttled) {
+            THROTTLE_FACTOR
+        } else {
+            1
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Team, &TeamScriptUsage)> {
+        self.0.iter()
+    }
+}
+
+/// Totals the last second of script usage once a second and decides which teams are throttled
+pub fn account_script_usage(mut script_usage: ResMut<ScriptUsage>, sim_tick: Res<SimTick>, programs: Query<(&UnitProgram, &Team)>) {
+    if !sim_tick.0.is_multiple_of(TICK_RATE) {
+        return
+    }
+    let mut memory: HashMap<Team, usize> = HashMap::new();
+    for (program, team) in programs.iter() {
+        *memory.entry(*team).or_default() += program.memory();
+    }
+    // teams without units left are forgotten
+    script_usage.0.retain(|team, _| memory.contains_key(team));
+    for (team, memory) in memory {
+        let usage = script_usage.0.entry(team).or_default();
+        usage.instructions = std::mem::take(&mut usage.running_instructions);
+        usage.memory = memory;
+        // throttled teams run fewer instructions only because they're throttled, they're released
+        // once they'd be within the budget running at full speed instead of every other second
+        let instructions = if usage.throttled { usage.instructions.saturating_mul(THROTTLE_FACTOR) } else { usage.instructions };
+        let throttled = instructions > TEAM_INSTRUCTION_BUDGET || usage.memory > TEAM_MEMORY_BUDGET;
+        if throttled && !usage.throttled {
+            warn!(target: SCRIPT, "Team {} is over its script budget with {} instructions and {} bytes, its units run {} times less often", team.0, usage.instructions, usage.memory, THROTTLE_FACTOR);
+        } else if !throttled && usage.throttled {
+            info!(target: SCRIPT, "Team {} is within its script budget again", team.0);
+        }
+        usage.throttled = throttled;
+    }
+}
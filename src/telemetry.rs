@@ -0,0 +1,148 @@
+use std::{collections::HashMap, fs::File, io::{BufWriter, Write}};
+#[cfg(not(feature = "headless"))]
+use std::io;
+use bevy::{prelude::*, ecs::schedule::SystemLabel};
+#[cfg(not(target_arch = "wasm32"))]
+use mlua::prelude::*;
+use super::{Unit, SimTick, TICK_RATE};
+use crate::energy::Energy;
+#[cfg(not(feature = "headless"))]
+use crate::debug::DebugSettings;
+
+const TELEMETRY_PATH: &str = "telemetry.csv";
+const MAX_METRIC_NAME_LEN: usize = 64;
+
+#[derive(SystemLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TelemetryLabel;
+
+/// Value emitted by a script with `handle.telemetry:emit`
+pub struct TelemetryMetric {
+    pub unit: Entity,
+    pub name: String,
+    pub value: f64
+}
+
+/// Opt-in recording of unit state and script metrics, written to a CSV file with one row per value
+#[derive(Default)]
+pub struct Telemetry {
+    writer: Option<BufWriter<File>>,
+    last_positions: HashMap<Entity, Vec2>,
+    // emitted since the last rows were written
+    metrics: Vec<TelemetryMetric>,
+    /// Unit shown in the metrics plot, its metrics are collected even when not recording
+    pub plotted_unit: Option<Entity>
+}
+
+impl Telemetry {
+    pub fn is_recording(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    /// Scripts can only emit metrics while recording or while their unit is plotted
+    pub fn metrics_mut(&mut self, unit: Entity) -> Option<&mut Vec<TelemetryMetric>> {
+        (self.is_recording() || self.plotted_unit == Some(unit)).then_some(&mut self.metrics)
+    }
+
+    #[cfg(not(feature = "headless"))]
+    pub fn metrics(&self) -> &[TelemetryMetric] {
+        &self.metrics
+    }
+
+    #[cfg(not(feature = "headless"))]
+    fn start(&mut self) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(TELEMETRY_PATH)?);
+        writeln!(writer, "tick,unit,metric,value")?;
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "headless"))]
+    fn stop(&mut self) -> io::Result<()> {
+        self.last_positions.clear();
+        self.metrics.clear();
+        match self.writer.take() {
+            Some(mut writer) => writer.flush(),
+            None => Ok(())
+        }
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+pub fn toggle_telemetry(settings: Res<DebugSettings>, mut telemetry: ResMut<Telemetry>) {
+    if !settings.is_changed() || settings.telemetry == telemetry.is_recording() {
+        return
+    }
+    let result = if settings.telemetry {
+        telemetry.start()
+    } else {
+        telemetry.stop()
+    };
+    match result {
+        Ok(()) if settings.telemetry => info!("Recording telemetry to {}", TELEMETRY_PATH),
+        Ok(()) => info!("Stopped recording telemetry"),
+        Err(error) => warn!("Telemetry file {} failed: {}", TELEMETRY_PATH, error)
+    }
+}
+
+// Speed is measured from the distance moved since the previous tick, in tiles per second
+pub fn record_telemetry(
+    mut telemetry: ResMut<Telemetry>,
+    sim_tick: Res<SimTick>,
+    units: Query<(Entity, &Transform, Option<&Energy>), With<Unit>>)
+{
+    if !telemetry.is_recording() {
+        telemetry.metrics.clear();
+        return
+    }
+    let mut rows = Vec::new();
+    let mut positions = HashMap::new();
+    for (entity, transform, energy) in units.iter() {
+        let position = transform.translation.truncate();
+        rows.push((entity, "x", position.x as f64));
+        rows.push((entity, "y", position.y as f64));
+        if let Some(last_position) = telemetry.last_positions.get(&entity) {
+            rows.push((entity, "speed", (position.distance(*last_position) * TICK_RATE as f32) as f64));
+        }
+        if let Some(energy) = energy {
+            rows.push((entity, "energy", energy.stored as f64));
+        }
+        positions.insert(entity, position);
+    }
+    telemetry.last_positions = positions;
+    let metrics = std::mem::take(&mut telemetry.metrics);
+    let mut rows = rows.into_iter().chain(metrics.iter().map(|metric| (metric.unit, metric.name.as_str(), metric.value)));
+    let writer = telemetry.writer.as_mut().unwrap();
+    let result = rows.try_for_each(|(unit, name, value)| writeln!(writer, "{},{},{},{}", sim_tick.0, unit.to_bits(), name, value));
+    if let Err(error) = result {
+        warn!("Telemetry file {} failed, recording stopped: {}", TELEMETRY_PATH, error);
+        telemetry.writer = None;
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct LuaTelemetryHandle<'a> {
+    pub metrics: Option<&'a mut Vec<TelemetryMetric>>,
+    pub unit: Entity
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LuaUserData for LuaTelemetryHandle<'_> {
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("is_collecting", |_lua, lua_handle| {
+            Ok(lua_handle.metrics.is_some())
+        });
+    }
+
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        // names end up unquoted in the CSV file
+        methods.add_method_mut("emit", |_lua, lua_handle, (name, value): (String, LuaNumber)| {
+            if name.is_empty() || name.len() > MAX_METRIC_NAME_LEN || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+                return Err(LuaError::RuntimeError(format!("invalid metric name {:?}", name)))
+            }
+            if let Some(metrics) = lua_handle.metrics.as_deref_mut() {
+                metrics.push(TelemetryMetric { unit: lua_handle.unit, name, value });
+            }
+            Ok(())
+        });
+    }
+}
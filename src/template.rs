@@ -0,0 +1,191 @@
+use serde::Deserialize;
+use super::{Prototype, Prototypes};
+#[cfg(not(feature = "headless"))]
+use bevy::{prelude::*, asset::{AssetLoader, LoadContext, LoadedAsset}, ecs::system::SystemParam, reflect::TypeUuid, utils::BoxedFuture};
+#[cfg(not(feature = "headless"))]
+use bevy_egui::{EguiContext, egui};
+#[cfg(not(feature = "headless"))]
+use crate::{program::{UnitProgram, ProgramShareEvent, ProgramShareOutcome}, plot::SelectedUnit, logging::SCRIPT, locale::Localize};
+
+#[cfg(not(feature = "headless"))]
+const TOGGLE_DIALOG_KEY: KeyCode = KeyCode::N;
+
+/// Example program offered in the new program dialog
+#[derive(Prototype, Deserialize)]
+#[prot_category(program_template)]
+pub struct ProgramTemplatePrototype {
+    name: String,
+    // translation keys, see `Localize`
+    #[cfg(not(feature = "headless"))]
+    #[serde(default)]
+    display_name: Option<String>,
+    #[cfg(not(feature = "headless"))]
+    #[serde(default)]
+    description: String,
+    path: String
+}
+
+impl ProgramTemplatePrototype {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+/// Text of a program file
+#[cfg(not(feature = "headless"))]
+#[derive(TypeUuid)]
+#[uuid = "3c9e6f1a-7b42-4d8e-b5a0-91f2c4d7e6b8"]
+pub struct ProgramSource(pub String);
+
+#[cfg(not(feature = "headless"))]
+pub struct ProgramSourceLoader;
+
+#[cfg(not(feature = "headless"))]
+impl AssetLoader for ProgramSourceLoader {
+    fn load<'a>(&'a self, bytes: &'a [u8], load_context: &'a mut LoadContext) -> BoxedFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            load_context.set_default_asset(LoadedAsset::new(ProgramSource(String::from_utf8(bytes.to_vec())?)));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["lua"]
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+struct ProgramTemplate {
+    name: String,
+    display_name: String,
+    description: String,
+    source: Handle<ProgramSource>
+}
+
+/// Templates sorted by name, loaded with the prototypes
+#[cfg(not(feature = "headless"))]
+#[derive(Default)]
+pub struct ProgramTemplates {
+    templates: Vec<ProgramTemplate>,
+    dialog_open: bool,
+    share: ShareForm
+}
+
+// the dialog's section for passing programs between players as strings
+#[cfg(not(feature = "headless"))]
+#[derive(Default)]
+struct ShareForm {
+    name: String,
+    description: String,
+    import: String,
+    // result of the last export or import
+    message: String
+}
+
+#[cfg(not(feature = "headless"))]
+impl ProgramTemplates {
+    pub fn load(assets: &AssetServer, prototypes: &Prototypes) -> Self {
+        let mut templates: Vec<ProgramTemplate> = prototypes.program_template.values().map(|template| ProgramTemplate {
+            name: template.name.clone(),
+            display_name: template.display_name.clone().unwrap_or_else(|| template.name.clone()),
+            description: template.description.clone(),
+            source: assets.load(template.path.as_str())
+        }).collect();
+        templates.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        Self { templates, ..default() }
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+pub fn toggle_new_program_dialog(mut egui_context: ResMut<EguiContext>, keys: Res<Input<KeyCode>>, mut templates: ResMut<ProgramTemplates>) {
+    if keys.just_pressed(TOGGLE_DIALOG_KEY) && !egui_context.ctx_mut().wants_keyboard_input() {
+        templates.dialog_open = !templates.dialog_open;
+    }
+}
+
+/// Requests to share programs and what came of them
+#[cfg(not(feature = "headless"))]
+#[derive(SystemParam)]
+pub struct ProgramSharing<'w, 's> {
+    events: EventWriter<'w, 's, ProgramShareEvent>,
+    outcomes: EventReader<'w, 's, ProgramShareOutcome>
+}
+
+#[cfg(not(feature = "headless"))]
+pub fn show_new_program_dialog(
+    mut egui_context: ResMut<EguiContext>,
+    mut templates: ResMut<ProgramTemplates>,
+    sources: Res<Assets<ProgramSource>>,
+    selected: Res<SelectedUnit>,
+    localize: Res<Localize>,
+    mut sharing: ProgramSharing,
+    mut programs: Query<&mut UnitProgram>)
+{
+    let templates = &mut *templates;
+    for outcome in sharing.outcomes.iter() {
+        templates.share.message = match outcome {
+            ProgramShareOutcome::Exported { string } => {
+                egui_context.ctx_mut().output().copied_text = string.clone();
+                localize.text("template-dialog-exported").to_string()
+            }
+            ProgramShareOutcome::Imported { name } => localize.format("template-dialog-imported", &[("name", name)]),
+            ProgramShareOutcome::Failed { error } => localize.format("template-dialog-import-failed", &[("error", error)])
+        };
+    }
+    if !templates.dialog_open {
+        return
+    }
+    let mut open = true;
+    egui::Window::new(localize.text("template-dialog-title")).open(&mut open).show(egui_context.ctx_mut(), |ui| {
+        let mut program = selected.0.and_then(|unit| programs.get_mut(unit).ok());
+        if program.is_none() {
+            ui.label(localize.text("template-dialog-select-unit"));
+        }
+        for template in &templates.templates {
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.strong(localize.text(&template.display_name));
+                let source = sources.get(&template.source);
+                if ui.add_enabled(program.is_some() && source.is_some(), egui::Button::new(localize.text("template-dialog-insert"))).clicked() {
+                    if let (Some(program), Some(source)) = (&mut program, source) {
+                        program.set_program(source.0.as_bytes());
+                        info!(target: SCRIPT, "Inserted the {} program", template.name);
+                    }
+                }
+            });
+            if !template.description.is_empty() {
+                ui.label(localize.text(&template.description));
+            }
+        }
+        ui.separator();
+        let share = &mut templates.share;
+        ui.strong(localize.text("template-dialog-share"));
+        egui::Grid::new("share_program").show(ui, |ui| {
+            ui.label(localize.text("template-dialog-name"));
+            ui.text_edit_singleline(&mut share.name);
+            ui.end_row();
+            ui.label(localize.text("template-dialog-description"));
+            ui.text_edit_singleline(&mut share.description);
+            ui.end_row();
+        });
+        if ui.add_enabled(program.is_some(), egui::Button::new(localize.text("template-dialog-export"))).clicked() {
+            if let Some(unit) = selected.0 {
+                sharing.events.send(ProgramShareEvent::Export { unit, name: share.name.clone(), description: share.description.clone() });
+            }
+        }
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut share.import);
+            if ui.add_enabled(program.is_some() && !share.import.trim().is_empty(), egui::Button::new(localize.text("template-dialog-import"))).clicked() {
+                if let Some(unit) = selected.0 {
+                    sharing.events.send(ProgramShareEvent::Import { unit, string: std::mem::take(&mut share.import) });
+                }
+            }
+        });
+        if !share.message.is_empty() {
+            ui.label(&share.message);
+        }
+    });
+    if !open {
+        templates.dialog_open = false;
+    }
+}
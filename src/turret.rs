@@ -0,0 +1,96 @@
+use bevy::{prelude::*, sprite::Anchor};
+#[cfg(not(target_arch = "wasm32"))]
+use mlua::prelude::*;
+use serde::Deserialize;
+use super::{Prototype, ComponentPrototype, Prototypes, TICK_RATE};
+use crate::heat::Heat;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::program::raise_intent;
+
+const TURRET_COLOR: Color = Color::rgb(0.7, 0.7, 0.75);
+
+/// Rotating equipment mounted on top of a unit. Spawned as a child entity of the unit.
+#[derive(Component, Prototype, ComponentPrototype, Deserialize, Clone)]
+#[prot_category(turret)]
+pub struct Turret {
+    name: String,
+    #[serde(default)]
+    rotation_speed: f32, // degrees / second
+    #[serde(default = "default_barrel_size")]
+    barrel_size: [f32; 2],
+    #[serde(default)]
+    heat_per_degree: f32,
+    // angle relative to the chassis, degrees clockwise
+    #[serde(skip)]
+    angle: f32,
+    #[serde(skip)]
+    target_angle: Option<f32>
+}
+
+fn default_barrel_size() -> [f32; 2] {
+    [0.6, 0.15]
+}
+
+impl Turret {
+    pub fn sprite_bundle(&self) -> SpriteBundle {
+        SpriteBundle {
+            sprite: Sprite {
+                color: TURRET_COLOR,
+                custom_size: Some(Vec2::from(self.barrel_size)),
+                anchor: Anchor::CenterLeft,
+                ..default()
+            },
+            transform: Transform::from_xyz(0.0, 0.0, 0.5),
+            ..default()
+        }
+    }
+}
+
+// Turning heats up the unit the turret is mounted on, an overheated unit's turret doesn't turn
+pub fn rotate_turrets(mut turrets: Query<(&mut Turret, &mut Transform, &Parent)>, mut heats: Query<&mut Heat>) {
+    for (mut turret, mut transform, parent) in turrets.iter_mut() {
+        if let Some(target_angle) = turret.target_angle {
+            let mut heat = heats.get_mut(parent.get()).ok();
+            if heat.as_ref().map_or(false, |heat| heat.is_overheated()) {
+                continue
+            }
+            let max_step = turret.rotation_speed / TICK_RATE as f32;
+            let difference = (target_angle - turret.angle + 180.0).rem_euclid(360.0) - 180.0;
+            if difference.abs() <= max_step {
+                turret.angle = target_angle;
+                turret.target_angle = None;
+            } else {
+                turret.angle = (turret.angle + max_step * difference.signum()).rem_euclid(360.0);
+            }
+            if let Some(heat) = &mut heat {
+                heat.add(difference.abs().min(max_step) * turret.heat_per_degree);
+            }
+            transform.rotation = Quat::from_rotation_z(-turret.angle.to_radians());
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct LuaTurretHandle<'a> {
+    pub turret: &'a mut Turret
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl LuaUserData for LuaTurretHandle<'_> {
+    fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut("aim", |lua, lua_handle, angle: f32| {
+            raise_intent(lua)?;
+            lua_handle.turret.target_angle = Some(angle.rem_euclid(360.0));
+            Ok(())
+        });
+    }
+
+    fn add_fields<'lua, F: LuaUserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("angle", |_lua, lua_handle| {
+            Ok(lua_handle.turret.angle)
+        });
+        fields.add_field_method_get("is_aimed", |_lua, lua_handle| {
+            Ok(lua_handle.turret.target_angle.is_none())
+        });
+    }
+}
@@ -0,0 +1,92 @@
+use bevy::{prelude::*, diagnostic::{FrameTimeDiagnosticsPlugin, EntityCountDiagnosticsPlugin}};
+use bevy_rapier2d::prelude::*;
+use bevy_prototype_debug_lines::DebugLinesPlugin;
+use bevy_egui::EguiPlugin;
+use super::{SimStepLabel, ClockLabel, UnitTickLabel, IntentLabel, MovementLabel, EventLabel};
+use crate::debug::{DebugSettings, DebugMenuLabel, PhaseTimings, start_phase_timing, end_phase_timing, spawn_debug_text, handle_debug_menu_input, apply_debug_render_settings, update_debug_text, record_position_trails, draw_debug_overlay};
+use crate::loading::{AppState, show_loading_screen, show_asset_error, spawn_loading_fade, fade_out_loading};
+use crate::registry::{spawn_unit_labels, update_unit_labels};
+use crate::telemetry::{TelemetryLabel, toggle_telemetry};
+use crate::plot::{SelectedUnit, MetricPlots, select_units, sample_metric_plots, show_metric_plots};
+use crate::logging::show_log_window;
+use crate::template::{toggle_new_program_dialog, show_new_program_dialog};
+use crate::api::{ApiReference, toggle_api_reference, show_api_reference};
+use crate::blueprint::clone_selected_unit;
+use crate::console::{Console, toggle_console, show_console};
+use crate::settings::{SettingsWindow, toggle_settings, show_settings};
+use crate::browser::{PrototypeBrowser, toggle_prototype_browser, show_prototype_browser};
+use crate::history::{UnitHistory, toggle_unit_history, record_unit_history, show_unit_history};
+#[cfg(target_arch = "wasm32")]
+use crate::program::show_scripting_unavailable;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::menu::{MainMenu, open_main_menu, show_main_menu};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::settings::select_saved_language;
+
+/// Everything drawn on top of the world: loading screens, the debug menu and overlays, unit
+/// labels and the egui windows. Nothing in the simulation depends on it.
+pub struct UiPlugin;
+
+impl Plugin for UiPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_plugin(EguiPlugin)
+            .add_system_set(SystemSet::on_update(AppState::Loading).with_system(show_loading_screen))
+            .add_system_set(SystemSet::on_update(AppState::Error).with_system(show_asset_error))
+            .add_system_set(SystemSet::on_enter(AppState::Playing).with_system(spawn_loading_fade))
+            .add_system(fade_out_loading)
+            .add_system(spawn_unit_labels)
+            .add_system(update_unit_labels)
+            .add_plugin(DebugLinesPlugin::default())
+            .add_plugin(RapierDebugRenderPlugin::default())
+            .add_plugin(FrameTimeDiagnosticsPlugin::default())
+            .add_plugin(EntityCountDiagnosticsPlugin::default())
+            .init_resource::<DebugSettings>()
+            .add_startup_system(spawn_debug_text)
+            .add_system(handle_debug_menu_input.label(DebugMenuLabel))
+            .add_system(apply_debug_render_settings.after(DebugMenuLabel))
+            .add_system(update_debug_text.after(DebugMenuLabel))
+            .add_system(record_position_trails.after(MovementLabel))
+            .add_system(draw_debug_overlay.after(UnitTickLabel).before(MovementLabel))
+            .add_system(toggle_telemetry.after(DebugMenuLabel))
+            .init_resource::<PhaseTimings>()
+            .add_system(start_phase_timing.before(ClockLabel))
+            .add_system(end_phase_timing::<0>.after(UnitTickLabel).before(IntentLabel))
+            .add_system(end_phase_timing::<1>.after(IntentLabel).before(MovementLabel))
+            .add_system(end_phase_timing::<2>.after(MovementLabel).before(EventLabel))
+            .add_system(end_phase_timing::<3>.after(EventLabel))
+            .init_resource::<SelectedUnit>()
+            .init_resource::<MetricPlots>()
+            .add_system(select_units)
+            .add_system(clone_selected_unit)
+            .add_system(show_metric_plots)
+            .add_system(sample_metric_plots.before(TelemetryLabel))
+            .add_system(show_log_window)
+            .add_system(toggle_new_program_dialog)
+            .add_system(show_new_program_dialog)
+            .init_resource::<ApiReference>()
+            .add_system(toggle_api_reference)
+            .add_system(show_api_reference)
+            .init_resource::<Console>()
+            .add_system(toggle_console)
+            .add_system(show_console)
+            .init_resource::<SettingsWindow>()
+            .add_system(toggle_settings)
+            .add_system(show_settings)
+            .init_resource::<PrototypeBrowser>()
+            .add_system(toggle_prototype_browser)
+            .add_system(show_prototype_browser)
+            .init_resource::<UnitHistory>()
+            .add_system(toggle_unit_history)
+            .add_system(record_unit_history.after(UnitTickLabel).before(MovementLabel).with_run_criteria(SimStepLabel))
+            .add_system(show_unit_history);
+        #[cfg(target_arch = "wasm32")]
+        app.add_system_set(SystemSet::on_update(AppState::Playing).with_system(show_scripting_unavailable));
+        #[cfg(not(target_arch = "wasm32"))]
+        app
+            .add_system(select_saved_language)
+            .init_resource::<MainMenu>()
+            .add_system_set(SystemSet::on_enter(AppState::MainMenu).with_system(open_main_menu))
+            .add_system_set(SystemSet::on_update(AppState::MainMenu).with_system(show_main_menu));
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use bevy::{prelude::*, ecs::system::EntityCommands, sprite::MaterialMesh2dBundle};
+use bevy::render::{mesh::{Indices, PrimitiveTopology}, primitives::Aabb};
+use serde::Deserialize;
+use super::{Prototype, Prototypes};
+use crate::{animation::{AnimationAtlases, SpriteAnimation}, logging::ASSETS};
+
+/// How an entity is drawn. With `animation` set, the texture is taken from that animation's sprite
+/// sheet instead of `texture`.
+#[derive(Prototype, Deserialize)]
+#[prot_category(visual)]
+pub struct VisualPrototype {
+    name: String,
+    #[serde(default)]
+    texture: Option<String>,
+    #[serde(default = "default_size")]
+    size: [f32; 2], // tiles
+    #[serde(default)]
+    z: f32,
+    #[serde(default = "default_tint")]
+    tint: [f32; 4], // rgba
+    #[serde(default)]
+    animation: Option<String>
+}
+
+fn default_size() -> [f32; 2] {
+    [1.0, 1.0]
+}
+
+fn default_tint() -> [f32; 4] {
+    [1.0, 1.0, 1.0, 1.0]
+}
+
+impl VisualPrototype {
+    pub fn texture(&self) -> Option<&str> {
+        self.texture.as_deref()
+    }
+}
+
+enum VisualTexture {
+    Image(Handle<Image>),
+    Animated(String, Handle<TextureAtlas>)
+}
+
+struct Visual {
+    texture: VisualTexture,
+    size: Vec2,
+    z: f32,
+    tint: Color
+}
+
+/// Textures of every visual prototype, loaded with the prototypes so they are ready before anything
+/// using them is spawned
+#[derive(Default)]
+pub struct Visuals(HashMap<String, Visual>);
+
+impl Visuals {
+    pub fn load(assets: &AssetServer, atlases: &AnimationAtlases, prototypes: &Prototypes) -> Self {
+        Self(prototypes.visual.values().filter_map(|visual| {
+            let texture = match (&visual.animation, &visual.texture) {
+                (Some(animation), _) => match atlases.get(animation) {
+                    Some(atlas) => VisualTexture::Animated(animation.clone(), atlas.clone()),
+                    None => {
+                        warn!(target: ASSETS, "Visual {} uses unknown animation {}", visual.name, animation);
+                        return None
+                    }
+                },
+                (None, Some(texture)) => VisualTexture::Image(assets.load(texture.as_str())),
+                (None, None) => {
+                    warn!(target: ASSETS, "Visual {} has neither a texture nor an animation", visual.name);
+                    return None
+                }
+            };
+            let [r, g, b, a] = visual.tint;
+            Some((visual.name.clone(), Visual {
+                texture,
+                size: Vec2::from(visual.size),
+                z: visual.z,
+                tint: Color::rgba(r, g, b, a)
+            }))
+        }).collect())
+    }
+
+    /// Every visual with an empty texture, for apps without a window. Units still get their sprite's
+    /// transform and size that way.
+    pub fn without_textures(prototypes: &Prototypes) -> Self {
+        Self(prototypes.visual.values().map(|visual| {
+            let [r, g, b, a] = visual.tint;
+            (visual.name.clone(), Visual {
+                texture: VisualTexture::Image(Handle::default()),
+                size: Vec2::from(visual.size),
+                z: visual.z,
+                tint: Color::rgba(r, g, b, a)
+            })
+        }).collect())
+    }
+
+    /// Inserts the sprite of the named visual at `position`. Unknown visuals get an empty sprite,
+    /// so the entity still has a transform. Bevy batches sprites of the same texture and z into one
+    /// draw call, but it only culls entities with an `Aabb`, which sprites don't get on their own, so
+    /// they're given one from their size.
+    pub fn insert(&self, entity: &mut EntityCommands, name: &str, position: Vec2) {
+        let visual = match self.0.get(name) {
+            Some(visual) => visual,
+            None => {
+                warn!(target: ASSETS, "Unknown visual {}", name);
+                entity.insert_bundle(SpriteBundle {
+                    transform: Transform::from_translation(position.extend(0.0)),
+                    ..default()
+                });
+                return
+            }
+        };
+        let transform = Transform::from_translation(position.extend(visual.z));
+        match &visual.texture {
+            VisualTexture::Image(image) => {
+                entity.insert_bundle(SpriteBundle {
+                    texture: image.clone(),
+                    sprite: Sprite {
+                        color: visual.tint,
+                        custom_size: Some(visual.size),
+                        ..default()
+                    },
+                    transform,
+                    ..default()
+                }).insert(sprite_bounds(visual.size));
+            }
+            VisualTexture::Animated(animation, atlas) => {
+                entity.insert_bundle(SpriteSheetBundle {
+                    texture_atlas: atlas.clone(),
+                    sprite: TextureAtlasSprite {
+                        color: visual.tint,
+                        custom_size: Some(visual.size),
+                        ..default()
+                    },
+                    transform,
+                    ..default()
+                }).insert(SpriteAnimation::new(animation)).insert(sprite_bounds(visual.size));
+            }
+        }
+    }
+
+    /// One mesh drawing the named visual at every position, for static tiles that would be too many
+    /// sprites. They're culled as a whole once they have an `Aabb`, which 2d meshes don't get on
+    /// their own. `None` for animated and unknown visuals, those need a sprite each.
+    pub fn tiles_mesh(&self, name: &str, positions: &[Vec2], meshes: &mut Assets<Mesh>, materials: &mut Assets<ColorMaterial>) -> Option<MaterialMesh2dBundle<ColorMaterial>> {
+        let (visual, image) = match self.0.get(name) {
+            Some(visual @ Visual { texture: VisualTexture::Image(image), .. }) => (visual, image),
+            _ => return None
+        };
+        let half = visual.size / 2.0;
+        let mut vertices = Vec::with_capacity(positions.len() * 4);
+        let mut indices = Vec::with_capacity(positions.len() * 6);
+        for position in positions {
+            let first = vertices.len() as u32;
+            for corner in [Vec2::new(-half.x, half.y), half, Vec2::new(half.x, -half.y), -half] {
+                vertices.push((*position + corner).extend(0.0).to_array());
+            }
+            indices.extend([first, first + 2, first + 1, first, first + 3, first + 2]);
+        }
+        // texture rows go down, so the top corners get v = 0
+        let uvs = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]].repeat(positions.len());
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 0.0, 1.0]; vertices.len()]);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.set_indices(Some(Indices::U32(indices)));
+        Some(MaterialMesh2dBundle {
+            mesh: meshes.add(mesh).into(),
+            material: materials.add(ColorMaterial { color: visual.tint, texture: Some(image.clone()) }),
+            transform: Transform::from_xyz(0.0, 0.0, visual.z),
+            ..default()
+        })
+    }
+}
+
+// Sprites are centered on their transform, the bounds are rotated and scaled along with it
+fn sprite_bounds(size: Vec2) -> Aabb {
+    Aabb::from_min_max(-size.extend(0.0) / 2.0, size.extend(0.0) / 2.0)
+}
+
+/// Visual prototype the entity was spawned with
+#[derive(Component, Clone)]
+pub struct VisualName(pub String);
@@ -0,0 +1,113 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+use super::{Prototype, ComponentPrototype, Prototypes, Unit, UnitId, SimTick, units_in_order};
+use crate::program::{UnitProgram, ProgramStatus};
+use crate::storage::Storage;
+use crate::subscriptions::{ScriptEvent, unit_id};
+use crate::data_value::DataValue;
+
+/// Restarts the unit's program after it faulted or crashed. Units without one stay faulted until
+/// their program is reloaded by hand.
+#[derive(Component, Prototype, ComponentPrototype, Deserialize, Clone)]
+#[prot_category(watchdog)]
+pub struct Watchdog {
+    name: String,
+    #[serde(default)]
+    action: WatchdogAction,
+    #[serde(default = "default_restart_delay")]
+    restart_delay: u64, // ticks
+    // faults within `window` ticks after which the program stays disabled
+    #[serde(default = "default_max_restarts")]
+    max_restarts: usize,
+    #[serde(default = "default_window")]
+    window: u64, // ticks
+    // ticks of the faults within the window
+    #[serde(skip)]
+    faults: Vec<u64>,
+    #[serde(skip)]
+    state: WatchdogState
+}
+
+fn default_restart_delay() -> u64 {
+    60
+}
+
+fn default_max_restarts() -> usize {
+    3
+}
+
+fn default_window() -> u64 {
+    3600
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WatchdogAction {
+    // the program stays faulted, but the fault is still announced
+    Disable,
+    // fresh interpreter state and an empty black box
+    #[default]
+    Restart,
+    // fresh interpreter state, the black box is left alone
+    RestartKeepStorage
+}
+
+#[derive(Clone, Copy, Default)]
+enum WatchdogState {
+    #[default]
+    Watching,
+    RestartingAt(u64),
+    Disabled
+}
+
+/// Restarts faulted programs when their delay ran out and handles new faults. Every fault sends a
+/// `program_faulted` event, with whether the program is going to be restarted.
+pub fn run_watchdogs(
+    mut units: Query<(&mut UnitProgram, &mut Watchdog, Option<&mut Storage>, &Transform), With<Unit>>,
+    ids: Query<(Entity, &UnitId)>,
+    sim_tick: Res<SimTick>,
+    mut script_events: EventWriter<ScriptEvent>)
+{
+    let tick = sim_tick.0;
+    for entity in units_in_order(&ids) {
+        let ((mut program, mut watchdog, storage, transform), (_, id)) = match (units.get_mut(entity), ids.get(entity)) {
+            (Ok(unit), Ok(id)) => (unit, id),
+            _ => continue
+        };
+        if !matches!(program.status(), ProgramStatus::Faulted | ProgramStatus::Crashed) {
+            // reloading by hand gives disabled programs another chance
+            watchdog.state = WatchdogState::Watching;
+            continue
+        }
+        match watchdog.state {
+            WatchdogState::RestartingAt(at) if at <= tick => {
+                if watchdog.action == WatchdogAction::Restart {
+                    if let Some(mut storage) = storage {
+                        storage.clear();
+                    }
+                }
+                program.reload();
+                program.wake_up();
+                watchdog.state = WatchdogState::Watching;
+            }
+            WatchdogState::RestartingAt(_) | WatchdogState::Disabled => (),
+            WatchdogState::Watching => {
+                let window = watchdog.window;
+                watchdog.faults.retain(|fault| tick - fault < window);
+                watchdog.faults.push(tick);
+                let restarting = watchdog.action != WatchdogAction::Disable && watchdog.faults.len() <= watchdog.max_restarts;
+                watchdog.state = if restarting {
+                    WatchdogState::RestartingAt(tick + watchdog.restart_delay)
+                } else {
+                    WatchdogState::Disabled
+                };
+                let error = program.error_log().last().cloned().unwrap_or_default();
+                script_events.send(ScriptEvent::new("program_faulted", transform.translation.truncate())
+                    .with("unit", unit_id(*id))
+                    .with("error", DataValue::String(error))
+                    .with("faults", DataValue::Integer(watchdog.faults.len() as i64))
+                    .with("restarting", DataValue::Boolean(restarting)));
+            }
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::HashSet;
+use bevy::prelude::*;
+use serde::Deserialize;
+use super::{Prototype, Prototypes, Unit, SimTick, SimStepLabel, MovementLabel, EventLabel, TICK_RATE};
+use crate::construction::{Structure, StructurePrototype};
+use crate::effects::ApplyEffectEvent;
+use crate::health::{Health, DamageEvent, DamageType};
+use crate::subscriptions::ScriptEvent;
+use crate::data_value::DataValue;
+
+// there are no scenarios yet, every game follows this schedule
+const DEFAULT_SCHEDULE: &str = "default";
+
+/// Weather following the `default` weather schedule. Changes and incoming meteors are announced
+/// to scripts, units inside shelter structures are spared from the weather.
+pub struct WeatherPlugin;
+
+impl Plugin for WeatherPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Weather>()
+            .add_system_set(SystemSet::new()
+                .after(MovementLabel)
+                .before(EventLabel)
+                .with_run_criteria(SimStepLabel)
+                .with_system(advance_weather)
+                .with_system(apply_weather));
+    }
+}
+
+#[derive(Prototype, Deserialize)]
+#[prot_category(weather)]
+pub struct WeatherPrototype {
+    name: String,
+    // multiplier of how far sensors reach
+    #[serde(default = "default_visibility")]
+    visibility: f32,
+    // status effect applied to units out in the open while it lasts
+    #[serde(default)]
+    unit_effect: Option<String>,
+    #[serde(default)]
+    meteors: Option<MeteorShower>
+}
+
+fn default_visibility() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize)]
+struct MeteorShower {
+    interval: f32, // seconds
+    warning: f32, // seconds between the announcement and the impact
+    radius: f32, // tiles
+    damage: f32,
+    // meteors land within this many tiles of the map origin
+    area: f32
+}
+
+/// Weather phases, looped
+#[derive(Prototype, Deserialize)]
+#[prot_category(weather_schedule)]
+pub struct WeatherSchedulePrototype {
+    name: String,
+    phases: Vec<WeatherPhase>
+}
+
+#[derive(Deserialize)]
+struct WeatherPhase {
+    // clear weather when not set
+    #[serde(default)]
+    weather: Option<String>,
+    duration: f32 // seconds
+}
+
+struct Meteor {
+    position: Vec2,
+    radius: f32,
+    damage: f32,
+    impact_tick: u64
+}
+
+pub struct Weather {
+    phase: usize,
+    phase_start: u64,
+    // `None` before the first phase started
+    phase_end: Option<u64>,
+    current: Option<String>,
+    visibility: f32,
+    meteors: Vec<Meteor>,
+    // units inside a shelter, as of the last tick
+    sheltered: HashSet<Entity>
+}
+
+impl Default for Weather {
+    fn default() -> Self {
+        Self {
+            phase: 0,
+            phase_start: 0,
+            phase_end: None,
+            current: None,
+            visibility: 1.0,
+            meteors: Vec::new(),
+            sheltered: HashSet::new()
+        }
+    }
+}
+
+impl Weather {
+    pub fn current(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    pub fn visibility(&self) -> f32 {
+        self.visibility
+    }
+
+    pub fn is_sheltered(&self, unit: Entity) -> bool {
+        self.sheltered.contains(&unit)
+    }
+}
+
+// Meteors have to land in the same spots on every machine, so their positions are derived from the
+// tick instead of a random generator
+fn meteor_position(tick: u64, area: f32) -> Vec2 {
+    let hash = blake3::hash(&tick.to_le_bytes());
+    let bytes = hash.as_bytes();
+    let fraction = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as f32 / u32::MAX as f32;
+    Vec2::new(fraction(0) * 2.0 - 1.0, fraction(4) * 2.0 - 1.0) * area
+}
+
+fn advance_weather(
+    mut weather: ResMut<Weather>,
+    sim_tick: Res<SimTick>,
+    prototypes: Res<Prototypes>,
+    targets: Query<(Entity, &Transform), With<Health>>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut script_events: EventWriter<ScriptEvent>)
+{
+    let tick = sim_tick.0;
+    let schedule = match WeatherSchedulePrototype::from_pt(&prototypes, DEFAULT_SCHEDULE) {
+        Some(schedule) if !schedule.phases.is_empty() => schedule,
+        _ => return
+    };
+    if weather.phase_end.map_or(true, |end| tick >= end) {
+        if weather.phase_end.is_some() {
+            weather.phase = (weather.phase + 1) % schedule.phases.len();
+        }
+        let phase = &schedule.phases[weather.phase % schedule.phases.len()];
+        weather.phase_start = tick;
+        weather.phase_end = Some(tick + ((phase.duration * TICK_RATE as f32) as u64).max(1));
+        if weather.current != phase.weather {
+            weather.current = phase.weather.clone();
+            weather.visibility = phase.weather.as_ref()
+                .and_then(|name| WeatherPrototype::from_pt(&prototypes, name))
+                .map_or(1.0, |prototype| prototype.visibility);
+            script_events.send(ScriptEvent::new("weather_changed", Vec2::ZERO)
+                .with("weather", phase.weather.clone().map_or(DataValue::Nil, DataValue::String))
+                .with("duration", DataValue::Number(phase.duration as f64)));
+        }
+    }
+    let meteors = weather.current.as_ref()
+        .and_then(|name| WeatherPrototype::from_pt(&prototypes, name))
+        .and_then(|prototype| prototype.meteors.as_ref());
+    if let Some(meteors) = meteors {
+        let interval = ((meteors.interval * TICK_RATE as f32) as u64).max(1);
+        if (tick - weather.phase_start) % interval == interval - 1 {
+            let position = meteor_position(tick, meteors.area);
+            weather.meteors.push(Meteor {
+                position,
+                radius: meteors.radius,
+                damage: meteors.damage,
+                impact_tick: tick + (meteors.warning * TICK_RATE as f32) as u64
+            });
+            script_events.send(ScriptEvent::new("meteor_incoming", position)
+                .with("radius", DataValue::Number(meteors.radius as f64))
+                .with("delay", DataValue::Number(meteors.warning as f64)));
+        }
+    }
+    // meteors announced before the weather changed still land
+    let weather = &mut *weather;
+    let sheltered = &weather.sheltered;
+    weather.meteors.retain(|meteor| {
+        if meteor.impact_tick > tick {
+            return true
+        }
+        for (entity, transform) in targets.iter() {
+            if !sheltered.contains(&entity) && transform.translation.truncate().distance(meteor.position) <= meteor.radius {
+                damage_events.send(DamageEvent { target: entity, amount: meteor.damage, damage_type: DamageType::Explosive });
+            }
+        }
+        script_events.send(ScriptEvent::new("meteor_strike", meteor.position).with("radius", DataValue::Number(meteor.radius as f64)));
+        false
+    });
+}
+
+fn apply_weather(
+    mut weather: ResMut<Weather>,
+    units: Query<(Entity, &Transform), With<Unit>>,
+    structures: Query<(&Structure, &Transform)>,
+    prototypes: Res<Prototypes>,
+    mut effect_events: EventWriter<ApplyEffectEvent>)
+{
+    let shelters: Vec<(&Transform, Vec2)> = structures.iter()
+        .filter_map(|(structure, transform)| Some((transform, StructurePrototype::from_pt(&prototypes, &structure.prototype)?)))
+        .filter(|(_, prototype)| prototype.is_shelter())
+        .map(|(transform, prototype)| (transform, Vec2::from(prototype.size()) / 2.0))
+        .collect();
+    let is_sheltered = |position: Vec2| shelters.iter().any(|(transform, half_size)| {
+        let local = transform.rotation.inverse() * (position - transform.translation.truncate()).extend(0.0);
+        local.x.abs() <= half_size.x && local.y.abs() <= half_size.y
+    });
+    weather.sheltered = units.iter()
+        .filter(|(_, transform)| is_sheltered(transform.translation.truncate()))
+        .map(|(entity, _)| entity)
+        .collect();
+    let effect = weather.current.as_ref()
+        .and_then(|name| WeatherPrototype::from_pt(&prototypes, name))
+        .and_then(|prototype| prototype.unit_effect.as_ref());
+    if let Some(effect) = effect {
+        for (entity, _) in units.iter().filter(|(entity, _)| !weather.sheltered.contains(entity)) {
+            effect_events.send(ApplyEffectEvent { target: entity, effect: effect.clone() });
+        }
+    }
+}